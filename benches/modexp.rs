@@ -0,0 +1,37 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use num_bigint::{BigInt, RandBigInt};
+use rust_dhke::crypto::crypto::{mod_pow, mod_pow_ct, mod_pow_ct_bits, mod_pow_window, ExponentPolicy};
+use rust_dhke::crypto::groups::DhGroup;
+
+/// Compare the exponentiation strategies on a full-size exponent in each MODP group
+fn modexp(c: &mut Criterion) {
+    for group in [DhGroup::Modp2048, DhGroup::Modp3072] {
+        let p = group.prime();
+        let base: BigInt = rand::thread_rng().gen_biguint_below(p.magnitude()).into();
+        let exp: BigInt = rand::thread_rng().gen_biguint_below(p.magnitude()).into();
+        assert_eq!(mod_pow(&base, &exp, &p), mod_pow_window(&base, &exp, &p));
+
+        let mut bench = c.benchmark_group(group.name());
+        bench.sample_size(20);
+        bench.bench_function("square_and_multiply", |b| {
+            b.iter(|| mod_pow(black_box(&base), black_box(&exp), &p))
+        });
+        bench.bench_function("sliding_window", |b| {
+            b.iter(|| mod_pow_window(black_box(&base), black_box(&exp), &p))
+        });
+        bench.bench_function("montgomery_ladder", |b| {
+            b.iter(|| mod_pow_ct(black_box(&base), black_box(&exp), &p))
+        });
+
+        // The ladder over an RFC 7919 short exponent
+        let short_bits = ExponentPolicy::Short.exponent_bits(&p);
+        let short_exp: BigInt = rand::thread_rng().gen_biguint(short_bits).into();
+        bench.bench_function("montgomery_ladder_short", |b| {
+            b.iter(|| mod_pow_ct_bits(black_box(&base), black_box(&short_exp), &p, short_bits))
+        });
+        bench.finish();
+    }
+}
+
+criterion_group!(benches, modexp);
+criterion_main!(benches);
@@ -0,0 +1,42 @@
+//! Per-message forward secrecy: a Double Ratchet channel keyed from an X3DH session
+//!
+//! Runs on its own: `cargo run --example ratchet`.
+
+use std::net::{TcpListener, TcpStream};
+
+use rust_dhke::crypto::x3dh::{initiate, PrekeyOwner, X3dhIdentity};
+use rust_dhke::network::ratchet::RatchetChannel;
+
+fn main() -> std::io::Result<()> {
+    let invalid = |reason: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, reason.to_string());
+
+    // Alice and Bob agree on a secret with X3DH (the prekey directory is skipped here)
+    let mut bob = PrekeyOwner::new(X3dhIdentity::generate(), 1, 1);
+    let alice = X3dhIdentity::generate();
+    let bundle = bob.publication().bundle(None);
+    let (alice_session, initial_message) = initiate(&alice, &bundle).map_err(invalid)?;
+    let bob_session = bob.respond(&initial_message).map_err(invalid)?;
+
+    // Bob answers every message; each one, both ways, is under a key of its own
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let bob_thread = std::thread::spawn(move || -> std::io::Result<()> {
+        let (stream, _) = listener.accept()?;
+        let mut channel = RatchetChannel::responder(stream, &bob_session.secret, &bob_session.associated_data)?;
+        while let Some(message) = channel.receive()? {
+            println!("[EXAMPLE] Bob received {:?}", String::from_utf8_lossy(&message));
+            channel.send(format!("ack: {}", String::from_utf8_lossy(&message)).as_bytes())?;
+        }
+        Ok(())
+    });
+
+    let stream = TcpStream::connect(addr)?;
+    let mut channel = RatchetChannel::initiator(stream, &alice_session.secret, &alice_session.associated_data)?;
+    for text in ["hello", "how are you", "bye"] {
+        channel.send(text.as_bytes())?;
+        let reply = channel.receive()?.ok_or_else(|| invalid("Bob hung up"))?;
+        println!("[EXAMPLE] Alice received {:?}", String::from_utf8_lossy(&reply));
+    }
+    drop(channel);
+    bob_thread.join().expect("Bob's thread panicked")
+}
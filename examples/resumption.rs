@@ -0,0 +1,26 @@
+//! Resuming a session from a ticket the server issued, skipping the key exchange
+//!
+//! Start a server first (`cargo run -- --group ffdhe2048 --tickets`), then run
+//! `cargo run --example resumption [server_addr]`.
+
+use rust_dhke::network::client::DHClient;
+use rust_dhke::network::tickets::TicketStore;
+
+fn main() -> std::io::Result<()> {
+    let server_addr = std::env::args().nth(1).unwrap_or_else(|| "127.0.0.1:8080".to_string());
+    let tickets = TicketStore::new();
+
+    // The first connection runs a full handshake and leaves a ticket in the store
+    let mut first = DHClient::new(&server_addr)?.with_ticket_store(tickets.clone());
+    first.perform_key_exchange()?;
+    first.send_message(b"hello from the full handshake")?;
+    println!("[EXAMPLE] First session resumed: {}", first.resumed());
+    first.close()?;
+
+    // The second presents it; the server resumes instead of running the key exchange
+    let mut second = DHClient::new(&server_addr)?.with_ticket_store(tickets);
+    second.perform_key_exchange()?;
+    second.send_message(b"hello from the resumed session")?;
+    println!("[EXAMPLE] Second session resumed: {}", second.resumed());
+    second.close()
+}
@@ -0,0 +1,51 @@
+//! Observing a server's connections through a telemetry exporter
+//!
+//! Run `cargo run --example telemetry [listen_addr]`. The example starts its own
+//! server, connects one client, and prints the span tree the server exports. With the
+//! `opentelemetry` feature, `telemetry::opentelemetry_exporter` sends the same spans
+//! to an OpenTelemetry collector instead.
+
+use std::sync::Arc;
+use std::thread;
+
+use rust_dhke::crypto::groups::DhGroup;
+use rust_dhke::network::client::DHClient;
+use rust_dhke::network::server::{DHServer, KeyMode, ParamSource};
+use rust_dhke::network::telemetry::{TelemetryExporter, TelemetrySpan};
+
+fn print_span(span: &TelemetrySpan, depth: usize) {
+    let duration = span.end.duration_since(span.start).unwrap_or_default();
+    let attributes: Vec<String> = span.attributes.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+    println!(
+        "[EXAMPLE] {}{} {:?} {}",
+        "  ".repeat(depth),
+        span.name,
+        duration,
+        attributes.join(" ")
+    );
+    for child in &span.children {
+        print_span(child, depth + 1);
+    }
+}
+
+fn main() -> std::io::Result<()> {
+    let listen_addr = std::env::args().nth(1).unwrap_or_else(|| "127.0.0.1:8090".to_string());
+
+    let exporter: TelemetryExporter = Arc::new(|span| print_span(span, 0));
+    let server = DHServer::new(&listen_addr, ParamSource::Group(DhGroup::Ffdhe2048), None, KeyMode::Ephemeral)?
+        .with_telemetry_exporter(exporter);
+    let cancel = server.cancel_token();
+    let server_thread = thread::spawn(move || server.run());
+
+    // The connection span is exported once the client closes the connection
+    let mut client = DHClient::new(&listen_addr)?;
+    client.perform_key_exchange()?;
+    client.close()?;
+
+    // Give the server a moment to finish the connection; shutting down then waits
+    // for the connection thread, and so for its export
+    thread::sleep(std::time::Duration::from_millis(500));
+    cancel.cancel();
+    server_thread.join().expect("server thread does not panic")?;
+    Ok(())
+}
@@ -0,0 +1,49 @@
+//! Starting a session with a party that is offline, through the server's prekey directory
+//!
+//! Start a server first (`cargo run -- --group ffdhe2048 --prekey-directory`), then run
+//! `cargo run --example x3dh [server_addr]`.
+
+use rust_dhke::crypto::ct::ct_eq;
+use rust_dhke::crypto::x3dh::{initiate, PrekeyOwner, X3dhIdentity, X3dhInitialMessage};
+use rust_dhke::network::prekeys::{fetch_prekey_bundle, publish_prekeys};
+
+fn main() -> std::io::Result<()> {
+    let server_addr = std::env::args().nth(1).unwrap_or_else(|| "127.0.0.1:8080".to_string());
+    let invalid = |reason: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, reason.to_string());
+
+    // Bob publishes prekeys and goes offline; he keeps their private halves
+    let bob_identity = X3dhIdentity::generate();
+    let bob_fingerprint = bob_identity.public().fingerprint();
+    let version = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let mut bob = PrekeyOwner::new(bob_identity, version, 2);
+    publish_prekeys(&server_addr, &bob.publication())?;
+    println!("[EXAMPLE] Bob published prekeys for {}", bob_fingerprint);
+
+    // Alice knows Bob's fingerprint (e.g., from a contact list) and starts a session
+    // from his bundle alone
+    let alice = X3dhIdentity::generate();
+    let bundle = fetch_prekey_bundle(&server_addr, &bob_fingerprint)?;
+    let (alice_session, initial_message) = initiate(&alice, &bundle).map_err(invalid)?;
+    println!(
+        "[EXAMPLE] Alice started a session using one-time prekey {:?}",
+        initial_message.one_time_prekey_id
+    );
+
+    // The initial message travels over whatever transport the application uses;
+    // Bob completes the session when he comes back online
+    let delivered = X3dhInitialMessage::from_bytes(&initial_message.to_bytes()).ok_or_else(|| invalid("bad message"))?;
+    let bob_session = bob.respond(&delivered).map_err(invalid)?;
+    println!("[EXAMPLE] Bob completed the session with {}", bob_session.peer.fingerprint());
+
+    // Replaying the initial message fails: its one-time prekey is gone
+    println!("[EXAMPLE] Replayed initial message rejected: {}", bob.respond(&delivered).is_err());
+
+    println!("[EXAMPLE] Secrets match: {}", ct_eq(&alice_session.secret, &bob_session.secret));
+    println!(
+        "[EXAMPLE] Associated data match: {}",
+        alice_session.associated_data == bob_session.associated_data
+    );
+    Ok(())
+}
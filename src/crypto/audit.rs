@@ -0,0 +1,233 @@
+use num_bigint::BigInt;
+use num_traits::{One, Signed, Zero};
+
+use crate::crypto::crypto::{gcd, is_prime, mod_pow_window, small_primes, PrimalityConfig};
+
+/// Pollard's rho steps spent on each composite part of p - 1 before giving up on it
+pub const RHO_ITERATIONS: u64 = 1 << 16;
+
+/// Security level below which an audit reports the parameters as too weak
+pub const MIN_SECURITY_BITS: u32 = 112;
+
+/// Outcome of auditing a finite-field (p, g) pair
+#[derive(Debug, Clone)]
+pub struct ParamAudit {
+    /// Size of p in bits
+    pub prime_bits: u64,
+    /// Whether p passed Miller-Rabin and the strong Lucas test
+    pub p_is_prime: bool,
+    /// Whether (p - 1) / 2 is prime as well
+    pub safe_prime: bool,
+    /// Prime factors of p - 1 that were found, with multiplicity, smallest first
+    pub factors: Vec<BigInt>,
+    /// The part of p - 1 that neither trial division nor Pollard's rho could split
+    pub unfactored: Option<BigInt>,
+    /// Order of g, or a multiple of it when p - 1 was not fully factored; None if p
+    /// is not prime or g is out of range
+    pub generator_order: Option<BigInt>,
+    /// Bits of the largest prime dividing g's order, which bounds how far
+    /// Pohlig-Hellman can break discrete logs down
+    pub subgroup_bits: u64,
+    /// Estimated security in bits: the cheaper of the number field sieve against p and
+    /// Pollard's rho in the largest prime-order subgroup
+    pub security_bits: u32,
+    /// Problems found, empty for parameters this implementation would generate itself
+    pub findings: Vec<String>,
+}
+
+impl ParamAudit {
+    /// Whether the audit found no problems
+    pub fn passed(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// Print the audit results in a human readable form
+    pub fn print(&self) {
+        // Large factors are only interesting for their size
+        let factors: Vec<String> = self
+            .factors
+            .iter()
+            .map(|f| if f.bits() > 64 { format!("({}-bit prime)", f.bits()) } else { f.to_string() })
+            .collect();
+        println!("Parameter audit");
+        println!("  prime:         {} bits, {}", self.prime_bits, if self.p_is_prime { "prime" } else { "composite" });
+        println!("  safe prime:    {}", if self.safe_prime { "yes" } else { "no" });
+        match &self.unfactored {
+            Some(rest) => println!("  p - 1:         {} * ({}-bit unfactored)", factors.join(" * "), rest.bits()),
+            None => println!("  p - 1:         {}", factors.join(" * ")),
+        }
+        match &self.generator_order {
+            Some(order) => println!("  g order:       {} bits (largest prime factor {} bits)", order.bits(), self.subgroup_bits),
+            None => println!("  g order:       unknown"),
+        }
+        println!("  security:      ~{} bits", self.security_bits);
+        if self.passed() {
+            println!("  findings:      none");
+        }
+        for finding in &self.findings {
+            println!("  finding:       {}", finding);
+        }
+    }
+}
+
+/// Analyze DH parameters: primality of p, safe-prime structure, the order of g, and
+/// how far p - 1 splits into small factors that Pohlig-Hellman could exploit
+///
+/// # Arguments
+/// * `p` - Claimed prime modulus
+/// * `g` - Claimed generator
+///
+/// # Returns
+/// The audit, with an estimated security level and any problems found
+pub fn audit_params(p: &BigInt, g: &BigInt) -> ParamAudit {
+    let config = PrimalityConfig::new(p.bits() as usize);
+    let one = BigInt::one();
+    let p_minus_1: BigInt = p - &one;
+    let p_is_prime = is_prime(p, &config);
+    let safe_prime = p_is_prime && is_prime(&(&p_minus_1 / 2), &config);
+    let (factors, unfactored) = factor(&p_minus_1);
+    let mut findings = Vec::new();
+
+    if !p_is_prime {
+        findings.push("p is not prime".to_string());
+    } else if !safe_prime {
+        findings.push("p is not a safe prime: (p - 1) / 2 is not prime".to_string());
+    }
+    let in_range = g > &one && g < &p_minus_1;
+    if !in_range {
+        findings.push("g is outside 2..p-2".to_string());
+    }
+
+    // The order divides p - 1: strip every prime factor g's order does not need
+    let generator_order = (p_is_prime && in_range).then(|| {
+        let mut order = p_minus_1.clone();
+        let mut primes: Vec<&BigInt> = factors.iter().chain(&unfactored).collect();
+        primes.dedup();
+        for prime in primes {
+            while (&order % prime).is_zero() && mod_pow_window(g, &(&order / prime), p).is_one() {
+                order /= prime;
+            }
+        }
+        order
+    });
+
+    let subgroup_bits = match &generator_order {
+        Some(order) => factors
+            .iter()
+            .chain(&unfactored)
+            .filter(|prime| (order % *prime).is_zero())
+            .map(BigInt::bits)
+            .max()
+            .unwrap_or(0),
+        None => 0,
+    };
+    if let Some(order) = &generator_order {
+        if safe_prime && order == &p_minus_1 {
+            findings.push("g generates the whole group, so public keys leak one bit of the secret".to_string());
+        }
+        if subgroup_bits < 2 * MIN_SECURITY_BITS as u64 {
+            findings.push(format!(
+                "the largest prime-order subgroup of g has only {} bits; Pohlig-Hellman reduces discrete logs to it",
+                subgroup_bits
+            ));
+        }
+    }
+
+    let security_bits = if p_is_prime && in_range {
+        number_field_sieve_bits(p).min((subgroup_bits / 2) as u32)
+    } else {
+        0
+    };
+    if security_bits < MIN_SECURITY_BITS {
+        findings.push(format!("estimated security of {} bits is below {}", security_bits, MIN_SECURITY_BITS));
+    }
+
+    ParamAudit {
+        prime_bits: p.bits(),
+        p_is_prime,
+        safe_prime,
+        factors,
+        unfactored,
+        generator_order,
+        subgroup_bits,
+        security_bits,
+        findings,
+    }
+}
+
+/// Cost of the number field sieve against p, L_p[1/3, (64/9)^(1/3)], scaled so a
+/// 2048-bit p rates 112 bits as in NIST SP 800-57
+fn number_field_sieve_bits(p: &BigInt) -> u32 {
+    let log2_cost = |bits: f64| {
+        let ln_p = bits * std::f64::consts::LN_2;
+        (64.0_f64 / 9.0).cbrt() * ln_p.cbrt() * ln_p.ln().powf(2.0 / 3.0) / std::f64::consts::LN_2
+    };
+    (log2_cost(p.bits() as f64) * 112.0 / log2_cost(2048.0)) as u32
+}
+
+/// Split n into prime factors by trial division, then Pollard's rho
+///
+/// # Returns
+/// The prime factors found, smallest first, and the composite part that resisted
+fn factor(n: &BigInt) -> (Vec<BigInt>, Option<BigInt>) {
+    let mut factors = Vec::new();
+    let mut rest = n.abs();
+    if rest.is_zero() {
+        return (factors, None);
+    }
+    for &r in std::iter::once(&2).chain(small_primes()) {
+        while (&rest % r).is_zero() {
+            factors.push(BigInt::from(r));
+            rest /= r;
+        }
+    }
+
+    let mut unfactored = BigInt::one();
+    let mut pending = vec![rest];
+    while let Some(m) = pending.pop() {
+        if m.is_one() {
+            continue;
+        }
+        if is_prime(&m, &PrimalityConfig::new(m.bits() as usize)) {
+            factors.push(m);
+            continue;
+        }
+        match pollard_rho(&m) {
+            Some(divisor) => {
+                pending.push(&m / &divisor);
+                pending.push(divisor);
+            }
+            None => unfactored *= m,
+        }
+    }
+    factors.sort();
+    (factors, (!unfactored.is_one()).then_some(unfactored))
+}
+
+/// A non-trivial divisor of the composite n, if Pollard's rho finds one within
+/// `RHO_ITERATIONS` steps (Floyd cycle detection, gcds batched every 64 steps)
+fn pollard_rho(n: &BigInt) -> Option<BigInt> {
+    let polynomials = 4;
+    for c in 1..=polynomials {
+        let step = |x: &BigInt| (x * x + c) % n;
+        let (mut x, mut y) = (BigInt::from(2), BigInt::from(2));
+        let mut product = BigInt::one();
+        for i in 1..=RHO_ITERATIONS / polynomials {
+            x = step(&x);
+            y = step(&step(&y));
+            product = product * (&x - &y).abs() % n;
+            if i % 64 == 0 || i == RHO_ITERATIONS / polynomials {
+                let divisor = gcd(&product, n);
+                if divisor.is_one() {
+                    continue;
+                }
+                // The batch overshot to n; this polynomial will not help
+                if &divisor == n {
+                    break;
+                }
+                return Some(divisor);
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,102 @@
+use num_bigint::BigInt;
+
+/// Arithmetic backend behind the modular exponentiations that dominate key
+/// generation, primality testing and key agreement
+///
+/// `mod_pow_window` and `mod_pow_ct_bits` dispatch to `Active`, which is num-bigint
+/// unless the `gmp` feature swaps in GMP. Everything else stays on num-bigint.
+pub(crate) trait ModPowBackend {
+    /// (base^exp) mod modulus for a public exponent; may branch on the exponent bits
+    fn mod_pow_public(base: &BigInt, exp: &BigInt, modulus: &BigInt) -> BigInt;
+
+    /// (base^exp) mod modulus for a secret exponent, whose operation sequence
+    /// depends only on `exponent_bits` (or the exponent's length, if longer)
+    fn mod_pow_secret(base: &BigInt, exp: &BigInt, modulus: &BigInt, exponent_bits: u64) -> BigInt;
+}
+
+/// Pure Rust arithmetic: the sliding window and the Montgomery ladder in `crypto`
+pub(crate) struct NumBigint;
+
+impl ModPowBackend for NumBigint {
+    fn mod_pow_public(base: &BigInt, exp: &BigInt, modulus: &BigInt) -> BigInt {
+        crate::crypto::crypto::sliding_window(base, exp, modulus)
+    }
+
+    fn mod_pow_secret(base: &BigInt, exp: &BigInt, modulus: &BigInt, exponent_bits: u64) -> BigInt {
+        crate::crypto::crypto::montgomery_ladder(base, exp, modulus, exponent_bits)
+    }
+}
+
+/// The backend in use
+#[cfg(not(feature = "gmp"))]
+pub(crate) type Active = NumBigint;
+
+/// The backend in use
+#[cfg(feature = "gmp")]
+pub(crate) type Active = gmp::Gmp;
+
+#[cfg(feature = "gmp")]
+mod gmp {
+    use num_bigint::{BigInt, Sign};
+    use num_traits::Zero;
+    use rug::integer::Order;
+    use rug::ops::RemRounding;
+    use rug::Integer;
+
+    use super::{ModPowBackend, NumBigint};
+
+    /// GMP through rug; secret exponents go through `mpz_powm_sec`
+    pub(crate) struct Gmp;
+
+    impl ModPowBackend for Gmp {
+        fn mod_pow_public(base: &BigInt, exp: &BigInt, modulus: &BigInt) -> BigInt {
+            if exp.sign() == Sign::Minus || modulus.sign() != Sign::Plus {
+                return NumBigint::mod_pow_public(base, exp, modulus);
+            }
+            let result = to_rug(base)
+                .pow_mod_ref(&to_rug(exp), &to_rug(modulus))
+                .map(Integer::from)
+                .expect("non-negative exponents need no inverse");
+            from_rug(&result)
+        }
+
+        fn mod_pow_secret(base: &BigInt, exp: &BigInt, modulus: &BigInt, exponent_bits: u64) -> BigInt {
+            // mpz_powm_sec needs an odd modulus and a positive exponent
+            if !modulus.bit(0) || modulus.sign() != Sign::Plus || exp.sign() == Sign::Minus {
+                return NumBigint::mod_pow_secret(base, exp, modulus, exponent_bits);
+            }
+            if exp.is_zero() {
+                return BigInt::from(1) % modulus;
+            }
+
+            // mpz_powm_sec takes time depending on the exponent's length in limbs, so
+            // set a bit above every exponent of the public bound, making them all the
+            // same length, and divide base^(2^bits) back out
+            let bits = exponent_bits.max(exp.bits());
+            let modulus = to_rug(modulus);
+            let base = to_rug(base).rem_euc(&modulus);
+            let padded = to_rug(exp) + (Integer::from(1) << bits as u32);
+            let result = Integer::from(base.secure_pow_mod_ref(&padded, &modulus));
+            let padding = Integer::from(base.pow_mod_ref(&(Integer::from(1) << bits as u32), &modulus).expect("exponent is positive"));
+            match padding.invert(&modulus) {
+                Ok(inverse) => from_rug(&((result * inverse) % &modulus)),
+                // base shares a factor with the modulus; nothing about it is secret
+                Err(_) => from_rug(&Integer::from(base.secure_pow_mod_ref(&to_rug(exp), &modulus))),
+            }
+        }
+    }
+
+    fn to_rug(value: &BigInt) -> Integer {
+        let (sign, bytes) = value.to_bytes_be();
+        let magnitude = Integer::from_digits(&bytes, Order::Msf);
+        match sign {
+            Sign::Minus => -magnitude,
+            _ => magnitude,
+        }
+    }
+
+    fn from_rug(value: &Integer) -> BigInt {
+        let sign = if *value < 0 { Sign::Minus } else { Sign::Plus };
+        BigInt::from_bytes_be(sign, &value.to_digits::<u8>(Order::Msf))
+    }
+}
@@ -0,0 +1,164 @@
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+use crate::crypto::rng::{with_rng, RngPurpose};
+
+/// How long a cookie stays valid unless configured otherwise; a client echoes it
+/// right away, so this only needs to cover a round trip
+pub const DEFAULT_COOKIE_LIFETIME: Duration = Duration::from_secs(30);
+
+/// Hardest puzzle a client solves, in leading zero bits (about 2^24 hashes, a
+/// fraction of a second); a server asking for more is refused rather than obeyed
+pub const MAX_PUZZLE_DIFFICULTY: u8 = 24;
+
+/// Prefix of the hash a puzzle solution is checked against
+const PUZZLE_LABEL: &[u8] = b"dhke client puzzle";
+
+/// Length of a puzzle solution, which the client appends to the cookie it echoes
+const SOLUTION_LEN: usize = 8;
+
+/// How hard the proof-of-work puzzles riding on HelloRetry cookies are
+///
+/// A client must find a solution whose hash with the cookie starts with
+/// `difficulty` zero bits, costing it about 2^difficulty hashes, before the server
+/// spends an exponentiation on it; checking a solution costs the server one hash.
+/// The difficulty rises linearly from the minimum while the server is idle to the
+/// maximum once `busy_connections` connections are established.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PuzzlePolicy {
+    /// Leading zero bits required while no connection is established
+    pub min_difficulty: u8,
+    /// Leading zero bits required once `busy_connections` are established
+    pub max_difficulty: u8,
+    /// Established connections at which the difficulty reaches its maximum
+    pub busy_connections: usize,
+}
+
+impl PuzzlePolicy {
+    /// Difficulty of the puzzles issued while `established` connections are open
+    pub fn difficulty(&self, established: usize) -> u8 {
+        let max = self.max_difficulty.max(self.min_difficulty);
+        let span = usize::from(max - self.min_difficulty);
+        let load = established.min(self.busy_connections.max(1));
+        self.min_difficulty + (span * load / self.busy_connections.max(1)) as u8
+    }
+}
+
+/// Server key for the cookies of HelloRetry messages (HMAC-SHA256), so the server
+/// keeps no state between a ClientHello and its repeat
+///
+/// A cookie binds the client's IP address, its ClientHello random, the time it was
+/// issued and the difficulty of its puzzle: only a client that received it at that
+/// address can echo it, and only with the same random, which the replay window then
+/// lets through once.
+#[derive(Clone)]
+pub struct CookieKey {
+    key: [u8; 32],
+    lifetime: Duration,
+}
+
+impl CookieKey {
+    /// Generate a random cookie key whose cookies stay valid for `lifetime`
+    pub fn generate(lifetime: Duration) -> Self {
+        CookieKey {
+            key: with_rng(RngPurpose::SecretKey, |rng| rng.r#gen()),
+            lifetime,
+        }
+    }
+
+    /// Issue a cookie for a ClientHello from `peer`, with a puzzle of `difficulty`
+    /// leading zero bits (0 for none)
+    ///
+    /// Layout: [issued:u64][difficulty][HMAC-SHA256 of [issued][difficulty][peer address][random]]
+    pub fn issue(&self, peer: IpAddr, random: &[u8; 32], difficulty: u8) -> Vec<u8> {
+        let issued = unix_time().to_be_bytes();
+        let tag = self.mac(&issued, difficulty, peer, random).finalize().into_bytes();
+        [issued.as_slice(), &[difficulty], tag.as_slice()].concat()
+    }
+
+    /// Check a cookie a ClientHello from `peer` echoed, with the solution to its
+    /// puzzle appended if it has one (see `answer_hello_retry`)
+    ///
+    /// # Returns
+    /// true if this key issued it for the same address and random, it has not expired,
+    /// and its puzzle is solved
+    pub fn verify(&self, echoed: &[u8], peer: IpAddr, random: &[u8; 32]) -> bool {
+        let Some((issued, rest)) = echoed.split_first_chunk::<8>() else {
+            return false;
+        };
+        let Some((&difficulty, rest)) = rest.split_first() else {
+            return false;
+        };
+        let solution_len = if difficulty == 0 { 0 } else { SOLUTION_LEN };
+        if rest.len() != 32 + solution_len {
+            return false;
+        }
+        let (tag, solution) = rest.split_at(32);
+        if unix_time().saturating_sub(u64::from_be_bytes(*issued)) > self.lifetime.as_secs() {
+            return false;
+        }
+        self.mac(issued, difficulty, peer, random).verify_slice(tag).is_ok()
+            && puzzle_solved(&echoed[..echoed.len() - solution_len], solution, difficulty)
+    }
+
+    fn mac(&self, issued: &[u8; 8], difficulty: u8, peer: IpAddr, random: &[u8; 32]) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(issued);
+        mac.update(&[difficulty]);
+        match peer {
+            IpAddr::V4(address) => mac.update(&address.octets()),
+            IpAddr::V6(address) => mac.update(&address.octets()),
+        }
+        mac.update(random);
+        mac
+    }
+}
+
+/// The cookie a client echoes in answer to a HelloRetry: the issued cookie, followed
+/// by the solution to its puzzle if it has one
+///
+/// The puzzle's difficulty is the cookie's ninth byte, in the clear so the client can
+/// read it; the MAC covers it, so the client cannot lower it.
+///
+/// # Returns
+/// The cookie to echo, or None if the puzzle is harder than `MAX_PUZZLE_DIFFICULTY`
+pub fn answer_hello_retry(cookie: &[u8]) -> Option<Vec<u8>> {
+    let difficulty = cookie.get(8).copied().unwrap_or(0);
+    if difficulty == 0 {
+        return Some(cookie.to_vec());
+    }
+    if difficulty > MAX_PUZZLE_DIFFICULTY {
+        return None;
+    }
+    let solution = (0..u64::MAX)
+        .map(u64::to_be_bytes)
+        .find(|solution| puzzle_solved(cookie, solution, difficulty))?;
+    Some([cookie, &solution].concat())
+}
+
+/// Whether SHA-256(label || cookie || solution) starts with `difficulty` zero bits
+fn puzzle_solved(cookie: &[u8], solution: &[u8], difficulty: u8) -> bool {
+    let hash = Sha256::new()
+        .chain_update(PUZZLE_LABEL)
+        .chain_update(cookie)
+        .chain_update(solution)
+        .finalize();
+    let mut zeros = 0;
+    for byte in hash {
+        zeros += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    zeros >= u32::from(difficulty)
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
@@ -1,9 +1,122 @@
-use num_bigint::{BigInt, RandBigInt, BigUint, ToBigInt};
-use num_traits::{One, Zero};
-use rand::Rng;
+use std::sync::OnceLock;
+
+use num_bigint::{BigInt, RandBigInt};
+use num_traits::{One, Signed, ToPrimitive, Zero};
+use rand::rngs::OsRng;
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use rayon::iter::ParallelIterator;
+use sha2::{Digest, Sha256};
+
+use crate::crypto::bignum::{Active, ModPowBackend};
+use crate::crypto::montgomery::MontgomeryModulus;
+use crate::crypto::rng::{with_rng, RngPurpose, SecureRandom};
+
+/// How prime candidates are tested during parameter generation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrimalityConfig {
+    /// Miller-Rabin rounds run on every candidate that survives the sieve
+    pub rounds: usize,
+    /// Bit length of the primes to generate
+    pub bit_length: usize,
+    /// Whether candidates that pass Miller-Rabin must also pass a strong Lucas test,
+    /// which together make the Baillie-PSW test
+    pub strong_lucas: bool,
+}
+
+impl PrimalityConfig {
+    /// Rounds used when none are configured, and for checking parameters from elsewhere
+    pub const DEFAULT_ROUNDS: usize = 64;
+
+    /// Test bit_length-bit candidates with the default number of rounds and a strong
+    /// Lucas test
+    pub fn new(bit_length: usize) -> Self {
+        PrimalityConfig { rounds: Self::DEFAULT_ROUNDS, bit_length, strong_lucas: true }
+    }
+
+    /// Use a different number of Miller-Rabin rounds
+    pub fn with_rounds(mut self, rounds: usize) -> Self {
+        self.rounds = rounds;
+        self
+    }
+
+    /// Enable or disable the strong Lucas test after Miller-Rabin
+    ///
+    /// No composite is known to pass both, and the two tests fail on unrelated sets of
+    /// numbers, so a composite built to survive Miller-Rabin (e.g., in parameters a
+    /// peer proposes) has to beat a test it was not built for as well. Disabling it
+    /// only saves time on random candidates, where Miller-Rabin alone is already sound.
+    pub fn with_strong_lucas(mut self, enabled: bool) -> Self {
+        self.strong_lucas = enabled;
+        self
+    }
+
+    /// Error bound that holds for any input, including adversarially chosen composites
+    ///
+    /// # Returns
+    /// b such that a composite passes all rounds with probability at most 2^-b (4^-rounds)
+    pub fn worst_case_bound_bits(&self) -> f64 {
+        2.0 * self.rounds as f64
+    }
+
+    /// Error bound for the candidates generation tests, which are random bit_length-bit
+    /// numbers rather than adversarial ones
+    ///
+    /// Uses the Damgård-Landrock-Pomerance bounds for random k-bit candidates, which
+    /// are far tighter than 4^-t for large k. A candidate that passed t rounds also
+    /// passed every smaller number of rounds, so the best bound for any t' <= t applies.
+    /// Parameters received from elsewhere only get `worst_case_bound_bits`.
+    ///
+    /// # Returns
+    /// b such that a random composite passes all rounds with probability at most 2^-b
+    pub fn false_positive_bound_bits(&self) -> f64 {
+        let k = self.bit_length as f64;
+        let t = self.rounds as f64;
+        let mut best = self.worst_case_bound_bits();
+        if self.rounds == 0 {
+            return best;
+        }
+
+        // p(k, 1) < k^2 4^(2 - sqrt(k)) for k >= 2
+        if self.bit_length >= 2 {
+            best = best.max(-(2.0 * k.log2() + 2.0 * (2.0 - k.sqrt())));
+        }
+
+        // p(k, t) < k^(3/2) 2^t t^(-1/2) 4^(2 - sqrt(tk)) for 3 <= t <= k/9, k >= 21
+        let t_mid = t.min((k / 9.0).floor());
+        if self.bit_length >= 21 && t_mid >= 3.0 {
+            let log2 = 1.5 * k.log2() + t_mid - 0.5 * t_mid.log2() + 2.0 * (2.0 - (t_mid * k).sqrt());
+            best = best.max(-log2);
+        }
+
+        // p(k, t) < 7/20 k 2^(-5t) + 1/7 k^(15/4) 2^(-k/2 - 2t) + 12 k 2^(-k/4 - 3t)
+        // for t >= k/4, k >= 88
+        if self.bit_length >= 88 && t >= k / 4.0 {
+            let terms = [
+                (7.0f64 / 20.0).log2() + k.log2() - 5.0 * t,
+                (1.0f64 / 7.0).log2() + 3.75 * k.log2() - k / 2.0 - 2.0 * t,
+                12f64.log2() + k.log2() - k / 4.0 - 3.0 * t,
+            ];
+            let largest = terms.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            let log2 = largest + terms.iter().map(|term| (term - largest).exp2()).sum::<f64>().log2();
+            best = best.max(-log2);
+        }
+
+        best
+    }
+}
+
+/// Tests a number for primality as `config` asks: Miller-Rabin, then optionally a
+/// strong Lucas test
+pub(crate) fn is_prime(n: &BigInt, config: &PrimalityConfig) -> bool {
+    miller_rabin(n, config.rounds) && (!config.strong_lucas || n < &BigInt::from(4) || strong_lucas(n))
+}
 
 /// Performs Miller-Rabin primality test on a number
-fn is_prime(n: &BigInt, rounds: usize) -> bool {
+///
+/// Witnesses always come from the OS: they are not secret, and whether a composite
+/// survives must not depend on a generator an attacker might predict.
+pub(crate) fn miller_rabin(n: &BigInt, rounds: usize) -> bool {
     if n < &BigInt::from(2) {
         return false;
     }
@@ -22,11 +135,11 @@ fn is_prime(n: &BigInt, rounds: usize) -> bool {
         r += 1;
     }
 
-    let mut rng = rand::thread_rng();
+    let mut rng = OsRng;
     
     'witness_loop: for _ in 0..rounds {
         let a = rng.gen_bigint_range(&BigInt::from(2), &(n - BigInt::one()));
-        let mut x = mod_pow(&a, &d, n);
+        let mut x = mod_pow_window(&a, &d, n);
 
         if x == BigInt::one() || x == n - BigInt::one() {
             continue 'witness_loop;
@@ -45,7 +158,107 @@ fn is_prime(n: &BigInt, rounds: usize) -> bool {
     true
 }
 
+/// Strong Lucas probable-prime test with Selfridge's parameters, for odd n > 3
+///
+/// D is the first of 5, -7, 9, -11, ... with Jacobi symbol (D/n) = -1, P = 1 and
+/// Q = (1 - D) / 4. Writing n + 1 = d * 2^s, n passes if U_d = 0 or V_(d 2^r) = 0
+/// (mod n) for some 0 <= r < s, which every prime does.
+pub fn strong_lucas(n: &BigInt) -> bool {
+    if !n.bit(0) {
+        return false;
+    }
+    // Perfect squares have no D with (D/n) = -1
+    let root = n.sqrt();
+    if &(&root * &root) == n {
+        return false;
+    }
+
+    let mut d_param = BigInt::from(5);
+    loop {
+        match jacobi(&d_param, n) {
+            -1 => break,
+            // A shared factor is a proper divisor unless it is n itself
+            0 if &d_param.abs() != n => return false,
+            _ => {}
+        }
+        d_param = if d_param.is_positive() { -(d_param + 2u32) } else { -(d_param - 2u32) };
+    }
+    let q_param: BigInt = (BigInt::one() - &d_param) / 4;
+
+    let reduce = |x: BigInt| non_negative_mod(&x, n);
+    let halve = |x: BigInt| {
+        let x = reduce(x);
+        if x.bit(0) { (x + n) / 2 } else { x / 2 }
+    };
+
+    let mut d = n + BigInt::one();
+    let mut s = 0;
+    while !d.bit(0) {
+        d /= 2;
+        s += 1;
+    }
+
+    // U_k, V_k and Q^k for k running through the prefixes of d's bits, with P = 1
+    let mut u = BigInt::one();
+    let mut v = BigInt::one();
+    let mut q_k = reduce(q_param.clone());
+    for i in (0..d.bits() - 1).rev() {
+        u = reduce(&u * &v);
+        v = reduce(&v * &v - &q_k * 2);
+        q_k = reduce(&q_k * &q_k);
+        if d.bit(i) {
+            let next_u = halve(&u + &v);
+            v = halve(&d_param * &u + &v);
+            u = next_u;
+            q_k = reduce(&q_k * &q_param);
+        }
+    }
+
+    if u.is_zero() || v.is_zero() {
+        return true;
+    }
+    for _ in 1..s {
+        v = reduce(&v * &v - &q_k * 2);
+        if v.is_zero() {
+            return true;
+        }
+        q_k = reduce(&q_k * &q_k);
+    }
+    false
+}
+
+/// Jacobi symbol (a/n) for odd positive n
+fn jacobi(a: &BigInt, n: &BigInt) -> i32 {
+    let mut a = non_negative_mod(a, n);
+    let mut n = n.clone();
+    let mut result = 1;
+    while !a.is_zero() {
+        while !a.bit(0) {
+            a /= 2;
+            let r = (&n % 8u32).to_u32().expect("residue is below 8");
+            if r == 3 || r == 5 {
+                result = -result;
+            }
+        }
+        std::mem::swap(&mut a, &mut n);
+        if (&a % 4u32).to_u32() == Some(3) && (&n % 4u32).to_u32() == Some(3) {
+            result = -result;
+        }
+        a = non_negative_mod(&a, &n);
+    }
+    if n.is_one() { result } else { 0 }
+}
+
+/// a mod n in 0..n, also for negative a
+fn non_negative_mod(a: &BigInt, n: &BigInt) -> BigInt {
+    let r = a % n;
+    if r.is_negative() { r + n } else { r }
+}
+
 /// Modular exponentiation: (base^exp) mod modulus
+///
+/// Branches on the exponent bits, so only use it for public exponents. Large public
+/// exponents are faster through `mod_pow_window`; secret ones go through `mod_pow_ct`.
 pub fn mod_pow(base: &BigInt, exp: &BigInt, modulus: &BigInt) -> BigInt {
     let mut result = BigInt::one();
     let mut base = base % modulus;
@@ -62,75 +275,537 @@ pub fn mod_pow(base: &BigInt, exp: &BigInt, modulus: &BigInt) -> BigInt {
     result
 }
 
+/// Modular exponentiation with a k-ary sliding window, for public exponents
+///
+/// Precomputes the odd powers base^1, base^3, ..., base^(2^k - 1) and then consumes
+/// the exponent up to k bits at a time, which saves most of the multiplications of
+/// `mod_pow` on large exponents. The window size grows with the exponent length.
+/// Like `mod_pow`, this branches on the exponent bits and must not see secrets.
+///
+/// Runs on GMP instead with the `gmp` feature.
+///
+/// # Returns
+/// (base^exp) mod modulus
+pub fn mod_pow_window(base: &BigInt, exp: &BigInt, modulus: &BigInt) -> BigInt {
+    Active::mod_pow_public(base, exp, modulus)
+}
+
+/// The num-bigint implementation of `mod_pow_window`
+pub(crate) fn sliding_window(base: &BigInt, exp: &BigInt, modulus: &BigInt) -> BigInt {
+    let bits = exp.bits();
+    if bits == 0 {
+        return BigInt::one() % modulus;
+    }
+    let window = window_size(bits);
+
+    // odd_powers[i] = base^(2i + 1) mod modulus
+    let base = base % modulus;
+    let square = (&base * &base) % modulus;
+    let mut odd_powers = vec![base];
+    for i in 1..1usize << (window - 1) {
+        let next = (&odd_powers[i - 1] * &square) % modulus;
+        odd_powers.push(next);
+    }
+
+    let mut result = BigInt::one();
+    let mut i = bits as i64 - 1;
+    while i >= 0 {
+        if !exp.bit(i as u64) {
+            result = (&result * &result) % modulus;
+            i -= 1;
+            continue;
+        }
+
+        // Longest window starting at bit i that is at most `window` bits and ends in a 1
+        let mut low = (i - window as i64 + 1).max(0);
+        while !exp.bit(low as u64) {
+            low += 1;
+        }
+        let mut value = 0usize;
+        for bit in (low..=i).rev() {
+            value = (value << 1) | exp.bit(bit as u64) as usize;
+        }
+
+        for _ in low..=i {
+            result = (&result * &result) % modulus;
+        }
+        result = (&result * &odd_powers[value >> 1]) % modulus;
+        i = low - 1;
+    }
+
+    result
+}
+
+/// Window size for `mod_pow_window`, balancing precomputation against multiplications saved
+fn window_size(exponent_bits: u64) -> u32 {
+    match exponent_bits {
+        672.. => 6,
+        240.. => 5,
+        80.. => 4,
+        24.. => 3,
+        _ => 1,
+    }
+}
+
+/// Modular exponentiation for secret exponents using a Montgomery ladder
+///
+/// Every bit position up to the size of the modulus costs exactly one multiply and
+/// one square over fixed-width limbs, and the ladder registers are swapped with masks
+/// instead of by branching, so neither the sequence of operations nor their timing
+/// depends on the exponent's value or its number of leading zeros.
+///
+/// # Returns
+/// (base^exp) mod modulus, the same value `mod_pow` computes
+pub fn mod_pow_ct(base: &BigInt, exp: &BigInt, modulus: &BigInt) -> BigInt {
+    mod_pow_ct_bits(base, exp, modulus, modulus.bits())
+}
+
+/// Montgomery ladder as in `mod_pow_ct`, over `exponent_bits` bit positions instead
+/// of the size of the modulus
+///
+/// For exponents drawn below a public bound (see `ExponentPolicy`), which saves the
+/// steps above it; an exponent longer than the bound still gets all of its bits.
+/// With the `gmp` feature this is GMP's `mpz_powm_sec` instead, on an exponent
+/// padded to the same length.
+pub fn mod_pow_ct_bits(base: &BigInt, exp: &BigInt, modulus: &BigInt, exponent_bits: u64) -> BigInt {
+    Active::mod_pow_secret(base, exp, modulus, exponent_bits)
+}
+
+/// The num-bigint implementation of `mod_pow_ct_bits`: the ladder runs on fixed-width
+/// limbs (see `MontgomeryModulus`), as num-bigint's own arithmetic takes time that
+/// depends on the values
+///
+/// Moduli that are even or below 2 (never a DH prime) fall back to `BigInt::modpow`.
+pub(crate) fn montgomery_ladder(base: &BigInt, exp: &BigInt, modulus: &BigInt, exponent_bits: u64) -> BigInt {
+    match MontgomeryModulus::new(modulus) {
+        Some(arithmetic) => arithmetic.ladder(base, exp.magnitude(), exponent_bits.max(exp.bits())),
+        None => base.modpow(exp, modulus),
+    }
+}
+
+/// Random bits in the multiple of the group order added to blinded exponents
+pub const BLINDING_BITS: u64 = 64;
+
+/// Randomization applied to secret exponentiations, on top of the constant-time ladder
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Blinding {
+    /// Exponentiate with the secret exponent as it is
+    #[default]
+    None,
+    /// Exponentiate with x + k * q for a fresh random k, so repeated operations with
+    /// the same key never process the same exponent bits
+    Exponent,
+    /// Exponent blinding, and also multiply the base by a random r first and remove
+    /// r^e afterwards, so the operands do not depend on the (attacker-chosen) base
+    ExponentAndBase,
+}
+
+impl Blinding {
+    /// Every blinding mode known to this implementation
+    pub const ALL: &'static [Blinding] = &[Blinding::None, Blinding::Exponent, Blinding::ExponentAndBase];
+
+    /// Short lowercase name of the mode (e.g., "exponent")
+    pub fn name(&self) -> &'static str {
+        match self {
+            Blinding::None => "none",
+            Blinding::Exponent => "exponent",
+            Blinding::ExponentAndBase => "base",
+        }
+    }
+
+    /// Look up a mode by its name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|blinding| blinding.name() == name)
+    }
+}
+
+/// Modular exponentiation for secret exponents with optional blinding
+///
+/// Blinding costs a longer ladder (`BLINDING_BITS` beyond the size of q) and, for the
+/// base, a modular inversion and a second ladder per call.
+///
+/// # Arguments
+/// * `base` - An element of the subgroup of order `order`
+/// * `exp` - The secret exponent
+/// * `modulus` - The prime modulus p
+/// * `order` - The order q of the subgroup `base` lies in
+/// * `blinding` - Which randomization to apply
+/// * `policy` - The policy the exponent was drawn under, which sets the ladder length
+///   without blinding
+///
+/// # Returns
+/// (base^exp) mod modulus, whatever the blinding
+pub fn mod_pow_blinded(
+    base: &BigInt,
+    exp: &BigInt,
+    modulus: &BigInt,
+    order: &BigInt,
+    blinding: Blinding,
+    policy: ExponentPolicy,
+) -> BigInt {
+    if blinding == Blinding::None {
+        return mod_pow_ct_bits(base, exp, modulus, policy.exponent_bits(modulus));
+    }
+
+    // base^q = 1, so adding multiples of q leaves the result unchanged
+    let (k, r) = with_rng(RngPurpose::SecretKey, |rng| {
+        let k: BigInt = rng.gen_biguint(BLINDING_BITS).into();
+        (k, rng.gen_bigint_range(&BigInt::from(2), &(modulus - BigInt::one())))
+    });
+    let blinded_exp = exp + k * order;
+    let bits = order.bits() + BLINDING_BITS + 1;
+    if blinding == Blinding::Exponent {
+        return mod_pow_ct_bits(base, &blinded_exp, modulus, bits);
+    }
+
+    // (base * r)^e * (r^-1)^e = base^e; r^-1 = r^(p - 2) since the modulus is prime
+    let r_inverse = mod_pow_window(&r, &(modulus - BigInt::from(2)), modulus);
+    let blinded = mod_pow_ct_bits(&((base * r) % modulus), &blinded_exp, modulus, bits);
+    let unblind = mod_pow_ct_bits(&r_inverse, &blinded_exp, modulus, bits);
+    (blinded * unblind) % modulus
+}
+
+/// Greatest common divisor of |a| and |b|
+pub(crate) fn gcd(a: &BigInt, b: &BigInt) -> BigInt {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while !b.is_zero() {
+        let remainder = &a % &b;
+        a = b;
+        b = remainder;
+    }
+    a
+}
+
+/// Generates a random odd number of exactly bit_length bits
+fn random_odd_candidate<R: SecureRandom + ?Sized>(rng: &mut R, bit_length: usize) -> BigInt {
+    let mut p: BigInt = rng.gen_biguint(bit_length as u64).into();
+    
+    // Ensure it's odd
+    if &p % 2 == BigInt::zero() {
+        p += BigInt::one();
+    }
+    
+    // Set MSB to ensure correct bit length
+    p.set_bit((bit_length - 1) as u64, true);
+    p
+}
+
+/// Odd primes below this bound are used to sieve candidates before Miller-Rabin
+const SIEVE_BOUND: u32 = 2048;
+
+/// Number of consecutive odd candidates sieved from each random starting point
+const SIEVE_WINDOW: u32 = 4096;
+
+/// The odd primes below SIEVE_BOUND, computed once with the sieve of Eratosthenes
+pub(crate) fn small_primes() -> &'static [u32] {
+    static SMALL_PRIMES: OnceLock<Vec<u32>> = OnceLock::new();
+    SMALL_PRIMES.get_or_init(|| {
+        let mut composite = vec![false; SIEVE_BOUND as usize];
+        let mut primes = Vec::new();
+        for n in 3..SIEVE_BOUND {
+            if !composite[n as usize] {
+                primes.push(n);
+                for multiple in (n * n..SIEVE_BOUND).step_by(n as usize) {
+                    composite[multiple as usize] = true;
+                }
+            }
+        }
+        primes
+    })
+}
+
+/// n mod r for every small prime r
+fn small_residues(n: &BigInt) -> Vec<u32> {
+    small_primes()
+        .iter()
+        .map(|&r| (n % r).to_u32().expect("residue is below the small prime"))
+        .collect()
+}
+
 /// Generates a random prime of approximately bit_length bits
-fn generate_random_prime(bit_length: usize) -> BigInt {
-    let mut rng = rand::thread_rng();
+///
+/// Each worker picks a random odd start and sieves the following odd numbers against
+/// the small primes, tracking residues incrementally so only survivors pay for
+/// Miller-Rabin. Workers run concurrently on all cores, each drawing its start from
+/// `OsRng`; the first prime found wins.
+pub fn generate_random_prime(config: &PrimalityConfig) -> BigInt {
+    rayon::iter::repeat(())
+        .find_map_any(|_| search_prime(&random_odd_candidate(&mut OsRng, config.bit_length), config))
+        .expect("candidate stream is infinite")
+}
 
+/// Generates a random prime as `generate_random_prime` does, drawing the starting
+/// points from `rng`
+///
+/// Windows are searched one after another, so a deterministic `rng` gives the same
+/// prime every time.
+pub fn generate_random_prime_with<R: SecureRandom + ?Sized>(rng: &mut R, config: &PrimalityConfig) -> BigInt {
     loop {
-        let mut p = rng.gen_bigint(bit_length as u64);
-        
-        // Ensure it's odd
-        if &p % 2 == BigInt::zero() {
-            p += BigInt::one();
-        }
-        
-        // Set MSB and LSB to ensure correct bit length
-        p.set_bit((bit_length - 1) as u64, true);
-        
-        if is_prime(&p, 64) {
+        if let Some(p) = search_prime(&random_odd_candidate(rng, config.bit_length), config) {
             return p;
         }
     }
 }
 
-/// Finds a generator g for the multiplicative group modulo p
-/// g should satisfy: 1 < g < p and g^((p-1)/2) mod p != 1
-fn find_generator(p: &BigInt) -> BigInt {
-    let mut rng = rand::thread_rng();
-    let exp = (p - BigInt::one()) / 2;
+/// Looks for a prime of config.bit_length bits in the sieve window starting at `start`
+fn search_prime(start: &BigInt, config: &PrimalityConfig) -> Option<BigInt> {
+    let residues = small_residues(start);
+    // Tiny candidates may be small primes themselves, so only sieve above the table
+    let sieve = start > &BigInt::from(SIEVE_BOUND);
+
+    (0..SIEVE_WINDOW).step_by(2).find_map(|offset| {
+        let divisible = sieve && small_primes()
+            .iter()
+            .zip(&residues)
+            .any(|(&r, &residue)| (residue + offset) % r == 0);
+        if divisible {
+            return None;
+        }
+        let p = start + offset;
+        (p.bits() == config.bit_length as u64 && is_prime(&p, config)).then_some(p)
+    })
+}
+
+/// Generates a safe prime p = 2q + 1 of bit_length bits where q is also prime
+///
+/// # Returns
+/// A tuple (p, q). The multiplicative group modulo p then has only the subgroups of
+/// order 1, 2, q and 2q, so there are no small subgroups to confine keys to.
+///
+/// Safe primes are rare, so each worker sieves a window of q candidates, dropping any
+/// where q or 2q + 1 has a small factor, and workers run concurrently on all cores,
+/// each drawing its start from `OsRng`; the first pair found wins.
+pub fn generate_safe_prime(config: &PrimalityConfig) -> (BigInt, BigInt) {
+    rayon::iter::repeat(())
+        .find_map_any(|_| {
+            let start = random_odd_candidate(&mut OsRng, config.bit_length - 1);
+            search_safe_prime(&start, config)
+        })
+        .expect("candidate stream is infinite")
+}
+
+/// Generates a safe prime as `generate_safe_prime` does, drawing the starting points
+/// from `rng` and searching their windows one after another
+pub fn generate_safe_prime_with<R: SecureRandom + ?Sized>(rng: &mut R, config: &PrimalityConfig) -> (BigInt, BigInt) {
+    loop {
+        let start = random_odd_candidate(rng, config.bit_length - 1);
+        if let Some(found) = search_safe_prime(&start, config) {
+            return found;
+        }
+    }
+}
+
+/// Looks for a safe prime p = 2q + 1 with q in the sieve window starting at `start`
+///
+/// # Returns
+/// The first (p, q) in the window where both are prime and p has config.bit_length bits
+fn search_safe_prime(start: &BigInt, config: &PrimalityConfig) -> Option<(BigInt, BigInt)> {
+    let residues = small_residues(start);
+    let sieve = start > &BigInt::from(SIEVE_BOUND);
 
+    (0..SIEVE_WINDOW).step_by(2).find_map(|offset| {
+        // q = start + offset and p = 2q + 1 are both free of small factors
+        let divisible = sieve && small_primes().iter().zip(&residues).any(|(&r, &residue)| {
+            let q = (residue + offset) % r;
+            q == 0 || (2 * q + 1) % r == 0
+        });
+        if divisible {
+            return None;
+        }
+
+        let q = start + offset;
+        let p: BigInt = &q * 2 + BigInt::one();
+        if p.bits() != config.bit_length as u64 {
+            return None;
+        }
+
+        // A single round weeds out almost every composite before paying for the full test
+        let is_safe = miller_rabin(&q, 1) && miller_rabin(&p, 1) && is_prime(&q, config) && is_prime(&p, config);
+        is_safe.then_some((p, q))
+    })
+}
+
+/// Finds a generator g of the subgroup of prime order q modulo p, where q divides p - 1
+/// g should satisfy: 1 < g < p - 1 and g^q mod p == 1 (see `validate_generator`)
+pub(crate) fn find_generator<R: SecureRandom + ?Sized>(rng: &mut R, p: &BigInt, q: &BigInt) -> BigInt {
+    let cofactor: BigInt = (p - BigInt::one()) / q;
     loop {
-        let g = rng.gen_bigint_range(&BigInt::from(2), &(p - BigInt::one()));
+        let h = rng.gen_bigint_range(&BigInt::from(2), &(p - BigInt::one()));
         
-        // Check if g is a valid generator
-        // For a safe prime, g should satisfy: g^((p-1)/2) mod p != 1
-        let test = mod_pow(&g, &exp, p);
-        if test != BigInt::one() {
+        // Raising to (p - 1) / q lands in the subgroup of order q (for a safe prime,
+        // squaring lands in the quadratic residues)
+        let g = mod_pow_window(&h, &cofactor, p);
+        if validate_generator(p, &g, q).is_ok() {
             return g;
         }
     }
 }
 
+/// Checks that g generates the subgroup of order q modulo p
+///
+/// Checking g^((p - 1) / 2) != 1 only suffices for safe primes; with other primes g
+/// could have any order dividing p - 1. For prime q, g != 1 with g^q = 1 pins g's
+/// order to exactly q.
+///
+/// # Arguments
+/// * `p` - Prime modulus
+/// * `g` - Claimed generator
+/// * `q` - Claimed prime order of the subgroup g generates
+///
+/// # Returns
+/// A description of the first check that failed, if any
+pub fn validate_generator(p: &BigInt, g: &BigInt, q: &BigInt) -> Result<(), &'static str> {
+    if g <= &BigInt::one() || g >= &(p - BigInt::one()) {
+        return Err("g must be between 2 and p - 2");
+    }
+    if q <= &BigInt::one() || !((p - BigInt::one()) % q).is_zero() {
+        return Err("q does not divide p - 1");
+    }
+    if !mod_pow_window(g, q, p).is_one() {
+        return Err("g does not generate the subgroup of order q");
+    }
+    Ok(())
+}
+
 /// Generates DH parameters (p, g) for key exchange
 /// 
 /// # Arguments
-/// * `bit_length` - The bit length of prime p (typically 1024, 2048, or 4096)
+/// * `config` - Bit length of prime p (typically 1024, 2048, or 4096) and Miller-Rabin rounds
 ///
 /// # Returns
-/// A tuple (p, g) where:
-/// - p is a large random prime
-/// - g is a generator of the multiplicative group modulo p
-pub fn generate_dh_params(bit_length: usize) -> (BigInt, BigInt) {
-    println!("Generating {} bit prime p...", bit_length);
-    let p = generate_random_prime(bit_length);
+/// A tuple (p, g, q) where:
+/// - p is a large random safe prime (p = 2q + 1)
+/// - g is a generator of the subgroup of order q modulo p
+/// - q is the prime order of that subgroup, used to validate generators and public keys
+pub fn generate_dh_params(config: &PrimalityConfig) -> (BigInt, BigInt, BigInt) {
+    println!("Generating {} bit safe prime p...", config.bit_length);
+    let (p, q) = generate_safe_prime(config);
     
     println!("Prime p generated. Generating generator g...");
-    let g = find_generator(&p);
+    let g = find_generator(&mut OsRng, &p, &q);
     
     println!("DH parameters generated successfully!");
-    (p, g)
+    (p, g, q)
+}
+
+/// Generates DH parameters as `generate_dh_params` does, drawing every candidate and
+/// the generator from `rng`
+///
+/// # Returns
+/// A tuple (p, g, q) as for `generate_dh_params`
+pub fn generate_dh_params_with<R: SecureRandom + ?Sized>(rng: &mut R, config: &PrimalityConfig) -> (BigInt, BigInt, BigInt) {
+    println!("Generating {} bit safe prime p...", config.bit_length);
+    let (p, q) = generate_safe_prime_with(rng, config);
+
+    println!("Prime p generated. Generating generator g...");
+    let g = find_generator(rng, &p, &q);
+
+    println!("DH parameters generated successfully!");
+    (p, g, q)
+}
+
+/// Generates DH parameters deterministically from a seed
+///
+/// Candidates and the generator are drawn from ChaCha20 keyed with SHA-256(seed) and
+/// searched sequentially, so anyone holding the seed can regenerate and verify the
+/// same (p, g, q). Miller-Rabin witnesses stay random; they only affect the outcome
+/// with negligible probability.
+///
+/// # Arguments
+/// * `config` - Bit length of prime p and Miller-Rabin rounds
+/// * `seed` - Arbitrary seed bytes; identical seeds and bit lengths give identical parameters
+///
+/// # Returns
+/// A tuple (p, g, q) as for `generate_dh_params`
+pub fn generate_dh_params_seeded(config: &PrimalityConfig, seed: &[u8]) -> (BigInt, BigInt, BigInt) {
+    let mut rng = ChaCha20Rng::from_seed(Sha256::digest(seed).into());
+
+    println!("Generating {} bit safe prime p from seed...", config.bit_length);
+    let (p, q) = generate_safe_prime_with(&mut rng, config);
+
+    println!("Prime p generated. Generating generator g...");
+    let g = find_generator(&mut rng, &p, &q);
+
+    println!("DH parameters generated successfully!");
+    (p, g, q)
+}
+
+/// Checks that (p, g) are parameters this implementation would generate itself
+///
+/// # Arguments
+/// * `p` - Claimed safe prime modulus
+/// * `g` - Claimed generator of the order-q subgroup
+///
+/// # Returns
+/// The subgroup order q = (p - 1) / 2, or a description of the first check that failed
+pub fn validate_dh_params(p: &BigInt, g: &BigInt) -> Result<BigInt, &'static str> {
+    let q: BigInt = (p - BigInt::one()) / 2;
+    let config = PrimalityConfig::new(p.bits() as usize);
+    if !is_prime(&q, &config) || !is_prime(p, &config) {
+        return Err("p is not a safe prime");
+    }
+    validate_generator(p, g, &q)?;
+    Ok(q)
+}
+
+/// How long the secret exponents `generate_secret_key` draws are
+///
+/// Clients and servers each have their own (see `DHClient::with_exponent_policy`).
+/// The exponentiations with a key use the same policy to set the ladder length, so
+/// the policy a key was drawn under must be passed along with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExponentPolicy {
+    /// Uniform in [2, p - 2]
+    #[default]
+    Full,
+    /// Short exponents of the length RFC 7919 section 5.2 gives for the size of p
+    ///
+    /// The best attacks on a short exponent are Pollard's lambda and baby-step
+    /// giant-step in the exponent, costing about 2^(bits / 2), so an exponent twice
+    /// as long as the group's security strength loses nothing against the number
+    /// field sieve on p. With safe primes no small subgroup speeds this up.
+    Short,
+    /// Exponents of this many bits, or the `Short` length if that is longer
+    Bits(u64),
+}
+
+impl ExponentPolicy {
+    /// Bit length of the exponents drawn for the prime p, at most the size of p
+    pub fn exponent_bits(&self, p: &BigInt) -> u64 {
+        let short = match p.bits() {
+            8192.. => 400,
+            6144.. => 375,
+            4096.. => 325,
+            3072.. => 275,
+            _ => 225,
+        };
+        let bits = match self {
+            ExponentPolicy::Full => p.bits(),
+            ExponentPolicy::Short => short,
+            ExponentPolicy::Bits(bits) => short.max(*bits),
+        };
+        bits.min(p.bits())
+    }
 }
 
 /// Generates a random secret key for DH key exchange
 ///
 /// # Arguments
 /// * `p` - The prime modulus from DH parameters
+/// * `policy` - How long the exponent is
 ///
 /// # Returns
-/// A random BigInt in the range (1, p-1) to be used as a secret key
-pub fn generate_secret_key(p: &BigInt) -> BigInt {
-    let mut rng = rand::thread_rng();
-    rng.gen_bigint_range(&BigInt::from(2), &(p - BigInt::one()))
+/// A random BigInt in the range (1, p-1) to be used as a secret key, below
+/// 2^bits when the exponent policy asks for shorter exponents
+pub fn generate_secret_key(p: &BigInt, policy: ExponentPolicy) -> BigInt {
+    with_rng(RngPurpose::SecretKey, |rng| generate_secret_key_with(rng, p, policy))
+}
+
+/// Generates a secret key as `generate_secret_key` does, drawing it from `rng`
+pub fn generate_secret_key_with<R: SecureRandom + ?Sized>(rng: &mut R, p: &BigInt, policy: ExponentPolicy) -> BigInt {
+    let bits = policy.exponent_bits(p);
+    let bound = (BigInt::one() << bits).min(p - BigInt::one());
+    rng.gen_bigint_range(&BigInt::from(2), &bound)
 }
 
 /// Computes the public key from a secret key using DH parameters
@@ -139,9 +814,67 @@ pub fn generate_secret_key(p: &BigInt) -> BigInt {
 /// * `secret_key` - The private key
 /// * `g` - The generator from DH parameters
 /// * `p` - The prime modulus from DH parameters
+/// * `policy` - The policy the secret key was drawn under, which sets the ladder length
 ///
 /// # Returns
 /// The public key: g^{secret_key} mod p
-pub fn compute_public_key(secret_key: &BigInt, g: &BigInt, p: &BigInt) -> BigInt {
-    mod_pow(g, secret_key, p)
+pub fn compute_public_key(secret_key: &BigInt, g: &BigInt, p: &BigInt, policy: ExponentPolicy) -> BigInt {
+    mod_pow_ct_bits(g, secret_key, p, policy.exponent_bits(p))
+}
+
+/// Computes the shared secret from the peer's (already validated) public key, with
+/// a secret key drawn under `policy`
+///
+/// # Returns
+/// peer_public_key^{secret_key} mod p
+pub fn compute_shared_secret(peer_public_key: &BigInt, secret_key: &BigInt, p: &BigInt, policy: ExponentPolicy) -> BigInt {
+    mod_pow_ct_bits(peer_public_key, secret_key, p, policy.exponent_bits(p))
+}
+
+/// Checks that a received finite-field public key is a usable group element
+///
+/// # Arguments
+/// * `y` - The public key received from the peer
+/// * `p` - The prime modulus from DH parameters
+/// * `q` - The order of the subgroup generated by g ((p - 1) / 2 for a safe prime)
+///
+/// # Returns
+/// An error if y is 0 or 1, p - 1 or larger (including values outside the field),
+/// or not in the order-q subgroup, where it would confine the shared secret to a
+/// small set of values the peer can predict
+pub fn validate_public_key(y: &BigInt, p: &BigInt, q: &BigInt) -> Result<(), &'static str> {
+    if y <= &BigInt::one() {
+        return Err("public key must be greater than 1");
+    }
+    if y >= &(p - BigInt::one()) {
+        return Err("public key must be less than p - 1");
+    }
+    if !mod_pow_window(y, q, p).is_one() {
+        return Err("public key is not in the prime-order subgroup");
+    }
+    Ok(())
+}
+
+/// Detects a reflected or degenerate peer public key
+///
+/// # Arguments
+/// * `peer_public_key` - The public key received from the peer
+/// * `own_public_key` - Our own public key for this exchange
+/// * `g` - The generator from DH parameters
+///
+/// # Returns
+/// An error describing the problem if the peer echoed our own public key back
+/// (a reflection attack or broken peer) or sent g itself (secret exponent of 1)
+pub fn check_not_reflected(
+    peer_public_key: &BigInt,
+    own_public_key: &BigInt,
+    g: &BigInt,
+) -> Result<(), &'static str> {
+    if peer_public_key == own_public_key {
+        return Err("peer public key is identical to our own (reflection)");
+    }
+    if peer_public_key == g || own_public_key == g {
+        return Err("public key equals the generator g");
+    }
+    Ok(())
 }
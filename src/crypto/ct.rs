@@ -0,0 +1,37 @@
+use std::hint::black_box;
+
+use num_bigint::BigInt;
+
+/// Compare two byte slices in time that depends only on their lengths
+///
+/// Use this instead of `==` for keys, MACs, and fingerprints, where an early exit at
+/// the first differing byte would tell an attacker how much of a guess was right.
+///
+/// # Returns
+/// Whether the slices are equal; slices of different lengths are unequal, which is
+/// decided without looking at their contents
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let difference = a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | black_box(x ^ y));
+    black_box(difference) == 0
+}
+
+/// Compare two non-negative BigInts (digests, shared secrets) in time that depends
+/// only on the length of the longer one
+///
+/// Both are compared as big-endian bytes zero-padded to the same width, so values
+/// that differ only in their leading zero bytes still take the same path.
+pub fn ct_eq_bigint(a: &BigInt, b: &BigInt) -> bool {
+    let (a_sign, a_bytes) = a.to_bytes_be();
+    let (b_sign, b_bytes) = b.to_bytes_be();
+    let width = a_bytes.len().max(b_bytes.len());
+    ct_eq(&pad(&a_bytes, width), &pad(&b_bytes, width)) & (a_sign == b_sign)
+}
+
+fn pad(bytes: &[u8], width: usize) -> Vec<u8> {
+    let mut padded = vec![0; width - bytes.len()];
+    padded.extend_from_slice(bytes);
+    padded
+}
@@ -0,0 +1,125 @@
+use crate::crypto::kdf::SessionKeys;
+
+/// Builds an application's own cipher from a key exported by the handshake
+///
+/// Implement this for whatever cipher an existing application already uses; the
+/// handshake then only supplies keys, and `CipherPipeline` supplies nonces.
+pub trait StreamCipherFactory {
+    /// Keyed cipher instance for one direction of traffic
+    type Cipher;
+
+    /// Create a cipher keyed with `key`, which is used for this direction only
+    fn create(&self, key: &[u8; 32]) -> Self::Cipher;
+}
+
+/// A 96-bit nonce, handed out exactly once by a `NonceSequence`
+///
+/// Nonces can only be obtained from a sequence and are neither `Clone` nor `Copy`,
+/// so code that passes a `Nonce` by value to its cipher cannot reuse one by accident.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Nonce {
+    bytes: [u8; 12],
+    sequence: u64,
+}
+
+impl Nonce {
+    /// The nonce as the 12 bytes most AEAD ciphers expect
+    pub fn as_bytes(&self) -> &[u8; 12] {
+        &self.bytes
+    }
+
+    /// Position of this nonce in its sequence (0 for the first message)
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+}
+
+/// Unique nonces for one direction: an exported 4-byte prefix followed by a 64-bit
+/// big-endian message counter
+///
+/// Both peers derive the same sequence for each direction, so the receiver's nonce
+/// for a message matches the sender's as long as messages arrive in order and none
+/// are skipped, as on a TCP stream.
+#[derive(Debug)]
+pub struct NonceSequence {
+    prefix: [u8; 4],
+    next: Option<u64>,
+}
+
+impl NonceSequence {
+    fn new(prefix: [u8; 4]) -> Self {
+        NonceSequence { prefix, next: Some(0) }
+    }
+
+    /// Take the next nonce
+    ///
+    /// # Returns
+    /// A nonce never returned before, or an error once the counter is exhausted;
+    /// the session must then be rekeyed with a new handshake
+    pub fn next_nonce(&mut self) -> std::io::Result<Nonce> {
+        let sequence = self.next.ok_or_else(|| std::io::Error::other("Nonce sequence exhausted"))?;
+        self.next = sequence.checked_add(1);
+
+        let mut bytes = [0; 12];
+        bytes[..4].copy_from_slice(&self.prefix);
+        bytes[4..].copy_from_slice(&sequence.to_be_bytes());
+        Ok(Nonce { bytes, sequence })
+    }
+}
+
+/// One direction of an external cipher pipeline: a cipher and the only source of its nonces
+pub struct CipherDirection<C> {
+    cipher: C,
+    nonces: NonceSequence,
+}
+
+impl<C> CipherDirection<C> {
+    /// The cipher together with the nonce for the next message
+    ///
+    /// Call this once per message, in the order messages are sent (or received).
+    pub fn next_message(&mut self) -> std::io::Result<(&mut C, Nonce)> {
+        let nonce = self.nonces.next_nonce()?;
+        Ok((&mut self.cipher, nonce))
+    }
+}
+
+/// Keys from a completed key exchange, handed to an application's own ciphers
+///
+/// Each direction gets its own exported key and nonce prefix, so the client and
+/// server never encrypt under the same (key, nonce) pair.
+pub struct CipherPipeline<C> {
+    /// Encrypts what this side sends
+    pub send: CipherDirection<C>,
+    /// Decrypts what this side receives
+    pub receive: CipherDirection<C>,
+}
+
+impl<C> CipherPipeline<C> {
+    /// Pipeline for the client side of a session
+    pub fn client<F: StreamCipherFactory<Cipher = C>>(keys: &SessionKeys, factory: &F) -> Self {
+        CipherPipeline {
+            send: direction(keys, factory, b"client"),
+            receive: direction(keys, factory, b"server"),
+        }
+    }
+
+    /// Pipeline for the server side of a session
+    pub fn server<F: StreamCipherFactory<Cipher = C>>(keys: &SessionKeys, factory: &F) -> Self {
+        CipherPipeline {
+            send: direction(keys, factory, b"server"),
+            receive: direction(keys, factory, b"client"),
+        }
+    }
+}
+
+/// Export the key and nonce prefix of the direction written by `writer`
+fn direction<F: StreamCipherFactory>(keys: &SessionKeys, factory: &F, writer: &[u8]) -> CipherDirection<F::Cipher> {
+    let mut key = [0; 32];
+    keys.export(&[b"dhke external ", writer, b" write key"].concat(), &mut key);
+    let mut prefix = [0; 4];
+    keys.export(&[b"dhke external ", writer, b" write nonce"].concat(), &mut prefix);
+    CipherDirection {
+        cipher: factory.create(&key),
+        nonces: NonceSequence::new(prefix),
+    }
+}
@@ -0,0 +1,100 @@
+use sha2::{Digest, Sha256};
+
+use crate::crypto::ct::ct_eq;
+use crate::crypto::transcript::from_hex;
+
+/// Number of leading fingerprint bytes spelled out as words; enough that a man in
+/// the middle cannot find a key whose words match by trial and error
+pub const FINGERPRINT_WORDS: usize = 8;
+
+/// SHA-256 fingerprint of a public key, for comparing keys out of band (read aloud,
+/// over the phone, or side by side on two screens), like SSH host key fingerprints
+///
+/// Equality is constant-time, since comparing against a pinned fingerprint is an
+/// authentication check.
+#[derive(Debug, Clone, Copy)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    /// Fingerprint the wire encoding of a public key
+    ///
+    /// # Arguments
+    /// * `public_key` - Big-endian public value, key share, or identity key bytes
+    pub fn of(public_key: &[u8]) -> Self {
+        Fingerprint(Sha256::digest(public_key).into())
+    }
+
+    /// Parse a fingerprint as printed by `to_hex_groups`; the "SHA256:" prefix and
+    /// the spaces between groups are optional
+    pub fn from_hex(text: &str) -> Option<Self> {
+        let hex: String = text.strip_prefix("SHA256:").unwrap_or(text).split_whitespace().collect();
+        from_hex(&hex)?.try_into().ok().map(Fingerprint)
+    }
+
+    /// Raw SHA-256 digest
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Hex in groups of four digits, prefixed with the hash name (e.g., "SHA256:3f2a 9c01 ...")
+    pub fn to_hex_groups(&self) -> String {
+        let groups: Vec<String> = self.0.chunks(2).map(|pair| format!("{:02x}{:02x}", pair[0], pair[1])).collect();
+        format!("SHA256:{}", groups.join(" "))
+    }
+
+    /// The first `FINGERPRINT_WORDS` bytes as words, one word per byte
+    pub fn to_words(&self) -> String {
+        let words: Vec<&str> = self.0[..FINGERPRINT_WORDS].iter().map(|&b| WORDS[b as usize]).collect();
+        words.join(" ")
+    }
+}
+
+impl PartialEq for Fingerprint {
+    fn eq(&self, other: &Self) -> bool {
+        ct_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Fingerprint {}
+
+impl std::fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.to_hex_groups(), self.to_words())
+    }
+}
+
+/// One distinct, easily spelled word per byte value
+const WORDS: [&str; 256] = [
+    "acid", "acorn", "actor", "adobe", "agent", "alarm", "album", "alley",
+    "amber", "anchor", "angle", "ankle", "apple", "apron", "arch", "arena",
+    "armor", "arrow", "atlas", "attic", "aunt", "autumn", "award", "axis",
+    "bacon", "badge", "bagel", "baker", "bamboo", "banjo", "barn", "basil",
+    "basin", "beach", "beacon", "beard", "beaver", "bench", "berry", "bison",
+    "blade", "blaze", "bloom", "board", "boat", "bonus", "boot", "bottle",
+    "brick", "bridge", "broom", "brush", "bucket", "bugle", "cabin", "cactus",
+    "camel", "canal", "candle", "canoe", "canyon", "carbon", "cargo", "carpet",
+    "castle", "cedar", "cello", "chalk", "cherry", "chess", "cider", "circus",
+    "clay", "cliff", "clock", "cloud", "clover", "coast", "cobra", "comet",
+    "coral", "cotton", "cougar", "crane", "crater", "crayon", "cricket", "crystal",
+    "cup", "daisy", "dancer", "delta", "desert", "diamond", "dinner", "doctor",
+    "dolphin", "donkey", "dragon", "drum", "eagle", "easel", "echo", "elbow",
+    "ember", "engine", "fabric", "falcon", "feather", "fence", "ferry", "fiddle",
+    "flame", "flute", "forest", "fossil", "fox", "galaxy", "garden", "garlic",
+    "gecko", "ginger", "glacier", "globe", "goat", "gravel", "guitar", "hammer",
+    "harbor", "harp", "hazel", "helmet", "heron", "hill", "honey", "hornet",
+    "igloo", "island", "ivory", "jacket", "jaguar", "jasmine", "jelly", "jigsaw",
+    "jungle", "kayak", "kettle", "kitten", "koala", "ladder", "lagoon", "lantern",
+    "lemon", "lily", "lizard", "llama", "locket", "magnet", "mango", "maple",
+    "marble", "meadow", "melon", "mirror", "monkey", "moose", "mosaic", "muffin",
+    "nectar", "needle", "noodle", "nutmeg", "oasis", "ocean", "olive", "onion",
+    "orbit", "orchid", "otter", "oyster", "paddle", "panda", "parrot", "peach",
+    "pebble", "pepper", "piano", "pillow", "pine", "pirate", "planet", "plum",
+    "pocket", "pony", "potato", "pumpkin", "puzzle", "quartz", "quilt", "rabbit",
+    "radar", "raven", "reef", "ribbon", "river", "robot", "rocket", "rose",
+    "ruby", "saddle", "salmon", "sandal", "satin", "scarf", "shadow", "shell",
+    "silver", "sketch", "sled", "snail", "sparrow", "spider", "sponge", "squid",
+    "stable", "statue", "stone", "sugar", "summit", "sunset", "swan", "tablet",
+    "tiger", "timber", "tomato", "torch", "trumpet", "tulip", "turtle", "valley",
+    "velvet", "violin", "volcano", "wagon", "walnut", "walrus", "whale", "wheat",
+    "willow", "window", "wizard", "wolf", "yacht", "yogurt", "zebra", "zipper",
+];
@@ -0,0 +1,222 @@
+use num_bigint::BigInt;
+use num_traits::Num;
+use sha2::{Digest, Sha256};
+
+/// RFC 3526 1536-bit MODP group prime (generator 2)
+const MODP_1536_P: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74\
+    020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437\
+    4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+    EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05\
+    98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB\
+    9ED529077096966D670C354E4ABC9804F1746C08CA237327FFFFFFFFFFFFFFFF";
+
+/// RFC 3526 2048-bit MODP group prime (generator 2)
+const MODP_2048_P: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74\
+    020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437\
+    4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+    EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05\
+    98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB\
+    9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B\
+    E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718\
+    3995497CEA956AE515D2261898FA051015728E5A8AACAA68FFFFFFFFFFFFFFFF";
+
+/// RFC 3526 3072-bit MODP group prime (generator 2)
+const MODP_3072_P: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74\
+    020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437\
+    4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+    EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05\
+    98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB\
+    9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B\
+    E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718\
+    3995497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33\
+    A85521ABDF1CBA64ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7\
+    ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA06D98A0864\
+    D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E2\
+    08E24FA074E5AB3143DB5BFCE0FD108E4B82D120A93AD2CAFFFFFFFFFFFFFFFF";
+
+/// RFC 3526 4096-bit MODP group prime (generator 2)
+const MODP_4096_P: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74\
+    020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437\
+    4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+    EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05\
+    98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB\
+    9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B\
+    E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718\
+    3995497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33\
+    A85521ABDF1CBA64ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7\
+    ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA06D98A0864\
+    D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E2\
+    08E24FA074E5AB3143DB5BFCE0FD108E4B82D120A92108011A723C12A787E6D7\
+    88719A10BDBA5B2699C327186AF4E23C1A946834B6150BDA2583E9CA2AD44CE8\
+    DBBBC2DB04DE8EF92E8EFC141FBECAA6287C59474E6BC05D99B2964FA090C3A2\
+    233BA186515BE7ED1F612970CEE2D7AFB81BDD762170481CD0069127D5B05AA9\
+    93B4EA988D8FDDC186FFB7DC90A6C08F4DF435C934063199FFFFFFFFFFFFFFFF";
+
+/// RFC 7919 2048-bit ffdhe group prime (generator 2)
+const FFDHE_2048_P: &str = "\
+    FFFFFFFFFFFFFFFFADF85458A2BB4A9AAFDC5620273D3CF1D8B9C583CE2D3695\
+    A9E13641146433FBCC939DCE249B3EF97D2FE363630C75D8F681B202AEC4617A\
+    D3DF1ED5D5FD65612433F51F5F066ED0856365553DED1AF3B557135E7F57C935\
+    984F0C70E0E68B77E2A689DAF3EFE8721DF158A136ADE73530ACCA4F483A797A\
+    BC0AB182B324FB61D108A94BB2C8E3FBB96ADAB760D7F4681D4F42A3DE394DF4\
+    AE56EDE76372BB190B07A7C8EE0A6D709E02FCE1CDF7E2ECC03404CD28342F61\
+    9172FE9CE98583FF8E4F1232EEF28183C3FE3B1B4C6FAD733BB5FCBC2EC22005\
+    C58EF1837D1683B2C6F34A26C1B2EFFA886B423861285C97FFFFFFFFFFFFFFFF";
+
+/// RFC 7919 3072-bit ffdhe group prime (generator 2)
+const FFDHE_3072_P: &str = "\
+    FFFFFFFFFFFFFFFFADF85458A2BB4A9AAFDC5620273D3CF1D8B9C583CE2D3695\
+    A9E13641146433FBCC939DCE249B3EF97D2FE363630C75D8F681B202AEC4617A\
+    D3DF1ED5D5FD65612433F51F5F066ED0856365553DED1AF3B557135E7F57C935\
+    984F0C70E0E68B77E2A689DAF3EFE8721DF158A136ADE73530ACCA4F483A797A\
+    BC0AB182B324FB61D108A94BB2C8E3FBB96ADAB760D7F4681D4F42A3DE394DF4\
+    AE56EDE76372BB190B07A7C8EE0A6D709E02FCE1CDF7E2ECC03404CD28342F61\
+    9172FE9CE98583FF8E4F1232EEF28183C3FE3B1B4C6FAD733BB5FCBC2EC22005\
+    C58EF1837D1683B2C6F34A26C1B2EFFA886B4238611FCFDCDE355B3B6519035B\
+    BC34F4DEF99C023861B46FC9D6E6C9077AD91D2691F7F7EE598CB0FAC186D91C\
+    AEFE130985139270B4130C93BC437944F4FD4452E2D74DD364F2E21E71F54BFF\
+    5CAE82AB9C9DF69EE86D2BC522363A0DABC521979B0DEADA1DBF9A42D5C4484E\
+    0ABCD06BFA53DDEF3C1B20EE3FD59D7C25E41D2B66C62E37FFFFFFFFFFFFFFFF";
+
+/// RFC 7919 4096-bit ffdhe group prime (generator 2)
+const FFDHE_4096_P: &str = "\
+    FFFFFFFFFFFFFFFFADF85458A2BB4A9AAFDC5620273D3CF1D8B9C583CE2D3695\
+    A9E13641146433FBCC939DCE249B3EF97D2FE363630C75D8F681B202AEC4617A\
+    D3DF1ED5D5FD65612433F51F5F066ED0856365553DED1AF3B557135E7F57C935\
+    984F0C70E0E68B77E2A689DAF3EFE8721DF158A136ADE73530ACCA4F483A797A\
+    BC0AB182B324FB61D108A94BB2C8E3FBB96ADAB760D7F4681D4F42A3DE394DF4\
+    AE56EDE76372BB190B07A7C8EE0A6D709E02FCE1CDF7E2ECC03404CD28342F61\
+    9172FE9CE98583FF8E4F1232EEF28183C3FE3B1B4C6FAD733BB5FCBC2EC22005\
+    C58EF1837D1683B2C6F34A26C1B2EFFA886B4238611FCFDCDE355B3B6519035B\
+    BC34F4DEF99C023861B46FC9D6E6C9077AD91D2691F7F7EE598CB0FAC186D91C\
+    AEFE130985139270B4130C93BC437944F4FD4452E2D74DD364F2E21E71F54BFF\
+    5CAE82AB9C9DF69EE86D2BC522363A0DABC521979B0DEADA1DBF9A42D5C4484E\
+    0ABCD06BFA53DDEF3C1B20EE3FD59D7C25E41D2B669E1EF16E6F52C3164DF4FB\
+    7930E9E4E58857B6AC7D5F42D69F6D187763CF1D5503400487F55BA57E31CC7A\
+    7135C886EFB4318AED6A1E012D9E6832A907600A918130C46DC778F971AD0038\
+    092999A333CB8B7A1A1DB93D7140003C2A4ECEA9F98D0ACC0A8291CDCEC97DCF\
+    8EC9B55A7F88A46B4DB5A851F44182E1C68A007E5E655F6AFFFFFFFFFFFFFFFF";
+
+/// Well-known DH groups that peers can reference by ID instead of sending (p, g) in full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DhGroup {
+    /// RFC 3526 1536-bit MODP group (IKE group 5)
+    Modp1536,
+    /// RFC 3526 2048-bit MODP group (IKE group 14)
+    Modp2048,
+    /// RFC 3526 3072-bit MODP group (IKE group 15)
+    Modp3072,
+    /// RFC 3526 4096-bit MODP group (IKE group 16)
+    Modp4096,
+    /// RFC 7919 2048-bit finite-field group (TLS named group ffdhe2048)
+    Ffdhe2048,
+    /// RFC 7919 3072-bit finite-field group (TLS named group ffdhe3072)
+    Ffdhe3072,
+    /// RFC 7919 4096-bit finite-field group (TLS named group ffdhe4096)
+    Ffdhe4096,
+}
+
+impl DhGroup {
+    /// Every group known to this implementation
+    pub const ALL: &'static [DhGroup] = &[
+        DhGroup::Modp1536,
+        DhGroup::Modp2048,
+        DhGroup::Modp3072,
+        DhGroup::Modp4096,
+        DhGroup::Ffdhe2048,
+        DhGroup::Ffdhe3072,
+        DhGroup::Ffdhe4096,
+    ];
+
+    /// Wire identifier of the group (IKE transform IDs for MODP groups, TLS named group
+    /// code points for ffdhe groups; the two ranges do not overlap)
+    pub fn id(&self) -> u16 {
+        match self {
+            DhGroup::Modp1536 => 5,
+            DhGroup::Modp2048 => 14,
+            DhGroup::Modp3072 => 15,
+            DhGroup::Modp4096 => 16,
+            DhGroup::Ffdhe2048 => 0x0100,
+            DhGroup::Ffdhe3072 => 0x0101,
+            DhGroup::Ffdhe4096 => 0x0102,
+        }
+    }
+
+    /// Short lowercase name of the group (e.g., "modp2048")
+    pub fn name(&self) -> &'static str {
+        match self {
+            DhGroup::Modp1536 => "modp1536",
+            DhGroup::Modp2048 => "modp2048",
+            DhGroup::Modp3072 => "modp3072",
+            DhGroup::Modp4096 => "modp4096",
+            DhGroup::Ffdhe2048 => "ffdhe2048",
+            DhGroup::Ffdhe3072 => "ffdhe3072",
+            DhGroup::Ffdhe4096 => "ffdhe4096",
+        }
+    }
+
+    /// Look up a group by its short name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|group| group.name() == name)
+    }
+
+    /// Look up a group by its wire identifier
+    pub fn from_id(id: u16) -> Option<Self> {
+        Self::ALL.iter().copied().find(|group| group.id() == id)
+    }
+
+    /// Prime modulus p of the group
+    pub fn prime(&self) -> BigInt {
+        let hex = match self {
+            DhGroup::Modp1536 => MODP_1536_P,
+            DhGroup::Modp2048 => MODP_2048_P,
+            DhGroup::Modp3072 => MODP_3072_P,
+            DhGroup::Modp4096 => MODP_4096_P,
+            DhGroup::Ffdhe2048 => FFDHE_2048_P,
+            DhGroup::Ffdhe3072 => FFDHE_3072_P,
+            DhGroup::Ffdhe4096 => FFDHE_4096_P,
+        };
+        BigInt::from_str_radix(hex, 16).expect("group primes are valid hex")
+    }
+
+    /// Generator g of the group
+    pub fn generator(&self) -> BigInt {
+        BigInt::from(2)
+    }
+
+    /// Order q of the subgroup generated by g; every group here uses a safe prime p = 2q + 1
+    pub fn subgroup_order(&self) -> BigInt {
+        (self.prime() - 1) / 2
+    }
+
+    /// The (p, g) pair of the group
+    pub fn params(&self) -> (BigInt, BigInt) {
+        (self.prime(), self.generator())
+    }
+
+    /// Find the named group matching the given parameters, if any
+    pub fn identify(p: &BigInt, g: &BigInt) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|group| &group.generator() == g && &group.prime() == p)
+    }
+}
+
+/// SHA-256 fingerprint of a (p, g) pair, for pinning and comparing server parameters
+///
+/// Hashes the length-prefixed big-endian encodings of p and g, so equal parameters
+/// always give the same fingerprint regardless of how they were transmitted.
+pub fn params_fingerprint(p: &BigInt, g: &BigInt) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for value in [p, g] {
+        let (_, bytes) = value.to_bytes_be();
+        hasher.update((bytes.len() as u32).to_be_bytes());
+        hasher.update(bytes);
+    }
+    hasher.finalize().into()
+}
@@ -0,0 +1,113 @@
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use hkdf::Hkdf;
+use num_bigint::BigInt;
+use sha2::Sha256;
+
+use crate::crypto::record::Padding;
+use crate::crypto::transcript::Transcript;
+use crate::structs::DH_Prot::DHMessage;
+
+/// Encrypts the handshake messages that carry identities (TLS 1.3-style)
+///
+/// Keys come from an ephemeral shared secret and the transcript so far, so a passive
+/// observer, who cannot compute the secret, never sees which static or signing key a
+/// party used. Each direction has its own AES-256-GCM key and message counter.
+pub struct HandshakeProtection {
+    send: Aes256Gcm,
+    receive: Aes256Gcm,
+    sent: u64,
+    received: u64,
+    /// Padding of the messages we seal, or None if messages are unpadded
+    padding: Option<Padding>,
+}
+
+impl HandshakeProtection {
+    /// Handshake encryption for the client side
+    ///
+    /// # Arguments
+    /// * `ephemeral_secret` - Shared secret of an exchange between ephemeral keys only
+    /// * `transcript` - The transcript through the server's key share
+    pub fn client(ephemeral_secret: &BigInt, transcript: &Transcript) -> Self {
+        let (client_key, server_key) = derive(ephemeral_secret, transcript);
+        HandshakeProtection::new(&client_key, &server_key)
+    }
+
+    /// Handshake encryption for the server side
+    pub fn server(ephemeral_secret: &BigInt, transcript: &Transcript) -> Self {
+        let (client_key, server_key) = derive(ephemeral_secret, transcript);
+        HandshakeProtection::new(&server_key, &client_key)
+    }
+
+    fn new(send_key: &[u8; 32], receive_key: &[u8; 32]) -> Self {
+        HandshakeProtection {
+            send: Aes256Gcm::new(send_key.into()),
+            receive: Aes256Gcm::new(receive_key.into()),
+            sent: 0,
+            received: 0,
+            padding: None,
+        }
+    }
+
+    /// Pad every sealed message, which the peer must do too (see `Padding`)
+    pub fn with_padding(mut self, padding: Padding) -> Self {
+        self.padding = Some(padding);
+        self
+    }
+
+    /// Seal a handshake message for sending
+    pub fn seal(&mut self, message: &DHMessage) -> DHMessage {
+        let nonce = counter_nonce(self.sent);
+        self.sent += 1;
+        let mut plaintext = message.to_bytes();
+        if let Some(padding) = self.padding {
+            padding.pad(&mut plaintext);
+        }
+        let ciphertext = self
+            .send
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+            .expect("AES-GCM encrypts messages of any handshake size");
+        DHMessage::EncryptedHandshake { ciphertext }
+    }
+
+    /// Open a received EncryptedHandshake
+    ///
+    /// # Returns
+    /// The inner message, or an error if the message is not an EncryptedHandshake or
+    /// does not decrypt (wrong keys, or tampered with on the way)
+    pub fn open(&mut self, message: Option<DHMessage>) -> Result<DHMessage, &'static str> {
+        let Some(DHMessage::EncryptedHandshake { ciphertext }) = message else {
+            return Err("identity was not sent under handshake encryption");
+        };
+        let nonce = counter_nonce(self.received);
+        self.received += 1;
+        let mut plaintext = self
+            .receive
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| "encrypted handshake message does not decrypt")?;
+        if self.padding.is_some() {
+            Padding::unpad(&mut plaintext).map_err(|_| "encrypted handshake message is malformed")?;
+        }
+        DHMessage::from_bytes(&plaintext).ok_or("encrypted handshake message is malformed")
+    }
+}
+
+/// Client and server handshake keys, bound to the transcript like the session keys
+fn derive(ephemeral_secret: &BigInt, transcript: &Transcript) -> ([u8; 32], [u8; 32]) {
+    let (_, secret_bytes) = ephemeral_secret.to_bytes_be();
+    let hkdf = Hkdf::<Sha256>::new(Some(&transcript.hash()), &secret_bytes);
+    let mut client_key = [0; 32];
+    let mut server_key = [0; 32];
+    hkdf.expand(b"dhke client handshake key", &mut client_key)
+        .expect("output length is far below the HKDF-SHA256 limit");
+    hkdf.expand(b"dhke server handshake key", &mut server_key)
+        .expect("output length is far below the HKDF-SHA256 limit");
+    (client_key, server_key)
+}
+
+/// 96-bit nonce holding the message's position in its direction
+fn counter_nonce(sequence: u64) -> [u8; 12] {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(&sequence.to_be_bytes());
+    nonce
+}
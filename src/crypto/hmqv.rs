@@ -0,0 +1,185 @@
+use num_bigint::{BigInt, Sign};
+use sha2::{Digest, Sha256};
+
+use crate::crypto::crypto::{
+    check_not_reflected, compute_public_key, compute_shared_secret, generate_secret_key, mod_pow_ct, validate_public_key,
+    ExponentPolicy,
+};
+use crate::crypto::kex::{KexAlgorithm, KeyExchange};
+
+/// Which side of an HMQV exchange we are; the two sides combine keys asymmetrically
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HmqvRole {
+    /// The client, which sends its key share first
+    Initiator,
+    /// The server
+    Responder,
+}
+
+/// HMQV (Krawczyk, 2005): the shared secret depends on both parties' static and
+/// ephemeral keys, so only holders of the static secrets can compute it. This
+/// authenticates both sides implicitly, without signatures.
+///
+/// Each key share is [4-byte length][static public key][4-byte length][ephemeral
+/// public key]. With A = g^a, X = g^x (initiator) and B = g^b, Y = g^y (responder):
+///
+/// d = H("d" || X || B), e = H("e" || Y || A)
+/// initiator: (Y * B^e)^(x + d*a), responder: (X * A^d)^(y + e*b), both mod p
+///
+/// With identity protection only the ephemeral keys travel in the clear; the
+/// ephemeral-only secret g^xy keys the encryption of the static keys, and the
+/// peer's full share is reassembled with `join_share` once its static key arrives.
+#[derive(Clone)]
+pub struct HmqvKeyExchange {
+    prime: BigInt,
+    base: BigInt,
+    subgroup_order: BigInt,
+    role: HmqvRole,
+    static_secret: BigInt,
+    static_public: BigInt,
+    ephemeral_secret: BigInt,
+    ephemeral_public: BigInt,
+    /// The policy the ephemeral exponent was drawn under
+    policy: ExponentPolicy,
+}
+
+impl HmqvKeyExchange {
+    /// Start an exchange with a long-term secret and a fresh ephemeral one
+    ///
+    /// # Arguments
+    /// * `prime` - The prime modulus p of a group with prime-order subgroup
+    /// * `base` - The generator g
+    /// * `subgroup_order` - The order q of g
+    /// * `static_secret` - Our long-term exponent, whose public value the peer pins
+    ///   (always full-length, see `static_key::load_or_generate`)
+    /// * `role` - Whether we are the client or the server
+    /// * `policy` - How long the ephemeral exponent is
+    pub fn new(
+        prime: &BigInt,
+        base: &BigInt,
+        subgroup_order: &BigInt,
+        static_secret: BigInt,
+        role: HmqvRole,
+        policy: ExponentPolicy,
+    ) -> Self {
+        let ephemeral_secret = generate_secret_key(prime, policy);
+        HmqvKeyExchange {
+            prime: prime.clone(),
+            base: base.clone(),
+            subgroup_order: subgroup_order.clone(),
+            role,
+            static_public: compute_public_key(&static_secret, base, prime, ExponentPolicy::Full),
+            static_secret,
+            ephemeral_public: compute_public_key(&ephemeral_secret, base, prime, policy),
+            ephemeral_secret,
+            policy,
+        }
+    }
+}
+
+impl HmqvKeyExchange {
+    /// Our static public key, the part of the share that identifies us
+    pub fn static_public_key(&self) -> Vec<u8> {
+        self.static_public.to_bytes_be().1
+    }
+
+    /// Our ephemeral public key, which is all that goes out in the clear under
+    /// identity protection
+    pub fn ephemeral_public_key(&self) -> Vec<u8> {
+        self.ephemeral_public.to_bytes_be().1
+    }
+
+    /// Plain Diffie-Hellman between the two ephemeral keys, which keys handshake
+    /// encryption but authenticates no one
+    pub fn ephemeral_shared_secret(&self, peer_ephemeral: &[u8]) -> Result<BigInt, &'static str> {
+        let peer_ephemeral = BigInt::from_bytes_be(Sign::Plus, peer_ephemeral);
+        validate_public_key(&peer_ephemeral, &self.prime, &self.subgroup_order)?;
+        check_not_reflected(&peer_ephemeral, &self.ephemeral_public, &self.base)?;
+        Ok(compute_shared_secret(&peer_ephemeral, &self.ephemeral_secret, &self.prime, self.policy))
+    }
+}
+
+impl KeyExchange for HmqvKeyExchange {
+    fn algorithm(&self) -> KexAlgorithm {
+        KexAlgorithm::Hmqv
+    }
+
+    fn public_key(&self) -> Vec<u8> {
+        join_share(&self.static_public_key(), &self.ephemeral_public_key())
+    }
+
+    fn identity_key<'a>(&self, public_key: &'a [u8]) -> &'a [u8] {
+        split_share(public_key).map_or(public_key, |(static_key, _)| static_key)
+    }
+
+    fn shared_secret(&self, peer_public_key: &[u8]) -> Result<BigInt, &'static str> {
+        let (peer_static, peer_ephemeral) = split_share(peer_public_key).ok_or("HMQV key share is malformed")?;
+        let peer_static = BigInt::from_bytes_be(Sign::Plus, peer_static);
+        let peer_ephemeral = BigInt::from_bytes_be(Sign::Plus, peer_ephemeral);
+        validate_public_key(&peer_static, &self.prime, &self.subgroup_order)?;
+        validate_public_key(&peer_ephemeral, &self.prime, &self.subgroup_order)?;
+        check_not_reflected(&peer_ephemeral, &self.ephemeral_public, &self.base)?;
+        if peer_static == self.static_public {
+            return Err("peer static key is identical to our own (reflection)");
+        }
+
+        // Name the four keys as in the paper, from the initiator's point of view
+        let (a, x, b, y) = match self.role {
+            HmqvRole::Initiator => (&self.static_public, &self.ephemeral_public, &peer_static, &peer_ephemeral),
+            HmqvRole::Responder => (&peer_static, &peer_ephemeral, &self.static_public, &self.ephemeral_public),
+        };
+        let d = hash_to_exponent(b"d", x, b);
+        let e = hash_to_exponent(b"e", y, a);
+
+        // Combine the peer's ephemeral and static keys, then raise to our combined secret
+        let (peer_combined, exponent) = match self.role {
+            HmqvRole::Initiator => (
+                peer_ephemeral * mod_pow_ct(&peer_static, &e, &self.prime),
+                &self.ephemeral_secret + &d * &self.static_secret,
+            ),
+            HmqvRole::Responder => (
+                peer_ephemeral * mod_pow_ct(&peer_static, &d, &self.prime),
+                &self.ephemeral_secret + &e * &self.static_secret,
+            ),
+        };
+        let exponent = exponent % &self.subgroup_order;
+        Ok(mod_pow_ct(&(peer_combined % &self.prime), &exponent, &self.prime))
+    }
+}
+
+/// 256-bit hash of an ephemeral key and the other party's static key; HMQV calls
+/// for |q|/2 bits, which SHA-256 meets for every group of 512 bits or more
+fn hash_to_exponent(label: &[u8], ephemeral: &BigInt, static_key: &BigInt) -> BigInt {
+    let mut hasher = Sha256::new();
+    hasher.update(b"dhke hmqv ");
+    hasher.update(label);
+    for value in [ephemeral, static_key] {
+        let bytes = value.to_bytes_be().1;
+        hasher.update((bytes.len() as u32).to_be_bytes());
+        hasher.update(bytes);
+    }
+    BigInt::from_bytes_be(Sign::Plus, &hasher.finalize())
+}
+
+/// Build a key share from a static and an ephemeral public key
+pub fn join_share(static_key: &[u8], ephemeral: &[u8]) -> Vec<u8> {
+    let mut share = Vec::new();
+    for value in [static_key, ephemeral] {
+        share.extend((value.len() as u32).to_be_bytes());
+        share.extend(value);
+    }
+    share
+}
+
+/// Split a key share into its static and ephemeral public keys
+pub(crate) fn split_share(share: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (static_key, rest) = take_field(share)?;
+    let (ephemeral, rest) = take_field(rest)?;
+    rest.is_empty().then_some((static_key, ephemeral))
+}
+
+fn take_field(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let len = u32::from_be_bytes(bytes.get(..4)?.try_into().ok()?) as usize;
+    let field = bytes.get(4..4 + len)?;
+    Some((field, &bytes[4 + len..]))
+}
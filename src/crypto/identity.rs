@@ -0,0 +1,276 @@
+use std::fs;
+use std::path::Path;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+#[cfg(feature = "rsa")]
+use rsa::{
+    pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey, EncodeRsaPublicKey},
+    pkcs8::{DecodePrivateKey, DecodePublicKey},
+    pss::{BlindedSigningKey, Signature as PssSignature, VerifyingKey as PssVerifyingKey},
+    signature::{RandomizedSigner, SignatureEncoding, Verifier},
+    RsaPrivateKey, RsaPublicKey,
+};
+#[cfg(feature = "rsa")]
+use sha2::Sha256;
+
+use crate::crypto::fingerprint::Fingerprint;
+use crate::crypto::rng::{with_rng, RngPurpose};
+use crate::crypto::transcript::{from_hex, to_hex};
+
+/// Prefix of every signed message, so a server signature can never be replayed as a
+/// signature over anything else the key might sign
+const SIGNATURE_CONTEXT: &[u8] = b"dhke server handshake signature\0";
+
+/// Signature algorithms a server identity can use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureScheme {
+    /// Ed25519 (RFC 8032)
+    Ed25519,
+    /// RSASSA-PSS with SHA-256 and a 32-byte salt
+    #[cfg(feature = "rsa")]
+    RsaPssSha256,
+}
+
+impl SignatureScheme {
+    /// Wire identifier of the scheme
+    pub fn id(&self) -> u8 {
+        match self {
+            SignatureScheme::Ed25519 => 0,
+            #[cfg(feature = "rsa")]
+            SignatureScheme::RsaPssSha256 => 1,
+        }
+    }
+
+    /// Short lowercase name of the scheme (e.g., "ed25519")
+    pub fn name(&self) -> &'static str {
+        match self {
+            SignatureScheme::Ed25519 => "ed25519",
+            #[cfg(feature = "rsa")]
+            SignatureScheme::RsaPssSha256 => "rsa_pss_sha256",
+        }
+    }
+}
+
+/// Long-term key pair that authenticates a server to its clients
+pub struct ServerIdentity {
+    key: IdentityKey,
+}
+
+enum IdentityKey {
+    Ed25519(SigningKey),
+    #[cfg(feature = "rsa")]
+    Rsa(RsaPrivateKey),
+}
+
+impl ServerIdentity {
+    /// Generate a fresh Ed25519 identity
+    pub fn generate() -> Self {
+        ServerIdentity {
+            key: IdentityKey::Ed25519(with_rng(RngPurpose::SecretKey, SigningKey::generate)),
+        }
+    }
+
+    /// Load an Ed25519 identity written by `store`
+    ///
+    /// # Arguments
+    /// * `path` - File holding the hex-encoded 32-byte secret key
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let secret = contents
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .and_then(from_hex)
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .ok_or_else(|| invalid_key_file(path, "a 32-byte hex identity key"))?;
+        Ok(ServerIdentity {
+            key: IdentityKey::Ed25519(SigningKey::from_bytes(&secret)),
+        })
+    }
+
+    /// Load an RSA identity from a PEM private key (PKCS#8 or PKCS#1)
+    #[cfg(feature = "rsa")]
+    pub fn load_rsa_pem(path: &Path) -> std::io::Result<Self> {
+        let pem = fs::read_to_string(path)?;
+        let key = RsaPrivateKey::from_pkcs8_pem(&pem)
+            .ok()
+            .or_else(|| RsaPrivateKey::from_pkcs1_pem(&pem).ok())
+            .ok_or_else(|| invalid_key_file(path, "an RSA private key in PEM"))?;
+        Ok(ServerIdentity {
+            key: IdentityKey::Rsa(key),
+        })
+    }
+
+    /// Write an Ed25519 secret key to a file atomically, readable only by its owner where supported
+    pub fn store(&self, path: &Path) -> std::io::Result<()> {
+        let secret = match &self.key {
+            IdentityKey::Ed25519(signing_key) => signing_key.as_bytes(),
+            #[cfg(feature = "rsa")]
+            IdentityKey::Rsa(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "RSA identities are managed as PEM files outside dhke",
+                ));
+            }
+        };
+        let contents = format!(
+            "# Ed25519 server identity generated by dhke; keep this file secret\n{}\n",
+            to_hex(secret)
+        );
+
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        fs::write(&temp_path, contents)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&temp_path, fs::Permissions::from_mode(0o600))?;
+        }
+        fs::rename(&temp_path, path)
+    }
+
+    /// Load the Ed25519 identity from `path`, generating and storing a new one if the file does not exist
+    pub fn load_or_generate(path: &Path) -> std::io::Result<Self> {
+        if path.exists() {
+            return Self::load(path);
+        }
+        let identity = Self::generate();
+        identity.store(path)?;
+        Ok(identity)
+    }
+
+    /// Public half of the identity, which clients pin to recognize this server
+    pub fn server_key(&self) -> ServerKey {
+        match &self.key {
+            IdentityKey::Ed25519(signing_key) => ServerKey::Ed25519(signing_key.verifying_key().to_bytes()),
+            #[cfg(feature = "rsa")]
+            IdentityKey::Rsa(key) => ServerKey::Rsa(Box::new(key.to_public_key())),
+        }
+    }
+
+    /// Sign a handshake transcript hash
+    pub fn sign(&self, transcript_hash: &[u8; 32]) -> Vec<u8> {
+        let message = signed_message(transcript_hash);
+        match &self.key {
+            IdentityKey::Ed25519(signing_key) => signing_key.sign(&message).to_vec(),
+            #[cfg(feature = "rsa")]
+            IdentityKey::Rsa(key) => {
+                // The PSS salt is public, so it comes from the nonce generator
+                let signer = BlindedSigningKey::<Sha256>::new(key.clone());
+                with_rng(RngPurpose::Nonce, |rng| signer.sign_with_rng(rng, &message)).to_vec()
+            }
+        }
+    }
+}
+
+/// A server public key a client expects the handshake to be signed with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerKey {
+    /// Raw 32-byte Ed25519 public key
+    Ed25519([u8; 32]),
+    /// RSA public key
+    #[cfg(feature = "rsa")]
+    Rsa(Box<RsaPublicKey>),
+}
+
+impl ServerKey {
+    /// Load an RSA public key from PEM (SubjectPublicKeyInfo or PKCS#1)
+    #[cfg(feature = "rsa")]
+    pub fn load_rsa_pem(path: &Path) -> std::io::Result<Self> {
+        let pem = fs::read_to_string(path)?;
+        let key = RsaPublicKey::from_public_key_pem(&pem)
+            .ok()
+            .or_else(|| RsaPublicKey::from_pkcs1_pem(&pem).ok())
+            .ok_or_else(|| invalid_key_file(path, "an RSA public key in PEM"))?;
+        Ok(ServerKey::Rsa(Box::new(key)))
+    }
+
+    /// Signature scheme used with this key
+    pub fn scheme(&self) -> SignatureScheme {
+        match self {
+            ServerKey::Ed25519(_) => SignatureScheme::Ed25519,
+            #[cfg(feature = "rsa")]
+            ServerKey::Rsa(_) => SignatureScheme::RsaPssSha256,
+        }
+    }
+
+    /// Wire encoding of the key: raw bytes for Ed25519, PKCS#1 DER for RSA
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            ServerKey::Ed25519(key) => key.to_vec(),
+            #[cfg(feature = "rsa")]
+            ServerKey::Rsa(key) => key
+                .to_pkcs1_der()
+                .expect("RSA public keys always encode")
+                .as_bytes()
+                .to_vec(),
+        }
+    }
+
+    /// Fingerprint of the wire encoding, for users comparing keys out of band
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint::of(&self.to_bytes())
+    }
+
+    /// Short description for logs: the hex key for Ed25519, the modulus size for RSA
+    pub fn describe(&self) -> String {
+        match self {
+            ServerKey::Ed25519(key) => to_hex(key),
+            #[cfg(feature = "rsa")]
+            ServerKey::Rsa(key) => {
+                use rsa::traits::PublicKeyParts;
+                format!("RSA-{}", key.n().bits())
+            }
+        }
+    }
+
+    /// Check a server's signature over a handshake transcript hash
+    ///
+    /// # Arguments
+    /// * `scheme` - Scheme ID from the ServerSignature message
+    /// * `public_key` - Key the server claims to have signed with
+    /// * `transcript_hash` - Transcript hash up to and including the server's public key
+    /// * `signature` - Signature from the ServerSignature message
+    ///
+    /// # Returns
+    /// Ok if the server signed with this key and the signature is valid, or a
+    /// description of why it was rejected
+    pub fn verify(
+        &self,
+        scheme: u8,
+        public_key: &[u8],
+        transcript_hash: &[u8; 32],
+        signature: &[u8],
+    ) -> Result<(), &'static str> {
+        if scheme != self.scheme().id() || public_key != self.to_bytes().as_slice() {
+            return Err("server signed with an unexpected identity key");
+        }
+        let message = signed_message(transcript_hash);
+        match self {
+            ServerKey::Ed25519(key) => {
+                let key = VerifyingKey::from_bytes(key).map_err(|_| "server identity key is not a valid Ed25519 key")?;
+                let signature = Signature::from_slice(signature).map_err(|_| "server signature is malformed")?;
+                key.verify_strict(&message, &signature)
+                    .map_err(|_| "server signature does not verify")
+            }
+            #[cfg(feature = "rsa")]
+            ServerKey::Rsa(key) => {
+                let signature = PssSignature::try_from(signature).map_err(|_| "server signature is malformed")?;
+                PssVerifyingKey::<Sha256>::new(key.as_ref().clone())
+                    .verify(&message, &signature)
+                    .map_err(|_| "server signature does not verify")
+            }
+        }
+    }
+}
+
+fn signed_message(transcript_hash: &[u8; 32]) -> Vec<u8> {
+    [SIGNATURE_CONTEXT, transcript_hash].concat()
+}
+
+fn invalid_key_file(path: &Path, expected: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("{} does not hold {}", path.display(), expected),
+    )
+}
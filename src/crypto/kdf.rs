@@ -0,0 +1,211 @@
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use num_bigint::BigInt;
+use sha2::{Digest, Sha256};
+
+use crate::crypto::ct::ct_eq;
+use crate::crypto::transcript::Transcript;
+
+/// Label of the client's Finished MAC
+pub const CLIENT_FINISHED_LABEL: &[u8] = b"dhke client finished";
+
+/// Label of the server's Finished MAC
+pub const SERVER_FINISHED_LABEL: &[u8] = b"dhke server finished";
+
+/// Key derivation functions the key schedule can use
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Kdf {
+    /// HKDF-SHA256 (RFC 5869), extracting with the transcript hash as salt
+    #[default]
+    Hkdf,
+    /// The NIST SP 800-56A one-step (concatenation) KDF with SHA-256, for compliance
+    /// regimes that require it: each output is SHA-256(counter || Z || label || transcript hash)
+    OneStep,
+}
+
+impl Kdf {
+    /// Every KDF known to this implementation
+    pub const ALL: &'static [Kdf] = &[Kdf::Hkdf, Kdf::OneStep];
+
+    /// Short lowercase name of the KDF (e.g., "sp800-56a")
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kdf::Hkdf => "hkdf",
+            Kdf::OneStep => "sp800-56a",
+        }
+    }
+
+    /// Look up a KDF by its short name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|kdf| kdf.name() == name)
+    }
+
+    /// Derive the output for one label from the shared secret `z` and a 32-byte context
+    fn derive(&self, z: &[u8], context: &[u8; 32], label: &[u8], output: &mut [u8]) {
+        match self {
+            Kdf::Hkdf => expand(&Hkdf::<Sha256>::new(Some(context), z), label, output),
+            Kdf::OneStep => one_step(z, &[label, context].concat(), output),
+        }
+    }
+}
+
+/// Symmetric keys derived from a completed key exchange; equality is constant-time
+#[derive(Clone)]
+pub struct SessionKeys {
+    /// 256-bit key protecting records sent by the client
+    pub client_write_key: [u8; 32],
+    /// 256-bit key protecting records sent by the server
+    pub server_write_key: [u8; 32],
+    /// 96-bit IV for records sent by the client
+    pub client_iv: [u8; 12],
+    /// 96-bit IV for records sent by the server
+    pub server_iv: [u8; 12],
+    /// 256-bit key for the Finished messages that confirm both sides derived these keys
+    pub finished_key: [u8; 32],
+    /// 256-bit secret from which keys for other protocols are exported (see `export`)
+    pub exporter_secret: [u8; 32],
+    /// KDF the keys were derived with, which rekeys and exports keep using
+    pub kdf: Kdf,
+}
+
+impl SessionKeys {
+    /// Derive session keys
+    ///
+    /// # Arguments
+    /// * `kdf` - The KDF negotiated in the handshake
+    /// * `shared_secret` - The shared secret from the key exchange (input keying material)
+    /// * `transcript` - The completed handshake transcript; its hash is the KDF's
+    ///   context, so the keys are bound to every message both sides saw
+    ///
+    /// # Returns
+    /// Independent keys and IVs, each derived under its own label
+    pub fn derive(kdf: Kdf, shared_secret: &BigInt, transcript: &Transcript) -> Self {
+        let (_, secret_bytes) = shared_secret.to_bytes_be();
+        Self::derive_all(kdf, &secret_bytes, &transcript.hash())
+    }
+
+    /// Derive the keys that replace these ones after a rekey exchange
+    ///
+    /// The fresh shared secret is combined with a secret expanded from the current
+    /// keys, so the new keys stay safe as long as either the session so far or the
+    /// fresh exchange is.
+    ///
+    /// # Arguments
+    /// * `shared_secret` - The shared secret of the rekey's ephemeral exchange
+    /// * `transcript` - Both Rekey messages, the client's first
+    pub fn rekey(&self, shared_secret: &BigInt, transcript: &Transcript) -> Self {
+        let mut chain = [0; 32];
+        self.export(b"dhke rekey secret", &mut chain);
+        let (_, secret_bytes) = shared_secret.to_bytes_be();
+        let ikm = [&chain[..], &secret_bytes].concat();
+        Self::derive_all(self.kdf, &ikm, &transcript.hash())
+    }
+
+    /// Derive every key and IV under its own label
+    fn derive_all(kdf: Kdf, z: &[u8], context: &[u8; 32]) -> Self {
+        let mut keys = SessionKeys {
+            client_write_key: [0; 32],
+            server_write_key: [0; 32],
+            client_iv: [0; 12],
+            server_iv: [0; 12],
+            finished_key: [0; 32],
+            exporter_secret: [0; 32],
+            kdf,
+        };
+        kdf.derive(z, context, b"dhke client write key", &mut keys.client_write_key);
+        kdf.derive(z, context, b"dhke server write key", &mut keys.server_write_key);
+        kdf.derive(z, context, b"dhke client iv", &mut keys.client_iv);
+        kdf.derive(z, context, b"dhke server iv", &mut keys.server_iv);
+        kdf.derive(z, context, b"dhke finished key", &mut keys.finished_key);
+        kdf.derive(z, context, b"dhke exporter secret", &mut keys.exporter_secret);
+        keys
+    }
+
+    /// Export keying material for use outside the record layer (like RFC 5705 / TLS exporters)
+    ///
+    /// Different labels give independent outputs, none of which reveal the record
+    /// layer keys, so an application can key its own cipher without weakening ours.
+    ///
+    /// # Arguments
+    /// * `label` - Names the purpose of the output (e.g., b"myapp client write key")
+    /// * `output` - Filled with the exported bytes (at most 8160)
+    pub fn export(&self, label: &[u8], output: &mut [u8]) {
+        match self.kdf {
+            Kdf::Hkdf => {
+                let hkdf = Hkdf::<Sha256>::from_prk(&self.exporter_secret).expect("exporter secret is a full-size PRK");
+                expand(&hkdf, label, output);
+            }
+            Kdf::OneStep => one_step(&self.exporter_secret, label, output),
+        }
+    }
+
+    /// Compute the verify data of a Finished message
+    ///
+    /// # Arguments
+    /// * `label` - `CLIENT_FINISHED_LABEL` or `SERVER_FINISHED_LABEL`
+    /// * `transcript_hash` - Hash of the handshake transcript the keys were derived from
+    ///
+    /// # Returns
+    /// HMAC-SHA256(finished_key, label || transcript_hash)
+    pub fn finished(&self, label: &[u8], transcript_hash: &[u8; 32]) -> [u8; 32] {
+        self.finished_mac(label, transcript_hash).finalize().into_bytes().into()
+    }
+
+    /// Check a peer's Finished verify data in constant time
+    pub fn verify_finished(&self, label: &[u8], transcript_hash: &[u8; 32], verify_data: &[u8]) -> bool {
+        ct_eq(&self.finished(label, transcript_hash), verify_data)
+    }
+
+    fn finished_mac(&self, label: &[u8], transcript_hash: &[u8; 32]) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.finished_key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(label);
+        mac.update(transcript_hash);
+        mac
+    }
+}
+
+impl PartialEq for SessionKeys {
+    fn eq(&self, other: &Self) -> bool {
+        let fields = |keys: &SessionKeys| {
+            [
+                &keys.client_write_key[..],
+                &keys.server_write_key,
+                &keys.client_iv,
+                &keys.server_iv,
+                &keys.finished_key,
+                &keys.exporter_secret,
+            ]
+            .concat()
+        };
+        ct_eq(&fields(self), &fields(other)) && self.kdf == other.kdf
+    }
+}
+
+impl Eq for SessionKeys {}
+
+impl std::fmt::Debug for SessionKeys {
+    // Never print key material
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionKeys").field("kdf", &self.kdf).finish_non_exhaustive()
+    }
+}
+
+/// SP 800-56A one-step KDF with SHA-256: output blocks SHA-256(counter || Z || fixed_info),
+/// with a 32-bit big-endian counter starting at 1
+fn one_step(z: &[u8], fixed_info: &[u8], output: &mut [u8]) {
+    for (counter, block) in output.chunks_mut(32).enumerate() {
+        let digest = Sha256::new()
+            .chain_update((counter as u32 + 1).to_be_bytes())
+            .chain_update(z)
+            .chain_update(fixed_info)
+            .finalize();
+        block.copy_from_slice(&digest[..block.len()]);
+    }
+}
+
+/// HKDF-Expand into a fixed-size output under the given label
+fn expand(hkdf: &Hkdf<Sha256>, label: &[u8], output: &mut [u8]) {
+    hkdf.expand(label, output)
+        .expect("output length is far below the HKDF-SHA256 limit");
+}
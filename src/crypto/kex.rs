@@ -0,0 +1,326 @@
+use num_bigint::{BigInt, Sign};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::crypto::crypto::{
+    check_not_reflected, generate_secret_key, mod_pow_blinded, validate_public_key, Blinding, ExponentPolicy,
+};
+use crate::crypto::hmqv;
+use crate::crypto::obfuscation::{ElligatorX25519KeyExchange, FFDH_PADDING_BYTES};
+use crate::crypto::rng::{with_rng, RngPurpose};
+use crate::crypto::srp;
+
+/// Key-exchange algorithms that can be negotiated in the handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KexAlgorithm {
+    /// Classic finite-field Diffie-Hellman over (p, g)
+    FiniteField,
+    /// Elliptic-curve Diffie-Hellman over Curve25519 (RFC 7748)
+    X25519,
+    /// Elliptic-curve Diffie-Hellman over NIST P-256 (uncompressed SEC1 points)
+    #[cfg(feature = "p256")]
+    P256,
+    /// HMQV over a named finite-field group: static and ephemeral keys on both sides,
+    /// implicitly authenticating client and server (see `hmqv`)
+    Hmqv,
+    /// SRP-6a over a named finite-field group: client and server share only a password,
+    /// of which the server stores a verifier (see `srp`)
+    Srp,
+    /// X25519 with public keys sent as Elligator 2 representatives, indistinguishable
+    /// from random bytes (see `obfuscation`)
+    X25519Elligator,
+    /// Finite-field Diffie-Hellman over a named group, with public values padded to a
+    /// fixed width that looks like random bytes (see `obfuscation`)
+    FiniteFieldPadded,
+}
+
+impl KexAlgorithm {
+    /// Every algorithm known to this implementation
+    pub const ALL: &'static [KexAlgorithm] = &[
+        KexAlgorithm::FiniteField,
+        KexAlgorithm::X25519,
+        #[cfg(feature = "p256")]
+        KexAlgorithm::P256,
+        KexAlgorithm::Hmqv,
+        KexAlgorithm::Srp,
+        KexAlgorithm::X25519Elligator,
+        KexAlgorithm::FiniteFieldPadded,
+    ];
+
+    /// Wire identifier of the algorithm
+    pub fn id(&self) -> u8 {
+        match self {
+            KexAlgorithm::FiniteField => 0,
+            KexAlgorithm::X25519 => 1,
+            #[cfg(feature = "p256")]
+            KexAlgorithm::P256 => 2,
+            KexAlgorithm::Hmqv => 3,
+            KexAlgorithm::Srp => 4,
+            KexAlgorithm::X25519Elligator => 5,
+            KexAlgorithm::FiniteFieldPadded => 6,
+        }
+    }
+
+    /// Look up an algorithm by its wire identifier
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.iter().copied().find(|algorithm| algorithm.id() == id)
+    }
+
+    /// Short lowercase name of the algorithm (e.g., "x25519")
+    pub fn name(&self) -> &'static str {
+        match self {
+            KexAlgorithm::FiniteField => "ffdh",
+            KexAlgorithm::X25519 => "x25519",
+            #[cfg(feature = "p256")]
+            KexAlgorithm::P256 => "p256",
+            KexAlgorithm::Hmqv => "hmqv",
+            KexAlgorithm::Srp => "srp",
+            KexAlgorithm::X25519Elligator => "x25519-elligator",
+            KexAlgorithm::FiniteFieldPadded => "ffdh-padded",
+        }
+    }
+
+    /// Look up an algorithm by its short name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|algorithm| algorithm.name() == name)
+    }
+
+    /// Length in bytes of the largest finite-field element in a key share of this
+    /// algorithm (0 for curve points, whose size is fixed); a share that does not
+    /// parse counts as one element
+    pub fn largest_integer(&self, share: &[u8]) -> usize {
+        match self {
+            KexAlgorithm::FiniteField => share.len(),
+            KexAlgorithm::Hmqv => hmqv::split_share(share).map_or(share.len(), |(static_key, ephemeral)| {
+                static_key.len().max(ephemeral.len())
+            }),
+            KexAlgorithm::Srp => srp::split(share).map_or(share.len(), |(_, value)| value.len()),
+            KexAlgorithm::FiniteFieldPadded => share.len().saturating_sub(FFDH_PADDING_BYTES),
+            _ => 0,
+        }
+    }
+}
+
+/// One side of an ephemeral key exchange
+pub trait KeyExchange {
+    /// The algorithm implemented
+    fn algorithm(&self) -> KexAlgorithm;
+
+    /// Our public value as sent on the wire
+    fn public_key(&self) -> Vec<u8>;
+
+    /// The part of a public value (ours or the peer's) that stays the same across
+    /// sessions, which is what users fingerprint and pin; the whole value for
+    /// algorithms without long-term keys
+    fn identity_key<'a>(&self, public_key: &'a [u8]) -> &'a [u8] {
+        public_key
+    }
+
+    /// Combine our secret with the peer's public value
+    ///
+    /// # Returns
+    /// The shared secret, or a description of why the peer's value was rejected
+    fn shared_secret(&self, peer_public_key: &[u8]) -> Result<BigInt, &'static str>;
+}
+
+/// Finite-field DH: public key g^x mod p, shared secret Y^x mod p
+pub struct FiniteFieldKeyExchange {
+    prime: BigInt,
+    base: BigInt,
+    subgroup_order: BigInt,
+    secret: BigInt,
+    public: BigInt,
+    blinding: Blinding,
+    policy: ExponentPolicy,
+}
+
+impl FiniteFieldKeyExchange {
+    /// Generate a fresh full-length secret exponent for the given parameters
+    ///
+    /// # Arguments
+    /// * `prime` - The prime modulus p
+    /// * `base` - The generator g
+    /// * `subgroup_order` - The order q of g, used to validate the peer's public key
+    pub fn new(prime: &BigInt, base: &BigInt, subgroup_order: &BigInt) -> Self {
+        Self::with_secret(prime, base, subgroup_order, generate_secret_key(prime, ExponentPolicy::Full))
+    }
+
+    /// Use an already generated full-length secret exponent
+    pub fn with_secret(prime: &BigInt, base: &BigInt, subgroup_order: &BigInt, secret: BigInt) -> Self {
+        Self::blinded(prime, base, subgroup_order, secret, Blinding::None, ExponentPolicy::Full)
+    }
+
+    /// Use an already generated secret exponent, drawn under `policy`, blinding both
+    /// exponentiations with it
+    pub fn blinded(
+        prime: &BigInt,
+        base: &BigInt,
+        subgroup_order: &BigInt,
+        secret: BigInt,
+        blinding: Blinding,
+        policy: ExponentPolicy,
+    ) -> Self {
+        let public = mod_pow_blinded(base, &secret, prime, subgroup_order, blinding, policy);
+        FiniteFieldKeyExchange {
+            prime: prime.clone(),
+            base: base.clone(),
+            subgroup_order: subgroup_order.clone(),
+            secret,
+            public,
+            blinding,
+            policy,
+        }
+    }
+}
+
+impl KeyExchange for FiniteFieldKeyExchange {
+    fn algorithm(&self) -> KexAlgorithm {
+        KexAlgorithm::FiniteField
+    }
+
+    fn public_key(&self) -> Vec<u8> {
+        self.public.to_bytes_be().1
+    }
+
+    fn shared_secret(&self, peer_public_key: &[u8]) -> Result<BigInt, &'static str> {
+        let peer = BigInt::from_bytes_be(Sign::Plus, peer_public_key);
+        validate_public_key(&peer, &self.prime, &self.subgroup_order)?;
+        check_not_reflected(&peer, &self.public, &self.base)?;
+        Ok(mod_pow_blinded(&peer, &self.secret, &self.prime, &self.subgroup_order, self.blinding, self.policy))
+    }
+}
+
+/// Width of fixed-width finite-field public keys for the prime p: ⌈|p|/8⌉ bytes
+pub fn fixed_width(p: &BigInt) -> usize {
+    p.bits().div_ceil(8) as usize
+}
+
+/// Left-pad a finite-field public key with zeros to `fixed_width(p)` bytes, so its
+/// length no longer depends on its magnitude
+pub fn to_fixed_width(public_key: &[u8], p: &BigInt) -> Vec<u8> {
+    let mut padded = vec![0; fixed_width(p).saturating_sub(public_key.len())];
+    padded.extend(public_key);
+    padded
+}
+
+/// Check a fixed-width public key is canonical: exactly `fixed_width(p)` bytes,
+/// encoding a value below p
+pub fn check_fixed_width(key: &[u8], p: &BigInt) -> Result<(), &'static str> {
+    if key.len() != fixed_width(p) {
+        return Err("fixed-width public key has the wrong length for the group");
+    }
+    if &BigInt::from_bytes_be(Sign::Plus, key) >= p {
+        return Err("fixed-width public key is not reduced mod p");
+    }
+    Ok(())
+}
+
+/// X25519: 32-byte Curve25519 points, shared secret is the 32-byte x-coordinate
+pub struct X25519KeyExchange {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl X25519KeyExchange {
+    /// Generate a fresh ephemeral key pair
+    pub fn new() -> Self {
+        let secret = with_rng(RngPurpose::SecretKey, |rng| StaticSecret::random_from_rng(rng));
+        let public = PublicKey::from(&secret);
+        X25519KeyExchange { secret, public }
+    }
+}
+
+impl Default for X25519KeyExchange {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyExchange for X25519KeyExchange {
+    fn algorithm(&self) -> KexAlgorithm {
+        KexAlgorithm::X25519
+    }
+
+    fn public_key(&self) -> Vec<u8> {
+        self.public.as_bytes().to_vec()
+    }
+
+    fn shared_secret(&self, peer_public_key: &[u8]) -> Result<BigInt, &'static str> {
+        let point: [u8; 32] = peer_public_key
+            .try_into()
+            .map_err(|_| "X25519 public key must be 32 bytes")?;
+        if &point == self.public.as_bytes() {
+            return Err("peer public key is identical to our own (reflection)");
+        }
+
+        let shared = self.secret.diffie_hellman(&PublicKey::from(point));
+        // Low-order points force an all-zero output regardless of our secret
+        if !shared.was_contributory() {
+            return Err("X25519 public key is a low-order point");
+        }
+        Ok(BigInt::from_bytes_be(Sign::Plus, shared.as_bytes()))
+    }
+}
+
+/// P-256 ECDH: 65-byte uncompressed points, shared secret is the 32-byte x-coordinate
+#[cfg(feature = "p256")]
+pub struct P256KeyExchange {
+    secret: p256::ecdh::EphemeralSecret,
+    public: p256::EncodedPoint,
+}
+
+#[cfg(feature = "p256")]
+impl P256KeyExchange {
+    /// Generate a fresh ephemeral key pair
+    pub fn new() -> Self {
+        let secret = with_rng(RngPurpose::SecretKey, p256::ecdh::EphemeralSecret::random);
+        let public = p256::EncodedPoint::from(secret.public_key());
+        P256KeyExchange { secret, public }
+    }
+}
+
+#[cfg(feature = "p256")]
+impl Default for P256KeyExchange {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "p256")]
+impl KeyExchange for P256KeyExchange {
+    fn algorithm(&self) -> KexAlgorithm {
+        KexAlgorithm::P256
+    }
+
+    fn public_key(&self) -> Vec<u8> {
+        self.public.as_bytes().to_vec()
+    }
+
+    fn shared_secret(&self, peer_public_key: &[u8]) -> Result<BigInt, &'static str> {
+        if peer_public_key == self.public.as_bytes() {
+            return Err("peer public key is identical to our own (reflection)");
+        }
+
+        // Decoding rejects the identity and points that are not on the curve
+        let peer = p256::PublicKey::from_sec1_bytes(peer_public_key)
+            .map_err(|_| "P-256 public key is not a valid curve point")?;
+        let shared = self.secret.diffie_hellman(&peer);
+        Ok(BigInt::from_bytes_be(Sign::Plus, shared.raw_secret_bytes()))
+    }
+}
+
+/// Start an exchange for an elliptic-curve algorithm, which needs no (p, g) parameters
+///
+/// # Returns
+/// A fresh key exchange, or None for the algorithms that work over finite-field groups
+pub fn curve_key_exchange(algorithm: KexAlgorithm) -> Option<Box<dyn KeyExchange>> {
+    match algorithm {
+        KexAlgorithm::FiniteField
+        | KexAlgorithm::Hmqv
+        | KexAlgorithm::Srp
+        | KexAlgorithm::FiniteFieldPadded => None,
+        KexAlgorithm::X25519 => Some(Box::new(X25519KeyExchange::new())),
+        KexAlgorithm::X25519Elligator => Some(Box::new(ElligatorX25519KeyExchange::new())),
+        #[cfg(feature = "p256")]
+        KexAlgorithm::P256 => Some(Box::new(P256KeyExchange::new())),
+    }
+}
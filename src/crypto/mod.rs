@@ -1 +1,29 @@
+pub mod audit;
+pub(crate) mod bignum;
+pub mod cookie;
+#[allow(clippy::module_inception)]
 pub mod crypto;
+pub mod ct;
+pub mod export;
+pub mod fingerprint;
+pub mod groups;
+pub mod handshake_protection;
+pub mod hmqv;
+pub mod identity;
+pub mod kdf;
+pub mod kex;
+pub(crate) mod montgomery;
+pub mod obfuscation;
+pub mod param_cache;
+pub mod params_policy;
+pub mod pem;
+pub mod pkcs3;
+pub mod ratchet;
+pub mod prime_certificate;
+pub mod record;
+pub mod rng;
+pub mod srp;
+pub mod static_key;
+pub mod ticket;
+pub mod transcript;
+pub mod x3dh;
@@ -0,0 +1,145 @@
+use num_bigint::{BigInt, BigUint, Sign};
+use num_traits::One;
+use subtle::{Choice, ConditionallySelectable};
+
+/// Montgomery arithmetic modulo an odd modulus over a fixed number of 64-bit limbs
+///
+/// Every multiplication runs the same loops over all limbs and ends in a masked
+/// subtraction, so its timing depends on the width of the modulus but not on the
+/// values multiplied. Converting values in and out goes through num-bigint, which
+/// only sees the base, the exponent's limbs and the result, never the ladder state.
+pub(crate) struct MontgomeryModulus {
+    /// The modulus itself, for reducing the base
+    modulus: BigUint,
+    /// The modulus, least significant limb first
+    limbs: Vec<u64>,
+    /// -modulus^-1 mod 2^64
+    inverse: u64,
+    /// R^2 mod modulus, with R = 2^(64 * limbs)
+    r_squared: Vec<u64>,
+    /// R mod modulus, the Montgomery form of 1
+    one: Vec<u64>,
+}
+
+impl MontgomeryModulus {
+    /// Set up arithmetic modulo `modulus`
+    ///
+    /// # Returns
+    /// None unless the modulus is odd and greater than 1
+    pub(crate) fn new(modulus: &BigInt) -> Option<Self> {
+        if modulus.sign() != Sign::Plus || !modulus.bit(0) || modulus.is_one() {
+            return None;
+        }
+        let modulus = modulus.magnitude().clone();
+        let limbs = modulus.to_u64_digits();
+        let r = BigUint::one() << (64 * limbs.len());
+
+        // Newton's iteration doubles the correct low bits of the inverse each step
+        let mut inverse = 1u64;
+        for _ in 0..6 {
+            inverse = inverse.wrapping_mul(2u64.wrapping_sub(limbs[0].wrapping_mul(inverse)));
+        }
+
+        Some(MontgomeryModulus {
+            r_squared: to_limbs(&((&r * &r) % &modulus), limbs.len()),
+            one: to_limbs(&(r % &modulus), limbs.len()),
+            inverse: inverse.wrapping_neg(),
+            limbs,
+            modulus,
+        })
+    }
+
+    /// (base^exp) mod modulus with a Montgomery ladder over `bits` bit positions
+    ///
+    /// Each position costs one multiplication and one squaring, and the registers are
+    /// swapped with masks, so the operation sequence and memory accesses depend only on
+    /// `bits` and the width of the modulus.
+    pub(crate) fn ladder(&self, base: &BigInt, exp: &BigUint, bits: u64) -> BigInt {
+        let modulus = BigInt::from(self.modulus.clone());
+        let mut reduced = base % &modulus;
+        if reduced.sign() == Sign::Minus {
+            reduced += modulus;
+        }
+        let exponent = to_limbs(exp, bits.div_ceil(64) as usize);
+
+        let mut r0 = self.one.clone();
+        let mut r1 = self.multiply(&to_limbs(reduced.magnitude(), self.limbs.len()), &self.r_squared);
+        for i in (0..bits).rev() {
+            let bit = Choice::from(((exponent[(i / 64) as usize] >> (i % 64)) & 1) as u8);
+            // Invariant: r1 = r0 * base. With the registers swapped when the bit is set,
+            // both cases become r1 = r0 * r1, r0 = r0^2
+            swap(&mut r0, &mut r1, bit);
+            r1 = self.multiply(&r0, &r1);
+            r0 = self.multiply(&r0, &r0);
+            swap(&mut r0, &mut r1, bit);
+        }
+
+        let mut unit = vec![0; self.limbs.len()];
+        unit[0] = 1;
+        BigInt::from(from_limbs(&self.multiply(&r0, &unit)))
+    }
+
+    /// a * b * R^-1 mod modulus (CIOS), for a and b below the modulus
+    fn multiply(&self, a: &[u64], b: &[u64]) -> Vec<u64> {
+        let n = self.limbs.len();
+        let mut t = vec![0u64; n + 2];
+        for &b_i in b {
+            let mut carry = 0;
+            for j in 0..n {
+                (t[j], carry) = multiply_add(t[j], a[j], b_i, carry);
+            }
+            let (sum, overflow) = t[n].overflowing_add(carry);
+            t[n] = sum;
+            t[n + 1] = overflow as u64;
+
+            let m = t[0].wrapping_mul(self.inverse);
+            let (_, mut carry) = multiply_add(t[0], m, self.limbs[0], 0);
+            for j in 1..n {
+                (t[j - 1], carry) = multiply_add(t[j], m, self.limbs[j], carry);
+            }
+            let (sum, overflow) = t[n].overflowing_add(carry);
+            t[n - 1] = sum;
+            t[n] = t[n + 1] + overflow as u64;
+        }
+
+        // The result is below 2 * modulus; subtract it unless that borrows past the
+        // carry limb, choosing by mask
+        let mut reduced = vec![0u64; n];
+        let mut borrow = 0u64;
+        for j in 0..n {
+            let (difference, under) = t[j].overflowing_sub(self.limbs[j]);
+            let (difference, under_borrow) = difference.overflowing_sub(borrow);
+            reduced[j] = difference;
+            borrow = (under | under_borrow) as u64;
+        }
+        let keep_reduced = Choice::from((t[n] | (borrow ^ 1)) as u8 & 1);
+        for j in 0..n {
+            reduced[j] = u64::conditional_select(&t[j], &reduced[j], keep_reduced);
+        }
+        reduced
+    }
+}
+
+/// a + b * c + carry as (low limb, high limb); cannot overflow 128 bits
+fn multiply_add(a: u64, b: u64, c: u64, carry: u64) -> (u64, u64) {
+    let wide = a as u128 + (b as u128) * (c as u128) + carry as u128;
+    (wide as u64, (wide >> 64) as u64)
+}
+
+/// Swap the registers limb by limb if `choice` is set
+fn swap(a: &mut [u64], b: &mut [u64], choice: Choice) {
+    for (x, y) in a.iter_mut().zip(b.iter_mut()) {
+        u64::conditional_swap(x, y, choice);
+    }
+}
+
+/// Little-endian limbs of `value`, zero-padded (or truncated) to `width`
+fn to_limbs(value: &BigUint, width: usize) -> Vec<u64> {
+    let mut limbs = value.to_u64_digits();
+    limbs.resize(width, 0);
+    limbs
+}
+
+fn from_limbs(limbs: &[u64]) -> BigUint {
+    BigUint::new(limbs.iter().flat_map(|limb| [*limb as u32, (limb >> 32) as u32]).collect())
+}
@@ -0,0 +1,253 @@
+use curve25519_dalek::constants::EIGHT_TORSION;
+use curve25519_dalek::edwards::EdwardsPoint;
+use num_bigint::{BigInt, RandBigInt, Sign};
+use num_traits::{One, Zero};
+use rand::Rng;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::crypto::crypto::{Blinding, ExponentPolicy};
+use crate::crypto::kex::{FiniteFieldKeyExchange, KexAlgorithm, KeyExchange};
+use crate::crypto::rng::{with_rng, RngPurpose};
+
+/// Extra bytes a padded finite-field value carries beyond the length of p; the
+/// encoding is then within 2^-128 of uniformly random
+pub const FFDH_PADDING_BYTES: usize = 16;
+
+/// Curve25519's Montgomery coefficient A
+const MONTGOMERY_A: u32 = 486662;
+
+/// X25519 whose public keys are sent as Elligator 2 representatives, which look like
+/// 32 uniformly random bytes
+///
+/// Only about half of all points have a representative, so key generation retries
+/// until it finds one. A low-order component is added to the public point, since
+/// points that are always in the prime-order subgroup would stand out once decoded;
+/// X25519's clamped scalars remove it again from the shared secret.
+pub struct ElligatorX25519KeyExchange {
+    secret: StaticSecret,
+    /// Our public point, including its low-order component
+    point: [u8; 32],
+    representative: [u8; 32],
+}
+
+impl ElligatorX25519KeyExchange {
+    /// Generate a fresh ephemeral key pair with a representative
+    pub fn new() -> Self {
+        loop {
+            let secret = with_rng(RngPurpose::SecretKey, |rng| StaticSecret::random_from_rng(rng));
+            let torsion = with_rng(RngPurpose::Nonce, |rng| rng.gen_range(0..EIGHT_TORSION.len()));
+            let point = (EdwardsPoint::mul_base_clamped(secret.to_bytes()) + EIGHT_TORSION[torsion])
+                .to_montgomery()
+                .to_bytes();
+            if let Some(representative) = elligator_representative(&point) {
+                return ElligatorX25519KeyExchange { secret, point, representative };
+            }
+        }
+    }
+}
+
+impl Default for ElligatorX25519KeyExchange {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyExchange for ElligatorX25519KeyExchange {
+    fn algorithm(&self) -> KexAlgorithm {
+        KexAlgorithm::X25519Elligator
+    }
+
+    fn public_key(&self) -> Vec<u8> {
+        self.representative.to_vec()
+    }
+
+    fn shared_secret(&self, peer_public_key: &[u8]) -> Result<BigInt, &'static str> {
+        let representative: [u8; 32] = peer_public_key
+            .try_into()
+            .map_err(|_| "Elligator representative must be 32 bytes")?;
+        let point = elligator_point(&representative);
+        if point == self.point {
+            return Err("peer public key is identical to our own (reflection)");
+        }
+
+        let shared = self.secret.diffie_hellman(&PublicKey::from(point));
+        // Low-order points force an all-zero output regardless of our secret
+        if !shared.was_contributory() {
+            return Err("X25519 public key is a low-order point");
+        }
+        Ok(BigInt::from_bytes_be(Sign::Plus, shared.as_bytes()))
+    }
+}
+
+/// Finite-field DH whose public values look like random bytes of a fixed width
+///
+/// Public values are quadratic residues below p, which sets them apart from random
+/// numbers twice over. Sending p - y instead of y half of the time hides the residue
+/// (p = 3 mod 4 for safe primes, so -1 is a non-residue), and adding a random multiple
+/// of p spreads the value over `FFDH_PADDING_BYTES` more bytes than p has.
+pub struct PaddedFiniteFieldKeyExchange {
+    inner: FiniteFieldKeyExchange,
+    prime: BigInt,
+    subgroup_order: BigInt,
+    encoded: Vec<u8>,
+}
+
+impl PaddedFiniteFieldKeyExchange {
+    /// Generate a fresh full-length secret exponent for the given parameters
+    ///
+    /// # Arguments
+    /// * `prime` - The safe prime modulus p
+    /// * `base` - The generator g
+    /// * `subgroup_order` - The order q = (p - 1) / 2 of g
+    pub fn new(prime: &BigInt, base: &BigInt, subgroup_order: &BigInt) -> Self {
+        Self::from_exchange(FiniteFieldKeyExchange::new(prime, base, subgroup_order), prime, subgroup_order)
+    }
+
+    /// Use an already generated full-length secret exponent
+    pub fn with_secret(prime: &BigInt, base: &BigInt, subgroup_order: &BigInt, secret: BigInt) -> Self {
+        Self::blinded(prime, base, subgroup_order, secret, Blinding::None, ExponentPolicy::Full)
+    }
+
+    /// Use an already generated secret exponent, drawn under `policy`, blinding both
+    /// exponentiations with it
+    pub fn blinded(
+        prime: &BigInt,
+        base: &BigInt,
+        subgroup_order: &BigInt,
+        secret: BigInt,
+        blinding: Blinding,
+        policy: ExponentPolicy,
+    ) -> Self {
+        let inner = FiniteFieldKeyExchange::blinded(prime, base, subgroup_order, secret, blinding, policy);
+        Self::from_exchange(inner, prime, subgroup_order)
+    }
+
+    fn from_exchange(inner: FiniteFieldKeyExchange, prime: &BigInt, subgroup_order: &BigInt) -> Self {
+        let public = BigInt::from_bytes_be(Sign::Plus, &inner.public_key());
+        let width = padded_width(prime);
+        let limit = BigInt::one() << (8 * width);
+        let encoded = with_rng(RngPurpose::Nonce, |rng| {
+            let value = if rng.r#gen::<bool>() { prime - &public } else { public };
+            // Every multiple keeps the value below 2^(8 * width)
+            let multiples = (&limit - 1 - &value) / prime + 1;
+            value + rng.gen_bigint_range(&BigInt::zero(), &multiples) * prime
+        });
+        let bytes = encoded.to_bytes_be().1;
+        let mut padded = vec![0; width - bytes.len()];
+        padded.extend(bytes);
+        PaddedFiniteFieldKeyExchange {
+            inner,
+            prime: prime.clone(),
+            subgroup_order: subgroup_order.clone(),
+            encoded: padded,
+        }
+    }
+}
+
+impl KeyExchange for PaddedFiniteFieldKeyExchange {
+    fn algorithm(&self) -> KexAlgorithm {
+        KexAlgorithm::FiniteFieldPadded
+    }
+
+    fn public_key(&self) -> Vec<u8> {
+        self.encoded.clone()
+    }
+
+    fn shared_secret(&self, peer_public_key: &[u8]) -> Result<BigInt, &'static str> {
+        if peer_public_key.len() != padded_width(&self.prime) {
+            return Err("padded public value has the wrong length");
+        }
+        // Of y and p - y, exactly one is a quadratic residue: the one in the subgroup
+        let value = BigInt::from_bytes_be(Sign::Plus, peer_public_key) % &self.prime;
+        let public = if value.modpow(&self.subgroup_order, &self.prime).is_one() {
+            value
+        } else {
+            &self.prime - value
+        };
+        self.inner.shared_secret(&public.to_bytes_be().1)
+    }
+}
+
+fn padded_width(prime: &BigInt) -> usize {
+    prime.to_bytes_be().1.len() + FFDH_PADDING_BYTES
+}
+
+/// p = 2^255 - 19
+fn field_prime() -> BigInt {
+    (BigInt::one() << 255) - 19
+}
+
+/// Whether a field element is a square (zero counts as one)
+fn is_square(value: &BigInt, p: &BigInt) -> bool {
+    let legendre = value.modpow(&((p - 1) >> 1), p);
+    legendre.is_zero() || legendre.is_one()
+}
+
+/// The square root at most (p - 1) / 2, if `value` is a square
+fn sqrt(value: &BigInt, p: &BigInt) -> Option<BigInt> {
+    // p = 5 mod 8: value^((p + 3) / 8) is a root of value or of -value
+    let mut root = value.modpow(&((p + 3) >> 3), p);
+    if (&root * &root - value) % p != BigInt::zero() {
+        let sqrt_minus_one = BigInt::from(2).modpow(&((p - 1) >> 2), p);
+        root = root * sqrt_minus_one % p;
+    }
+    if (&root * &root - value) % p != BigInt::zero() {
+        return None;
+    }
+    if root > (p - 1) >> 1 {
+        root = p - root;
+    }
+    Some(root)
+}
+
+fn inverse(value: &BigInt, p: &BigInt) -> BigInt {
+    value.modpow(&(p - 2), p)
+}
+
+/// Elligator 2 inverse map with non-square 2: a random-looking encoding of the
+/// Montgomery u-coordinate, if the point has one
+///
+/// Either r = sqrt(-u / (2 (u + A))) or r = sqrt(-(u + A) / (2 u)) maps back to u; one
+/// is picked at random. Roots stay below 2^254, so the top two bits are random too.
+fn elligator_representative(point: &[u8; 32]) -> Option<[u8; 32]> {
+    let p = field_prime();
+    let a = BigInt::from(MONTGOMERY_A);
+    let u = BigInt::from_bytes_le(Sign::Plus, point) % &p;
+    let u_plus_a = (&u + &a) % &p;
+    if u_plus_a.is_zero() || !is_square(&(&p - (2 * &u * &u_plus_a) % &p), &p) {
+        return None;
+    }
+
+    let (flip, top_bits) = with_rng(RngPurpose::Nonce, |rng| (rng.r#gen::<bool>(), rng.r#gen::<u8>() & 0xC0));
+    let (numerator, denominator) = if flip && !u.is_zero() { (&u_plus_a, &u) } else { (&u, &u_plus_a) };
+    let quotient = (&p - numerator) * inverse(&(2 * denominator % &p), &p) % &p;
+    let root = sqrt(&quotient, &p)?;
+
+    let mut representative = [0; 32];
+    let bytes = root.to_bytes_le().1;
+    representative[..bytes.len()].copy_from_slice(&bytes);
+    representative[31] |= top_bits;
+    Some(representative)
+}
+
+/// Elligator 2 forward map: the Montgomery u-coordinate a representative encodes
+///
+/// Every 32-byte string decodes to a point, so representatives cannot be rejected
+/// (or told apart from random bytes) by decoding them.
+fn elligator_point(representative: &[u8; 32]) -> [u8; 32] {
+    let p = field_prime();
+    let a = BigInt::from(MONTGOMERY_A);
+    let mut masked = *representative;
+    masked[31] &= 0x3F;
+    let r = BigInt::from_bytes_le(Sign::Plus, &masked);
+
+    // w = -A / (1 + 2 r^2); u = w if w^3 + A w^2 + w is a square, else -w - A
+    let w = (&p - &a) * inverse(&((1 + 2 * &r * &r) % &p), &p) % &p;
+    let curve = (&w * &w * &w + &a * &w * &w + &w) % &p;
+    let u = if is_square(&curve, &p) { w } else { (&p * 2 - w - a) % &p };
+
+    let mut point = [0; 32];
+    let bytes = u.to_bytes_le().1;
+    point[..bytes.len()].copy_from_slice(&bytes);
+    point
+}
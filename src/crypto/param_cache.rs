@@ -0,0 +1,61 @@
+use std::fs;
+use std::path::Path;
+
+use num_bigint::BigInt;
+use num_traits::Num;
+
+use crate::crypto::crypto::validate_dh_params;
+
+/// Load cached DH parameters, accepting them only if they are still valid
+///
+/// # Arguments
+/// * `path` - Cache file written by `store`
+/// * `bit_length` - Required size of p; a cache for another size is ignored
+///
+/// # Returns
+/// (p, g, q) if the file exists, parses, has the right size, and passes validation
+pub fn load(path: &Path, bit_length: usize) -> Option<(BigInt, BigInt, BigInt)> {
+    let contents = fs::read_to_string(path).ok()?;
+    let p = read_field(&contents, "p")?;
+    let g = read_field(&contents, "g")?;
+
+    if p.bits() != bit_length as u64 {
+        println!("[SERVER] Cached parameters in {} are not {} bits, ignoring", path.display(), bit_length);
+        return None;
+    }
+    match validate_dh_params(&p, &g) {
+        Ok(q) => Some((p, g, q)),
+        Err(reason) => {
+            eprintln!("[SERVER] Cached parameters in {} are invalid: {}", path.display(), reason);
+            None
+        }
+    }
+}
+
+/// Write DH parameters to the cache atomically
+///
+/// The file is written under a temporary name and renamed into place, so a crash
+/// mid-write never leaves a truncated cache behind.
+pub fn store(path: &Path, p: &BigInt, g: &BigInt) -> std::io::Result<()> {
+    let contents = format!(
+        "# Diffie-Hellman parameters generated by dhke\np = {}\ng = {}\n",
+        p.to_str_radix(16),
+        g.to_str_radix(16)
+    );
+
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    fs::write(&temp_path, contents)?;
+    fs::rename(&temp_path, path)
+}
+
+/// Parse a `name = hex` line
+fn read_field(contents: &str, name: &str) -> Option<BigInt> {
+    contents.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        if key.trim() != name {
+            return None;
+        }
+        BigInt::from_str_radix(value.trim(), 16).ok()
+    })
+}
@@ -0,0 +1,42 @@
+use num_bigint::BigInt;
+
+use crate::crypto::crypto::validate_dh_params;
+use crate::crypto::groups::DhGroup;
+
+/// Largest prime a client may propose; validating one means two primality tests,
+/// which past this size would let a single ClientHello tie up a server thread
+pub const MAX_PROPOSED_PRIME_BITS: u64 = 8192;
+
+/// Which client-proposed (p, g) a server accepts (see `DHMessage::ClientParams`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamsPolicy {
+    /// Only the well-known groups, recognized by value
+    NamedGroups,
+    /// Well-known groups, and explicit parameters with p a safe prime of at least
+    /// `min_bits` bits and g generating the subgroup of order (p - 1) / 2
+    Validated { min_bits: u64 },
+}
+
+impl ParamsPolicy {
+    /// Check proposed parameters against the policy
+    ///
+    /// # Returns
+    /// The subgroup order q = (p - 1) / 2 if the parameters are accepted, or why not
+    pub fn check(&self, p: &BigInt, g: &BigInt) -> Result<BigInt, &'static str> {
+        if let Some(group) = DhGroup::identify(p, g) {
+            return Ok(group.subgroup_order());
+        }
+        match self {
+            ParamsPolicy::NamedGroups => Err("only well-known groups are accepted"),
+            ParamsPolicy::Validated { min_bits } => {
+                if p.bits() < *min_bits {
+                    return Err("p is too small");
+                }
+                if p.bits() > MAX_PROPOSED_PRIME_BITS {
+                    return Err("p is too large");
+                }
+                validate_dh_params(p, g)
+            }
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::fs;
+use std::path::Path;
+
+use pem_rfc7468::LineEnding;
+
+/// PEM label of PKCS#3 DH parameters (`openssl dhparam`)
+pub const DH_PARAMETERS_LABEL: &str = "DH PARAMETERS";
+
+/// PEM label of a PKCS#8 private key (`openssl genpkey`)
+pub const PRIVATE_KEY_LABEL: &str = "PRIVATE KEY";
+
+/// PEM label of a SubjectPublicKeyInfo public key (`openssl pkey -pubout`)
+pub const PUBLIC_KEY_LABEL: &str = "PUBLIC KEY";
+
+/// Wrap DER in PEM armor (RFC 7468), with 64-character lines as OpenSSL writes them
+pub fn encode(label: &str, der: &[u8]) -> String {
+    pem_rfc7468::encode_string(label, LineEnding::LF, der).expect("PEM labels used here are valid")
+}
+
+/// Strip PEM armor, checking it carries the expected label
+///
+/// # Returns
+/// The DER contents, or a description of what is wrong with the armor
+pub fn decode(label: &str, pem: &str) -> Result<Vec<u8>, &'static str> {
+    let (found, der) = pem_rfc7468::decode_vec(pem.trim().as_bytes()).map_err(|_| "malformed PEM")?;
+    if found != label {
+        return Err("unexpected PEM label");
+    }
+    Ok(der)
+}
+
+/// Whether file contents look like PEM rather than raw DER
+pub fn is_pem(contents: &[u8]) -> bool {
+    contents.trim_ascii_start().starts_with(b"-----BEGIN ")
+}
+
+/// DER from contents holding either PEM under `label` or the raw DER itself
+pub fn to_der(contents: Vec<u8>, label: &str) -> Result<Vec<u8>, &'static str> {
+    if !is_pem(&contents) {
+        return Ok(contents);
+    }
+    let text = String::from_utf8(contents).map_err(|_| "malformed PEM")?;
+    decode(label, &text)
+}
+
+/// Read a file holding either PEM under `label` or the raw DER itself
+pub fn read_der(path: &Path, label: &str) -> std::io::Result<Vec<u8>> {
+    to_der(fs::read(path)?, label).map_err(|reason| invalid(path, reason))
+}
+
+/// Write DER to a file as PEM under `label`
+pub fn write(path: &Path, label: &str, der: &[u8]) -> std::io::Result<()> {
+    fs::write(path, encode(label, der))
+}
+
+/// Write DER to a file as PEM under `label`, atomically and readable only by its
+/// owner where supported
+pub fn write_secret(path: &Path, label: &str, der: &[u8]) -> std::io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    fs::write(&temp_path, encode(label, der))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&temp_path, fs::Permissions::from_mode(0o600))?;
+    }
+    fs::rename(&temp_path, path)
+}
+
+fn invalid(path: &Path, reason: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), reason))
+}
@@ -0,0 +1,311 @@
+use num_bigint::{BigInt, RandBigInt, Sign};
+use num_traits::{One, Zero};
+use rand::rngs::OsRng;
+use rand::Rng;
+use rayon::iter::ParallelIterator;
+
+use crate::crypto::crypto::{find_generator, gcd, miller_rabin, mod_pow_window, small_primes, PrimalityConfig};
+use crate::crypto::rng::SecureRandom;
+
+/// Primes of at most this many bits end a certificate chain; they are proven by
+/// trial division
+pub const BASE_PRIME_BITS: u64 = 32;
+
+/// Most Pocklington steps a certificate may hold (each roughly halves the prime's
+/// size, so this covers primes far beyond any usable DH modulus)
+pub const MAX_CERTIFICATE_STEPS: usize = 32;
+
+/// Witnesses tried for each candidate that passed Miller-Rabin before giving up on it
+const WITNESS_ATTEMPTS: u32 = 32;
+
+/// Candidates tried with one chain of smaller primes before drawing a new chain
+const CANDIDATE_WINDOW: u32 = 4096;
+
+/// One link of a certificate: n is prime because n - 1 has the previous prime of the
+/// chain f as a factor, f^2 > n, a^(n - 1) = 1 mod n and gcd(a^((n - 1) / f) - 1, n) = 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PocklingtonStep {
+    /// The prime this step proves
+    pub n: BigInt,
+    /// The witness a
+    pub witness: BigInt,
+}
+
+/// A Pocklington primality certificate: a chain of primes, each proven from the one
+/// before it, starting from a prime small enough for trial division
+///
+/// Checking a certificate costs two modular exponentiations per step and involves no
+/// randomness, so a peer can verify that a prime is genuinely prime instead of
+/// trusting Miller-Rabin on numbers an attacker may have chosen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrimeCertificate {
+    /// The prime the chain starts from, at most `BASE_PRIME_BITS` bits
+    pub base: u64,
+    /// Steps from the smallest prime to the one certified, each proven with the
+    /// prime before it (`base` for the first)
+    pub steps: Vec<PocklingtonStep>,
+}
+
+impl PrimeCertificate {
+    /// The prime the certificate claims to prove
+    pub fn prime(&self) -> BigInt {
+        self.steps.last().map_or_else(|| BigInt::from(self.base), |step| step.n.clone())
+    }
+
+    /// Check every link of the chain
+    ///
+    /// # Returns
+    /// The proven prime, or a description of the first link that does not hold
+    pub fn verify(&self) -> Result<BigInt, &'static str> {
+        if self.steps.len() > MAX_CERTIFICATE_STEPS {
+            return Err("certificate has too many steps");
+        }
+        if !is_small_prime(self.base) {
+            return Err("certificate does not start from a prime");
+        }
+
+        let mut factor = BigInt::from(self.base);
+        for step in &self.steps {
+            let n = &step.n;
+            let n_minus_1: BigInt = n - BigInt::one();
+            if n <= &factor || !(&n_minus_1 % &factor).is_zero() {
+                return Err("certificate step does not extend the previous prime");
+            }
+            // The factor must exceed sqrt(n) for n's primality to follow
+            if &factor * &factor <= *n {
+                return Err("certificate step uses a factor below the square root");
+            }
+            let a = &step.witness;
+            if a <= &BigInt::one() || a >= &n_minus_1 {
+                return Err("certificate witness is out of range");
+            }
+            if !mod_pow_window(a, &n_minus_1, n).is_one() {
+                return Err("certificate witness fails Fermat's test");
+            }
+            let partial = mod_pow_window(a, &(&n_minus_1 / &factor), n) - BigInt::one();
+            if !gcd(&partial, n).is_one() {
+                return Err("certificate witness does not prove the step");
+            }
+            factor = n.clone();
+        }
+        Ok(factor)
+    }
+
+    /// Check that the certificate proves p to be a safe prime p = 2q + 1
+    ///
+    /// # Returns
+    /// q, proven prime by the step before p's, or a description of the problem
+    pub fn verify_safe_prime(&self, p: &BigInt) -> Result<BigInt, &'static str> {
+        if &self.verify()? != p {
+            return Err("certificate is for a different prime");
+        }
+        let q = match self.steps.len() {
+            0 => return Err("certificate does not prove a safe prime"),
+            1 => BigInt::from(self.base),
+            len => self.steps[len - 2].n.clone(),
+        };
+        if &q * 2 + BigInt::one() != *p {
+            return Err("certificate does not prove a safe prime");
+        }
+        Ok(q)
+    }
+
+    /// Serialize as [8-byte base][1-byte step count], then per step
+    /// [4-byte length][n][4-byte length][witness]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.base.to_be_bytes().to_vec();
+        bytes.push(self.steps.len() as u8);
+        for step in &self.steps {
+            for value in [&step.n, &step.witness] {
+                let value = value.to_bytes_be().1;
+                bytes.extend((value.len() as u32).to_be_bytes());
+                bytes.extend(value);
+            }
+        }
+        bytes
+    }
+
+    /// Parse a certificate written by `to_bytes`; the chain is not checked here
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (base, rest) = bytes.split_first_chunk::<8>()?;
+        let (&count, mut rest) = rest.split_first()?;
+        if count as usize > MAX_CERTIFICATE_STEPS {
+            return None;
+        }
+        let mut read_value = || {
+            let (len, tail) = rest.split_first_chunk::<4>()?;
+            let len = u32::from_be_bytes(*len) as usize;
+            let value = tail.get(..len)?;
+            rest = &tail[len..];
+            Some(BigInt::from_bytes_be(Sign::Plus, value))
+        };
+        let mut steps = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            steps.push(PocklingtonStep { n: read_value()?, witness: read_value()? });
+        }
+        rest.is_empty().then_some(PrimeCertificate { base: u64::from_be_bytes(*base), steps })
+    }
+}
+
+/// Generates DH parameters as `generate_dh_params` does, with p and q provably prime
+///
+/// q is built up from a chain of smaller primes as in Maurer's and Shawe-Taylor's
+/// constructions, so every prime comes with a Pocklington proof instead of a
+/// probability. Workers search concurrently on all cores; the first pair found wins.
+///
+/// # Arguments
+/// * `config` - Bit length of p; its rounds go unused, since candidates are proven instead
+///
+/// # Returns
+/// A tuple (p, g, q) as for `generate_dh_params`, and the certificate for p
+pub fn generate_provable_dh_params(config: &PrimalityConfig) -> (BigInt, BigInt, BigInt, PrimeCertificate) {
+    println!("Generating {} bit provable safe prime p...", config.bit_length);
+    let certificate = rayon::iter::repeat(())
+        .find_map_any(|_| search_provable_safe_prime(&mut OsRng, config.bit_length as u64))
+        .expect("candidate stream is infinite");
+    finish_params(&mut OsRng, certificate)
+}
+
+/// Generates provable DH parameters as `generate_provable_dh_params` does, drawing
+/// every candidate and the generator from `rng`
+pub fn generate_provable_dh_params_with<R: SecureRandom + ?Sized>(
+    rng: &mut R,
+    config: &PrimalityConfig,
+) -> (BigInt, BigInt, BigInt, PrimeCertificate) {
+    println!("Generating {} bit provable safe prime p...", config.bit_length);
+    let certificate = loop {
+        if let Some(certificate) = search_provable_safe_prime(rng, config.bit_length as u64) {
+            break certificate;
+        }
+    };
+    finish_params(rng, certificate)
+}
+
+fn finish_params<R: SecureRandom + ?Sized>(
+    rng: &mut R,
+    certificate: PrimeCertificate,
+) -> (BigInt, BigInt, BigInt, PrimeCertificate) {
+    let p = certificate.prime();
+    let q: BigInt = (&p - BigInt::one()) / 2;
+    println!("Prime p generated and certified. Generating generator g...");
+    let g = find_generator(rng, &p, &q);
+    println!("DH parameters generated successfully!");
+    (p, g, q, certificate)
+}
+
+/// Generates a random prime of exactly `bits` bits with a certificate
+///
+/// # Returns
+/// The certificate; its `prime()` is the generated prime
+pub fn generate_provable_prime<R: SecureRandom + ?Sized>(rng: &mut R, bits: u64) -> PrimeCertificate {
+    if bits <= BASE_PRIME_BITS {
+        return PrimeCertificate { base: random_small_prime(rng, bits), steps: Vec::new() };
+    }
+    loop {
+        let mut certificate = generate_provable_prime(rng, half_bits(bits));
+        let found = search_extension(rng, &certificate.prime(), bits, |n, f| {
+            let witness = find_witness(n, f)?;
+            Some(PocklingtonStep { n: n.clone(), witness })
+        });
+        if let Some(step) = found {
+            certificate.steps.push(step);
+            return certificate;
+        }
+    }
+}
+
+/// Looks for a safe prime p = 2q + 1 of `bits` bits, with q = 2kf + 1 for one freshly
+/// generated provable prime f
+fn search_provable_safe_prime<R: SecureRandom + ?Sized>(rng: &mut R, bits: u64) -> Option<PrimeCertificate> {
+    let mut certificate = generate_provable_prime(rng, half_bits(bits - 1));
+    let (q_step, p_step) = search_extension(rng, &certificate.prime(), bits - 1, |q, f| {
+        // Sieve p = 2q + 1 as well before paying for any exponentiation
+        let p: BigInt = q * 2 + BigInt::one();
+        if has_small_factor(&p) || !miller_rabin(&p, 1) {
+            return None;
+        }
+        let q_step = PocklingtonStep { n: q.clone(), witness: find_witness(q, f)? };
+        let p_step = PocklingtonStep { witness: find_witness(&p, q)?, n: p };
+        Some((q_step, p_step))
+    })?;
+    certificate.steps.push(q_step);
+    certificate.steps.push(p_step);
+    Some(certificate)
+}
+
+/// Bit length of the factor used to prove a prime of `bits` bits, just large enough
+/// that its square always exceeds the prime
+fn half_bits(bits: u64) -> u64 {
+    bits.div_ceil(2) + 1
+}
+
+/// Tries n = 2kf + 1 for consecutive k from a random start, keeping n at `bits` bits
+///
+/// # Returns
+/// The first result of `prove` for a candidate that survives sieving and one round of
+/// Miller-Rabin, or None if the window ran out
+fn search_extension<R, T>(
+    rng: &mut R,
+    f: &BigInt,
+    bits: u64,
+    mut prove: impl FnMut(&BigInt, &BigInt) -> Option<T>,
+) -> Option<T>
+where
+    R: SecureRandom + ?Sized,
+{
+    // n has exactly `bits` bits when 2^(bits - 1) <= 2kf + 1 < 2^bits
+    let two_f: BigInt = f * 2;
+    let low = ((BigInt::one() << (bits - 1)) + &two_f - 1) / &two_f;
+    let high = ((BigInt::one() << bits) - 2) / &two_f;
+    if low > high {
+        return None;
+    }
+    let start = rng.gen_bigint_range(&low, &(&high + 1));
+
+    (0..CANDIDATE_WINDOW).find_map(|offset| {
+        let k = &start + offset;
+        if k > high {
+            return None;
+        }
+        let n: BigInt = &k * &two_f + BigInt::one();
+        if has_small_factor(&n) || !miller_rabin(&n, 1) {
+            return None;
+        }
+        prove(&n, f)
+    })
+}
+
+/// A witness a proving n prime from the prime factor f of n - 1, if n is prime
+fn find_witness(n: &BigInt, f: &BigInt) -> Option<BigInt> {
+    let n_minus_1: BigInt = n - BigInt::one();
+    let cofactor = &n_minus_1 / f;
+    (2..2 + WITNESS_ATTEMPTS).map(BigInt::from).find(|a| {
+        mod_pow_window(a, &n_minus_1, n).is_one() && gcd(&(mod_pow_window(a, &cofactor, n) - BigInt::one()), n).is_one()
+    })
+}
+
+/// Whether n is divisible by one of the small sieving primes other than itself
+fn has_small_factor(n: &BigInt) -> bool {
+    small_primes()
+        .iter()
+        .any(|&r| (n % r).is_zero() && n != &BigInt::from(r))
+}
+
+/// A random prime of exactly `bits` bits (at most `BASE_PRIME_BITS`), proven by trial division
+fn random_small_prime<R: SecureRandom + ?Sized>(rng: &mut R, bits: u64) -> u64 {
+    let bits = bits.clamp(2, BASE_PRIME_BITS);
+    loop {
+        let candidate = rng.gen_range(1u64 << (bits - 1)..1u64 << bits) | 1;
+        if is_small_prime(candidate) {
+            return candidate;
+        }
+    }
+}
+
+/// Trial division up to the square root, for n of at most `BASE_PRIME_BITS` bits
+fn is_small_prime(n: u64) -> bool {
+    if n < 2 || n >> BASE_PRIME_BITS != 0 {
+        return false;
+    }
+    (2..).take_while(|d: &u64| d * d <= n).all(|d| !n.is_multiple_of(d))
+}
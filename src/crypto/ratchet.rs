@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::crypto::rng::{with_rng, RngPurpose};
+
+/// Most keys of skipped messages kept at once, so a forged message number cannot
+/// make us derive (and store) an unbounded number of keys
+pub const MAX_SKIP: u32 = 1000;
+
+/// Size of an encoded `RatchetHeader`
+pub const HEADER_LEN: usize = 40;
+
+/// Sent in the clear with every message: the sender's current ratchet public key and
+/// where the message sits in the sender's chains
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RatchetHeader {
+    /// The sender's ratchet public key
+    pub public_key: [u8; 32],
+    /// Number of messages in the sender's previous sending chain
+    pub previous_chain_length: u32,
+    /// Number of the message in the sender's current sending chain
+    pub message_number: u32,
+}
+
+impl RatchetHeader {
+    /// Encode as [public key][previous chain length][message number], integers big-endian
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[..32].copy_from_slice(&self.public_key);
+        bytes[32..36].copy_from_slice(&self.previous_chain_length.to_be_bytes());
+        bytes[36..].copy_from_slice(&self.message_number.to_be_bytes());
+        bytes
+    }
+
+    /// Decode a header; None unless `bytes` is exactly `HEADER_LEN` long
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let bytes: &[u8; HEADER_LEN] = bytes.try_into().ok()?;
+        Some(RatchetHeader {
+            public_key: bytes[..32].try_into().ok()?,
+            previous_chain_length: u32::from_be_bytes(bytes[32..36].try_into().ok()?),
+            message_number: u32::from_be_bytes(bytes[36..].try_into().ok()?),
+        })
+    }
+}
+
+/// Double Ratchet (as in the Signal protocol): every message is encrypted under its
+/// own key
+///
+/// A symmetric chain advances with every message, so a key that leaks exposes
+/// neither earlier messages nor, once the peer replies with a new ratchet key, later
+/// ones. Each new ratchet key from the peer mixes a fresh X25519 output into the
+/// root key from which both chains are derived.
+#[derive(Clone)]
+pub struct DoubleRatchet {
+    ratchet_secret: StaticSecret,
+    ratchet_public: PublicKey,
+    remote_public: Option<PublicKey>,
+    root_key: [u8; 32],
+    sending_chain: Option<[u8; 32]>,
+    receiving_chain: Option<[u8; 32]>,
+    sent: u32,
+    received: u32,
+    previous_sent: u32,
+    /// Keys of messages that were skipped over, by ratchet key and message number
+    skipped: HashMap<([u8; 32], u32), [u8; 32]>,
+    /// Authenticated with every message (e.g., both parties' identities)
+    associated_data: Vec<u8>,
+}
+
+impl DoubleRatchet {
+    /// Ratchet for the party that sends first
+    ///
+    /// # Arguments
+    /// * `secret` - 256-bit secret both parties agreed on (e.g., an X3DH session
+    ///   secret or a value exported from `SessionKeys`)
+    /// * `responder_key` - The responder's initial ratchet public key
+    /// * `associated_data` - Authenticated with every message
+    ///
+    /// # Returns
+    /// The ratchet, or an error if the responder's key is a low-order point
+    pub fn initiator(secret: &[u8; 32], responder_key: &[u8; 32], associated_data: &[u8]) -> Result<Self, &'static str> {
+        let mut ratchet = Self::new(secret, random_secret(), associated_data);
+        let remote_public = PublicKey::from(*responder_key);
+        let (root_key, sending_chain) = kdf_root(&ratchet.root_key, &dh(&ratchet.ratchet_secret, &remote_public)?);
+        ratchet.root_key = root_key;
+        ratchet.sending_chain = Some(sending_chain);
+        ratchet.remote_public = Some(remote_public);
+        Ok(ratchet)
+    }
+
+    /// Ratchet for the party that answers; it can send once the initiator's first
+    /// message has arrived
+    ///
+    /// # Arguments
+    /// * `secret` - The same secret as the initiator's
+    /// * `ratchet_secret` - Private half of the key the initiator was given (see `responder_key_pair`)
+    /// * `associated_data` - Authenticated with every message
+    pub fn responder(secret: &[u8; 32], ratchet_secret: StaticSecret, associated_data: &[u8]) -> Self {
+        Self::new(secret, ratchet_secret, associated_data)
+    }
+
+    fn new(secret: &[u8; 32], ratchet_secret: StaticSecret, associated_data: &[u8]) -> Self {
+        DoubleRatchet {
+            ratchet_public: PublicKey::from(&ratchet_secret),
+            ratchet_secret,
+            remote_public: None,
+            root_key: *secret,
+            sending_chain: None,
+            receiving_chain: None,
+            sent: 0,
+            received: 0,
+            previous_sent: 0,
+            skipped: HashMap::new(),
+            associated_data: associated_data.to_vec(),
+        }
+    }
+
+    /// Encrypt one message under the next key of the sending chain
+    ///
+    /// # Returns
+    /// The header to send along with the ciphertext, or an error if this is a
+    /// responder that has not yet received a message
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<(RatchetHeader, Vec<u8>), &'static str> {
+        let chain = self.sending_chain.ok_or("no sending chain until the initiator's first message arrives")?;
+        let (chain, message_key) = kdf_chain(&chain);
+        self.sending_chain = Some(chain);
+
+        let header = RatchetHeader {
+            public_key: self.ratchet_public.to_bytes(),
+            previous_chain_length: self.previous_sent,
+            message_number: self.sent,
+        };
+        self.sent = self.sent.checked_add(1).ok_or("sending chain exhausted")?;
+        let ciphertext = seal(&message_key, &self.associated_data, &header, plaintext);
+        Ok((header, ciphertext))
+    }
+
+    /// Decrypt one message, stepping the ratchet if it carries a new ratchet key
+    ///
+    /// Messages may arrive out of order; keys of skipped messages are kept until
+    /// they arrive. The state only changes if the message authenticates.
+    pub fn decrypt(&mut self, header: &RatchetHeader, ciphertext: &[u8]) -> Result<Vec<u8>, &'static str> {
+        if let Some(message_key) = self.skipped.remove(&(header.public_key, header.message_number)) {
+            return open(&message_key, &self.associated_data, header, ciphertext).inspect_err(|_| {
+                self.skipped.insert((header.public_key, header.message_number), message_key);
+            });
+        }
+
+        let mut next = self.clone();
+        if next.remote_public.map(|key| key.to_bytes()) != Some(header.public_key) {
+            next.skip_until(header.previous_chain_length)?;
+            next.step(&header.public_key)?;
+        }
+        next.skip_until(header.message_number)?;
+        let chain = next.receiving_chain.ok_or("no receiving chain")?;
+        let (chain, message_key) = kdf_chain(&chain);
+        next.receiving_chain = Some(chain);
+        next.received = next.received.checked_add(1).ok_or("receiving chain exhausted")?;
+
+        let plaintext = open(&message_key, &next.associated_data, header, ciphertext)?;
+        *self = next;
+        Ok(plaintext)
+    }
+
+    /// Store the keys of the current receiving chain's messages before `until`
+    fn skip_until(&mut self, until: u32) -> Result<(), &'static str> {
+        let Some(mut chain) = self.receiving_chain else {
+            return Ok(());
+        };
+        if self.skipped.len() as u64 + until.saturating_sub(self.received) as u64 > MAX_SKIP as u64 {
+            return Err("too many skipped messages");
+        }
+        let remote = self.remote_public.map(|key| key.to_bytes()).unwrap_or_default();
+        while self.received < until {
+            let (next_chain, message_key) = kdf_chain(&chain);
+            self.skipped.insert((remote, self.received), message_key);
+            chain = next_chain;
+            self.received += 1;
+        }
+        self.receiving_chain = Some(chain);
+        Ok(())
+    }
+
+    /// DH ratchet step on the peer's new ratchet key: a receiving chain for its
+    /// messages, then a new key pair of ours and a sending chain for our replies
+    fn step(&mut self, remote_key: &[u8; 32]) -> Result<(), &'static str> {
+        let remote_public = PublicKey::from(*remote_key);
+        self.previous_sent = self.sent;
+        self.sent = 0;
+        self.received = 0;
+
+        let (root_key, receiving_chain) = kdf_root(&self.root_key, &dh(&self.ratchet_secret, &remote_public)?);
+        self.ratchet_secret = random_secret();
+        self.ratchet_public = PublicKey::from(&self.ratchet_secret);
+        let (root_key, sending_chain) = kdf_root(&root_key, &dh(&self.ratchet_secret, &remote_public)?);
+
+        self.root_key = root_key;
+        self.receiving_chain = Some(receiving_chain);
+        self.sending_chain = Some(sending_chain);
+        self.remote_public = Some(remote_public);
+        Ok(())
+    }
+}
+
+/// Key pair for `DoubleRatchet::responder`, whose public half the initiator needs first
+pub fn responder_key_pair() -> (StaticSecret, [u8; 32]) {
+    let secret = random_secret();
+    let public = PublicKey::from(&secret).to_bytes();
+    (secret, public)
+}
+
+fn random_secret() -> StaticSecret {
+    with_rng(RngPurpose::SecretKey, |rng| StaticSecret::random_from_rng(rng))
+}
+
+/// X25519 that rejects low-order points, whose output does not depend on our secret
+fn dh(secret: &StaticSecret, public: &PublicKey) -> Result<[u8; 32], &'static str> {
+    let shared = secret.diffie_hellman(public);
+    if !shared.was_contributory() {
+        return Err("ratchet key is a low-order point");
+    }
+    Ok(shared.to_bytes())
+}
+
+/// KDF_RK: HKDF keyed by the root key over a DH output, giving the next root key
+/// and a new chain key
+fn kdf_root(root_key: &[u8; 32], dh_output: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let mut output = [0; 64];
+    Hkdf::<Sha256>::new(Some(root_key), dh_output)
+        .expand(b"dhke ratchet root", &mut output)
+        .expect("output length is far below the HKDF-SHA256 limit");
+    let (root_key, chain_key) = output.split_at(32);
+    (root_key.try_into().unwrap(), chain_key.try_into().unwrap())
+}
+
+/// KDF_CK: the next chain key and a message key, both HMACs of the current chain key
+fn kdf_chain(chain_key: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let hmac = |input: u8| -> [u8; 32] {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(chain_key).expect("HMAC accepts any key length");
+        mac.update(&[input]);
+        mac.finalize().into_bytes().into()
+    };
+    (hmac(0x02), hmac(0x01))
+}
+
+/// AES-256-GCM key and nonce expanded from a message key, which is used only once
+fn message_cipher(message_key: &[u8; 32]) -> (Aes256Gcm, [u8; 12]) {
+    let mut output = [0; 44];
+    Hkdf::<Sha256>::new(None, message_key)
+        .expand(b"dhke ratchet message", &mut output)
+        .expect("output length is far below the HKDF-SHA256 limit");
+    let (key, nonce) = output.split_at(32);
+    (Aes256Gcm::new(key.into()), nonce.try_into().unwrap())
+}
+
+fn seal(message_key: &[u8; 32], associated_data: &[u8], header: &RatchetHeader, plaintext: &[u8]) -> Vec<u8> {
+    let (cipher, nonce) = message_cipher(message_key);
+    let aad = [associated_data, &header.to_bytes()].concat();
+    cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: &aad })
+        .expect("AES-GCM encrypts messages of any record size")
+}
+
+fn open(message_key: &[u8; 32], associated_data: &[u8], header: &RatchetHeader, ciphertext: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let (cipher, nonce) = message_cipher(message_key);
+    let aad = [associated_data, &header.to_bytes()].concat();
+    cipher
+        .decrypt(Nonce::from_slice(&nonce), Payload { msg: ciphertext, aad: &aad })
+        .map_err(|_| "ratchet message does not authenticate")
+}
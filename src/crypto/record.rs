@@ -0,0 +1,748 @@
+use std::io::{IoSlice, Read, Write};
+use std::time::{Duration, Instant};
+
+use aes_gcm::aead::{Aead, AeadInPlace, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use chacha20poly1305::ChaCha20Poly1305;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::Sha256;
+
+use crate::crypto::ct::ct_eq;
+use crate::crypto::kdf::{SessionKeys, CLIENT_FINISHED_LABEL, SERVER_FINISHED_LABEL};
+use crate::crypto::kex::{KeyExchange, X25519KeyExchange};
+use crate::crypto::rng::{with_rng, RngPurpose};
+use crate::crypto::transcript::Transcript;
+use crate::network::alert::{Alert, AlertCode};
+use crate::protocol::record::{ContentType, Record};
+use crate::structs::DH_Prot::DHMessage;
+
+/// Largest plaintext carried by a single record
+pub const MAX_RECORD_PLAINTEXT: usize = 16 * 1024;
+
+/// Size of the authentication tag appended to every record (the same for every suite)
+const TAG_LEN: usize = 16;
+
+/// Most padding a single record or encrypted handshake message carries
+pub const MAX_PADDING: usize = 4096;
+
+/// Size of the padding length that ends every padded plaintext
+const PADDING_TRAILER_LEN: usize = 2;
+
+/// Why a peer closed an established connection, carried in an authenticated Close record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    /// The server is shutting down
+    Shutdown,
+    /// The server closed the connection to stay within its connection limits or
+    /// maximum connection age; the client should reconnect
+    Drained,
+    /// The peer is done with the connection (see `DHClient::close`); a Close sent in
+    /// reply has this reason too
+    Normal,
+}
+
+impl CloseReason {
+    /// Every close reason known to this implementation
+    pub const ALL: &'static [CloseReason] = &[CloseReason::Shutdown, CloseReason::Drained, CloseReason::Normal];
+
+    /// Wire identifier of the reason
+    pub fn id(&self) -> u8 {
+        match self {
+            CloseReason::Shutdown => 0,
+            CloseReason::Drained => 1,
+            CloseReason::Normal => 2,
+        }
+    }
+
+    /// Look up a reason by its wire identifier
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.iter().copied().find(|reason| reason.id() == id)
+    }
+
+    /// Short lowercase name of the reason (e.g., "drained")
+    pub fn name(&self) -> &'static str {
+        match self {
+            CloseReason::Shutdown => "shutdown",
+            CloseReason::Drained => "drained",
+            CloseReason::Normal => "normal",
+        }
+    }
+}
+
+/// When an established session replaces its keys with a fresh ephemeral exchange
+///
+/// Either side starts a rekey once one of its thresholds passes; the peer answers
+/// whatever its own policy, so one side's thresholds are enough.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RekeyPolicy {
+    /// Rekey once this many plaintext bytes have been sent and received under the current keys
+    pub max_bytes: Option<u64>,
+    /// Rekey once the current keys are this old (checked whenever a record is sent or received)
+    pub max_age: Option<Duration>,
+}
+
+impl RekeyPolicy {
+    /// Whether keys that protected `bytes` bytes and were installed at `installed` are due
+    fn due(&self, bytes: u64, installed: Instant) -> bool {
+        self.max_bytes.is_some_and(|max| bytes >= max) || self.max_age.is_some_and(|max| installed.elapsed() >= max)
+    }
+}
+
+/// When an established session checks that its peer is still there
+///
+/// After `interval` without a record from the peer, a Ping is sent; every record
+/// received counts as an answer. Besides detecting dead peers, the Pings keep NATs and
+/// firewalls from dropping idle connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepalivePolicy {
+    /// Silence after which a Ping is sent, and between unanswered Pings
+    pub interval: Duration,
+    /// Unanswered Pings in a row after which the peer is considered dead
+    pub max_missed: u32,
+}
+
+impl Default for KeepalivePolicy {
+    fn default() -> Self {
+        KeepalivePolicy {
+            interval: Duration::from_secs(30),
+            max_missed: 3,
+        }
+    }
+}
+
+/// How much padding a side adds to what it encrypts, once the client asked for padded
+/// records (see `RECORD_PADDING_SIGNAL`), so sizes on the wire say less about what
+/// is in flight
+///
+/// Padded plaintexts end in [zeros][padding length:u16], inside the encryption, so
+/// the length is authenticated. Handshake messages sent before handshake encryption
+/// show their type in the clear and are never padded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Padding {
+    /// No padding bytes, only the padding length; the peer may still pad
+    #[default]
+    None,
+    /// A random number of bytes, from zero up to the bound (at most `MAX_PADDING`)
+    Random(usize),
+    /// Enough bytes to make each padded plaintext a multiple of the block size (at
+    /// most `MAX_PADDING`)
+    Block(usize),
+}
+
+impl Padding {
+    /// Pad `buffer` in place, appending the padding and its length
+    pub fn pad(&self, buffer: &mut Vec<u8>) {
+        let len = buffer.len() + PADDING_TRAILER_LEN;
+        let amount = match *self {
+            Padding::None => 0,
+            Padding::Random(max) => with_rng(RngPurpose::Nonce, |rng| rng.gen_range(0..=max.min(MAX_PADDING))),
+            Padding::Block(size) => {
+                let size = size.clamp(1, MAX_PADDING);
+                (size - len % size) % size
+            }
+        };
+        buffer.resize(buffer.len() + amount, 0);
+        buffer.extend((amount as u16).to_be_bytes());
+    }
+
+    /// Strip the padding `pad` added from an authenticated plaintext
+    pub fn unpad(plaintext: &mut Vec<u8>) -> Result<(), &'static str> {
+        let Some((rest, trailer)) = plaintext.split_last_chunk::<PADDING_TRAILER_LEN>() else {
+            return Err("Missing padding length");
+        };
+        let amount = u16::from_be_bytes(*trailer) as usize;
+        if amount > MAX_PADDING || amount > rest.len() {
+            return Err("Padding length out of range");
+        }
+        let unpadded = rest.len() - amount;
+        if rest[unpadded..].iter().any(|&byte| byte != 0) {
+            return Err("Malformed padding");
+        }
+        plaintext.truncate(unpadded);
+        Ok(())
+    }
+}
+
+/// Ciphers that can be negotiated for the record layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CipherSuite {
+    /// AES-256 in Galois/Counter Mode, fastest where AES-NI is available
+    Aes256Gcm,
+    /// ChaCha20-Poly1305 (RFC 8439), fast in software on platforms without AES instructions
+    ChaCha20Poly1305,
+    /// INSECURE: no encryption, only an HMAC-SHA256 tag truncated to 16 bytes. Records
+    /// are framed, sequenced, and authenticated exactly as with the AEAD suites, but the
+    /// plaintext is readable on the wire, for debugging and teaching only
+    InsecureHmacSha256,
+}
+
+impl CipherSuite {
+    /// Every cipher known to this implementation, including the insecure one
+    pub const ALL: &'static [CipherSuite] = &[
+        CipherSuite::Aes256Gcm,
+        CipherSuite::ChaCha20Poly1305,
+        CipherSuite::InsecureHmacSha256,
+    ];
+
+    /// Ciphers offered and accepted unless configured otherwise; the insecure suite
+    /// must always be asked for by name
+    pub const DEFAULT: &'static [CipherSuite] = &[CipherSuite::Aes256Gcm, CipherSuite::ChaCha20Poly1305];
+
+    /// Wire identifier of the cipher
+    pub fn id(&self) -> u8 {
+        match self {
+            CipherSuite::Aes256Gcm => 0,
+            CipherSuite::ChaCha20Poly1305 => 1,
+            CipherSuite::InsecureHmacSha256 => 0xF0,
+        }
+    }
+
+    /// Whether the suite keeps application data confidential
+    pub fn encrypts(&self) -> bool {
+        *self != CipherSuite::InsecureHmacSha256
+    }
+
+    /// Look up a cipher by its wire identifier
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.iter().copied().find(|cipher| cipher.id() == id)
+    }
+
+    /// Short lowercase name of the cipher (e.g., "chacha20poly1305")
+    pub fn name(&self) -> &'static str {
+        match self {
+            CipherSuite::Aes256Gcm => "aes256gcm",
+            CipherSuite::ChaCha20Poly1305 => "chacha20poly1305",
+            CipherSuite::InsecureHmacSha256 => "insecure_hmac_sha256",
+        }
+    }
+
+    /// Look up a cipher by its short name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|cipher| cipher.name() == name)
+    }
+}
+
+/// Keyed instance of the negotiated cipher
+enum RecordCipher {
+    Aes256Gcm(Box<Aes256Gcm>),
+    ChaCha20Poly1305(ChaCha20Poly1305),
+    InsecureHmacSha256(Box<Hmac<Sha256>>),
+}
+
+impl RecordCipher {
+    fn new(suite: CipherSuite, key: &[u8; 32]) -> Self {
+        match suite {
+            CipherSuite::Aes256Gcm => RecordCipher::Aes256Gcm(Box::new(Aes256Gcm::new(key.into()))),
+            CipherSuite::ChaCha20Poly1305 => RecordCipher::ChaCha20Poly1305(ChaCha20Poly1305::new(key.into())),
+            CipherSuite::InsecureHmacSha256 => RecordCipher::InsecureHmacSha256(Box::new(
+                <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length"),
+            )),
+        }
+    }
+
+    /// Encrypt `buffer` in place and return the authentication tag
+    fn encrypt_in_place(&self, nonce: &[u8; 12], aad: &[u8], buffer: &mut [u8]) -> Result<[u8; TAG_LEN], aes_gcm::aead::Error> {
+        let tag = match self {
+            RecordCipher::Aes256Gcm(cipher) => cipher.encrypt_in_place_detached(Nonce::from_slice(nonce), aad, buffer)?,
+            RecordCipher::ChaCha20Poly1305(cipher) => cipher.encrypt_in_place_detached(nonce.into(), aad, buffer)?,
+            RecordCipher::InsecureHmacSha256(mac) => {
+                let mut tag = [0; TAG_LEN];
+                tag.copy_from_slice(&record_mac(mac, nonce, aad, buffer).finalize().into_bytes()[..TAG_LEN]);
+                return Ok(tag);
+            }
+        };
+        Ok(tag.into())
+    }
+
+    fn decrypt(&self, nonce: &[u8; 12], payload: Payload) -> Result<Vec<u8>, aes_gcm::aead::Error> {
+        match self {
+            RecordCipher::Aes256Gcm(cipher) => cipher.decrypt(Nonce::from_slice(nonce), payload),
+            RecordCipher::ChaCha20Poly1305(cipher) => cipher.decrypt(nonce.into(), payload),
+            RecordCipher::InsecureHmacSha256(mac) => {
+                let (plaintext, tag) = payload.msg.split_at(payload.msg.len() - TAG_LEN);
+                record_mac(mac, nonce, payload.aad, plaintext)
+                    .verify_truncated_left(tag)
+                    .map_err(|_| aes_gcm::aead::Error)?;
+                Ok(plaintext.to_vec())
+            }
+        }
+    }
+}
+
+fn rekey_error(reason: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, reason)
+}
+
+/// Error for any use of a record layer after it rejected a record
+fn failed_error() -> std::io::Error {
+    std::io::Error::other("Record layer closed after a rejected record")
+}
+
+/// Strip the padding of an authenticated padded record, and the content type hidden
+/// before it
+fn unpad_record(outer: ContentType, plaintext: &mut Vec<u8>) -> std::io::Result<ContentType> {
+    let malformed = |reason| std::io::Error::new(std::io::ErrorKind::InvalidData, reason);
+    if outer != ContentType::ApplicationData {
+        return Err(malformed("Unpadded record"));
+    }
+    Padding::unpad(plaintext).map_err(malformed)?;
+    plaintext
+        .pop()
+        .and_then(ContentType::from_id)
+        .ok_or_else(|| malformed("Unknown record content type"))
+}
+
+/// HMAC over the nonce, header, and plaintext of a record, so the integrity-only suite
+/// binds records to their position and length just like the AEADs do
+fn record_mac(mac: &Hmac<Sha256>, nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Hmac<Sha256> {
+    let mut mac = mac.clone();
+    mac.update(nonce);
+    mac.update(aad);
+    mac.update(plaintext);
+    mac
+}
+
+/// One direction of the record layer: its own keyed cipher, an IV, and the sequence
+/// number of the next record
+struct Direction {
+    cipher: RecordCipher,
+    iv: [u8; 12],
+    sequence: u64,
+}
+
+impl Direction {
+    fn new(suite: CipherSuite, key: &[u8; 32], iv: [u8; 12]) -> Self {
+        Direction { cipher: RecordCipher::new(suite, key), iv, sequence: 0 }
+    }
+
+    /// Per-record nonce: the direction's IV XORed with the big-endian sequence number
+    /// (as in TLS 1.3), so no nonce repeats under the direction's key
+    fn next_nonce(&mut self) -> std::io::Result<[u8; 12]> {
+        let mut nonce = self.iv;
+        for (byte, seq) in nonce[4..].iter_mut().zip(self.sequence.to_be_bytes()) {
+            *byte ^= seq;
+        }
+        self.sequence = self.sequence.checked_add(1).ok_or_else(|| {
+            std::io::Error::other("Record sequence number exhausted")
+        })?;
+        Ok(nonce)
+    }
+}
+
+/// Receive direction of a rekey, installed once the peer's RekeyFinished verifies
+struct NextKeys {
+    keys: SessionKeys,
+    receive: Direction,
+    verify_data: [u8; 32],
+}
+
+/// Record layer for traffic after the handshake
+///
+/// Each record is sent as a `Record` whose payload is ciphertext || tag; its header,
+/// content type and length, is authenticated as associated data. A Close record
+/// carries the one-byte close reason, and an Alert record the peer's Alert message.
+///
+/// Sequence numbers are implicit: each direction counts its records and feeds the
+/// count into the nonce, so a replayed, dropped, or reordered record fails
+/// authentication. After any record is rejected the two sides no longer agree on the
+/// count, so the layer refuses every later record but an Alert (see `write_alert`),
+/// and the connection must be closed.
+///
+/// Rekeys travel in Rekey records. The side starting one sends Rekey with a
+/// fresh X25519 key; the peer answers with its own (if both start at once, each
+/// takes the other's as the answer). Each side then derives the next keys from the
+/// new shared secret and the current keys, sends RekeyFinished, and protects every
+/// later record with the new keys. It switches its receive direction when the peer's
+/// RekeyFinished verifies, so each direction changes keys at one exact record.
+pub struct RecordLayer {
+    suite: CipherSuite,
+    client: bool,
+    /// Keys of the current send and receive directions, from which the next ones are derived
+    keys: SessionKeys,
+    send: Direction,
+    receive: Direction,
+    /// Rekey thresholds, or None if the peer cannot rekey
+    rekey: Option<RekeyPolicy>,
+    /// Plaintext bytes sent and received since the current keys were installed
+    traffic: u64,
+    installed: Instant,
+    /// Our half of a rekey we started, until the peer's Rekey arrives
+    rekey_exchange: Option<X25519KeyExchange>,
+    /// Keys of a rekey whose sending side we already switched
+    next_keys: Option<NextKeys>,
+    rekeys: u64,
+    /// Reason from the peer's Close record, once one arrives
+    close_reason: Option<CloseReason>,
+    /// Set once a received record is rejected, after which no record is sent or read
+    failed: bool,
+    /// Ping schedule, or None to send no Pings (those of the peer are still answered)
+    keepalive: Option<KeepalivePolicy>,
+    /// When the last record from the peer arrived
+    last_heard: Instant,
+    /// When we last sent a Ping, and how many in a row went unanswered
+    last_ping: Option<Instant>,
+    unanswered_pings: u32,
+    /// Padding of the records we send, or None if records are unpadded
+    padding: Option<Padding>,
+}
+
+impl RecordLayer {
+    /// Record layer for the client side (sends with the client write key and IV)
+    pub fn client(keys: &SessionKeys, suite: CipherSuite) -> Self {
+        Self::new(keys, suite, true)
+    }
+
+    /// Record layer for the server side (sends with the server write key and IV)
+    pub fn server(keys: &SessionKeys, suite: CipherSuite) -> Self {
+        Self::new(keys, suite, false)
+    }
+
+    fn new(keys: &SessionKeys, suite: CipherSuite, client: bool) -> Self {
+        let (send, receive) = Self::directions(keys, suite, client);
+        RecordLayer {
+            suite,
+            client,
+            keys: keys.clone(),
+            send,
+            receive,
+            rekey: None,
+            traffic: 0,
+            installed: Instant::now(),
+            rekey_exchange: None,
+            next_keys: None,
+            rekeys: 0,
+            close_reason: None,
+            failed: false,
+            keepalive: None,
+            last_heard: Instant::now(),
+            last_ping: None,
+            unanswered_pings: 0,
+            padding: None,
+        }
+    }
+
+    /// Send and receive directions under `keys`
+    fn directions(keys: &SessionKeys, suite: CipherSuite, client: bool) -> (Direction, Direction) {
+        let client_direction = Direction::new(suite, &keys.client_write_key, keys.client_iv);
+        let server_direction = Direction::new(suite, &keys.server_write_key, keys.server_iv);
+        match client {
+            true => (client_direction, server_direction),
+            false => (server_direction, client_direction),
+        }
+    }
+
+    /// Allow rekeys, which the peer must also support, starting them at the policy's thresholds
+    pub fn with_rekeying(mut self, policy: RekeyPolicy) -> Self {
+        self.rekey = Some(policy);
+        self
+    }
+
+    /// Ping the peer when it goes quiet, and give up on it after too many unanswered
+    /// Pings (see `poll_keepalive`)
+    pub fn with_keepalive(mut self, policy: KeepalivePolicy) -> Self {
+        self.keepalive = Some(policy);
+        self
+    }
+
+    /// Pad every record, which the peer must do too; the content type then travels
+    /// inside the encryption, before the padding, and every header says application data
+    pub fn with_padding(mut self, padding: Padding) -> Self {
+        self.padding = Some(padding);
+        self
+    }
+
+    /// Send a Ping if the peer has been quiet for the keepalive interval
+    ///
+    /// Call this whenever the connection is idle, at least as often as
+    /// `next_keepalive` says.
+    ///
+    /// # Returns
+    /// A TimedOut error once `max_missed` Pings in a row went unanswered
+    pub fn poll_keepalive<W: Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        let Some(policy) = self.keepalive else {
+            return Ok(());
+        };
+        if self.next_keepalive() != Some(Duration::ZERO) {
+            return Ok(());
+        }
+        if self.unanswered_pings >= policy.max_missed {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "Peer stopped answering keepalives",
+            ));
+        }
+        self.write_frame(writer, &[], ContentType::Ping)?;
+        self.last_ping = Some(Instant::now());
+        self.unanswered_pings += 1;
+        Ok(())
+    }
+
+    /// How long until `poll_keepalive` has something to do, or None without keepalives
+    pub fn next_keepalive(&self) -> Option<Duration> {
+        let policy = self.keepalive?;
+        let quiet_since = self.last_ping.map_or(self.last_heard, |ping| ping.max(self.last_heard));
+        Some(policy.interval.saturating_sub(quiet_since.elapsed()))
+    }
+
+    /// Number of rekeys completed so far
+    pub fn rekeys(&self) -> u64 {
+        self.rekeys
+    }
+
+    /// Encrypt one record and write it to the stream
+    ///
+    /// # Arguments
+    /// * `writer` - Stream to write the record to
+    /// * `plaintext` - At most `MAX_RECORD_PLAINTEXT` bytes of application data
+    pub fn write_record<W: Write>(&mut self, writer: &mut W, plaintext: &[u8]) -> std::io::Result<()> {
+        self.write_record_vectored(writer, &[IoSlice::new(plaintext)])
+    }
+
+    /// Encrypt one record whose plaintext is the concatenation of `parts`
+    ///
+    /// The parts are gathered straight into the outgoing frame and encrypted there in
+    /// place, so callers that build headers and bodies separately need not join them first.
+    ///
+    /// # Arguments
+    /// * `writer` - Stream to write the record to
+    /// * `parts` - Buffers totalling at most `MAX_RECORD_PLAINTEXT` bytes
+    pub fn write_record_vectored<W: Write>(&mut self, writer: &mut W, parts: &[IoSlice]) -> std::io::Result<()> {
+        self.start_rekey_if_due(writer)?;
+        self.write_frame(writer, parts, ContentType::ApplicationData)?;
+        self.traffic += parts.iter().map(|part| part.len() as u64).sum::<u64>();
+        Ok(())
+    }
+
+    /// Tell the peer why the connection is closing; it sees the end of the stream
+    /// and can look the reason up with `close_reason`
+    pub fn write_close<W: Write>(&mut self, writer: &mut W, reason: CloseReason) -> std::io::Result<()> {
+        self.write_frame(writer, &[IoSlice::new(&[reason.id()])], ContentType::Close)
+    }
+
+    /// Tell the peer why we rejected its records before closing; unlike every other
+    /// record, this can still be sent after `read_record` failed
+    pub fn write_alert<W: Write>(&mut self, writer: &mut W, code: AlertCode, description: &str) -> std::io::Result<()> {
+        let message = Alert::message(code, description).to_bytes();
+        self.write_frame(writer, &[IoSlice::new(&message)], ContentType::Alert)
+    }
+
+    fn write_frame<W: Write>(&mut self, writer: &mut W, parts: &[IoSlice], content_type: ContentType) -> std::io::Result<()> {
+        // Our sending direction is still in step after a rejected record, so an Alert
+        // can get through
+        if self.failed && content_type != ContentType::Alert {
+            return Err(failed_error());
+        }
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+        if len > MAX_RECORD_PLAINTEXT {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Record plaintext too large",
+            ));
+        }
+
+        // [plaintext -> ciphertext][tag], gathered and encrypted in one buffer; padded
+        // records hide the content type after the plaintext
+        let mut payload = Vec::with_capacity(len + TAG_LEN);
+        for part in parts {
+            payload.extend_from_slice(part);
+        }
+        let content_type = match self.padding {
+            Some(padding) => {
+                payload.push(content_type.id());
+                padding.pad(&mut payload);
+                ContentType::ApplicationData
+            }
+            None => content_type,
+        };
+        let header = Record::header(content_type, (payload.len() + TAG_LEN) as u32);
+
+        let nonce = self.send.next_nonce()?;
+        let tag = self
+            .send
+            .cipher
+            .encrypt_in_place(&nonce, &header, &mut payload)
+            .map_err(|_| std::io::Error::other("Record encryption failed"))?;
+        payload.extend_from_slice(&tag);
+
+        Record::new(content_type, payload).write(writer)
+    }
+
+    /// Read one record from the stream and decrypt it
+    ///
+    /// Rekey messages are answered on the same stream.
+    ///
+    /// # Returns
+    /// The plaintext, None if the peer closed the connection between records or sent
+    /// a Close record, an Interrupted error if the record was a rekey or keepalive
+    /// message (call again for the next record), or an InvalidData error if the record is oversized,
+    /// fails authentication (including replays and gaps in the sequence), breaks the
+    /// rekey protocol, or is an Alert (carrying the `Alert`); every later call then
+    /// fails too
+    pub fn read_record<S: Read + Write>(&mut self, stream: &mut S) -> std::io::Result<Option<Vec<u8>>> {
+        if self.failed {
+            return Err(failed_error());
+        }
+        let result = self.read_frame(stream);
+        if result.as_ref().is_err_and(|e| e.kind() == std::io::ErrorKind::InvalidData) {
+            self.failed = true;
+        }
+        result
+    }
+
+    fn read_frame<S: Read + Write>(&mut self, stream: &mut S) -> std::io::Result<Option<Vec<u8>>> {
+        if self.close_reason.is_some() {
+            return Ok(None);
+        }
+        let max_padding = match self.padding {
+            Some(_) => 1 + MAX_PADDING + PADDING_TRAILER_LEN,
+            None => 0,
+        };
+        let Some(record) = Record::read(stream, MAX_RECORD_PLAINTEXT + max_padding + TAG_LEN)? else {
+            return Ok(None);
+        };
+        if record.payload.len() < TAG_LEN {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Record length out of range",
+            ));
+        }
+
+        let nonce = self.receive.next_nonce()?;
+        let header = Record::header(record.content_type, record.length);
+        let mut plaintext = self
+            .receive
+            .cipher
+            .decrypt(&nonce, Payload { msg: &record.payload, aad: &header })
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "Record authentication failed"))?;
+        let content_type = match self.padding {
+            Some(_) => unpad_record(record.content_type, &mut plaintext)?,
+            None => record.content_type,
+        };
+        // Any authentic record shows the peer is alive, answering our Pings
+        self.last_heard = Instant::now();
+        self.unanswered_pings = 0;
+        match content_type {
+            ContentType::ApplicationData => {
+                self.traffic += plaintext.len() as u64;
+                self.start_rekey_if_due(stream)?;
+                return Ok(Some(plaintext));
+            }
+            ContentType::Close => {
+                let [reason] = plaintext.as_slice() else {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Malformed close record"));
+                };
+                // Unknown reasons from newer peers still close the connection
+                self.close_reason = Some(CloseReason::from_id(*reason).unwrap_or(CloseReason::Shutdown));
+                return Ok(None);
+            }
+            ContentType::Alert => {
+                return Err(match DHMessage::from_bytes(&plaintext).as_ref().and_then(Alert::from_message) {
+                    Some(alert) => alert.into(),
+                    None => std::io::Error::new(std::io::ErrorKind::InvalidData, "Malformed alert record"),
+                });
+            }
+            ContentType::Ping => {
+                self.write_frame(stream, &[IoSlice::new(&plaintext)], ContentType::Pong)?;
+                return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "Ping answered"));
+            }
+            ContentType::Pong => {
+                return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "Pong received"));
+            }
+            ContentType::Rekey => {}
+        }
+        match DHMessage::from_bytes(&plaintext) {
+            Some(DHMessage::Rekey { public_key }) if self.rekey.is_some() && self.next_keys.is_none() => {
+                self.answer_rekey(stream, &public_key)?;
+            }
+            Some(DHMessage::RekeyFinished { verify_data }) if self.next_keys.is_some() => {
+                self.finish_rekey(&verify_data)?;
+            }
+            Some(DHMessage::Rekey { .. } | DHMessage::RekeyFinished { .. }) => {
+                return Err(rekey_error("Unexpected rekey message"));
+            }
+            _ => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Malformed rekey record")),
+        }
+        Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "Rekey message handled"))
+    }
+
+    /// Send Rekey if the current keys reached a threshold and no rekey is under way
+    fn start_rekey_if_due<W: Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        let Some(policy) = self.rekey else {
+            return Ok(());
+        };
+        if self.rekey_exchange.is_some() || self.next_keys.is_some() || !policy.due(self.traffic, self.installed) {
+            return Ok(());
+        }
+        let exchange = X25519KeyExchange::new();
+        self.write_control(writer, &DHMessage::Rekey { public_key: exchange.public_key() })?;
+        self.rekey_exchange = Some(exchange);
+        Ok(())
+    }
+
+    /// Handle the peer's Rekey: answer it unless it answers ours, then derive the next
+    /// keys, confirm them with RekeyFinished, and send under them from now on
+    fn answer_rekey<W: Write>(&mut self, writer: &mut W, peer_public_key: &[u8]) -> std::io::Result<()> {
+        let exchange = match self.rekey_exchange.take() {
+            Some(exchange) => exchange,
+            None => {
+                let exchange = X25519KeyExchange::new();
+                self.write_control(writer, &DHMessage::Rekey { public_key: exchange.public_key() })?;
+                exchange
+            }
+        };
+        let shared_secret = exchange.shared_secret(peer_public_key).map_err(rekey_error)?;
+
+        let ours = DHMessage::Rekey { public_key: exchange.public_key() };
+        let theirs = DHMessage::Rekey { public_key: peer_public_key.to_vec() };
+        let (client_rekey, server_rekey) = match self.client {
+            true => (ours, theirs),
+            false => (theirs, ours),
+        };
+        let mut transcript = Transcript::new();
+        transcript.record(&client_rekey);
+        transcript.record(&server_rekey);
+        let transcript_hash = transcript.hash();
+        let keys = self.keys.rekey(&shared_secret, &transcript);
+
+        let (send_label, receive_label) = match self.client {
+            true => (CLIENT_FINISHED_LABEL, SERVER_FINISHED_LABEL),
+            false => (SERVER_FINISHED_LABEL, CLIENT_FINISHED_LABEL),
+        };
+        self.write_control(writer, &DHMessage::RekeyFinished {
+            verify_data: keys.finished(send_label, &transcript_hash),
+        })?;
+
+        let (send, receive) = Self::directions(&keys, self.suite, self.client);
+        self.send = send;
+        self.next_keys = Some(NextKeys {
+            verify_data: keys.finished(receive_label, &transcript_hash),
+            keys,
+            receive,
+        });
+        Ok(())
+    }
+
+    /// Handle the peer's RekeyFinished: check it and receive under the next keys from now on
+    fn finish_rekey(&mut self, verify_data: &[u8; 32]) -> std::io::Result<()> {
+        let next = self.next_keys.take().expect("caller checked a rekey is under way");
+        if !ct_eq(&next.verify_data, verify_data) {
+            return Err(rekey_error("Rekey Finished does not verify"));
+        }
+        self.keys = next.keys;
+        self.receive = next.receive;
+        self.traffic = 0;
+        self.installed = Instant::now();
+        self.rekeys += 1;
+        Ok(())
+    }
+
+    fn write_control<W: Write>(&mut self, writer: &mut W, message: &DHMessage) -> std::io::Result<()> {
+        self.write_frame(writer, &[IoSlice::new(&message.to_bytes())], ContentType::Rekey)
+    }
+
+    /// Reason the peer gave for closing the connection, if it sent a Close record
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.close_reason
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::{Mutex, OnceLock};
+
+use rand::rngs::{OsRng, StdRng};
+use rand::{CryptoRng, RngCore, SeedableRng};
+
+/// A cryptographically secure random source that key and parameter generation can
+/// draw from
+///
+/// Implemented by every `RngCore + CryptoRng`. The `_with` generation functions take
+/// one, so tests can pass a seeded generator (e.g., `rand_chacha::ChaCha20Rng`) and
+/// deployments can insist on `OsRng`; the functions without it use `OsRng` for
+/// parameters and the per-purpose DRBGs below for secrets.
+pub trait SecureRandom: RngCore + CryptoRng {}
+
+impl<R: RngCore + CryptoRng + ?Sized> SecureRandom for R {}
+
+/// What random bytes are being drawn for; every purpose has its own DRBG instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RngPurpose {
+    /// Long-term or ephemeral secrets (DH exponents, curve scalars)
+    SecretKey,
+    /// Public randomness that goes on the wire (GREASE types and padding)
+    Nonce,
+}
+
+impl RngPurpose {
+    /// Every purpose known to this implementation
+    pub const ALL: &'static [RngPurpose] = &[RngPurpose::SecretKey, RngPurpose::Nonce];
+
+    /// Short lowercase name of the purpose (e.g., "secret_key")
+    pub fn name(&self) -> &'static str {
+        match self {
+            RngPurpose::SecretKey => "secret_key",
+            RngPurpose::Nonce => "nonce",
+        }
+    }
+
+    /// Draws between reseeds from the OS unless configured otherwise
+    fn default_reseed_interval(&self) -> u64 {
+        match self {
+            RngPurpose::SecretKey => 64,
+            RngPurpose::Nonce => 4096,
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            RngPurpose::SecretKey => 0,
+            RngPurpose::Nonce => 1,
+        }
+    }
+}
+
+/// Usage counters for one purpose's DRBG
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RngUsage {
+    /// Number of times the DRBG was handed out
+    pub draws: u64,
+    /// Number of times it was reseeded from the OS
+    pub reseeds: u64,
+    /// Draws allowed between reseeds
+    pub reseed_interval: u64,
+}
+
+/// A DRBG seeded from the OS, reseeded every `reseed_interval` draws
+struct PurposeRng {
+    rng: StdRng,
+    draws_since_reseed: u64,
+    usage: RngUsage,
+}
+
+impl PurposeRng {
+    fn new(purpose: RngPurpose) -> Self {
+        PurposeRng {
+            rng: StdRng::from_rng(OsRng).expect("OS random source unavailable"),
+            draws_since_reseed: 0,
+            usage: RngUsage {
+                reseed_interval: purpose.default_reseed_interval(),
+                ..RngUsage::default()
+            },
+        }
+    }
+
+    fn draw(&mut self) -> &mut StdRng {
+        if self.draws_since_reseed >= self.usage.reseed_interval {
+            self.rng = StdRng::from_rng(OsRng).expect("OS random source unavailable");
+            self.draws_since_reseed = 0;
+            self.usage.reseeds += 1;
+        }
+        self.draws_since_reseed += 1;
+        self.usage.draws += 1;
+        &mut self.rng
+    }
+}
+
+/// One independently seeded DRBG per purpose, created on first use
+fn instances() -> &'static [Mutex<PurposeRng>] {
+    static INSTANCES: OnceLock<Vec<Mutex<PurposeRng>>> = OnceLock::new();
+    INSTANCES.get_or_init(|| {
+        RngPurpose::ALL
+            .iter()
+            .map(|purpose| Mutex::new(PurposeRng::new(*purpose)))
+            .collect()
+    })
+}
+
+fn instance(purpose: RngPurpose) -> std::sync::MutexGuard<'static, PurposeRng> {
+    // A panic while drawing cannot leave the DRBG in a weaker state, so ignore poisoning
+    instances()[purpose.index()]
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Run `f` with the DRBG dedicated to `purpose`
+///
+/// Secrets and public nonces never come from the same generator, so a weakness
+/// exposed through values on the wire does not carry over to key material.
+pub fn with_rng<T>(purpose: RngPurpose, f: impl FnOnce(&mut StdRng) -> T) -> T {
+    f(instance(purpose).draw())
+}
+
+/// Set how many draws a purpose's DRBG serves before reseeding from the OS
+///
+/// # Arguments
+/// * `purpose` - The DRBG to configure
+/// * `draws` - Draws between reseeds; 1 reseeds on every draw
+pub fn set_reseed_interval(purpose: RngPurpose, draws: u64) {
+    instance(purpose).usage.reseed_interval = draws.max(1);
+}
+
+/// Usage counters for a purpose, for audits confirming the separation is in effect
+pub fn usage(purpose: RngPurpose) -> RngUsage {
+    instance(purpose).usage
+}
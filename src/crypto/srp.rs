@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+use hmac::{Hmac, Mac};
+use num_bigint::{BigInt, Sign};
+use num_traits::{Num, Zero};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::crypto::crypto::{compute_public_key, generate_secret_key, mod_pow_ct, validate_public_key, ExponentPolicy};
+use crate::crypto::groups::DhGroup;
+use crate::crypto::kex::{KexAlgorithm, KeyExchange};
+use crate::crypto::rng::{with_rng, RngPurpose};
+use crate::crypto::transcript::{from_hex, to_hex};
+
+/// Longest username an SRP key share may carry
+pub const MAX_SRP_USERNAME: usize = 255;
+
+/// Length of the salts `SrpVerifierStore::add` generates
+const SALT_LEN: usize = 16;
+
+/// Password verifier of one user: v = g^x mod N, with x = H(salt || H(username ":" password))
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrpVerifier {
+    /// Random per-user salt
+    pub salt: Vec<u8>,
+    /// The verifier v
+    pub verifier: BigInt,
+}
+
+/// SRP-6a verifiers of every user a server accepts, all over one group
+///
+/// The server never learns passwords, but a verifier still allows offline guessing
+/// of its password, so the file is kept as secret as a private key.
+pub struct SrpVerifierStore {
+    group: DhGroup,
+    users: HashMap<String, SrpVerifier>,
+    /// Derives stable fake verifiers for unknown users, so a handshake does not reveal
+    /// whether a username exists
+    fake_key: [u8; 32],
+}
+
+impl SrpVerifierStore {
+    /// Create an empty store for verifiers over `group`
+    pub fn new(group: DhGroup) -> Self {
+        let mut fake_key = [0; 32];
+        with_rng(RngPurpose::SecretKey, |rng| rng.fill_bytes(&mut fake_key));
+        SrpVerifierStore {
+            group,
+            users: HashMap::new(),
+            fake_key,
+        }
+    }
+
+    /// Load verifiers written by `store`
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let invalid = |problem: &str| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("SRP verifier file {} {}", path.display(), problem),
+            )
+        };
+        let contents = fs::read_to_string(path)?;
+        let group = contents
+            .lines()
+            .find_map(|line| {
+                let (key, value) = line.split_once('=')?;
+                (key.trim() == "group").then(|| DhGroup::from_name(value.trim()))?
+            })
+            .ok_or_else(|| invalid("names no known group"))?;
+
+        let mut store = SrpVerifierStore::new(group);
+        for line in contents.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if let ["user", username, salt, verifier] = fields[..] {
+                let salt = from_hex(salt).ok_or_else(|| invalid("holds a salt that is not hex"))?;
+                let verifier =
+                    BigInt::from_str_radix(verifier, 16).map_err(|_| invalid("holds a verifier that is not hex"))?;
+                store.users.insert(username.to_string(), SrpVerifier { salt, verifier });
+            }
+        }
+        Ok(store)
+    }
+
+    /// Write the verifiers atomically, readable only by their owner where supported
+    pub fn store(&self, path: &Path) -> std::io::Result<()> {
+        let mut contents = format!(
+            "# SRP-6a verifiers written by dhke; they allow offline password guessing, so keep this file secret\ngroup = {}\n",
+            self.group.name()
+        );
+        let mut usernames: Vec<&String> = self.users.keys().collect();
+        usernames.sort();
+        for username in usernames {
+            let entry = &self.users[username];
+            contents.push_str(&format!(
+                "user {} {} {}\n",
+                username,
+                to_hex(&entry.salt),
+                entry.verifier.to_str_radix(16)
+            ));
+        }
+
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        fs::write(&temp_path, contents)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&temp_path, fs::Permissions::from_mode(0o600))?;
+        }
+        fs::rename(&temp_path, path)
+    }
+
+    /// Add a user or change their password, under a fresh salt
+    ///
+    /// # Returns
+    /// An error if the username is empty, too long, or contains whitespace
+    pub fn add(&mut self, username: &str, password: &str) -> Result<(), &'static str> {
+        if username.is_empty() || username.len() > MAX_SRP_USERNAME || username.contains(char::is_whitespace) {
+            return Err("usernames must be 1 to 255 bytes without whitespace");
+        }
+        let mut salt = vec![0; SALT_LEN];
+        with_rng(RngPurpose::Nonce, |rng| rng.fill_bytes(&mut salt));
+        let (prime, generator) = self.group.params();
+        let x = private_key(&salt, username, password);
+        let verifier = compute_public_key(&x, &generator, &prime, ExponentPolicy::Full);
+        self.users.insert(username.to_string(), SrpVerifier { salt, verifier });
+        Ok(())
+    }
+
+    /// Group every verifier in the store belongs to
+    pub fn group(&self) -> DhGroup {
+        self.group
+    }
+
+    /// Number of users
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    /// Whether the store has no users
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+
+    /// The user's verifier, or a fake one that is the same on every lookup
+    fn lookup(&self, username: &str) -> SrpVerifier {
+        if let Some(entry) = self.users.get(username) {
+            return entry.clone();
+        }
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.fake_key).expect("HMAC accepts keys of any length");
+        mac.update(username.as_bytes());
+        let seed = mac.finalize().into_bytes();
+        let (prime, generator) = self.group.params();
+        SrpVerifier {
+            salt: seed[..SALT_LEN].to_vec(),
+            verifier: compute_public_key(&BigInt::from_bytes_be(Sign::Plus, &seed), &generator, &prime, ExponentPolicy::Full),
+        }
+    }
+}
+
+/// Client side of SRP-6a (RFC 5054 with SHA-256): proves knowledge of a password to a
+/// server that stores only a verifier, without exposing the password to offline
+/// guessing by an eavesdropper or a fake server
+///
+/// The client's share is [4-byte length][username][A]; the server's is [4-byte
+/// length][salt][B]. The Finished messages double as the SRP proofs M1 and M2: only
+/// a party that knows the password (or the verifier) derives the same keys.
+pub struct SrpClientExchange {
+    prime: BigInt,
+    generator: BigInt,
+    username: String,
+    password: String,
+    secret: BigInt,
+    public: BigInt,
+}
+
+impl SrpClientExchange {
+    /// Start an exchange over a well-known group, with an ephemeral exponent drawn
+    /// under `policy`
+    pub fn new(group: DhGroup, username: &str, password: &str, policy: ExponentPolicy) -> Self {
+        let (prime, generator) = group.params();
+        let secret = generate_secret_key(&prime, policy);
+        SrpClientExchange {
+            public: compute_public_key(&secret, &generator, &prime, policy),
+            prime,
+            generator,
+            username: username.to_string(),
+            password: password.to_string(),
+            secret,
+        }
+    }
+}
+
+impl KeyExchange for SrpClientExchange {
+    fn algorithm(&self) -> KexAlgorithm {
+        KexAlgorithm::Srp
+    }
+
+    fn public_key(&self) -> Vec<u8> {
+        join(self.username.as_bytes(), &pad(&self.public, &self.prime))
+    }
+
+    fn shared_secret(&self, peer_public_key: &[u8]) -> Result<BigInt, &'static str> {
+        let (salt, b) = split(peer_public_key).ok_or("SRP server share is malformed")?;
+        let b = BigInt::from_bytes_be(Sign::Plus, b);
+        if b >= self.prime || (&b % &self.prime).is_zero() {
+            return Err("SRP server public value is out of range");
+        }
+        let u = scrambler(&self.public, &b, &self.prime)?;
+        let k = multiplier(&self.prime, &self.generator);
+        let x = private_key(salt, &self.username, &self.password);
+
+        // S = (B - k * g^x) ^ (a + u * x) mod N
+        let masked = (k * mod_pow_ct(&self.generator, &x, &self.prime)) % &self.prime;
+        let base = ((b - masked) % &self.prime + &self.prime) % &self.prime;
+        Ok(mod_pow_ct(&base, &(&self.secret + u * x), &self.prime))
+    }
+}
+
+/// Server side of SRP-6a; see `SrpClientExchange`
+///
+/// B depends on the verifier of the user named in the client's share, so the
+/// server's share only exists once `shared_secret` has seen the client's.
+pub struct SrpServerExchange {
+    store: Arc<SrpVerifierStore>,
+    prime: BigInt,
+    generator: BigInt,
+    subgroup_order: BigInt,
+    secret: BigInt,
+    share: OnceLock<Vec<u8>>,
+}
+
+impl SrpServerExchange {
+    /// Start an exchange for any user in `store`, with an ephemeral exponent drawn
+    /// under `policy`
+    pub fn new(store: Arc<SrpVerifierStore>, policy: ExponentPolicy) -> Self {
+        let (prime, generator) = store.group().params();
+        SrpServerExchange {
+            subgroup_order: store.group().subgroup_order(),
+            secret: generate_secret_key(&prime, policy),
+            store,
+            prime,
+            generator,
+            share: OnceLock::new(),
+        }
+    }
+}
+
+impl KeyExchange for SrpServerExchange {
+    fn algorithm(&self) -> KexAlgorithm {
+        KexAlgorithm::Srp
+    }
+
+    /// Salt and B, or nothing before the client's share arrived
+    fn public_key(&self) -> Vec<u8> {
+        self.share.get().cloned().unwrap_or_default()
+    }
+
+    fn shared_secret(&self, peer_public_key: &[u8]) -> Result<BigInt, &'static str> {
+        let (username, a) = split(peer_public_key).ok_or("SRP client share is malformed")?;
+        let username = std::str::from_utf8(username).map_err(|_| "SRP username is not UTF-8")?;
+        if username.len() > MAX_SRP_USERNAME {
+            return Err("SRP username is too long");
+        }
+        let a = BigInt::from_bytes_be(Sign::Plus, a);
+        validate_public_key(&a, &self.prime, &self.subgroup_order)?;
+
+        // B = k * v + g^b mod N
+        let entry = self.store.lookup(username);
+        let k = multiplier(&self.prime, &self.generator);
+        let b = (k * &entry.verifier + mod_pow_ct(&self.generator, &self.secret, &self.prime)) % &self.prime;
+        self.share
+            .set(join(&entry.salt, &pad(&b, &self.prime)))
+            .map_err(|_| "SRP exchange already answered a client")?;
+
+        // S = (A * v^u) ^ b mod N
+        let u = scrambler(&a, &b, &self.prime)?;
+        let base = (a * mod_pow_ct(&entry.verifier, &u, &self.prime)) % &self.prime;
+        Ok(mod_pow_ct(&base, &self.secret, &self.prime))
+    }
+}
+
+/// x = H(salt || H(username ":" password))
+fn private_key(salt: &[u8], username: &str, password: &str) -> BigInt {
+    let inner = Sha256::new()
+        .chain_update(username.as_bytes())
+        .chain_update(b":")
+        .chain_update(password.as_bytes())
+        .finalize();
+    hash_to_int(&[salt, &inner])
+}
+
+/// k = H(N || PAD(g))
+fn multiplier(prime: &BigInt, generator: &BigInt) -> BigInt {
+    hash_to_int(&[&prime.to_bytes_be().1, &pad(generator, prime)])
+}
+
+/// u = H(PAD(A) || PAD(B)), which must not be zero
+fn scrambler(a: &BigInt, b: &BigInt, prime: &BigInt) -> Result<BigInt, &'static str> {
+    let u = hash_to_int(&[&pad(a, prime), &pad(b, prime)]);
+    if u.is_zero() {
+        return Err("SRP scrambling parameter is zero");
+    }
+    Ok(u)
+}
+
+fn hash_to_int(parts: &[&[u8]]) -> BigInt {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    BigInt::from_bytes_be(Sign::Plus, &hasher.finalize())
+}
+
+/// Big-endian bytes of `value`, left-padded with zeros to the length of the prime
+fn pad(value: &BigInt, prime: &BigInt) -> Vec<u8> {
+    let bytes = value.to_bytes_be().1;
+    let len = prime.to_bytes_be().1.len().max(bytes.len());
+    let mut padded = vec![0; len - bytes.len()];
+    padded.extend(bytes);
+    padded
+}
+
+/// [4-byte length][first][rest]
+fn join(first: &[u8], rest: &[u8]) -> Vec<u8> {
+    let mut share = (first.len() as u32).to_be_bytes().to_vec();
+    share.extend(first);
+    share.extend(rest);
+    share
+}
+
+/// The first field of a share (username or salt) and the value after it
+pub(crate) fn split(share: &[u8]) -> Option<(&[u8], &[u8])> {
+    let len = u32::from_be_bytes(share.get(..4)?.try_into().ok()?) as usize;
+    let first = share.get(4..4 + len)?;
+    Some((first, &share[4 + len..]))
+}
@@ -0,0 +1,100 @@
+use std::fs;
+use std::path::Path;
+
+use num_bigint::BigInt;
+use num_traits::Num;
+
+use crate::crypto::crypto::{generate_secret_key, ExponentPolicy};
+use crate::crypto::fingerprint::Fingerprint;
+use crate::crypto::pem;
+use crate::crypto::pkcs3::DhPrivateKey;
+use crate::crypto::transcript::to_hex;
+
+/// Load a static DH exponent, generating and storing one if the file does not exist
+///
+/// The file records which prime the exponent belongs to. A key stored for other
+/// parameters is an error rather than silently replaced, since clients have pinned
+/// the public value derived from it. The file may also be a PEM PKCS#8 DH key (e.g.,
+/// from `openssl genpkey -paramfile`), whose parameters must have the same prime.
+/// New keys are full-length whatever exponent policy is used for ephemeral keys,
+/// and are used as such.
+///
+/// # Arguments
+/// * `path` - Key file written by a previous run
+/// * `p` - Prime modulus the key belongs to
+///
+/// # Returns
+/// The secret exponent x, with 2 <= x <= p - 2
+pub fn load_or_generate(path: &Path, p: &BigInt) -> std::io::Result<BigInt> {
+    if !path.exists() {
+        let secret = generate_secret_key(p, ExponentPolicy::Full);
+        store(path, p, &secret)?;
+        return Ok(secret);
+    }
+
+    let contents = fs::read_to_string(path)?;
+    let secret = if pem::is_pem(contents.as_bytes()) {
+        let key = DhPrivateKey::from_pem(&contents).map_err(|reason| invalid(path, reason))?;
+        if &key.params.p != p {
+            return Err(invalid(path, "was generated for different DH parameters"));
+        }
+        key.x
+    } else {
+        if read_field(&contents, "p")? != prime_id(p) {
+            return Err(invalid(path, "was generated for different DH parameters"));
+        }
+        BigInt::from_str_radix(&read_field(&contents, "x")?, 16)
+            .map_err(|_| invalid(path, "does not hold a hex exponent"))?
+    };
+    if secret < BigInt::from(2) || secret > p - BigInt::from(2) {
+        return Err(invalid(path, "holds an exponent outside [2, p - 2]"));
+    }
+    Ok(secret)
+}
+
+/// Write the static exponent atomically, readable only by its owner where supported
+pub fn store(path: &Path, p: &BigInt, secret: &BigInt) -> std::io::Result<()> {
+    let contents = format!(
+        "# Static Diffie-Hellman key generated by dhke; keep this file secret\np = {}\nx = {}\n",
+        prime_id(p),
+        secret.to_str_radix(16)
+    );
+
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    fs::write(&temp_path, contents)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&temp_path, fs::Permissions::from_mode(0o600))?;
+    }
+    fs::rename(&temp_path, path)
+}
+
+/// Short identifier of a prime: the hex SHA-256 of its big-endian bytes
+fn prime_id(p: &BigInt) -> String {
+    to_hex(Fingerprint::of(&p.to_bytes_be().1).as_bytes())
+}
+
+/// Value of a `name = value` line
+fn read_field(contents: &str, name: &str) -> std::io::Result<String> {
+    contents
+        .lines()
+        .find_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            (key.trim() == name).then(|| value.trim().to_string())
+        })
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Static key file has no {} field", name),
+            )
+        })
+}
+
+fn invalid(path: &Path, problem: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("Static key file {} {}", path.display(), problem),
+    )
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use num_bigint::{BigInt, Sign};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+use crate::crypto::kdf::{Kdf, SessionKeys};
+use crate::crypto::record::CipherSuite;
+use crate::crypto::rng::{with_rng, RngPurpose};
+
+/// How long tickets stay valid unless configured otherwise
+pub const DEFAULT_TICKET_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Label of the secret a session's tickets resume from, exported from its keys
+const RESUMPTION_LABEL: &[u8] = b"dhke resumption secret";
+
+/// Associated data of every sealed ticket, so tickets cannot be confused with other
+/// ciphertexts under the same key
+const TICKET_AAD: &[u8] = b"dhke session ticket";
+
+/// Version of the ticket plaintext layout
+const TICKET_FORMAT: u8 = 2;
+
+/// What a session ticket lets a client resume: the secret the resumed keys are
+/// derived from, and the cipher, KDF and client identity of the session that issued it
+///
+/// The secret is private and redacted from `Debug`: whoever learns it can derive the
+/// keys of every session resumed from the ticket.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Resumption {
+    /// 256-bit secret both sides export from the issuing session's keys
+    secret: [u8; 32],
+    /// Record-layer cipher of the issuing session
+    pub cipher: CipherSuite,
+    /// KDF of the issuing session, which the resumed session keeps using
+    pub kdf: Kdf,
+    /// Client identity the issuing session authenticated, which the resumed session
+    /// keeps for quotas; only the server knows it, so clients keep None
+    pub identity: Option<String>,
+}
+
+impl Resumption {
+    /// The resumption state of a completed session
+    pub fn of(keys: &SessionKeys, cipher: CipherSuite) -> Self {
+        let mut secret = [0; 32];
+        keys.export(RESUMPTION_LABEL, &mut secret);
+        Resumption { secret, cipher, kdf: keys.kdf, identity: None }
+    }
+
+    /// The same state, for a session that authenticated its client as `identity`
+    pub fn with_identity(mut self, identity: Option<String>) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// The secret as the shared secret a resumed handshake derives its keys from;
+    /// never log it
+    pub(crate) fn shared_secret(&self) -> BigInt {
+        BigInt::from_bytes_be(Sign::Plus, &self.secret)
+    }
+
+    /// Rebuild the state a client stored with `secret`, e.g. in a persistent ticket store
+    pub(crate) fn from_secret(secret: [u8; 32], cipher: CipherSuite, kdf: Kdf) -> Self {
+        Resumption { secret, cipher, kdf, identity: None }
+    }
+
+    /// The secret itself, for a client storing the state; never log it
+    pub(crate) fn secret(&self) -> &[u8; 32] {
+        &self.secret
+    }
+}
+
+impl fmt::Debug for Resumption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Resumption")
+            .field("secret", &"<redacted>")
+            .field("cipher", &self.cipher)
+            .field("kdf", &self.kdf)
+            .field("identity", &self.identity)
+            .finish()
+    }
+}
+
+/// Server key sealing session tickets (AES-256-GCM), so the server keeps no state
+/// per ticket
+///
+/// Tickets from one key only open under the same key: restarting the server with a
+/// fresh key invalidates every ticket issued before.
+#[derive(Clone)]
+pub struct TicketKey {
+    cipher: Aes256Gcm,
+    lifetime: Duration,
+}
+
+impl TicketKey {
+    /// Generate a random ticket key whose tickets stay valid for `lifetime`
+    pub fn generate(lifetime: Duration) -> Self {
+        let key: [u8; 32] = with_rng(RngPurpose::SecretKey, |rng| rng.r#gen());
+        TicketKey {
+            cipher: Aes256Gcm::new(&key.into()),
+            lifetime,
+        }
+    }
+
+    /// How long a ticket stays valid after it is issued
+    pub fn lifetime(&self) -> Duration {
+        self.lifetime
+    }
+
+    /// Seal a session's resumption state into a ticket for its client
+    ///
+    /// Layout: [12-byte nonce][AES-256-GCM of [format][secret][cipher][kdf][issued:u64]
+    /// [identity length:u16][identity]], where an empty identity means none
+    pub fn seal(&self, resumption: &Resumption) -> Vec<u8> {
+        let identity = resumption.identity.as_deref().unwrap_or_default().as_bytes();
+        let mut plaintext = vec![TICKET_FORMAT];
+        plaintext.extend(resumption.secret);
+        plaintext.extend([resumption.cipher.id(), kdf_id(resumption.kdf)]);
+        plaintext.extend(unix_time().to_be_bytes());
+        let identity_len = u16::try_from(identity.len()).expect("identities are an SRP username or a key fingerprint");
+        plaintext.extend(identity_len.to_be_bytes());
+        plaintext.extend(identity);
+        let nonce: [u8; 12] = with_rng(RngPurpose::Nonce, |rng| rng.r#gen());
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: &plaintext, aad: TICKET_AAD })
+            .expect("AES-GCM encrypts tickets");
+        [nonce.to_vec(), ciphertext].concat()
+    }
+
+    /// Open a ticket a client presented
+    ///
+    /// # Returns
+    /// The resumption state, or None if the ticket was not sealed under this key, was
+    /// tampered with, or has expired
+    pub fn open(&self, ticket: &[u8]) -> Option<Resumption> {
+        if ticket.len() < 12 {
+            return None;
+        }
+        let (nonce, ciphertext) = ticket.split_at(12);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: TICKET_AAD })
+            .ok()?;
+        let [TICKET_FORMAT, rest @ ..] = plaintext.as_slice() else {
+            return None;
+        };
+        let (secret, rest) = rest.split_first_chunk::<32>()?;
+        let [cipher, kdf, rest @ ..] = rest else {
+            return None;
+        };
+        let (issued, rest) = rest.split_first_chunk::<8>()?;
+        let issued = u64::from_be_bytes(*issued);
+        let (identity_len, identity) = rest.split_first_chunk::<2>()?;
+        if identity.len() != u16::from_be_bytes(*identity_len) as usize {
+            return None;
+        }
+        let identity = String::from_utf8(identity.to_vec()).ok()?;
+        if unix_time().saturating_sub(issued) > self.lifetime.as_secs() {
+            return None;
+        }
+        Some(Resumption {
+            secret: *secret,
+            cipher: CipherSuite::from_id(*cipher)?,
+            kdf: Kdf::ALL.iter().copied().find(|candidate| kdf_id(*candidate) == *kdf)?,
+            identity: (!identity.is_empty()).then_some(identity),
+        })
+    }
+}
+
+/// Identifier under which a server remembers that a ticket was redeemed (its SHA-256)
+pub(crate) fn ticket_id(ticket: &[u8]) -> [u8; 32] {
+    Sha256::digest(ticket).into()
+}
+
+/// Identifier of a KDF inside tickets, which only this server reads
+fn kdf_id(kdf: Kdf) -> u8 {
+    match kdf {
+        Kdf::Hkdf => 0,
+        Kdf::OneStep => 1,
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
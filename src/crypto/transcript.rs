@@ -0,0 +1,68 @@
+use hmac::{Hmac, Mac};
+use num_bigint::BigInt;
+use sha2::{Digest, Sha256};
+
+use crate::structs::DH_Prot::DHMessage;
+
+/// Label mixed into the channel binding value (mirrors RFC 9266 tls-exporter)
+const CHANNEL_BINDING_LABEL: &[u8] = b"EXPORTER-Channel-Binding";
+
+/// Running SHA-256 hash over every handshake message sent and received
+#[derive(Debug, Clone, Default)]
+pub struct Transcript {
+    hasher: Sha256,
+}
+
+impl Transcript {
+    /// Create an empty transcript
+    pub fn new() -> Self {
+        Transcript {
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Append a handshake message to the transcript using its wire encoding
+    pub fn record(&mut self, message: &DHMessage) {
+        self.hasher.update(message.to_bytes());
+    }
+
+    /// Hash of all messages recorded so far
+    pub fn hash(&self) -> [u8; 32] {
+        self.hasher.clone().finalize().into()
+    }
+}
+
+/// Derives a channel binding value for this DH session
+///
+/// # Arguments
+/// * `shared_secret` - The DH shared secret of the session
+/// * `transcript_hash` - Hash of the completed handshake transcript
+///
+/// # Returns
+/// HMAC-SHA256(shared_secret, label || transcript_hash). Both peers obtain the same
+/// value, and it differs for every session, so an external authentication protocol
+/// (e.g. SCRAM) that signs it cannot be replayed over another DH session.
+pub fn channel_binding(shared_secret: &BigInt, transcript_hash: &[u8; 32]) -> [u8; 32] {
+    let (_, secret_bytes) = shared_secret.to_bytes_be();
+    let mut mac = Hmac::<Sha256>::new_from_slice(&secret_bytes)
+        .expect("HMAC accepts keys of any length");
+    mac.update(CHANNEL_BINDING_LABEL);
+    mac.update(transcript_hash);
+    mac.finalize().into_bytes().into()
+}
+
+/// Format bytes as lowercase hex for logging
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parse hex (either case) into bytes, or None if it is not valid hex
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
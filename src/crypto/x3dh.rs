@@ -0,0 +1,466 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::crypto::fingerprint::Fingerprint;
+use crate::crypto::rng::{with_rng, RngPurpose};
+use crate::crypto::transcript::{from_hex, to_hex};
+
+/// Most one-time prekeys a single publication may carry
+pub const MAX_ONE_TIME_PREKEYS: usize = 100;
+
+/// Prefix of the signature over a signed prekey
+const SIGNED_PREKEY_CONTEXT: &[u8] = b"dhke x3dh signed prekey\0";
+
+/// Prefix of the signature over a whole prekey publication
+const PUBLICATION_CONTEXT: &[u8] = b"dhke x3dh prekey publication\0";
+
+/// Long-term identity of a messaging party (X3DH, as in the Signal protocol)
+///
+/// The X25519 key takes part in the key agreement; the Ed25519 key signs the party's
+/// prekeys so the directory cannot substitute its own.
+pub struct X3dhIdentity {
+    dh: StaticSecret,
+    signing: SigningKey,
+}
+
+impl X3dhIdentity {
+    /// Generate a fresh identity
+    pub fn generate() -> Self {
+        X3dhIdentity {
+            dh: with_rng(RngPurpose::SecretKey, |rng| StaticSecret::random_from_rng(rng)),
+            signing: with_rng(RngPurpose::SecretKey, SigningKey::generate),
+        }
+    }
+
+    /// Load the identity from `path`, generating and storing a new one if the file does not exist
+    pub fn load_or_generate(path: &Path) -> std::io::Result<Self> {
+        if !path.exists() {
+            let identity = Self::generate();
+            identity.store(path)?;
+            return Ok(identity);
+        }
+
+        let contents = fs::read_to_string(path)?;
+        let field = |name: &str| {
+            contents
+                .lines()
+                .find_map(|line| {
+                    let (key, value) = line.split_once('=')?;
+                    (key.trim() == name).then(|| from_hex(value.trim()))?
+                })
+                .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("X3DH identity file {} has no 32-byte hex {} key", path.display(), name),
+                    )
+                })
+        };
+        Ok(X3dhIdentity {
+            dh: StaticSecret::from(field("dh")?),
+            signing: SigningKey::from_bytes(&field("signing")?),
+        })
+    }
+
+    /// Write the identity atomically, readable only by its owner where supported
+    pub fn store(&self, path: &Path) -> std::io::Result<()> {
+        let contents = format!(
+            "# X3DH identity generated by dhke; keep this file secret\ndh = {}\nsigning = {}\n",
+            to_hex(self.dh.as_bytes()),
+            to_hex(self.signing.as_bytes())
+        );
+
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        fs::write(&temp_path, contents)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&temp_path, fs::Permissions::from_mode(0o600))?;
+        }
+        fs::rename(&temp_path, path)
+    }
+
+    /// Public half of the identity, which peers use to address and recognize us
+    pub fn public(&self) -> X3dhPublicIdentity {
+        X3dhPublicIdentity {
+            dh: PublicKey::from(&self.dh).to_bytes(),
+            signing: self.signing.verifying_key().to_bytes(),
+        }
+    }
+}
+
+/// Public identity keys of a messaging party
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct X3dhPublicIdentity {
+    /// X25519 identity key
+    pub dh: [u8; 32],
+    /// Ed25519 key that signs the party's prekeys
+    pub signing: [u8; 32],
+}
+
+impl X3dhPublicIdentity {
+    /// Fingerprint of both keys; the prekey directory indexes parties by it
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint::of(&self.to_bytes())
+    }
+
+    fn to_bytes(self) -> [u8; 64] {
+        let mut bytes = [0; 64];
+        bytes[..32].copy_from_slice(&self.dh);
+        bytes[32..].copy_from_slice(&self.signing);
+        bytes
+    }
+
+    fn verify(&self, context: &[u8], message: &[u8], signature: &[u8; 64]) -> Result<(), &'static str> {
+        let key = VerifyingKey::from_bytes(&self.signing).map_err(|_| "invalid Ed25519 identity key")?;
+        key.verify_strict(&[context, message].concat(), &Signature::from_bytes(signature))
+            .map_err(|_| "prekey signature does not verify")
+    }
+}
+
+/// What a party uploads to the prekey directory before going offline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrekeyPublication {
+    /// Who the prekeys belong to
+    pub identity: X3dhPublicIdentity,
+    /// Increases with every publication, so the directory can refuse replays of old ones
+    pub version: u64,
+    /// Medium-term X25519 prekey
+    pub signed_prekey: [u8; 32],
+    /// Identity signature over the signed prekey
+    pub prekey_signature: [u8; 64],
+    /// Single-use X25519 prekeys and their IDs; the directory hands out each at most once
+    pub one_time_prekeys: Vec<(u32, [u8; 32])>,
+    /// Identity signature over all of the above
+    pub signature: [u8; 64],
+}
+
+impl PrekeyPublication {
+    /// Check both signatures and the one-time prekey limit
+    pub fn verify(&self) -> Result<(), &'static str> {
+        if self.one_time_prekeys.len() > MAX_ONE_TIME_PREKEYS {
+            return Err("too many one-time prekeys");
+        }
+        self.identity.verify(SIGNED_PREKEY_CONTEXT, &self.signed_prekey, &self.prekey_signature)?;
+        self.identity.verify(PUBLICATION_CONTEXT, &self.signed_content(), &self.signature)
+    }
+
+    /// The bundle an initiator receives, with at most one of the one-time prekeys
+    pub fn bundle(&self, one_time_prekey: Option<(u32, [u8; 32])>) -> PrekeyBundle {
+        PrekeyBundle {
+            identity: self.identity,
+            signed_prekey: self.signed_prekey,
+            prekey_signature: self.prekey_signature,
+            one_time_prekey,
+        }
+    }
+
+    /// Wire encoding: the signed content followed by the 64-byte signature
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.signed_content();
+        bytes.extend(self.signature);
+        bytes
+    }
+
+    /// Parse a publication written by `to_bytes`; signatures are not checked here
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader(bytes);
+        let identity = reader.identity()?;
+        let version = u64::from_be_bytes(reader.array()?);
+        let signed_prekey = reader.array()?;
+        let prekey_signature = reader.array()?;
+        let count = u16::from_be_bytes(reader.array()?) as usize;
+        if count > MAX_ONE_TIME_PREKEYS {
+            return None;
+        }
+        let mut one_time_prekeys = Vec::with_capacity(count);
+        for _ in 0..count {
+            one_time_prekeys.push((u32::from_be_bytes(reader.array()?), reader.array()?));
+        }
+        let signature = reader.array()?;
+        reader.finish()?;
+        Some(PrekeyPublication { identity, version, signed_prekey, prekey_signature, one_time_prekeys, signature })
+    }
+
+    fn signed_content(&self) -> Vec<u8> {
+        let mut bytes = self.identity.to_bytes().to_vec();
+        bytes.extend(self.version.to_be_bytes());
+        bytes.extend(self.signed_prekey);
+        bytes.extend(self.prekey_signature);
+        bytes.extend((self.one_time_prekeys.len() as u16).to_be_bytes());
+        for (id, key) in &self.one_time_prekeys {
+            bytes.extend(id.to_be_bytes());
+            bytes.extend(key);
+        }
+        bytes
+    }
+}
+
+/// Prekeys of one party as handed to an initiator by the directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrekeyBundle {
+    /// Who the prekeys belong to
+    pub identity: X3dhPublicIdentity,
+    /// Medium-term X25519 prekey
+    pub signed_prekey: [u8; 32],
+    /// Identity signature over the signed prekey
+    pub prekey_signature: [u8; 64],
+    /// A one-time prekey, unless the directory ran out
+    pub one_time_prekey: Option<(u32, [u8; 32])>,
+}
+
+impl PrekeyBundle {
+    /// Wire encoding: identity, signed prekey and signature, then a 1-byte flag and
+    /// the one-time prekey if present
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.identity.to_bytes().to_vec();
+        bytes.extend(self.signed_prekey);
+        bytes.extend(self.prekey_signature);
+        match self.one_time_prekey {
+            Some((id, key)) => {
+                bytes.push(1);
+                bytes.extend(id.to_be_bytes());
+                bytes.extend(key);
+            }
+            None => bytes.push(0),
+        }
+        bytes
+    }
+
+    /// Parse a bundle written by `to_bytes`; the signature is checked by `initiate`
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader(bytes);
+        let identity = reader.identity()?;
+        let signed_prekey = reader.array()?;
+        let prekey_signature = reader.array()?;
+        let one_time_prekey = match reader.array::<1>()? {
+            [0] => None,
+            [1] => Some((u32::from_be_bytes(reader.array()?), reader.array()?)),
+            _ => return None,
+        };
+        reader.finish()?;
+        Some(PrekeyBundle { identity, signed_prekey, prekey_signature, one_time_prekey })
+    }
+}
+
+/// First message of an X3DH session, which the initiator delivers to the other
+/// party along with (or before) anything encrypted under the session secret
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct X3dhInitialMessage {
+    /// The initiator's identity
+    pub identity: X3dhPublicIdentity,
+    /// The initiator's ephemeral X25519 key
+    pub ephemeral: [u8; 32],
+    /// Which one-time prekey the initiator used, if the bundle had one
+    pub one_time_prekey_id: Option<u32>,
+}
+
+impl X3dhInitialMessage {
+    /// Wire encoding: identity, ephemeral key, then a 1-byte flag and the prekey ID if present
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.identity.to_bytes().to_vec();
+        bytes.extend(self.ephemeral);
+        match self.one_time_prekey_id {
+            Some(id) => {
+                bytes.push(1);
+                bytes.extend(id.to_be_bytes());
+            }
+            None => bytes.push(0),
+        }
+        bytes
+    }
+
+    /// Parse a message written by `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader(bytes);
+        let identity = reader.identity()?;
+        let ephemeral = reader.array()?;
+        let one_time_prekey_id = match reader.array::<1>()? {
+            [0] => None,
+            [1] => Some(u32::from_be_bytes(reader.array()?)),
+            _ => return None,
+        };
+        reader.finish()?;
+        Some(X3dhInitialMessage { identity, ephemeral, one_time_prekey_id })
+    }
+}
+
+/// Result of an X3DH key agreement
+pub struct X3dhSession {
+    /// 256-bit secret shared by both parties
+    pub secret: [u8; 32],
+    /// Initiator identity followed by responder identity; authenticate it alongside
+    /// the first encrypted message (e.g., as AEAD associated data)
+    pub associated_data: Vec<u8>,
+    /// The other party's identity
+    pub peer: X3dhPublicIdentity,
+}
+
+/// Private half of a party's published prekeys, kept until peers have used them
+pub struct PrekeyOwner {
+    identity: X3dhIdentity,
+    version: u64,
+    signed_prekey: StaticSecret,
+    one_time_prekeys: HashMap<u32, StaticSecret>,
+}
+
+impl PrekeyOwner {
+    /// Generate a signed prekey and `one_time_count` one-time prekeys
+    ///
+    /// # Arguments
+    /// * `identity` - The owner's long-term identity
+    /// * `version` - Must exceed the version of any earlier publication (e.g., a Unix time)
+    /// * `one_time_count` - Number of one-time prekeys, at most `MAX_ONE_TIME_PREKEYS`
+    pub fn new(identity: X3dhIdentity, version: u64, one_time_count: usize) -> Self {
+        let random_secret = || with_rng(RngPurpose::SecretKey, |rng| StaticSecret::random_from_rng(rng));
+        PrekeyOwner {
+            identity,
+            version,
+            signed_prekey: random_secret(),
+            one_time_prekeys: (0..one_time_count.min(MAX_ONE_TIME_PREKEYS) as u32)
+                .map(|id| (id, random_secret()))
+                .collect(),
+        }
+    }
+
+    /// The signed publication to upload to the directory
+    pub fn publication(&self) -> PrekeyPublication {
+        let signed_prekey = PublicKey::from(&self.signed_prekey).to_bytes();
+        let mut one_time_prekeys: Vec<(u32, [u8; 32])> = self
+            .one_time_prekeys
+            .iter()
+            .map(|(id, secret)| (*id, PublicKey::from(secret).to_bytes()))
+            .collect();
+        one_time_prekeys.sort_by_key(|(id, _)| *id);
+
+        let mut publication = PrekeyPublication {
+            identity: self.identity.public(),
+            version: self.version,
+            signed_prekey,
+            prekey_signature: self.sign(SIGNED_PREKEY_CONTEXT, &signed_prekey),
+            one_time_prekeys,
+            signature: [0; 64],
+        };
+        publication.signature = self.sign(PUBLICATION_CONTEXT, &publication.signed_content());
+        publication
+    }
+
+    /// Complete a session an initiator started while we were offline
+    ///
+    /// The one-time prekey it used is deleted, so a replayed initial message cannot
+    /// produce the same session again.
+    pub fn respond(&mut self, message: &X3dhInitialMessage) -> Result<X3dhSession, &'static str> {
+        let one_time_secret = match message.one_time_prekey_id {
+            Some(id) => Some(self.one_time_prekeys.remove(&id).ok_or("one-time prekey is unknown or already used")?),
+            None => None,
+        };
+        let ephemeral = PublicKey::from(message.ephemeral);
+
+        // The mirror image of the initiator's DH1..DH4
+        let mut outputs = vec![
+            dh(&self.signed_prekey, &PublicKey::from(message.identity.dh))?,
+            dh(&self.identity.dh, &ephemeral)?,
+            dh(&self.signed_prekey, &ephemeral)?,
+        ];
+        if let Some(secret) = &one_time_secret {
+            outputs.push(dh(secret, &ephemeral)?);
+        }
+        Ok(session(&outputs, &message.identity, &self.identity.public(), message.identity))
+    }
+
+    fn sign(&self, context: &[u8], message: &[u8]) -> [u8; 64] {
+        self.identity.signing.sign(&[context, message].concat()).to_bytes()
+    }
+}
+
+/// Start a session with a party that may be offline, from its prekey bundle
+///
+/// # Arguments
+/// * `identity` - Our long-term identity
+/// * `bundle` - The peer's bundle from the directory; check `bundle.identity`
+///   against the identity you expect before trusting the session
+///
+/// # Returns
+/// The session and the initial message to deliver to the peer, or an error if the
+/// bundle's signature or keys are invalid
+pub fn initiate(
+    identity: &X3dhIdentity,
+    bundle: &PrekeyBundle,
+) -> Result<(X3dhSession, X3dhInitialMessage), &'static str> {
+    bundle.identity.verify(SIGNED_PREKEY_CONTEXT, &bundle.signed_prekey, &bundle.prekey_signature)?;
+    let ephemeral = with_rng(RngPurpose::SecretKey, |rng| StaticSecret::random_from_rng(rng));
+    let signed_prekey = PublicKey::from(bundle.signed_prekey);
+
+    // DH1 = DH(IK_A, SPK_B), DH2 = DH(EK_A, IK_B), DH3 = DH(EK_A, SPK_B), DH4 = DH(EK_A, OPK_B)
+    let mut outputs = vec![
+        dh(&identity.dh, &signed_prekey)?,
+        dh(&ephemeral, &PublicKey::from(bundle.identity.dh))?,
+        dh(&ephemeral, &signed_prekey)?,
+    ];
+    if let Some((_, one_time_prekey)) = bundle.one_time_prekey {
+        outputs.push(dh(&ephemeral, &PublicKey::from(one_time_prekey))?);
+    }
+
+    let message = X3dhInitialMessage {
+        identity: identity.public(),
+        ephemeral: PublicKey::from(&ephemeral).to_bytes(),
+        one_time_prekey_id: bundle.one_time_prekey.map(|(id, _)| id),
+    };
+    Ok((session(&outputs, &identity.public(), &bundle.identity, bundle.identity), message))
+}
+
+/// X25519 that rejects low-order points, whose output does not depend on our secret
+fn dh(secret: &StaticSecret, public: &PublicKey) -> Result<[u8; 32], &'static str> {
+    let shared = secret.diffie_hellman(public);
+    if !shared.was_contributory() {
+        return Err("X3DH key is a low-order point");
+    }
+    Ok(shared.to_bytes())
+}
+
+/// SK = HKDF(0xFF * 32 || DH1 || DH2 || DH3 [|| DH4]), as in the X3DH specification
+fn session(
+    outputs: &[[u8; 32]],
+    initiator: &X3dhPublicIdentity,
+    responder: &X3dhPublicIdentity,
+    peer: X3dhPublicIdentity,
+) -> X3dhSession {
+    let mut input = vec![0xFF; 32];
+    for output in outputs {
+        input.extend(output);
+    }
+    let hkdf = Hkdf::<Sha256>::new(Some(&[0; 32]), &input);
+    let mut secret = [0; 32];
+    hkdf.expand(b"dhke x3dh", &mut secret)
+        .expect("output length is far below the HKDF-SHA256 limit");
+
+    let mut associated_data = initiator.to_bytes().to_vec();
+    associated_data.extend(responder.to_bytes());
+    X3dhSession { secret, associated_data, peer }
+}
+
+/// Reads fixed-size fields off the front of a byte string
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (field, rest) = self.0.split_first_chunk::<N>()?;
+        self.0 = rest;
+        Some(*field)
+    }
+
+    fn identity(&mut self) -> Option<X3dhPublicIdentity> {
+        Some(X3dhPublicIdentity { dh: self.array()?, signing: self.array()? })
+    }
+
+    fn finish(&self) -> Option<()> {
+        self.0.is_empty().then_some(())
+    }
+}
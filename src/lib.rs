@@ -0,0 +1,4 @@
+pub mod crypto;
+pub mod network;
+pub mod protocol;
+pub mod structs;
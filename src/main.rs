@@ -1,58 +1,483 @@
-mod crypto;
-mod network;
-mod structs;
-
 use std::env;
-use network::server::DHServer;
-use network::client::DHClient;
+use num_bigint::BigInt;
+use num_traits::Num;
+use rust_dhke::crypto::audit::audit_params;
+use rust_dhke::crypto::cookie::{CookieKey, PuzzlePolicy, DEFAULT_COOKIE_LIFETIME};
+use rust_dhke::crypto::crypto::{generate_dh_params, Blinding, ExponentPolicy, PrimalityConfig};
+use rust_dhke::crypto::fingerprint::Fingerprint;
+use rust_dhke::crypto::groups::DhGroup;
+use rust_dhke::crypto::identity::{ServerIdentity, ServerKey};
+use rust_dhke::crypto::kdf::Kdf;
+use rust_dhke::crypto::kex::KexAlgorithm;
+use rust_dhke::crypto::params_policy::ParamsPolicy;
+use rust_dhke::crypto::pkcs3::DhParams;
+use rust_dhke::crypto::record::{CipherSuite, CloseReason, KeepalivePolicy, Padding, RekeyPolicy};
+use rust_dhke::crypto::srp::SrpVerifierStore;
+use rust_dhke::crypto::ticket::{TicketKey, DEFAULT_TICKET_LIFETIME};
+use rust_dhke::crypto::transcript::{from_hex, to_hex};
+use rust_dhke::network::server::{DHServer, KeyMode, ParamSource};
+use rust_dhke::network::tickets::TicketStore;
+use rust_dhke::network::bench::{run_benchmarks, BenchReport};
+use rust_dhke::network::capabilities::CapabilityCache;
+use rust_dhke::network::client::{DHClient, HandshakeProfile};
+use rust_dhke::network::conformance::ConformanceSuite;
+use rust_dhke::network::framing::{Codec, MessageLimits};
+use rust_dhke::network::lifecycle::LifecyclePolicy;
+use rust_dhke::network::middleware::RetryPolicy;
+use rust_dhke::network::prekeys::PrekeyDirectory;
+use rust_dhke::network::quotas::IdentityQuota;
+use rust_dhke::network::probe::probe_server;
 
 fn main() -> std::io::Result<()> {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() > 1 && args[1] == "client" {
-        // Run as client
-        let server_addr = if args.len() > 2 {
-            &args[2]
-        } else {
-            "127.0.0.1:8080"
+    if args.len() > 1 && (args[1] == "client" || args[1] == "connect") {
+        // Run as client; connect exchanges keys, reports on the session and closes it
+        let server_addr = match args.get(2) {
+            Some(addr) if !addr.starts_with("--") => addr.as_str(),
+            _ => "127.0.0.1:8080",
         };
+        let grease = args.iter().any(|arg| arg == "--grease");
 
         println!("=== Diffie-Hellman Key Exchange Client ===\n");
-        let mut client = DHClient::new(server_addr)?;
-        let shared_secret = client.perform_key_exchange()?;
+        let mut policy = RetryPolicy::default();
+        if let Some(attempts) = flag_value(&args, "--retries").and_then(|n| n.parse().ok()) {
+            policy.max_attempts = attempts;
+        }
+        let capability_cache = match flag_value(&args, "--capability-cache") {
+            Some(path) => Some(CapabilityCache::persistent(std::path::Path::new(path))?),
+            None => None,
+        };
+        let session_cache = match flag_value(&args, "--session-cache") {
+            Some(path) => Some(TicketStore::persistent(std::path::Path::new(path))?),
+            None => None,
+        };
+        let proposed_params = match flag_value(&args, "--propose-params") {
+            Some(path) => Some(load_params(path)?),
+            None => None,
+        };
+        let srp_credentials = match flag_value(&args, "--srp-user") {
+            Some(username) => Some((username, read_password(username)?)),
+            None => None,
+        };
+
+        'connect: loop {
+            // Reconnect from scratch on every attempt; a failed handshake leaves the stream unusable
+            let mut client = policy.run(|_| {
+                let mut client = DHClient::new(server_addr)?;
+                if let Some(profile) = profile(&args) {
+                    client = client.with_profile(profile);
+                }
+                client = client.with_grease(grease);
+                if let Some(algorithms) = kex_algorithms(&args) {
+                    client = client.with_kex_algorithms(&algorithms);
+                }
+                if let Some(ciphers) = ciphers(&args) {
+                    client = client.with_ciphers(&ciphers);
+                }
+                if let Some(groups) = groups(&args) {
+                    client = client.with_groups(&groups);
+                }
+                if let Some(params) = &proposed_params {
+                    client = client.with_proposed_params(params.p.clone(), params.g.clone());
+                }
+                if let Some(key) = server_key(&args)? {
+                    client = client.with_server_key(key);
+                }
+                if let Some(fingerprint) = pinned_fingerprint(&args) {
+                    client = client.with_pinned_fingerprint(fingerprint);
+                }
+                if let Some(path) = flag_value(&args, "--static-key") {
+                    client = client.with_static_key(std::path::PathBuf::from(path));
+                }
+                if let Some((username, password)) = &srp_credentials {
+                    client = client.with_srp_credentials(username, password);
+                }
+                if args.iter().any(|arg| arg == "--obfuscate-keys") {
+                    client = client.with_key_obfuscation(true);
+                }
+                client = client.with_identity_protection(args.iter().any(|arg| arg == "--protect-identity"));
+                client = client.with_prime_certificate(args.iter().any(|arg| arg == "--require-prime-proof"));
+                if let Some(blinding) = blinding(&args) {
+                    client = client.with_blinding(blinding);
+                }
+                client = client.with_exponent_policy(exponent_policy(&args));
+                client = client.with_rekey_policy(rekey_policy(&args));
+                if let Some(policy) = keepalive(&args) {
+                    client = client.with_keepalive(policy);
+                }
+                if let Some(padding) = padding(&args) {
+                    client = client.with_padding(padding);
+                }
+                if let Some(limits) = message_limits(&args) {
+                    client = client.with_message_limits(limits);
+                }
+                if let Some(kdf) = kdf(&args) {
+                    client = client.with_kdf(kdf);
+                }
+                client = client.with_fixed_width_keys(args.iter().any(|arg| arg == "--fixed-width-keys"));
+                if let Some(codec) = codec(&args) {
+                    client = client.with_codec(codec);
+                }
+                if args.iter().any(|arg| arg == "--json-wire") {
+                    client = client.with_codec(Codec::Json);
+                }
+                if let Some(cache) = &capability_cache {
+                    client = client.with_capability_cache(cache.clone());
+                }
+                if let Some(store) = &session_cache {
+                    client = client.with_ticket_store(store.clone());
+                }
+                client.perform_key_exchange()?;
+                Ok(client)
+            })?;
+
+            if args.iter().any(|arg| arg == "--diagnose") {
+                println!("\n[CLIENT] Probing path to {}", client.server_addr());
+                client.probe(10)?.print();
+            }
 
-        println!("\n[CLIENT] Connection established with shared secret");
-        println!("[CLIENT] You can now send messages to the server");
+            println!("\n[CLIENT] Connection established with shared secret");
+            if let Some(path) = flag_value(&args, "--save-params") {
+                save_params(&client, path)?;
+            }
+            if args.iter().any(|arg| arg == "--show-fingerprint") {
+                show_fingerprint(&client);
+            }
+            if args[1] == "connect" {
+                client.close()?;
+                break 'connect;
+            }
+            println!("[CLIENT] You can now send messages to the server");
         
-        // Keep connection alive for communication
-        let mut buffer = [0; 1024];
-        loop {
-            match client.receive_message(&mut buffer) {
-                Ok(0) => {
-                    println!("[CLIENT] Server closed connection");
-                    break;
+            // Keep connection alive for communication
+            let mut buffer = [0; 1024];
+            loop {
+                match client.receive_message(&mut buffer) {
+                    Ok(0) => match client.close_reason() {
+                        Some(CloseReason::Drained) => {
+                            println!("[CLIENT] Server drained the connection, reconnecting");
+                            continue 'connect;
+                        }
+                        Some(reason) => {
+                            println!("[CLIENT] Server closed connection ({})", reason.name());
+                            break 'connect;
+                        }
+                        None => {
+                            println!("[CLIENT] Server closed connection");
+                            break 'connect;
+                        }
+                    },
+                    Ok(n) => {
+                        println!("[CLIENT] Received: {:?}", String::from_utf8_lossy(&buffer[..n]));
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        std::thread::sleep(std::time::Duration::from_millis(100));
+                    }
+                    Err(e) => {
+                        eprintln!("[CLIENT] Connection error: {}", e);
+                        break 'connect;
+                    }
                 }
-                Ok(n) => {
-                    println!("[CLIENT] Received: {:?}", String::from_utf8_lossy(&buffer[..n]));
+            }
+        }
+
+        Ok(())
+    } else if args.len() > 1 && args[1] == "probe" {
+        // Report a server's parameters and capabilities without exchanging keys
+        let target = match args.get(2) {
+            Some(addr) if !addr.starts_with("--") => addr.as_str(),
+            _ => "127.0.0.1:8080",
+        };
+
+        println!("=== Diffie-Hellman Server Probe ===\n");
+        probe_server(target)?.print();
+        Ok(())
+    } else if args.len() > 1 && args[1] == "audit" {
+        // Check (p, g) for weaknesses without connecting anywhere
+        let (p, g) = match flag_value(&args, "--group") {
+            Some(name) => match DhGroup::from_name(name) {
+                Some(group) => group.params(),
+                None => {
+                    eprintln!("Unknown group {}", name);
+                    std::process::exit(1);
                 }
-                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    std::thread::sleep(std::time::Duration::from_millis(100));
+            },
+            None => match (hex_flag(&args, "--p"), hex_flag(&args, "--g"), flag_value(&args, "--params")) {
+                (Some(p), Some(g), _) => (p, g),
+                (_, _, Some(path)) => {
+                    let params = load_params(path)?;
+                    (params.p, params.g)
                 }
-                Err(e) => {
-                    eprintln!("[CLIENT] Connection error: {}", e);
-                    break;
+                _ => {
+                    eprintln!("Usage: dhke audit [--group name | --p hex --g hex | --params file|-]");
+                    std::process::exit(1);
                 }
+            },
+        };
+
+        println!("=== Diffie-Hellman Parameter Audit ===\n");
+        let audit = audit_params(&p, &g);
+        audit.print();
+        if !audit.passed() {
+            std::process::exit(1);
+        }
+        Ok(())
+    } else if args.len() > 1 && args[1] == "export-params" {
+        // Write parameters as PKCS#3 (DER, or PEM for a .pem file) for OpenSSL and other implementations
+        let Some(path) = args.get(2).filter(|arg| !arg.starts_with("--")) else {
+            eprintln!("Usage: dhke export-params <file> [--group name | --bits n]");
+            std::process::exit(1);
+        };
+        let (p, g) = match flag_value(&args, "--group") {
+            Some(name) => DhGroup::from_name(name)
+                .unwrap_or_else(|| {
+                    eprintln!("Unknown group {}", name);
+                    std::process::exit(1);
+                })
+                .params(),
+            None => {
+                let bits = flag_value(&args, "--bits").and_then(|n| n.parse().ok()).unwrap_or(2048);
+                let (p, g, _) = generate_dh_params(&PrimalityConfig::new(bits));
+                (p, g)
             }
+        };
+        let params = DhParams::new(p, g);
+        if path.ends_with(".pem") {
+            params.store_pem(std::path::Path::new(path))?;
+        } else {
+            params.store(std::path::Path::new(path))?;
         }
+        println!("Wrote PKCS#3 DH parameters to {}", path);
+        Ok(())
+    } else if args.len() > 1 && args[1] == "srp-verifier" {
+        // Add a user to an SRP verifier file, creating it if needed
+        let (Some(path), Some(username)) = (args.get(2), args.get(3)) else {
+            eprintln!("Usage: dhke srp-verifier <file> <username> [--group name]");
+            std::process::exit(1);
+        };
+        let path = std::path::Path::new(path);
+        let mut verifiers = if path.exists() {
+            SrpVerifierStore::load(path)?
+        } else {
+            let group = match flag_value(&args, "--group") {
+                Some(name) => DhGroup::from_name(name).unwrap_or_else(|| {
+                    eprintln!("Unknown group {}", name);
+                    std::process::exit(1);
+                }),
+                None => DhGroup::Ffdhe2048,
+            };
+            SrpVerifierStore::new(group)
+        };
+        let password = read_password(username)?;
+        if let Err(reason) = verifiers.add(username, &password) {
+            eprintln!("Cannot add {}: {}", username, reason);
+            std::process::exit(1);
+        }
+        verifiers.store(path)?;
+        println!("Stored SRP verifier for {} in {}", username, path.display());
+        Ok(())
+    } else if args.len() > 2 && args[1] == "bench" && args[2] == "compare" {
+        // Compare two stored reports, failing on regressions past the threshold
+        let (Some(old), Some(new)) = (args.get(3), args.get(4)) else {
+            eprintln!("Usage: dhke bench compare <old.json> <new.json> [--fail-if-slower percent]");
+            std::process::exit(1);
+        };
+        let threshold = flag_value(&args, "--fail-if-slower").map(|value| {
+            value.trim_end_matches('%').parse::<f64>().unwrap_or_else(|_| {
+                eprintln!("Invalid threshold {}", value);
+                std::process::exit(1);
+            })
+        });
+        let old = BenchReport::load(std::path::Path::new(old))?;
+        let new = BenchReport::load(std::path::Path::new(new))?;
 
+        let mut regressions = 0;
+        for comparison in old.compare(&new) {
+            let slowdown = comparison.slowdown_percent();
+            let regressed = threshold.is_some_and(|threshold| slowdown > threshold);
+            println!(
+                "  {:<24} {:>12?} -> {:>12?}  {:+.1}%{}",
+                comparison.name,
+                std::time::Duration::from_nanos(comparison.old_ns),
+                std::time::Duration::from_nanos(comparison.new_ns),
+                slowdown,
+                if regressed { "  REGRESSION" } else { "" }
+            );
+            regressions += usize::from(regressed);
+        }
+        if regressions > 0 {
+            eprintln!("{} benchmark(s) slower than the baseline by more than {}%", regressions, threshold.unwrap_or_default());
+            std::process::exit(1);
+        }
+        Ok(())
+    } else if args.len() > 1 && args[1] == "bench" {
+        // Time mod_pow, prime generation and handshakes, optionally storing the results
+        let iterations = flag_value(&args, "--iterations").and_then(|n| n.parse().ok()).unwrap_or(10);
+
+        println!("=== Diffie-Hellman Benchmarks ===\n");
+        let report = run_benchmarks(iterations)?;
+        println!();
+        report.print();
+        if let Some(path) = flag_value(&args, "--json") {
+            report.store(std::path::Path::new(path))?;
+            println!("Wrote benchmark results to {}", path);
+        }
+        Ok(())
+    } else if args.len() > 1 && args[1] == "session-cache" {
+        // List the session tickets a client stored with --session-cache, or forget them
+        let Some(path) = args.get(2).filter(|arg| !arg.starts_with("--")) else {
+            eprintln!("Usage: dhke session-cache <file> [--clear]");
+            std::process::exit(1);
+        };
+        let store = TicketStore::persistent(std::path::Path::new(path))?;
+        if args.iter().any(|arg| arg == "--clear") {
+            store.clear();
+            println!("Cleared session tickets in {}", path);
+            return Ok(());
+        }
+        let entries = store.entries();
+        println!("{} session ticket(s) in {}", entries.len(), path);
+        for (endpoint, ticket) in entries {
+            println!("  {}", endpoint);
+            println!("    expires in:  {}s", ticket.expires.saturating_duration_since(std::time::Instant::now()).as_secs());
+            println!(
+                "    session:     {} with {}",
+                ticket.capabilities.kex_algorithm.name(),
+                ticket.capabilities.cipher.name()
+            );
+            if let Some(fingerprint) = ticket.params_fingerprint {
+                println!("    params:      {}", to_hex(&fingerprint));
+            }
+            println!("    server key:  {}", ticket.server_fingerprint);
+        }
+        Ok(())
+    } else if args.len() > 1 && args[1] == "conformance" {
+        // Run the protocol conformance suite against a server
+        let target = flag_value(&args, "--target").unwrap_or("127.0.0.1:8080");
+        let idle_timeout = flag_value(&args, "--idle-timeout")
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(35);
+
+        println!("=== Diffie-Hellman Protocol Conformance ===\n");
+        let suite = ConformanceSuite::new(target, std::time::Duration::from_secs(idle_timeout));
+        let report = suite.run();
+        println!();
+        report.print();
+
+        if !report.all_passed() {
+            std::process::exit(1);
+        }
         Ok(())
     } else {
         // Run as server
         println!("=== Diffie-Hellman Key Exchange Server ===\n");
-        println!("Usage: cargo run [client [server_addr]]\n");
+        println!("Usage: dhke [--group name | --params file|- | --seed text | --provable] [--param-cache file] [--static-key file] [--short-exponents] [--blinding exponent|base] [--kdf hkdf|sp800-56a] [--rekey-bytes n] [--rekey-after secs] [--keepalive secs [--keepalive-missed n]] [--padding none|random:max|block:size] [--max-group-bits n] [--max-connections n] [--max-age secs] [--max-sessions-per-identity n] [--max-handshakes-per-hour n] [--identity file | --rsa-identity pem] [--prekey-directory] [--tickets] [--replay-window secs] [--cookies] [--puzzle bits[:max_bits]] [--client-params named|min_bits] [--srp-verifiers file] [--grease] [--json-wire] [--kex ffdh,x25519,hmqv,srp] [--cipher aes256gcm,chacha20poly1305]");
+        println!("       dhke client [server_addr] [--grease] [--kex x25519,ffdh] [--cipher chacha20poly1305] [--group ffdhe2048,ffdhe3072] [--propose-params file] [--profile compact] [--server-key hex | --server-rsa-key pem] [--pin-fingerprint hex] [--static-key file] [--short-exponents] [--blinding exponent|base] [--kdf hkdf|sp800-56a] [--fixed-width-keys] [--codec bincode|cbor|protobuf | --json-wire] [--rekey-bytes n] [--rekey-after secs] [--keepalive secs [--keepalive-missed n]] [--padding none|random:max|block:size] [--max-group-bits n] [--srp-user name] [--protect-identity] [--require-prime-proof] [--obfuscate-keys] [--capability-cache file] [--session-cache file] [--retries n] [--diagnose] [--save-params file] [--show-fingerprint]");
+        println!("       dhke connect [server_addr] [--save-params file] [--show-fingerprint] [client options]");
+        println!("       dhke probe [server_addr]");
+        println!("       dhke audit [--group name | --p hex --g hex | --params file|-]");
+        println!("       dhke export-params <file> [--group name | --bits n]");
+        println!("       dhke srp-verifier <file> <username> [--group name]");
+        println!("       dhke session-cache <file> [--clear]");
+        println!("       dhke conformance [--target addr] [--idle-timeout secs]");
+        println!("       dhke bench [--iterations n] [--json file]");
+        println!("       dhke bench compare <old.json> <new.json> [--fail-if-slower percent]\n");
         
-        // Create server on localhost:8080 with 512-bit primes (fast for testing, use 2048+ for production)
-        let server = DHServer::new("127.0.0.1:8080", 512)?;
+        // Use a predefined group if requested, otherwise generate 512-bit primes
+        // (fast for testing, use 2048+ for production)
+        let params = match flag_value(&args, "--group") {
+            Some(name) => match DhGroup::from_name(name) {
+                Some(group) => ParamSource::Group(group),
+                None => {
+                    eprintln!("Unknown group {}", name);
+                    std::process::exit(1);
+                }
+            },
+            None => match (flag_value(&args, "--params"), flag_value(&args, "--seed")) {
+                (Some("-"), _) => ParamSource::Params(load_params("-")?),
+                (Some(path), _) => ParamSource::File(std::path::PathBuf::from(path)),
+                (None, Some(seed)) => ParamSource::Seeded(512, seed.as_bytes().to_vec()),
+                (None, None) if args.iter().any(|arg| arg == "--provable") => ParamSource::Provable(512),
+                (None, None) => ParamSource::Generate(512),
+            },
+        };
+
+        // Create server on localhost:8080
+        let grease = args.iter().any(|arg| arg == "--grease");
+        let param_cache = flag_value(&args, "--param-cache").map(std::path::Path::new);
+        let key_mode = match flag_value(&args, "--static-key") {
+            Some(path) => KeyMode::Static(std::path::PathBuf::from(path)),
+            None => KeyMode::Ephemeral,
+        };
+        let mut server = DHServer::new("127.0.0.1:8080", params, param_cache, key_mode)?
+            .with_grease(grease)
+            .with_json_wire(args.iter().any(|arg| arg == "--json-wire"));
+        if let Some(algorithms) = kex_algorithms(&args) {
+            server = server.with_kex_algorithms(&algorithms);
+        }
+        if let Some(ciphers) = ciphers(&args) {
+            server = server.with_ciphers(&ciphers);
+        }
+        if let Some(blinding) = blinding(&args) {
+            server = server.with_blinding(blinding);
+        }
+        server = server.with_exponent_policy(exponent_policy(&args));
+        server = server.with_rekey_policy(rekey_policy(&args));
+        if let Some(policy) = keepalive(&args) {
+            server = server.with_keepalive(policy);
+        }
+        if let Some(padding) = padding(&args) {
+            server = server.with_padding(padding);
+        }
+        if let Some(limits) = message_limits(&args) {
+            server = server.with_message_limits(limits);
+        }
+        if let Some(kdf) = kdf(&args) {
+            server = server.with_kdfs(&[kdf]);
+        }
+        server = server.with_lifecycle_policy(LifecyclePolicy {
+            max_connections: flag_value(&args, "--max-connections").and_then(|n| n.parse().ok()),
+            max_age: flag_value(&args, "--max-age")
+                .and_then(|secs| secs.parse().ok())
+                .map(std::time::Duration::from_secs),
+            ..LifecyclePolicy::default()
+        });
+        if let Some(quota) = identity_quota(&args) {
+            server = server.with_identity_quota(quota);
+        }
+        if args.iter().any(|arg| arg == "--prekey-directory") {
+            server = server.with_prekey_directory(PrekeyDirectory::new());
+        }
+        if let Some(window) = flag_value(&args, "--replay-window").and_then(|secs| secs.parse().ok()) {
+            server = server.with_replay_window(std::time::Duration::from_secs(window));
+        }
+        if let Some(policy) = client_params_policy(&args) {
+            server = server.with_client_params(policy);
+        }
+        if args.iter().any(|arg| arg == "--cookies") {
+            server = server.with_cookie_key(CookieKey::generate(DEFAULT_COOKIE_LIFETIME));
+        }
+        if let Some(policy) = puzzle_policy(&args) {
+            server = server.with_client_puzzle(policy);
+        }
+        if args.iter().any(|arg| arg == "--tickets") {
+            server = server.with_ticket_key(TicketKey::generate(DEFAULT_TICKET_LIFETIME));
+        }
+        if let Some(path) = flag_value(&args, "--srp-verifiers") {
+            server = server.with_srp_verifiers(SrpVerifierStore::load(std::path::Path::new(path))?);
+        }
+        if let Some(path) = flag_value(&args, "--identity") {
+            server = server.with_identity(ServerIdentity::load_or_generate(std::path::Path::new(path))?);
+        }
+        if let Some(path) = flag_value(&args, "--rsa-identity") {
+            #[cfg(feature = "rsa")]
+            {
+                server = server.with_identity(ServerIdentity::load_rsa_pem(std::path::Path::new(path))?);
+            }
+            #[cfg(not(feature = "rsa"))]
+            require_rsa(path);
+        }
         
         // Run the server (blocks indefinitely, handling incoming connections)
         server.run()?;
@@ -61,3 +486,288 @@ fn main() -> std::io::Result<()> {
     }
 }
 
+
+/// Look up the value following a `--flag` argument
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|index| args.get(index + 1))
+        .map(String::as_str)
+}
+
+/// Parse a hexadecimal big integer flag such as `--p`
+fn hex_flag(args: &[String], flag: &str) -> Option<BigInt> {
+    let text = flag_value(args, flag)?;
+    let value = BigInt::from_str_radix(text.trim_start_matches("0x"), 16).unwrap_or_else(|_| {
+        eprintln!("{} must be hexadecimal", flag);
+        std::process::exit(1);
+    });
+    Some(value)
+}
+
+/// Load PKCS#3 parameters named by `--params`, where `-` reads them (e.g., piped
+/// from `openssl dhparam`) from stdin
+fn load_params(path: &str) -> std::io::Result<DhParams> {
+    match path {
+        "-" => DhParams::read_from(std::io::stdin()),
+        path => DhParams::load(std::path::Path::new(path)),
+    }
+}
+
+/// Write the group the server used for this session as PKCS#3 (DER, or PEM for a
+/// .pem file), ready for `--propose-params`, `--params` or `dhke audit --params`
+fn save_params(client: &DHClient, path: &str) -> std::io::Result<()> {
+    let Some((p, g)) = client.group_params() else {
+        return Err(std::io::Error::other("Server did not use a finite-field group; there are no parameters to save"));
+    };
+    let params = DhParams::new(p.clone(), g.clone());
+    if path.ends_with(".pem") {
+        params.store_pem(std::path::Path::new(path))?;
+    } else {
+        params.store(std::path::Path::new(path))?;
+    }
+    println!("[CLIENT] Saved the server's {}-bit DH parameters to {}", p.bits(), path);
+    println!("[CLIENT] Parameter fingerprint: {}", Fingerprint::of(&params.to_der()));
+    Ok(())
+}
+
+/// Print the fingerprint of the server's key for this session, in the form
+/// `--pin-fingerprint` accepts
+fn show_fingerprint(client: &DHClient) {
+    match client.server_fingerprint() {
+        Some(fingerprint) => println!("[CLIENT] Server key fingerprint: {}", fingerprint),
+        None => println!("[CLIENT] Server key fingerprint: unavailable"),
+    }
+}
+
+/// Parse a comma-separated `--kex` list of key-exchange algorithm names
+fn kex_algorithms(args: &[String]) -> Option<Vec<KexAlgorithm>> {
+    let names = flag_value(args, "--kex")?;
+    let algorithms = names
+        .split(',')
+        .map(|name| {
+            KexAlgorithm::from_name(name).unwrap_or_else(|| {
+                eprintln!("Unknown key exchange {}", name);
+                std::process::exit(1);
+            })
+        })
+        .collect();
+    Some(algorithms)
+}
+
+/// Parse the server key to pin: `--server-key` with a hex Ed25519 public key, or
+/// `--server-rsa-key` with an RSA public key PEM file
+fn server_key(args: &[String]) -> std::io::Result<Option<ServerKey>> {
+    if let Some(path) = flag_value(args, "--server-rsa-key") {
+        #[cfg(feature = "rsa")]
+        return ServerKey::load_rsa_pem(std::path::Path::new(path)).map(Some);
+        #[cfg(not(feature = "rsa"))]
+        require_rsa(path);
+    }
+    let Some(hex) = flag_value(args, "--server-key") else {
+        return Ok(None);
+    };
+    let key = from_hex(hex).and_then(|bytes| bytes.try_into().ok()).unwrap_or_else(|| {
+        eprintln!("Server key must be 32 bytes of hex");
+        std::process::exit(1);
+    });
+    Ok(Some(ServerKey::Ed25519(key)))
+}
+
+/// Parse the `--pin-fingerprint` server key fingerprint
+fn pinned_fingerprint(args: &[String]) -> Option<Fingerprint> {
+    let text = flag_value(args, "--pin-fingerprint")?;
+    let fingerprint = Fingerprint::from_hex(text).unwrap_or_else(|| {
+        eprintln!("Fingerprint must be 32 bytes of hex");
+        std::process::exit(1);
+    });
+    Some(fingerprint)
+}
+
+/// Refuse RSA key options in builds without RSA, rather than silently running unauthenticated
+#[cfg(not(feature = "rsa"))]
+fn require_rsa(path: &str) -> ! {
+    eprintln!("Cannot use RSA key {}: dhke was built without the rsa feature", path);
+    std::process::exit(1);
+}
+
+/// Parse the `--profile` handshake profile name
+fn profile(args: &[String]) -> Option<HandshakeProfile> {
+    let name = flag_value(args, "--profile")?;
+    let profile = HandshakeProfile::from_name(name).unwrap_or_else(|| {
+        eprintln!("Unknown handshake profile {}", name);
+        std::process::exit(1);
+    });
+    Some(profile)
+}
+
+/// Parse the `--blinding` mode (none, exponent or base)
+fn blinding(args: &[String]) -> Option<Blinding> {
+    let name = flag_value(args, "--blinding")?;
+    let blinding = Blinding::from_name(name).unwrap_or_else(|| {
+        eprintln!("Unknown blinding mode {}", name);
+        std::process::exit(1);
+    });
+    Some(blinding)
+}
+
+/// Parse the `--kdf` key derivation function (hkdf or sp800-56a)
+fn kdf(args: &[String]) -> Option<Kdf> {
+    let name = flag_value(args, "--kdf")?;
+    let kdf = Kdf::from_name(name).unwrap_or_else(|| {
+        eprintln!("Unknown key derivation function {}", name);
+        std::process::exit(1);
+    });
+    Some(kdf)
+}
+
+/// Parse the `--codec` message encoding (native, bincode, cbor, json, or protobuf
+/// when built with the `protobuf` feature)
+fn codec(args: &[String]) -> Option<Codec> {
+    let name = flag_value(args, "--codec")?;
+    let codec = Codec::from_name(name).unwrap_or_else(|| {
+        eprintln!("Unknown codec {}", name);
+        std::process::exit(1);
+    });
+    Some(codec)
+}
+
+/// Parse the `--rekey-bytes` and `--rekey-after` (seconds) thresholds
+fn rekey_policy(args: &[String]) -> RekeyPolicy {
+    RekeyPolicy {
+        max_bytes: flag_value(args, "--rekey-bytes").and_then(|n| n.parse().ok()),
+        max_age: flag_value(args, "--rekey-after")
+            .and_then(|secs| secs.parse().ok())
+            .map(std::time::Duration::from_secs),
+    }
+}
+
+/// Parse the `--keepalive` interval (seconds) and `--keepalive-missed` count
+fn keepalive(args: &[String]) -> Option<KeepalivePolicy> {
+    let interval = flag_value(args, "--keepalive")?.parse().ok()?;
+    let mut policy = KeepalivePolicy {
+        interval: std::time::Duration::from_secs(interval),
+        ..KeepalivePolicy::default()
+    };
+    if let Some(missed) = flag_value(args, "--keepalive-missed").and_then(|n| n.parse().ok()) {
+        policy.max_missed = missed;
+    }
+    Some(policy)
+}
+
+/// Parse `--max-sessions-per-identity` and `--max-handshakes-per-hour`, the limits
+/// of each authenticated client identity
+fn identity_quota(args: &[String]) -> Option<IdentityQuota> {
+    let quota = IdentityQuota {
+        max_sessions: flag_value(args, "--max-sessions-per-identity").and_then(|n| n.parse().ok()),
+        max_handshakes_per_hour: flag_value(args, "--max-handshakes-per-hour").and_then(|n| n.parse().ok()),
+    };
+    (quota.max_sessions.is_some() || quota.max_handshakes_per_hour.is_some()).then_some(quota)
+}
+
+/// Parse `--puzzle bits[:max_bits]`: the puzzle difficulty of an idle server, and the
+/// one it reaches at `--max-connections` established connections (64 if unbounded)
+fn puzzle_policy(args: &[String]) -> Option<PuzzlePolicy> {
+    let spec = flag_value(args, "--puzzle")?;
+    let (min, max) = spec.split_once(':').unwrap_or((spec, spec));
+    let (Ok(min_difficulty), Ok(max_difficulty)) = (min.parse(), max.parse()) else {
+        eprintln!("Invalid --puzzle {} (expected bits or bits:max_bits)", spec);
+        std::process::exit(1);
+    };
+    Some(PuzzlePolicy {
+        min_difficulty,
+        max_difficulty,
+        busy_connections: flag_value(args, "--max-connections").and_then(|n| n.parse().ok()).unwrap_or(64),
+    })
+}
+
+/// Parse a comma-separated `--cipher` list of record-layer cipher names
+fn ciphers(args: &[String]) -> Option<Vec<CipherSuite>> {
+    let names = flag_value(args, "--cipher")?;
+    let ciphers = names
+        .split(',')
+        .map(|name| {
+            CipherSuite::from_name(name).unwrap_or_else(|| {
+                eprintln!("Unknown cipher {}", name);
+                std::process::exit(1);
+            })
+        })
+        .collect();
+    Some(ciphers)
+}
+
+/// Parse `--client-params`: "named" for well-known groups only, or the smallest
+/// prime size (bits) of validated explicit parameters
+fn client_params_policy(args: &[String]) -> Option<ParamsPolicy> {
+    match flag_value(args, "--client-params")? {
+        "named" => Some(ParamsPolicy::NamedGroups),
+        bits => match bits.parse() {
+            Ok(min_bits) => Some(ParamsPolicy::Validated { min_bits }),
+            Err(_) => {
+                eprintln!("Unknown parameter policy {}", bits);
+                std::process::exit(1);
+            }
+        },
+    }
+}
+
+/// `--short-exponents` selects RFC 7919 short exponents, full-length otherwise
+fn exponent_policy(args: &[String]) -> ExponentPolicy {
+    match args.iter().any(|arg| arg == "--short-exponents") {
+        true => ExponentPolicy::Short,
+        false => ExponentPolicy::Full,
+    }
+}
+
+/// Parse `--padding`: "none", "random:max" or "block:size" (bytes)
+fn padding(args: &[String]) -> Option<Padding> {
+    let value = flag_value(args, "--padding")?;
+    let padding = match value.split_once(':') {
+        None if value == "none" => Some(Padding::None),
+        Some(("random", max)) => max.parse().ok().map(Padding::Random),
+        Some(("block", size)) => size.parse().ok().map(Padding::Block),
+        _ => None,
+    };
+    if padding.is_none() {
+        eprintln!("Unknown padding {}", value);
+        std::process::exit(1);
+    }
+    padding
+}
+
+/// Parse `--max-group-bits`: the largest group whose integers the peer may send
+fn message_limits(args: &[String]) -> Option<MessageLimits> {
+    let value = flag_value(args, "--max-group-bits")?;
+    match value.parse() {
+        Ok(bits) => Some(MessageLimits::for_group_bits(bits)),
+        Err(_) => {
+            eprintln!("Invalid group size {}", value);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Parse a comma-separated `--group` list of named groups (client side)
+fn groups(args: &[String]) -> Option<Vec<DhGroup>> {
+    let names = flag_value(args, "--group")?;
+    let groups = names
+        .split(',')
+        .map(|name| {
+            DhGroup::from_name(name).unwrap_or_else(|| {
+                eprintln!("Unknown group {}", name);
+                std::process::exit(1);
+            })
+        })
+        .collect();
+    Some(groups)
+}
+
+/// Read a user's password from the first line of stdin
+///
+/// The password is echoed if stdin is a terminal; pipe it in to keep it off screen.
+fn read_password(username: &str) -> std::io::Result<String> {
+    println!("Password for {}:", username);
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
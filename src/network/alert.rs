@@ -0,0 +1,131 @@
+use std::fmt;
+
+use crate::structs::DH_Prot::DHMessage;
+
+/// Why a peer gave up on the handshake, carried in an Alert message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertCode {
+    /// A message arrived that is valid but not allowed at this point of the handshake
+    UnexpectedMessage,
+    /// A message did not decode (malformed, unknown type, or oversized)
+    DecodeError,
+    /// The peer offered or selected nothing acceptable
+    HandshakeFailure,
+    /// A field held a value we refuse: an unknown group, invalid parameters, or a
+    /// rejected public key
+    IllegalParameter,
+    /// A Finished message did not verify, or handshake encryption or a record failed
+    /// to open
+    DecryptError,
+    /// The peer's identity did not check out: a bad signature or an unpinned key
+    AuthenticationFailed,
+    /// The peer's identity is over its quota of sessions or handshakes; it may retry
+    /// once a session closes or the hour is up
+    RateLimited,
+}
+
+impl AlertCode {
+    /// Every alert code known to this implementation
+    pub const ALL: &'static [AlertCode] = &[
+        AlertCode::UnexpectedMessage,
+        AlertCode::DecodeError,
+        AlertCode::HandshakeFailure,
+        AlertCode::IllegalParameter,
+        AlertCode::DecryptError,
+        AlertCode::AuthenticationFailed,
+        AlertCode::RateLimited,
+    ];
+
+    /// Wire identifier of the code
+    pub fn id(&self) -> u8 {
+        match self {
+            AlertCode::UnexpectedMessage => 0,
+            AlertCode::DecodeError => 1,
+            AlertCode::HandshakeFailure => 2,
+            AlertCode::IllegalParameter => 3,
+            AlertCode::DecryptError => 4,
+            AlertCode::AuthenticationFailed => 5,
+            AlertCode::RateLimited => 6,
+        }
+    }
+
+    /// Look up a code by its wire identifier
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.iter().copied().find(|code| code.id() == id)
+    }
+
+    /// Short lowercase name of the code (e.g., "decode_error")
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlertCode::UnexpectedMessage => "unexpected_message",
+            AlertCode::DecodeError => "decode_error",
+            AlertCode::HandshakeFailure => "handshake_failure",
+            AlertCode::IllegalParameter => "illegal_parameter",
+            AlertCode::DecryptError => "decrypt_error",
+            AlertCode::AuthenticationFailed => "authentication_failed",
+            AlertCode::RateLimited => "rate_limited",
+        }
+    }
+
+    /// Code for a message that was not the one expected: a decode error if it did not
+    /// decode at all
+    pub fn unexpected(message: &Option<DHMessage>) -> Self {
+        match message {
+            Some(_) => AlertCode::UnexpectedMessage,
+            None => AlertCode::DecodeError,
+        }
+    }
+}
+
+/// An Alert received from the peer, returned as the source of the `io::Error` that
+/// ends the handshake (see `Alert::from_error`); its kind is `InvalidData`, like
+/// every other protocol failure, so it is not retried
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alert {
+    /// Wire identifier of the code, which may be one this implementation does not know
+    pub code: u8,
+    /// What the peer said was wrong
+    pub description: String,
+}
+
+impl Alert {
+    /// Build the Alert message to send for a violation
+    pub fn message(code: AlertCode, description: &str) -> DHMessage {
+        DHMessage::Alert { code: code.id(), description: description.to_string() }
+    }
+
+    /// The alert in a received message, if it is one
+    pub fn from_message(message: &DHMessage) -> Option<Self> {
+        match message {
+            DHMessage::Alert { code, description } => Some(Alert { code: *code, description: description.clone() }),
+            _ => None,
+        }
+    }
+
+    /// The alert that ended a handshake, if the peer sent one
+    pub fn from_error(error: &std::io::Error) -> Option<&Self> {
+        error.get_ref()?.downcast_ref()
+    }
+
+    /// The code, if this implementation knows it
+    pub fn kind(&self) -> Option<AlertCode> {
+        AlertCode::from_id(self.code)
+    }
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind() {
+            Some(code) => write!(f, "peer sent alert {}: {}", code.name(), self.description),
+            None => write!(f, "peer sent alert {}: {}", self.code, self.description),
+        }
+    }
+}
+
+impl std::error::Error for Alert {}
+
+impl From<Alert> for std::io::Error {
+    fn from(alert: Alert) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, alert)
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+/// Security-relevant events an embedding application may want to act on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnomalyKind {
+    /// The peer sent a message that is malformed, of an unknown type, or out of order
+    ProtocolViolation(String),
+    /// The peer offered or selected nothing we support (possible downgrade attempt)
+    NegotiationFailed(String),
+    /// The peer's public key failed validation (reflected, degenerate, or off-curve)
+    PublicKeyRejected(&'static str),
+    /// The peer's DH parameters failed validation (p not provably prime, or g not
+    /// generating the subgroup of order q)
+    ParametersRejected(&'static str),
+    /// An encrypted record was oversized or failed authentication (tampering or desync)
+    RecordRejected(String),
+    /// The server could not prove its identity, or a peer's Finished message did not
+    /// verify (possible man in the middle)
+    AuthenticationFailed(&'static str),
+    /// The peer repeated something that must be fresh (e.g., a ClientHello random)
+    ReplayDetected(&'static str),
+    /// An authenticated identity went over its quota (see `IdentityQuota`), with the
+    /// identity and the limit it reached
+    QuotaExceeded(String),
+}
+
+/// An anomaly observed on one connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Anomaly {
+    /// Address of the peer involved
+    pub peer: String,
+    /// What happened
+    pub kind: AnomalyKind,
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            AnomalyKind::ProtocolViolation(detail) => write!(f, "protocol violation from {}: {}", self.peer, detail),
+            AnomalyKind::NegotiationFailed(detail) => write!(f, "negotiation with {} failed: {}", self.peer, detail),
+            AnomalyKind::PublicKeyRejected(reason) => write!(f, "rejected public key from {}: {}", self.peer, reason),
+            AnomalyKind::ParametersRejected(reason) => write!(f, "rejected parameters from {}: {}", self.peer, reason),
+            AnomalyKind::RecordRejected(detail) => write!(f, "rejected record from {}: {}", self.peer, detail),
+            AnomalyKind::AuthenticationFailed(reason) => write!(f, "{} failed to authenticate: {}", self.peer, reason),
+            AnomalyKind::ReplayDetected(what) => write!(f, "replay from {}: {}", self.peer, what),
+            AnomalyKind::QuotaExceeded(detail) => write!(f, "{} is over its quota: {}", self.peer, detail),
+        }
+    }
+}
+
+/// Callback invoked for every anomaly; called from connection threads, so it must be thread-safe
+pub type AnomalyListener = Arc<dyn Fn(&Anomaly) + Send + Sync>;
+
+/// Build a listener that forwards anomalies into a channel, for applications that
+/// prefer polling a receiver over running code on connection threads
+pub fn channel_listener(sender: Sender<Anomaly>) -> AnomalyListener {
+    Arc::new(move |anomaly| {
+        // A dropped receiver just means nobody is listening any more
+        let _ = sender.send(anomaly.clone());
+    })
+}
+
+/// Deliver an anomaly to the listener, if one is installed
+pub(crate) fn report(listener: &Option<AnomalyListener>, peer: &str, kind: AnomalyKind) {
+    if let Some(listener) = listener {
+        listener(&Anomaly {
+            peer: peer.to_string(),
+            kind,
+        });
+    }
+}
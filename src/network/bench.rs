@@ -0,0 +1,172 @@
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use num_bigint::{BigInt, RandBigInt};
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::crypto::{generate_dh_params_seeded, mod_pow_ct, PrimalityConfig};
+use crate::crypto::groups::DhGroup;
+use crate::network::client::DHClient;
+use crate::network::server::{DHServer, KeyMode, ParamSource};
+
+/// Bit length of the safe primes timed by the prime generation benchmark; small enough
+/// to finish in seconds, large enough that the primality tests dominate
+const PRIME_BENCH_BITS: usize = 512;
+
+/// Timings of one operation over a number of runs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BenchResult {
+    /// What was timed (e.g., "mod_pow/ffdhe2048")
+    pub name: String,
+    /// Number of timed runs
+    pub iterations: u32,
+    /// Median run, in nanoseconds
+    pub median_ns: u64,
+    /// Fastest run, in nanoseconds
+    pub min_ns: u64,
+}
+
+impl BenchResult {
+    /// Time `iterations` runs of `operation`, which is given the run number
+    fn measure(name: &str, iterations: u32, mut operation: impl FnMut(u32) -> std::io::Result<()>) -> std::io::Result<Self> {
+        let mut runs = Vec::with_capacity(iterations as usize);
+        for run in 0..iterations {
+            let start = Instant::now();
+            operation(run)?;
+            runs.push(start.elapsed());
+        }
+        runs.sort();
+        let nanos = |run: Option<&Duration>| run.map_or(0, |run| u64::try_from(run.as_nanos()).unwrap_or(u64::MAX));
+        Ok(BenchResult {
+            name: name.to_string(),
+            iterations,
+            median_ns: nanos(runs.get(runs.len() / 2)),
+            min_ns: nanos(runs.first()),
+        })
+    }
+}
+
+/// Results of `run_benchmarks`, stored as JSON so later runs can be compared against
+/// them (see `BenchReport::compare`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BenchReport {
+    /// One result per operation, in the order they ran
+    pub results: Vec<BenchResult>,
+}
+
+impl BenchReport {
+    /// Load a report written by `store`
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        serde_json::from_slice(&fs::read(path)?).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} is not a benchmark report: {}", path.display(), e),
+            )
+        })
+    }
+
+    /// Write the report as JSON
+    pub fn store(&self, path: &Path) -> std::io::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self).expect("every BenchReport is serializable"))
+    }
+
+    /// Compare `newer` against this report, operation by operation; operations that
+    /// only one of them timed are skipped
+    pub fn compare(&self, newer: &BenchReport) -> Vec<BenchComparison> {
+        self.results
+            .iter()
+            .filter_map(|old| {
+                let new = newer.results.iter().find(|new| new.name == old.name)?;
+                Some(BenchComparison {
+                    name: old.name.clone(),
+                    old_ns: old.median_ns,
+                    new_ns: new.median_ns,
+                })
+            })
+            .collect()
+    }
+
+    /// Print the report in a human readable form
+    pub fn print(&self) {
+        println!("Benchmark report");
+        for result in &self.results {
+            println!(
+                "  {:<24} median {:>12?}  min {:>12?}  ({} runs)",
+                result.name,
+                Duration::from_nanos(result.median_ns),
+                Duration::from_nanos(result.min_ns),
+                result.iterations
+            );
+        }
+    }
+}
+
+/// The median timings of one operation in two reports
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchComparison {
+    /// Operation compared
+    pub name: String,
+    /// Median in the older report, in nanoseconds
+    pub old_ns: u64,
+    /// Median in the newer report, in nanoseconds
+    pub new_ns: u64,
+}
+
+impl BenchComparison {
+    /// How much slower the newer median is, in percent (negative when faster)
+    pub fn slowdown_percent(&self) -> f64 {
+        match self.old_ns {
+            0 => 0.0,
+            old => (self.new_ns as f64 - old as f64) / old as f64 * 100.0,
+        }
+    }
+}
+
+/// Time modular exponentiation, safe prime generation, and a full handshake against a
+/// local server
+///
+/// Prime generation is seeded by the run number, so every report times the same
+/// searches and the results compare across runs.
+///
+/// # Arguments
+/// * `iterations` - Number of timed runs per operation
+///
+/// # Returns
+/// The report, or an error if the local server or a handshake failed
+pub fn run_benchmarks(iterations: u32) -> std::io::Result<BenchReport> {
+    let mut results = Vec::new();
+    for group in [DhGroup::Ffdhe2048, DhGroup::Ffdhe3072] {
+        let p = group.prime();
+        let base: BigInt = rand::thread_rng().gen_biguint_below(p.magnitude()).into();
+        let exp: BigInt = rand::thread_rng().gen_biguint_below(p.magnitude()).into();
+        results.push(BenchResult::measure(&format!("mod_pow/{}", group.name()), iterations, |_| {
+            mod_pow_ct(&base, &exp, &p);
+            Ok(())
+        })?);
+    }
+
+    let config = PrimalityConfig::new(PRIME_BENCH_BITS);
+    results.push(BenchResult::measure(&format!("prime_generation/{}", PRIME_BENCH_BITS), iterations, |run| {
+        generate_dh_params_seeded(&config, &run.to_be_bytes());
+        Ok(())
+    })?);
+
+    let server = DHServer::new("127.0.0.1:0", ParamSource::Group(DhGroup::Ffdhe2048), None, KeyMode::Ephemeral)?;
+    let server_addr = server.local_addr()?.to_string();
+    let cancel = server.cancel_token();
+    let server_thread = thread::spawn(move || server.run());
+    let handshakes = BenchResult::measure("handshake/ffdhe2048", iterations, |_| {
+        let mut client = DHClient::new(&server_addr)?;
+        client.perform_key_exchange()?;
+        client.close()
+    });
+    cancel.cancel();
+    server_thread
+        .join()
+        .map_err(|_| std::io::Error::other("benchmark server panicked"))??;
+    results.push(handshakes?);
+
+    Ok(BenchReport { results })
+}
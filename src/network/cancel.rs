@@ -0,0 +1,120 @@
+use std::fmt;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often blocking operations check whether they were cancelled
+pub const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Cloneable flag that asks blocking client and server operations to stop
+///
+/// Every clone shares the same flag, so an embedder keeps one clone and hands
+/// another to the client or server; cancelling is permanent.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Create a token that has not been cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel every operation watching this token; they notice within a poll interval
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the token has been cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Error to return if the token has been cancelled
+    pub(crate) fn check(&self) -> std::io::Result<()> {
+        if self.is_cancelled() {
+            return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, Cancelled));
+        }
+        Ok(())
+    }
+}
+
+/// Payload of the errors returned by operations stopped through a CancelToken
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Operation cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Whether an error came from a cancelled operation rather than a failure
+pub fn is_cancelled(error: &std::io::Error) -> bool {
+    error.get_ref().is_some_and(|inner| inner.is::<Cancelled>())
+}
+
+/// Connect to `addr`, giving up as soon as the token is cancelled
+///
+/// The connect runs on a helper thread; if cancelled, that thread is left to finish
+/// (bounded by the OS connect timeout) and drops the socket it gets.
+pub(crate) fn connect(addr: &str, cancel: &CancelToken) -> std::io::Result<TcpStream> {
+    let addrs: Vec<_> = addr.to_socket_addrs()?.collect();
+    let (sender, receiver) = mpsc::channel();
+    thread::Builder::new()
+        .name(format!("connect-{}", addr))
+        .spawn(move || {
+            // A dropped receiver means the connect was cancelled
+            let _ = sender.send(TcpStream::connect(&addrs[..]));
+        })?;
+
+    loop {
+        cancel.check()?;
+        match receiver.recv_timeout(CANCEL_POLL_INTERVAL) {
+            Ok(result) => return result,
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Err(std::io::Error::other("Connect thread exited without a result"));
+            }
+        }
+    }
+}
+
+/// Block until the stream has data to read, polling the token in between
+///
+/// # Arguments
+/// * `stream` - Stream to wait on; its read timeout is restored before returning
+/// * `cancel` - Token checked every poll interval
+/// * `deadline` - How long to wait for data; None waits indefinitely
+///
+/// # Returns
+/// Ok once data (or end of stream) is available, a Cancelled error if the token was
+/// cancelled, or WouldBlock once the deadline passes
+pub(crate) fn wait_readable(stream: &TcpStream, cancel: &CancelToken, deadline: Option<Duration>) -> std::io::Result<()> {
+    let previous = stream.read_timeout()?;
+    stream.set_read_timeout(Some(CANCEL_POLL_INTERVAL))?;
+    let started = Instant::now();
+
+    let result = loop {
+        if let Err(e) = cancel.check() {
+            break Err(e);
+        }
+        match stream.peek(&mut [0u8; 1]) {
+            Ok(_) => break Ok(()),
+            Err(ref e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {
+                // Same kind a timed-out blocking read reports, so callers handle both alike
+                if deadline.is_some_and(|deadline| started.elapsed() >= deadline) {
+                    break Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "Timed out waiting for peer"));
+                }
+            }
+            Err(e) => break Err(e),
+        }
+    };
+
+    stream.set_read_timeout(previous)?;
+    result
+}
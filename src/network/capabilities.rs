@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::crypto::groups::DhGroup;
+use crate::crypto::kex::KexAlgorithm;
+use crate::crypto::record::CipherSuite;
+use crate::network::lock_ignoring_poison;
+
+/// What a server selected the last time we completed a handshake with it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerCapabilities {
+    /// Key-exchange algorithm the server picked
+    pub kex_algorithm: KexAlgorithm,
+    /// Record-layer cipher the server picked
+    pub cipher: CipherSuite,
+    /// Well-known group the server used, if it named one
+    pub group: Option<DhGroup>,
+}
+
+/// Client-side cache of server capabilities, keyed by endpoint
+///
+/// A client holding an entry for its server offers only the cached algorithm and
+/// cipher, which keeps ClientHello minimal. Entries are dropped as soon as a
+/// narrowed handshake fails, so the next attempt falls back to the full offer.
+/// Clones share the same entries.
+#[derive(Debug, Clone, Default)]
+pub struct CapabilityCache {
+    entries: Arc<Mutex<HashMap<String, ServerCapabilities>>>,
+    /// File the entries are mirrored to, if the cache is persistent
+    path: Option<PathBuf>,
+}
+
+impl CapabilityCache {
+    /// Create an empty in-memory cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open a cache mirrored to a file, loading any entries it already holds
+    ///
+    /// Lines that do not parse (e.g., naming a cipher this build does not know) are
+    /// skipped, so those servers are simply negotiated from scratch.
+    pub fn persistent(path: &Path) -> std::io::Result<Self> {
+        let mut entries = HashMap::new();
+        if path.exists() {
+            for line in fs::read_to_string(path)?.lines() {
+                if let Some((endpoint, capabilities)) = parse_entry(line) {
+                    entries.insert(endpoint, capabilities);
+                }
+            }
+        }
+        Ok(CapabilityCache {
+            entries: Arc::new(Mutex::new(entries)),
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    /// Cached capabilities of an endpoint
+    pub fn get(&self, endpoint: &str) -> Option<ServerCapabilities> {
+        self.lock().get(endpoint).copied()
+    }
+
+    /// Remember what an endpoint selected
+    pub fn store(&self, endpoint: &str, capabilities: ServerCapabilities) {
+        let mut entries = self.lock();
+        if entries.insert(endpoint.to_string(), capabilities) != Some(capabilities) {
+            self.persist(&entries);
+        }
+    }
+
+    /// Forget an endpoint, e.g. after it rejected a narrowed ClientHello
+    pub fn invalidate(&self, endpoint: &str) {
+        let mut entries = self.lock();
+        if entries.remove(endpoint).is_some() {
+            self.persist(&entries);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, ServerCapabilities>> {
+        lock_ignoring_poison(&self.entries)
+    }
+
+    /// Rewrite the cache file atomically; the cache is only an optimization, so a
+    /// failed write is logged rather than returned
+    fn persist(&self, entries: &HashMap<String, ServerCapabilities>) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let mut contents = String::from("# Server capabilities cached by dhke: endpoint algorithm cipher [group]\n");
+        for (endpoint, capabilities) in entries {
+            contents.push_str(&format!(
+                "{} {} {}",
+                endpoint,
+                capabilities.kex_algorithm.name(),
+                capabilities.cipher.name()
+            ));
+            if let Some(group) = capabilities.group {
+                contents.push_str(&format!(" {}", group.name()));
+            }
+            contents.push('\n');
+        }
+
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        if let Err(e) = fs::write(&temp_path, contents).and_then(|_| fs::rename(&temp_path, path)) {
+            eprintln!("[CLIENT] Failed to write capability cache {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Parse an `endpoint algorithm cipher [group]` line
+fn parse_entry(line: &str) -> Option<(String, ServerCapabilities)> {
+    let mut fields = line.split_whitespace();
+    let endpoint = fields.next().filter(|endpoint| !endpoint.starts_with('#'))?;
+    let kex_algorithm = KexAlgorithm::from_name(fields.next()?)?;
+    let cipher = CipherSuite::from_name(fields.next()?)?;
+    let group = match fields.next() {
+        Some(name) => Some(DhGroup::from_name(name)?),
+        None => None,
+    };
+    Some((endpoint.to_string(), ServerCapabilities { kex_algorithm, cipher, group }))
+}
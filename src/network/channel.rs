@@ -0,0 +1,227 @@
+use std::io::{IoSlice, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+use crate::crypto::record::{CloseReason, RecordLayer, MAX_RECORD_PLAINTEXT};
+use crate::network::alert::{Alert, AlertCode};
+use crate::network::anomaly::{report, AnomalyKind, AnomalyListener};
+use crate::network::cancel::{wait_readable, CancelToken};
+
+/// An established session, either end: the connection and the record layer that
+/// encrypts it, read and written as a byte stream
+///
+/// Reads hand out records across calls when the caller's buffer is smaller than a
+/// record, and answer rekey and keepalive messages on the way; Ok(0) means the peer
+/// closed the connection. Every successful write sends one record of up to
+/// MAX_RECORD_PLAINTEXT bytes straight away, so wrap the channel in a BufWriter when
+/// writing many small pieces.
+pub struct SecureChannel {
+    stream: TcpStream,
+    records: RecordLayer,
+    /// Aborts pending reads when cancelled
+    cancel: CancelToken,
+    /// Notified of records we reject, under the peer's address
+    anomaly_listener: Option<AnomalyListener>,
+    peer: String,
+    /// Plaintext of a record only partly read so far
+    pending: Vec<u8>,
+    /// How much of `pending` has already been read
+    pending_offset: usize,
+}
+
+impl SecureChannel {
+    /// Wrap a connection whose handshake completed, with the record layer keyed by it
+    pub(crate) fn new(stream: TcpStream, records: RecordLayer, cancel: CancelToken) -> Self {
+        let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+        SecureChannel {
+            stream,
+            records,
+            cancel,
+            anomaly_listener: None,
+            peer,
+            pending: Vec::new(),
+            pending_offset: 0,
+        }
+    }
+
+    /// Report rejected records to `listener`, naming the peer `peer`
+    pub(crate) fn with_anomaly_listener(mut self, listener: Option<AnomalyListener>, peer: &str) -> Self {
+        self.anomaly_listener = listener;
+        self.peer = peer.to_string();
+        self
+    }
+
+    /// Encrypt and send one record
+    pub fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.records.write_record(&mut self.stream, data)
+    }
+
+    /// Encrypt several buffers as one record without first concatenating them
+    pub fn send_vectored(&mut self, parts: &[IoSlice]) -> std::io::Result<()> {
+        self.records.write_record_vectored(&mut self.stream, parts)
+    }
+
+    /// The rest of a partly read record, or else the next record
+    ///
+    /// # Returns
+    /// The plaintext, or None if the peer closed the connection
+    pub fn receive(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        if self.pending_offset < self.pending.len() {
+            let rest = self.pending.split_off(self.pending_offset);
+            self.pending.clear();
+            self.pending_offset = 0;
+            return Ok(Some(rest));
+        }
+        self.read_record(self.stream.read_timeout()?)
+    }
+
+    /// Ping the peer if it has been quiet for the keepalive interval
+    ///
+    /// # Returns
+    /// A TimedOut error once the peer missed too many Pings
+    pub fn keepalive(&mut self) -> std::io::Result<()> {
+        self.records.poll_keepalive(&mut self.stream)
+    }
+
+    /// Wait up to `timeout` (None: the read timeout) for the next record, answering
+    /// rekey and keepalive messages on the way
+    ///
+    /// # Returns
+    /// The plaintext, None if the peer closed the connection, or a WouldBlock error if
+    /// `timeout` passed without one
+    pub(crate) fn read_record(&mut self, timeout: Option<Duration>) -> std::io::Result<Option<Vec<u8>>> {
+        let started = Instant::now();
+        loop {
+            // Wake up for each Ping due while waiting, up to the timeout
+            self.records.poll_keepalive(&mut self.stream)?;
+            let remaining = timeout.map(|timeout| timeout.saturating_sub(started.elapsed()));
+            let deadline = match (remaining, self.records.next_keepalive()) {
+                (Some(remaining), Some(ping)) => Some(remaining.min(ping)),
+                (remaining, ping) => remaining.or(ping),
+            };
+            match wait_readable(&self.stream, &self.cancel, deadline) {
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock && deadline != remaining => continue,
+                result => result?,
+            }
+            match self.records.read_record(&mut self.stream) {
+                // A rekey or keepalive message, answered by the record layer
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == std::io::ErrorKind::InvalidData && Alert::from_error(&e).is_none() => {
+                    report(&self.anomaly_listener, &self.peer, AnomalyKind::RecordRejected(e.to_string()));
+                    // Best effort: tell the peer why before giving up on the connection
+                    let _ = self.records.write_alert(&mut self.stream, AlertCode::DecryptError, "record rejected");
+                    return Err(e);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Send Close, telling the peer why we end the session
+    pub fn write_close(&mut self, reason: CloseReason) -> std::io::Result<()> {
+        self.records.write_close(&mut self.stream, reason)
+    }
+
+    /// Close the session cleanly
+    ///
+    /// Sends Close and waits, up to the read timeout, for the peer's Close in reply,
+    /// which confirms it read everything we sent; records the peer sent meanwhile are
+    /// discarded. The stream is shut down either way.
+    ///
+    /// # Returns
+    /// An UnexpectedEof error if the peer went away without confirming
+    pub fn close(&mut self) -> std::io::Result<()> {
+        self.write_close(CloseReason::Normal)?;
+        let drained = loop {
+            match self.receive() {
+                Ok(Some(_)) => continue,
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        let confirmed = self.close_reason().is_some();
+        // The peer may already have shut its side down
+        let _ = self.stream.shutdown(Shutdown::Both);
+        drained?;
+        match confirmed {
+            true => Ok(()),
+            false => Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "Peer closed the connection without confirming",
+            )),
+        }
+    }
+
+    /// Why the peer closed the connection, if it said so before closing
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.records.close_reason()
+    }
+
+    /// How many times the session was rekeyed, by either side
+    pub fn rekeys(&self) -> u64 {
+        self.records.rekeys()
+    }
+
+    /// Address of the peer
+    pub fn peer_addr(&self) -> std::io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+}
+
+impl Read for SecureChannel {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // Empty records carry no data, so keep reading until one does
+        while self.pending_offset == self.pending.len() {
+            match self.read_record(self.stream.read_timeout()?)? {
+                Some(plaintext) => {
+                    self.pending = plaintext;
+                    self.pending_offset = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.pending.len() - self.pending_offset);
+        buf[..n].copy_from_slice(&self.pending[self.pending_offset..self.pending_offset + n]);
+        self.pending_offset += n;
+        Ok(n)
+    }
+}
+
+impl Write for SecureChannel {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let n = buf.len().min(MAX_RECORD_PLAINTEXT);
+        self.send(&buf[..n])?;
+        Ok(n)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> std::io::Result<usize> {
+        // Gather as many whole buffers as fit in one record
+        let mut len = 0;
+        let mut count = 0;
+        for buf in bufs {
+            if len + buf.len() > MAX_RECORD_PLAINTEXT {
+                break;
+            }
+            len += buf.len();
+            count += 1;
+        }
+        match (count, len) {
+            (0, _) => self.write(bufs.first().map_or(&[][..], |buf| &buf[..])),
+            (_, 0) => Ok(0),
+            _ => {
+                self.send_vectored(&bufs[..count])?;
+                Ok(len)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
@@ -1,14 +1,143 @@
 use std::net::TcpStream;
 use std::io::{Read, Write};
-use num_bigint::BigInt;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use num_bigint::{BigInt, BigUint};
 
-use crate::structs::DH_Prot::{DHMessage, DHConnection};
-use crate::crypto::crypto::{generate_secret_key, compute_public_key, mod_pow};
+use rand::Rng;
+
+use crate::structs::DH_Prot::{DHMessage, ABORT_UNAUTHENTICATED, ABORT_VERSION_MISMATCH, PRIME_CERTIFICATE_SIGNAL, FIXED_WIDTH_KEYS_SIGNAL, ONE_STEP_KDF_SIGNAL, PROTECT_IDENTITIES_SIGNAL, PROTOCOL_VERSIONS, REKEY_SIGNAL, SESSION_TICKET_SIGNAL, CLIENT_PARAMS_SIGNAL, RECORD_PADDING_SIGNAL};
+use crate::crypto::cookie::answer_hello_retry;
+use crate::crypto::crypto::{generate_secret_key, validate_generator, Blinding, ExponentPolicy};
+use crate::crypto::fingerprint::Fingerprint;
+use crate::crypto::groups::{params_fingerprint, DhGroup};
+use crate::crypto::handshake_protection::HandshakeProtection;
+use crate::crypto::hmqv::{join_share, HmqvKeyExchange, HmqvRole};
+use crate::crypto::identity::ServerKey;
+use crate::crypto::kdf::{Kdf, SessionKeys, CLIENT_FINISHED_LABEL, SERVER_FINISHED_LABEL};
+use crate::crypto::kex::{check_fixed_width, curve_key_exchange, to_fixed_width, FiniteFieldKeyExchange, KexAlgorithm, KeyExchange};
+use crate::crypto::record::{CipherSuite, CloseReason, KeepalivePolicy, Padding, RecordLayer, RekeyPolicy};
+use crate::crypto::obfuscation::PaddedFiniteFieldKeyExchange;
+use crate::crypto::prime_certificate::PrimeCertificate;
+use crate::crypto::rng::{with_rng, RngPurpose};
+use crate::crypto::srp::SrpClientExchange;
+use crate::crypto::static_key;
+use crate::crypto::ticket::Resumption;
+use crate::crypto::transcript::{channel_binding, to_hex, Transcript};
+use crate::network::alert::{Alert, AlertCode};
+use crate::network::anomaly::{report, AnomalyKind, AnomalyListener};
+use crate::network::capabilities::{CapabilityCache, ServerCapabilities};
+use crate::network::channel::SecureChannel;
+use crate::network::cancel::{self, wait_readable, CancelToken};
+use crate::network::framing::{read_message_within, write_message, write_messages, Codec, LimitExceeded, MessageLimits};
+use crate::network::tickets::{SessionTicket, TicketStore};
+
+/// How the client shapes its handshake on the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HandshakeProfile {
+    /// Offer everything, one message per write
+    #[default]
+    Standard,
+    /// Fewest bytes and round trips, for constrained links: X25519 first, a single
+    /// cipher, no GREASE, and the client key share sent together with Done
+    Compact,
+}
+
+impl HandshakeProfile {
+    /// Every profile known to this implementation
+    pub const ALL: &'static [HandshakeProfile] = &[HandshakeProfile::Standard, HandshakeProfile::Compact];
+
+    /// Short lowercase name of the profile (e.g., "compact")
+    pub fn name(&self) -> &'static str {
+        match self {
+            HandshakeProfile::Standard => "standard",
+            HandshakeProfile::Compact => "compact",
+        }
+    }
+
+    /// Look up a profile by its name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|profile| profile.name() == name)
+    }
+}
 
 /// DH Client that connects to a server and performs key exchange
 pub struct DHClient {
     stream: TcpStream,
     server_addr: String,
+    /// Running hash of the handshake messages exchanged with the server
+    transcript: Transcript,
+    /// Channel binding value, set once the key exchange completes
+    channel_binding: Option<[u8; 32]>,
+    /// Symmetric keys derived from the shared secret, set once the key exchange completes
+    session_keys: Option<SessionKeys>,
+    /// Fingerprint of the server's public key, set once the key exchange completes
+    server_fingerprint: Option<Fingerprint>,
+    /// Encrypted application data, set once the key exchange completes
+    channel: Option<SecureChannel>,
+    /// Whether to interleave GREASE messages into the handshake
+    grease: bool,
+    /// Key-exchange algorithms offered in ClientHello, most preferred first
+    kex_algorithms: Vec<KexAlgorithm>,
+    /// Record-layer ciphers offered in ClientHello, most preferred first
+    ciphers: Vec<CipherSuite>,
+    /// Named groups accepted for finite-field algorithms, most preferred first; empty
+    /// accepts any group the server picks, explicit parameters included
+    groups: Vec<DhGroup>,
+    /// Notified of protocol violations, rejected keys, and other security events
+    anomaly_listener: Option<AnomalyListener>,
+    /// Wire shape of the handshake
+    profile: HandshakeProfile,
+    /// Key the server must sign the handshake with, if authentication is required
+    server_key: Option<ServerKey>,
+    /// Fingerprint the server's public key must have, for servers with a static key
+    pinned_fingerprint: Option<Fingerprint>,
+    /// File holding our static DH key, which HMQV authenticates us with
+    static_key: Option<PathBuf>,
+    /// Username and password for SRP
+    srp_credentials: Option<(String, String)>,
+    /// Whether identities may only cross the wire under handshake encryption
+    protect_identities: bool,
+    /// Whether explicit parameters must come with a certificate proving p a safe prime
+    require_prime_certificate: bool,
+    /// (p, g) we propose for finite-field algorithms, the only ones we then accept
+    proposed_params: Option<(BigInt, BigInt)>,
+    /// Randomization of the finite-field exponentiations with our secret exponent
+    blinding: Blinding,
+    /// How long our ephemeral finite-field exponents are
+    exponent_policy: ExponentPolicy,
+    /// When to start rekeying the session
+    rekey: RekeyPolicy,
+    /// When to ping a quiet server, and how many missed Pongs end the connection
+    keepalive: Option<KeepalivePolicy>,
+    /// Padding of what we encrypt, if we ask for padded records
+    padding: Option<Padding>,
+    /// Key derivation function to ask the server for
+    kdf: Kdf,
+    /// Whether to send and expect finite-field public keys of a fixed width
+    fixed_width_keys: bool,
+    /// Encoding to ask the server for, used for every message after ClientHello
+    codec: Codec,
+    /// Size limits of the handshake messages we read
+    limits: MessageLimits,
+    /// Encoding of the messages on the wire right now
+    wire_codec: Codec,
+    /// Key-exchange algorithm the server selected, whose format the key shares we
+    /// read are in
+    selected_kex: KexAlgorithm,
+    /// Prime and generator of the finite-field group the server selected, set once
+    /// ServerHello arrives
+    group_params: Option<(BigInt, BigInt)>,
+    /// Protocol version the server selected, set once ServerHello arrives
+    protocol_version: Option<u8>,
+    /// Aborts the handshake and pending reads when cancelled
+    cancel: CancelToken,
+    /// What servers selected before, used to narrow ClientHello
+    capability_cache: Option<CapabilityCache>,
+    /// Session tickets servers issued, presented to resume their sessions
+    tickets: Option<TicketStore>,
+    /// Whether the server resumed a session from our ticket instead of a key exchange
+    resumed: bool,
 }
 
 impl DHClient {
@@ -22,150 +151,1225 @@ impl DHClient {
     pub fn new(server_addr: &str) -> std::io::Result<Self> {
         println!("[CLIENT] Connecting to server at {}", server_addr);
         let stream = TcpStream::connect(server_addr)?;
+        Self::from_stream(stream, server_addr, CancelToken::new())
+    }
+
+    /// Create a new DH client, giving up on the connection attempt if `cancel` is cancelled
+    ///
+    /// The token stays attached to the client, so cancelling it later also aborts the
+    /// handshake and any blocked `receive_message`.
+    ///
+    /// # Arguments
+    /// * `server_addr` - Server address (e.g., "127.0.0.1:8080")
+    /// * `cancel` - Token another thread can cancel
+    ///
+    /// # Returns
+    /// A new connected DHClient, or a Cancelled error (see `cancel::is_cancelled`)
+    pub fn connect(server_addr: &str, cancel: CancelToken) -> std::io::Result<Self> {
+        println!("[CLIENT] Connecting to server at {}", server_addr);
+        let stream = cancel::connect(server_addr, &cancel)?;
+        Self::from_stream(stream, server_addr, cancel)
+    }
+
+    fn from_stream(stream: TcpStream, server_addr: &str, cancel: CancelToken) -> std::io::Result<Self> {
         stream.set_read_timeout(Some(std::time::Duration::from_secs(30)))?;
         
         println!("[CLIENT] Connected to server at {}", server_addr);
         Ok(DHClient {
             stream,
             server_addr: server_addr.to_string(),
+            transcript: Transcript::new(),
+            channel_binding: None,
+            session_keys: None,
+            server_fingerprint: None,
+            channel: None,
+            grease: false,
+            kex_algorithms: vec![KexAlgorithm::FiniteField, KexAlgorithm::X25519],
+            ciphers: CipherSuite::DEFAULT.to_vec(),
+            groups: Vec::new(),
+            anomaly_listener: None,
+            profile: HandshakeProfile::Standard,
+            server_key: None,
+            pinned_fingerprint: None,
+            static_key: None,
+            srp_credentials: None,
+            protect_identities: false,
+            require_prime_certificate: false,
+            proposed_params: None,
+            blinding: Blinding::None,
+            exponent_policy: ExponentPolicy::Full,
+            rekey: RekeyPolicy::default(),
+            keepalive: None,
+            padding: None,
+            kdf: Kdf::Hkdf,
+            fixed_width_keys: false,
+            codec: Codec::Native,
+            limits: MessageLimits::default(),
+            wire_codec: Codec::Native,
+            selected_kex: KexAlgorithm::FiniteField,
+            group_params: None,
+            cancel,
+            capability_cache: None,
+            tickets: None,
+            resumed: false,
+            protocol_version: None,
         })
     }
 
+    /// Set the key-exchange algorithms to offer, most preferred first
+    pub fn with_kex_algorithms(mut self, algorithms: &[KexAlgorithm]) -> Self {
+        self.kex_algorithms = algorithms.to_vec();
+        self
+    }
+
+    /// Set the record-layer ciphers to offer, most preferred first
+    pub fn with_ciphers(mut self, ciphers: &[CipherSuite]) -> Self {
+        self.ciphers = ciphers.to_vec();
+        self
+    }
+
+    /// Accept finite-field algorithms only over these named groups, most preferred first
+    ///
+    /// The server then skips the finite-field algorithms unless its group is listed,
+    /// and explicit parameters are refused. By default any group is accepted.
+    pub fn with_groups(mut self, groups: &[DhGroup]) -> Self {
+        self.groups = groups.to_vec();
+        self
+    }
+
+    /// Install a callback for security-relevant events on this connection
+    pub fn with_anomaly_listener(mut self, listener: AnomalyListener) -> Self {
+        self.anomaly_listener = Some(listener);
+        self
+    }
+
+    /// Enable or disable sending GREASE messages during the handshake
+    ///
+    /// GREASE messages use reserved types that every conforming peer must skip,
+    /// which catches peers that would choke on future message types.
+    pub fn with_grease(mut self, enabled: bool) -> Self {
+        self.grease = enabled;
+        self
+    }
+
+    /// Abort the handshake and pending reads when this token is cancelled
+    pub fn with_cancel_token(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Require the server to prove it holds the identity key with this public key
+    ///
+    /// The client sends a fresh nonce in ClientHello and only completes the exchange
+    /// if the server signs the transcript (parameters, both public keys, and the
+    /// nonce) with this key, which defeats a man in the middle.
+    pub fn with_server_key(mut self, public_key: ServerKey) -> Self {
+        self.server_key = Some(public_key);
+        self
+    }
+
+    /// Only accept a server whose public key has this fingerprint
+    ///
+    /// Meant for servers running with a static DH key, whose public value (and so its
+    /// fingerprint) is the same on every connection.
+    pub fn with_pinned_fingerprint(mut self, fingerprint: Fingerprint) -> Self {
+        self.pinned_fingerprint = Some(fingerprint);
+        self
+    }
+
+    /// Authenticate with a static DH key, offering HMQV ahead of the other algorithms
+    ///
+    /// The key is generated on first use and stored in `path`. Servers with a static
+    /// key of their own then run HMQV, which only completes for the holders of both
+    /// static keys; pin the server's fingerprint with `with_pinned_fingerprint` so
+    /// the client also knows whose key it is talking to.
+    pub fn with_static_key(mut self, path: PathBuf) -> Self {
+        self.static_key = Some(path);
+        if !self.kex_algorithms.contains(&KexAlgorithm::Hmqv) {
+            self.kex_algorithms.insert(0, KexAlgorithm::Hmqv);
+        }
+        self
+    }
+
+    /// Authenticate with a password, offering SRP ahead of the other algorithms
+    ///
+    /// Servers holding a verifier for the user then run SRP, which only completes if
+    /// the client knows the password and the server its verifier. Neither an
+    /// eavesdropper nor a fake server learns anything to guess the password offline.
+    pub fn with_srp_credentials(mut self, username: &str, password: &str) -> Self {
+        self.srp_credentials = Some((username.to_string(), password.to_string()));
+        if !self.kex_algorithms.contains(&KexAlgorithm::Srp) {
+            self.kex_algorithms.insert(0, KexAlgorithm::Srp);
+        }
+        self
+    }
+
+    /// Send public keys in encodings indistinguishable from random bytes, against
+    /// traffic analysis that looks for key exchanges
+    ///
+    /// Enabled, only x25519-elligator and ffdh-padded are offered, so a server without
+    /// them fails the handshake instead of falling back to recognizable keys; disabled,
+    /// they are removed from the offer. The hellos themselves remain recognizable.
+    pub fn with_key_obfuscation(mut self, enabled: bool) -> Self {
+        let obfuscated = [KexAlgorithm::X25519Elligator, KexAlgorithm::FiniteFieldPadded];
+        if enabled {
+            self.kex_algorithms = obfuscated.to_vec();
+        } else {
+            self.kex_algorithms.retain(|algorithm| !obfuscated.contains(algorithm));
+        }
+        self
+    }
+
+    /// Keep identities away from passive observers
+    ///
+    /// The client asks the server to send its signature (and, for HMQV, both sides
+    /// to send their static keys) only after an ephemeral exchange, encrypted under
+    /// keys derived from it. A server that sends an identity in the clear anyway
+    /// fails the handshake.
+    pub fn with_identity_protection(mut self, enabled: bool) -> Self {
+        self.protect_identities = enabled;
+        self
+    }
+
+    /// Only accept explicit (p, g) that come with a proof that p is a safe prime
+    ///
+    /// The client asks for a Pocklington certificate (see `PrimeCertificate`) and
+    /// checks it, which takes a few exponentiations and no trust in probabilistic
+    /// tests. A server sending explicit parameters without one fails the handshake;
+    /// named groups and curves need no certificate.
+    pub fn with_prime_certificate(mut self, required: bool) -> Self {
+        self.require_prime_certificate = required;
+        self
+    }
+
+    /// Propose (p, g) for the finite-field algorithms instead of taking the server's
+    ///
+    /// The parameters follow ClientHello in a ClientParams message; servers without a
+    /// policy accepting them fail the handshake, and a server selecting any other
+    /// parameters is refused. We trust our own parameters, so no prime certificate is
+    /// asked for. Curve algorithms, if offered, are unaffected.
+    pub fn with_proposed_params(mut self, p: BigInt, g: BigInt) -> Self {
+        self.proposed_params = Some((p, g));
+        self
+    }
+
+    /// Blind the finite-field exponentiations with our secret exponent
+    ///
+    /// Applies to ffdh and ffdh-padded; our exponent is ephemeral, so this mostly
+    /// matters where the same process is timed over many handshakes.
+    pub fn with_blinding(mut self, blinding: Blinding) -> Self {
+        self.blinding = blinding;
+        self
+    }
+
+    /// Draw our ephemeral finite-field exponents under this policy (full-length by
+    /// default)
+    ///
+    /// Applies to ffdh, ffdh-padded, HMQV's ephemeral key and SRP; static keys stay
+    /// full-length. Exponentiation cost scales with the exponent length, so
+    /// `ExponentPolicy::Short` makes a 2048-bit key exchange roughly 9 times cheaper.
+    pub fn with_exponent_policy(mut self, policy: ExponentPolicy) -> Self {
+        self.exponent_policy = policy;
+        self
+    }
+
+    /// Derive the session keys with this KDF
+    ///
+    /// `Kdf::OneStep` needs a server that supports it; with any other server the
+    /// Finished check fails and the handshake aborts.
+    pub fn with_kdf(mut self, kdf: Kdf) -> Self {
+        self.kdf = kdf;
+        self
+    }
+
+    /// Send finite-field public keys as exactly ⌈|p|/8⌉ bytes, so their length does not
+    /// reveal their magnitude, and accept only canonical fixed-width keys back
+    ///
+    /// Needs a server that supports fixed-width keys; others close the connection.
+    pub fn with_fixed_width_keys(mut self, enabled: bool) -> Self {
+        self.fixed_width_keys = enabled;
+        self
+    }
+
+    /// Encode every message after ClientHello with `codec` (every message at all, for
+    /// `Codec::Json`)
+    ///
+    /// Needs a server that supports the codec, or for `Codec::Json` one started in
+    /// JSON mode; with any other server the handshake fails.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Rekey the session once it reaches the policy's thresholds
+    ///
+    /// The client always answers rekeys the server starts; starting them needs a
+    /// server that lists rekey support, which older servers do not have.
+    pub fn with_rekey_policy(mut self, policy: RekeyPolicy) -> Self {
+        self.rekey = policy;
+        self
+    }
+
+    /// Ping the server when it goes quiet, and fail reads with TimedOut once it stops
+    /// answering
+    ///
+    /// Pings are only sent while the client waits in `receive_message` (or `Read`);
+    /// a client that never reads should call `keepalive` from time to time.
+    pub fn with_keepalive(mut self, policy: KeepalivePolicy) -> Self {
+        self.keepalive = Some(policy);
+        self
+    }
+
+    /// Ask the server to pad its records and encrypted handshake messages, padding
+    /// ours by `padding`
+    ///
+    /// Padded records also carry their content type inside the encryption, so an
+    /// observer cannot tell application data from Pings, rekeys or closes. Servers that
+    /// do not support padding fail at the first record.
+    pub fn with_padding(mut self, padding: Padding) -> Self {
+        self.padding = Some(padding);
+        self
+    }
+
+    /// Limit the size of the handshake messages the server sends (by default, frames
+    /// of `MAX_FRAME` bytes and integers of up to `MAX_GROUP_BITS` bits)
+    ///
+    /// A server choosing a larger group than the limit allows fails the handshake
+    /// with a DecodeError alert.
+    pub fn with_message_limits(mut self, limits: MessageLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Narrow ClientHello to what this server selected last time
+    ///
+    /// If the cache holds an entry for the server, only the cached algorithm and
+    /// cipher are offered (as long as they are still configured here). A failed
+    /// narrowed handshake drops the entry, so a retry offers everything again.
+    pub fn with_capability_cache(mut self, cache: CapabilityCache) -> Self {
+        self.capability_cache = Some(cache);
+        self
+    }
+
+    /// Ask servers for session tickets, and present the one in this store for the
+    /// server, if any, to resume its session
+    ///
+    /// A resumed handshake skips the key exchange and the server's signature, trusting
+    /// the session that issued the ticket instead. Each ticket is used once; the server
+    /// issues a fresh one at the end of every handshake.
+    pub fn with_ticket_store(mut self, store: TicketStore) -> Self {
+        self.tickets = Some(store);
+        self
+    }
+
+    /// Select the handshake profile
+    ///
+    /// Compact replaces the offered algorithms with X25519 then finite-field DH
+    /// (which servers answer with a group ID when they use a well-known group),
+    /// offers only ChaCha20-Poly1305, and turns GREASE off. Builder calls made
+    /// after this one override those choices.
+    pub fn with_profile(mut self, profile: HandshakeProfile) -> Self {
+        if profile == HandshakeProfile::Compact {
+            self.kex_algorithms = vec![KexAlgorithm::X25519, KexAlgorithm::FiniteField];
+            self.ciphers = vec![CipherSuite::ChaCha20Poly1305];
+            self.grease = false;
+        }
+        self.profile = profile;
+        self
+    }
+
     /// Perform the Diffie-Hellman key exchange with the server
     pub fn perform_key_exchange(&mut self) -> std::io::Result<BigInt> {
         println!("[CLIENT] Starting DH key exchange with {}", self.server_addr);
 
-        // Step 1: Send ClientHello
+        let cached = self.capability_cache.as_ref().and_then(|cache| cache.get(&self.server_addr)).filter(
+            |cached| self.kex_algorithms.contains(&cached.kex_algorithm) && self.ciphers.contains(&cached.cipher),
+        );
+        let (kex_algorithms, ciphers) = match cached {
+            Some(cached) => {
+                println!(
+                    "[CLIENT] Offering cached capabilities {} with {}",
+                    cached.kex_algorithm.name(),
+                    cached.cipher.name()
+                );
+                (vec![cached.kex_algorithm], vec![cached.cipher])
+            }
+            None => (self.kex_algorithms.clone(), self.ciphers.clone()),
+        };
+
+        let result = self.handshake(&kex_algorithms, &ciphers);
+        if let Some(cache) = &self.capability_cache {
+            match &result {
+                Ok((_, capabilities)) => cache.store(&self.server_addr, *capabilities),
+                Err(_) if cached.is_some() => {
+                    println!("[CLIENT] Narrowed handshake failed; dropping cached capabilities for {}", self.server_addr);
+                    cache.invalidate(&self.server_addr);
+                }
+                Err(_) => {}
+            }
+        }
+        result.map(|(shared_secret, _)| shared_secret)
+    }
+
+    /// Run the handshake offering exactly these algorithms and ciphers
+    ///
+    /// # Returns
+    /// The shared secret and what the server selected
+    fn handshake(
+        &mut self,
+        kex_algorithms: &[KexAlgorithm],
+        ciphers: &[CipherSuite],
+    ) -> std::io::Result<(BigInt, ServerCapabilities)> {
+        self.selected_kex = KexAlgorithm::FiniteField;
+        self.group_params = None;
+
+        // Step 1: Send ClientHello listing our key-exchange algorithms
         println!("[CLIENT] Sending ClientHello");
-        let client_hello = DHMessage::ClientHello;
-        write_message(&mut self.stream, &client_hello)?;
+        let nonce = match &self.server_key {
+            Some(_) => with_rng(RngPurpose::Nonce, |rng| rng.r#gen::<[u8; 32]>().to_vec()),
+            None => Vec::new(),
+        };
+        let mut offered_kex: Vec<u8> = kex_algorithms.iter().map(KexAlgorithm::id).collect();
+        if self.protect_identities {
+            offered_kex.push(PROTECT_IDENTITIES_SIGNAL);
+        }
+        if self.require_prime_certificate && self.proposed_params.is_none() {
+            offered_kex.push(PRIME_CERTIFICATE_SIGNAL);
+        }
+        if self.proposed_params.is_some() {
+            offered_kex.push(CLIENT_PARAMS_SIGNAL);
+        }
+        if self.kdf == Kdf::OneStep {
+            offered_kex.push(ONE_STEP_KDF_SIGNAL);
+        }
+        if self.fixed_width_keys {
+            offered_kex.push(FIXED_WIDTH_KEYS_SIGNAL);
+        }
+        if self.padding.is_some() {
+            offered_kex.push(RECORD_PADDING_SIGNAL);
+        }
+        offered_kex.extend(self.codec.signal());
+        offered_kex.push(REKEY_SIGNAL);
+        if self.tickets.is_some() {
+            offered_kex.push(SESSION_TICKET_SIGNAL);
+        }
+        // A ticket only resumes a session whose cipher and KDF we would still pick, over
+        // a group we still accept
+        let ticket = self
+            .tickets
+            .as_ref()
+            .and_then(|store| store.take(&self.server_addr))
+            .filter(|ticket| ticket.resumption.kdf == self.kdf && ciphers.contains(&ticket.resumption.cipher))
+            .filter(|ticket| {
+                self.groups.is_empty()
+                    || ticket.params_fingerprint.is_none_or(|fingerprint| {
+                        self.groups.iter().any(|group| {
+                            let (p, g) = group.params();
+                            params_fingerprint(&p, &g) == fingerprint
+                        })
+                    })
+            });
+        if ticket.is_some() {
+            println!("[CLIENT] Presenting a session ticket");
+        }
+        let mut client_hello = DHMessage::ClientHello {
+            kex_algorithms: offered_kex,
+            ciphers: ciphers.iter().map(CipherSuite::id).collect(),
+            nonce,
+            versions: PROTOCOL_VERSIONS.to_vec(),
+            groups: self.groups.iter().map(DhGroup::id).collect(),
+            ticket: ticket.as_ref().map(|ticket| ticket.ticket.clone()).unwrap_or_default(),
+            random: with_rng(RngPurpose::Nonce, |rng| rng.r#gen()),
+            cookie: Vec::new(),
+        };
+        self.send_hello(&client_hello)?;
 
-        // Step 2: Receive ServerHello with (p, g)
+        // Step 2: Receive ServerHello with (p, g), a named group, or another selected algorithm
         println!("[CLIENT] Waiting for ServerHello");
-        let server_hello = read_message(&mut self.stream)?;
+        let mut server_hello = self.read_handshake_message()?;
+        // A server requiring cookies answers with a HelloRetry first and closes the
+        // connection; the handshake starts over on a new one once we repeat the Hello
+        // with its cookie, and the solution to its puzzle if it has one
+        if let Some(DHMessage::HelloRetry { cookie: issued }) = server_hello {
+            println!("[CLIENT] Server sent HelloRetry; reconnecting to repeat ClientHello with its cookie");
+            let answer = answer_hello_retry(&issued).ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "HelloRetry puzzle is too hard to solve")
+            })?;
+            if let DHMessage::ClientHello { cookie, .. } = &mut client_hello {
+                *cookie = answer;
+            }
+            self.reconnect()?;
+            self.transcript = Transcript::new();
+            self.send_hello(&client_hello)?;
+            server_hello = self.read_handshake_message()?;
+        }
+        if let Some(message) = &server_hello {
+            self.transcript.record(message);
+        }
 
-        let (prime, base) = match server_hello {
-            Some(DHMessage::ServerHello { p, g }) => {
+        // Whatever the variant, the server must pick a version we listed
+        if let Some(version) = server_hello.as_ref().and_then(DHMessage::protocol_version) {
+            if !PROTOCOL_VERSIONS.contains(&version) {
+                eprintln!("[CLIENT] Server selected protocol version {} which we did not offer", version);
+                self.report(AnomalyKind::NegotiationFailed(format!("protocol version {} was not offered", version)));
+                return Err(self.alert(AlertCode::HandshakeFailure, "Server selected a protocol version we did not offer"));
+            }
+            println!("[CLIENT] Server selected protocol version {}", version);
+            self.protocol_version = Some(version);
+        }
+
+        // A server accepting our ticket skips straight to the Finished messages
+        if let Some(DHMessage::ServerHelloResume { cipher, .. }) = &server_hello {
+            let Some(ticket) = ticket.filter(|ticket| ticket.resumption.cipher.id() == *cipher) else {
+                eprintln!("[CLIENT] Server resumed a session we did not offer");
+                self.report(AnomalyKind::ProtocolViolation("server resumed a session we did not offer".to_string()));
+                return Err(self.alert(AlertCode::IllegalParameter, "Server resumed a session we did not offer"));
+            };
+            println!("[CLIENT] Server resumed the session with cipher {}", ticket.resumption.cipher.name());
+            self.resumed = true;
+            let shared_secret = ticket.resumption.shared_secret();
+            self.confirm_keys(&shared_secret, ticket.capabilities, ticket.server_fingerprint, ticket.params_fingerprint)?;
+            return Ok((shared_secret, ticket.capabilities));
+        }
+        if ticket.is_some() {
+            println!("[CLIENT] Server declined the session ticket");
+        }
+
+        // With groups listed, the server must pick one of them for finite-field algorithms
+        if !self.groups.is_empty() {
+            let accepted = match &server_hello {
+                Some(DHMessage::ServerHello { .. }) => false,
+                Some(
+                    DHMessage::ServerHelloNamed { group, .. }
+                    | DHMessage::ServerHelloHmqv { group, .. }
+                    | DHMessage::ServerHelloSrp { group, .. }
+                    | DHMessage::ServerHelloPadded { group, .. },
+                ) => self.groups.iter().any(|accepted| accepted.id() == *group),
+                _ => true,
+            };
+            if !accepted {
+                eprintln!("[CLIENT] Server selected a group outside {:?}", self.groups);
+                self.report(AnomalyKind::NegotiationFailed("server selected a group we did not offer".to_string()));
+                return Err(self.alert(AlertCode::IllegalParameter, "Server selected a group we did not offer"));
+            }
+        }
+
+        // Having proposed parameters, we accept finite-field algorithms only over them
+        if let Some((p, g)) = &self.proposed_params {
+            let accepted = match &server_hello {
+                Some(DHMessage::ServerHello { p: server_p, g: server_g, .. }) => {
+                    p.magnitude() == server_p && g.magnitude() == server_g
+                }
+                Some(
+                    DHMessage::ServerHelloNamed { group, .. }
+                    | DHMessage::ServerHelloHmqv { group, .. }
+                    | DHMessage::ServerHelloSrp { group, .. }
+                    | DHMessage::ServerHelloPadded { group, .. },
+                ) => DhGroup::from_id(*group).is_some_and(|group| &group.prime() == p && &group.generator() == g),
+                _ => true,
+            };
+            if !accepted {
+                eprintln!("[CLIENT] Server selected parameters other than the ones we proposed");
+                self.report(AnomalyKind::NegotiationFailed("server selected parameters we did not propose".to_string()));
+                return Err(self.alert(AlertCode::IllegalParameter, "Server selected parameters we did not propose"));
+            }
+        }
+
+        let offers_ffdh = kex_algorithms.contains(&KexAlgorithm::FiniteField);
+        let offers_padded_ffdh = kex_algorithms.contains(&KexAlgorithm::FiniteFieldPadded);
+        let hmqv_key = self.static_key.clone().filter(|_| kex_algorithms.contains(&KexAlgorithm::Hmqv));
+        let srp_credentials = self.srp_credentials.clone().filter(|_| kex_algorithms.contains(&KexAlgorithm::Srp));
+        let mut named_group = None;
+        let mut explicit_params = None;
+        let mut hmqv = None;
+        // The prime whose width fixed-width keys have, once the server picks ffdh
+        let mut fixed_width_prime = None;
+        let (kex, cipher): (Box<dyn KeyExchange>, u8) = match server_hello {
+            Some(DHMessage::ServerHello { p, g, cipher, .. }) if offers_ffdh => {
                 println!("[CLIENT] Received ServerHello with p and g");
-                (p, g)
+                let (p, g) = (BigInt::from(p), BigInt::from(g));
+                if self.profile == HandshakeProfile::Compact {
+                    println!("[CLIENT] Server sent explicit parameters; a named group would save {} bytes", p.bits() / 8);
+                }
+                // Every group this protocol negotiates uses a safe prime p = 2q + 1
+                let q = if self.require_prime_certificate && self.proposed_params.is_none() {
+                    self.read_prime_certificate(&p)?
+                } else {
+                    (&p - 1) / 2
+                };
+                if let Err(reason) = validate_generator(&p, &g, &q) {
+                    eprintln!("[CLIENT] Rejecting parameters: {}", reason);
+                    self.report(AnomalyKind::ParametersRejected(reason));
+                    return Err(self.alert(AlertCode::IllegalParameter, reason));
+                }
+                let secret = generate_secret_key(&p, self.exponent_policy);
+                let exchange = FiniteFieldKeyExchange::blinded(&p, &g, &q, secret, self.blinding, self.exponent_policy);
+                explicit_params = Some((p.clone(), g));
+                fixed_width_prime = Some(p).filter(|_| self.fixed_width_keys);
+                (Box::new(exchange), cipher)
             }
-            _ => {
-                eprintln!("[CLIENT] Expected ServerHello, got {:?}", server_hello);
+            Some(DHMessage::ServerHelloNamed { group, cipher, .. }) if offers_ffdh => match DhGroup::from_id(group) {
+                Some(named) => {
+                    println!("[CLIENT] Received ServerHello for named group {:?}", named);
+                    named_group = Some(named);
+                    let (p, g) = named.params();
+                    let secret = generate_secret_key(&p, self.exponent_policy);
+                    let exchange = FiniteFieldKeyExchange::blinded(&p, &g, &named.subgroup_order(), secret, self.blinding, self.exponent_policy);
+                    fixed_width_prime = Some(p).filter(|_| self.fixed_width_keys);
+                    (Box::new(exchange), cipher)
+                }
+                None => {
+                    eprintln!("[CLIENT] Server selected unknown group ID {}", group);
+                    self.report(AnomalyKind::ProtocolViolation(format!("unknown group ID {}", group)));
+                    return Err(self.alert(AlertCode::IllegalParameter, "Unknown named group"));
+                }
+            },
+            Some(DHMessage::ServerHelloHmqv { group, cipher, .. }) if hmqv_key.is_some() => match DhGroup::from_id(group) {
+                Some(named) => {
+                    println!("[CLIENT] Received ServerHello selecting hmqv over {:?}", named);
+                    named_group = Some(named);
+                    let (p, g) = named.params();
+                    let path = hmqv_key.expect("the match arm checks for a static key");
+                    let secret = static_key::load_or_generate(&path, &p)?;
+                    println!("[CLIENT] Using static DH key from {}", path.display());
+                    let exchange = HmqvKeyExchange::new(&p, &g, &named.subgroup_order(), secret, HmqvRole::Initiator, self.exponent_policy);
+                    hmqv = Some(exchange.clone());
+                    (Box::new(exchange), cipher)
+                }
+                None => {
+                    eprintln!("[CLIENT] Server selected unknown group ID {}", group);
+                    self.report(AnomalyKind::ProtocolViolation(format!("unknown group ID {}", group)));
+                    return Err(self.alert(AlertCode::IllegalParameter, "Unknown named group"));
+                }
+            },
+            Some(DHMessage::ServerHelloPadded { group, cipher, .. }) if offers_padded_ffdh => match DhGroup::from_id(group) {
+                Some(named) => {
+                    println!("[CLIENT] Received ServerHello selecting ffdh-padded over {:?}", named);
+                    named_group = Some(named);
+                    let (p, g) = named.params();
+                    let secret = generate_secret_key(&p, self.exponent_policy);
+                    let exchange = PaddedFiniteFieldKeyExchange::blinded(&p, &g, &named.subgroup_order(), secret, self.blinding, self.exponent_policy);
+                    (Box::new(exchange), cipher)
+                }
+                None => {
+                    eprintln!("[CLIENT] Server selected unknown group ID {}", group);
+                    self.report(AnomalyKind::ProtocolViolation(format!("unknown group ID {}", group)));
+                    return Err(self.alert(AlertCode::IllegalParameter, "Unknown named group"));
+                }
+            },
+            Some(DHMessage::ServerHelloSrp { group, cipher, .. }) if srp_credentials.is_some() => match DhGroup::from_id(group) {
+                Some(named) => {
+                    println!("[CLIENT] Received ServerHello selecting srp over {:?}", named);
+                    named_group = Some(named);
+                    let (username, password) = srp_credentials.expect("the match arm checks for credentials");
+                    (Box::new(SrpClientExchange::new(named, &username, &password, self.exponent_policy)), cipher)
+                }
+                None => {
+                    eprintln!("[CLIENT] Server selected unknown group ID {}", group);
+                    self.report(AnomalyKind::ProtocolViolation(format!("unknown group ID {}", group)));
+                    return Err(self.alert(AlertCode::IllegalParameter, "Unknown named group"));
+                }
+            },
+            Some(DHMessage::ServerHelloKex { algorithm, cipher, .. }) => match KexAlgorithm::from_id(algorithm)
+                .filter(|selected| kex_algorithms.contains(selected))
+                .and_then(curve_key_exchange)
+            {
+                Some(kex) => {
+                    println!("[CLIENT] Received ServerHello selecting {}", kex.algorithm().name());
+                    (kex, cipher)
+                }
+                None => {
+                    eprintln!("[CLIENT] Server selected algorithm {} which we did not offer", algorithm);
+                    self.report(AnomalyKind::NegotiationFailed(format!("algorithm {} was not offered", algorithm)));
+                    return Err(self.alert(AlertCode::HandshakeFailure, "Server selected an algorithm we did not offer"));
+                }
+            },
+            Some(DHMessage::Abort { reason: ABORT_VERSION_MISMATCH }) => {
+                eprintln!("[CLIENT] Server speaks none of protocol versions {:?}", PROTOCOL_VERSIONS);
+                self.report(AnomalyKind::NegotiationFailed(format!("server speaks none of protocol versions {:?}", PROTOCOL_VERSIONS)));
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Server speaks none of our protocol versions",
+                ));
+            }
+            Some(DHMessage::Abort { reason: ABORT_UNAUTHENTICATED }) => {
+                eprintln!("[CLIENT] Server cannot authenticate itself");
+                self.report(AnomalyKind::AuthenticationFailed("server has no identity key"));
                 return Err(std::io::Error::new(
                     std::io::ErrorKind::InvalidData,
-                    "Invalid response from server",
+                    "Server cannot authenticate itself",
                 ));
             }
+            _ => {
+                eprintln!("[CLIENT] Expected ServerHello, got {:?}", server_hello);
+                self.report(AnomalyKind::ProtocolViolation(format!("expected ServerHello, got {:?}", server_hello)));
+                return Err(self.alert(AlertCode::unexpected(&server_hello), "Invalid response from server"));
+            }
         };
+        self.selected_kex = kex.algorithm();
+        self.group_params = explicit_params.or_else(|| named_group.map(|named| named.params()));
 
-        // Step 3: Generate client's secret exponent and compute public key
-        println!("[CLIENT] Generating client secret exponent");
-        let client_secret = generate_secret_key(&prime);
-        let client_public_key = compute_public_key(&client_secret, &base, &prime);
+        let cipher = match CipherSuite::from_id(cipher).filter(|selected| ciphers.contains(selected)) {
+            Some(cipher) => cipher,
+            None => {
+                eprintln!("[CLIENT] Server selected cipher {} which we did not offer", cipher);
+                self.report(AnomalyKind::NegotiationFailed(format!("cipher {} was not offered", cipher)));
+                return Err(self.alert(AlertCode::HandshakeFailure, "Server selected a cipher we did not offer"));
+            }
+        };
+        println!("[CLIENT] Server selected cipher {}", cipher.name());
+        if !cipher.encrypts() {
+            eprintln!("[CLIENT] WARNING: {} does not encrypt; application data is readable on the wire", cipher.name());
+        }
 
-        println!("[CLIENT] Sending ClientPublicKey");
-        let client_key_msg = DHMessage::ClientPublicKey {
-            x: client_public_key.clone(),
+        // Step 3: Generate client's ephemeral key pair and send the public key; with
+        // protected HMQV the static key waits for handshake encryption
+        println!("[CLIENT] Generating client {} key pair", kex.algorithm().name());
+        let protected_hmqv = hmqv.filter(|_| self.protect_identities);
+        let client_key_msg = match (kex.algorithm(), &protected_hmqv) {
+            (KexAlgorithm::FiniteField, _) if fixed_width_prime.is_some() => DHMessage::ClientPublicKeyFixed {
+                key: to_fixed_width(&kex.public_key(), fixed_width_prime.as_ref().expect("checked by the guard")),
+            },
+            (KexAlgorithm::FiniteField, _) => DHMessage::ClientPublicKey {
+                x: BigUint::from_bytes_be(&kex.public_key()),
+            },
+            (_, Some(hmqv)) => DHMessage::ClientKeyShare {
+                key: hmqv.ephemeral_public_key(),
+            },
+            (_, None) => DHMessage::ClientKeyShare {
+                key: kex.public_key(),
+            },
         };
-        write_message(&mut self.stream, &client_key_msg)?;
 
-        // Step 4: Receive ServerPublicKey
-        println!("[CLIENT] Waiting for ServerPublicKey");
-        let server_key_msg = read_message(&mut self.stream)?;
+        // Done carries nothing, so the compact profile sends it in the same flight as
+        // the key; the transcript still records it after the server's key, as the
+        // server sees it. Protected HMQV sends our static key before Done
+        let done_msg = DHMessage::Done;
+        let done_with_key = self.profile == HandshakeProfile::Compact && protected_hmqv.is_none();
+        self.send_grease()?;
+        if done_with_key {
+            println!("[CLIENT] Sending client public key and Done");
+            write_messages(&mut self.stream, self.wire_codec, &[&client_key_msg, &done_msg])?;
+        } else {
+            println!("[CLIENT] Sending client public key");
+            write_message(&mut self.stream, self.wire_codec, &client_key_msg)?;
+        }
+        self.transcript.record(&client_key_msg);
+
+        // Step 4: Receive the server's public key
+        println!("[CLIENT] Waiting for server public key");
+        let server_key_msg = self.read_handshake_message()?;
+        if let Some(message) = &server_key_msg {
+            self.transcript.record(message);
+        }
 
-        let server_public_key = match server_key_msg {
-            Some(DHMessage::ServerPublicKey { y }) => {
+        let server_public_key = match (kex.algorithm(), server_key_msg) {
+            (KexAlgorithm::FiniteField, Some(DHMessage::ServerPublicKey { y })) if fixed_width_prime.is_none() => {
                 println!("[CLIENT] Received ServerPublicKey");
-                y
+                y.to_bytes_be()
             }
-            _ => {
-                eprintln!("[CLIENT] Expected ServerPublicKey, got {:?}", server_key_msg);
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "Invalid response from server",
-                ));
+            (KexAlgorithm::FiniteField, Some(DHMessage::ServerPublicKeyFixed { key })) if fixed_width_prime.is_some() => {
+                println!("[CLIENT] Received ServerPublicKeyFixed");
+                let p = fixed_width_prime.as_ref().expect("checked by the guard");
+                if let Err(reason) = check_fixed_width(&key, p) {
+                    eprintln!("[CLIENT] Aborting key exchange: {}", reason);
+                    self.report(AnomalyKind::PublicKeyRejected(reason));
+                    return Err(self.alert(AlertCode::IllegalParameter, reason));
+                }
+                key
+            }
+            (algorithm, Some(DHMessage::ServerKeyShare { key })) if algorithm != KexAlgorithm::FiniteField => {
+                println!("[CLIENT] Received ServerKeyShare");
+                key
+            }
+            (_, other) => {
+                eprintln!("[CLIENT] Expected server public key, got {:?}", other);
+                self.report(AnomalyKind::ProtocolViolation(format!("expected server public key, got {:?}", other)));
+                return Err(self.alert(AlertCode::unexpected(&other), "Invalid response from server"));
             }
         };
 
-        // Step 5: Send Done
-        println!("[CLIENT] Sending Done");
-        let done_msg = DHMessage::Done;
-        write_message(&mut self.stream, &done_msg)?;
+        // Protected HMQV: the server's static key follows, sealed under keys from the
+        // ephemeral exchange
+        let mut protection = None;
+        let server_public_key = match &protected_hmqv {
+            Some(hmqv) => {
+                let ephemeral_secret = match hmqv.ephemeral_shared_secret(&server_public_key) {
+                    Ok(secret) => secret,
+                    Err(reason) => {
+                        eprintln!("[CLIENT] Aborting key exchange: {}", reason);
+                        self.report(AnomalyKind::PublicKeyRejected(reason));
+                        return Err(self.alert(AlertCode::IllegalParameter, reason));
+                    }
+                };
+                let mut handshake_protection = self.handshake_protection(&ephemeral_secret);
+                let static_key_msg = self.read_handshake_message()?;
+                let server_static_key = match handshake_protection.open(static_key_msg) {
+                    Ok(message) if self.limits.check(&message, KexAlgorithm::Hmqv).is_err() => {
+                        eprintln!("[CLIENT] Aborting key exchange: static key exceeds our size limits");
+                        self.report(AnomalyKind::ProtocolViolation("oversized static key".to_string()));
+                        return Err(self.alert(AlertCode::DecodeError, "Static key exceeds the size limits"));
+                    }
+                    Ok(DHMessage::StaticKey { key }) => key,
+                    Ok(other) => {
+                        eprintln!("[CLIENT] Expected StaticKey, got {:?}", other);
+                        self.report(AnomalyKind::ProtocolViolation(format!("expected StaticKey, got {:?}", other)));
+                        return Err(self.alert(AlertCode::UnexpectedMessage, "Invalid response from server"));
+                    }
+                    Err(reason) => {
+                        eprintln!("[CLIENT] Aborting key exchange: {}", reason);
+                        self.report(AnomalyKind::AuthenticationFailed(reason));
+                        return Err(self.alert(AlertCode::DecryptError, reason));
+                    }
+                };
+                println!("[CLIENT] Received encrypted StaticKey");
+                self.transcript.record(&DHMessage::StaticKey { key: server_static_key.clone() });
+                protection = Some(handshake_protection);
+                join_share(&server_static_key, &server_public_key)
+            }
+            None => server_public_key,
+        };
+
+        // For HMQV only the server's static key is pinned; its ephemeral key is fresh
+        let server_identity_key = kex.identity_key(&server_public_key).to_vec();
+        if let Some(pinned) = self.pinned_fingerprint {
+            if Fingerprint::of(&server_identity_key) != pinned {
+                eprintln!("[CLIENT] Aborting key exchange: server key fingerprint does not match the pinned one");
+                self.report(AnomalyKind::AuthenticationFailed("server key fingerprint does not match"));
+                return Err(self.alert(AlertCode::AuthenticationFailed, "Server key fingerprint does not match"));
+            }
+            println!("[CLIENT] Server key matches the pinned fingerprint");
+        } else if kex.algorithm() == KexAlgorithm::Hmqv {
+            eprintln!("[CLIENT] WARNING: hmqv without a pinned server fingerprint does not tell us who the server is");
+        }
 
-        // Step 6: Compute shared secret: Y^secret mod p
+        // Step 4b: Compute shared secret, rejecting reflected or degenerate keys
         println!("[CLIENT] Computing shared secret");
-        let shared_secret = mod_pow(&server_public_key, &client_secret, &prime);
+        let shared_secret = match kex.shared_secret(&server_public_key) {
+            Ok(secret) => secret,
+            Err(reason) => {
+                eprintln!("[CLIENT] Aborting key exchange: {}", reason);
+                self.report(AnomalyKind::PublicKeyRejected(reason));
+                return Err(self.alert(AlertCode::IllegalParameter, reason));
+            }
+        };
+
+        // Step 5: Check the server's signature over the transcript so far, which must
+        // arrive encrypted if we asked for identity protection
+        if let Some(expected_key) = self.server_key.clone() {
+            if self.protect_identities && protection.is_none() {
+                protection = Some(self.handshake_protection(&shared_secret));
+            }
+            let transcript_hash = self.transcript.hash();
+            let signature_msg = self.read_handshake_message()?;
+            let signature_msg = match protection.as_mut() {
+                Some(protection) => protection.open(signature_msg).ok(),
+                None => signature_msg,
+            };
+            let verified = match &signature_msg {
+                Some(DHMessage::ServerSignature { scheme, public_key, signature }) => {
+                    expected_key.verify(*scheme, public_key, &transcript_hash, signature)
+                }
+                _ if protection.is_some() => Err("server did not sign the handshake under handshake encryption"),
+                _ => Err("server did not sign the handshake"),
+            };
+            if let Err(reason) = verified {
+                eprintln!("[CLIENT] Aborting key exchange: {}", reason);
+                self.report(AnomalyKind::AuthenticationFailed(reason));
+                return Err(self.alert(AlertCode::AuthenticationFailed, reason));
+            }
+            println!("[CLIENT] Verified server identity {}", expected_key.describe());
+            println!("[CLIENT] Identity fingerprint: {}", expected_key.fingerprint());
+            if let Some(message) = &signature_msg {
+                self.transcript.record(message);
+            }
+        }
+
+        // Step 5b: Protected HMQV: send our static key under handshake encryption. As in
+        // TLS 1.3 this hides it from passive observers, not from an active attacker
+        if let (Some(hmqv), Some(protection)) = (&protected_hmqv, protection.as_mut()) {
+            println!("[CLIENT] Sending encrypted StaticKey");
+            let static_key_msg = DHMessage::StaticKey { key: hmqv.static_public_key() };
+            write_message(&mut self.stream, self.wire_codec, &protection.seal(&static_key_msg))?;
+            self.transcript.record(&static_key_msg);
+        }
+
+        // Step 6: Send Done, unless it already went out with the key
+        if !done_with_key {
+            println!("[CLIENT] Sending Done");
+            write_message(&mut self.stream, self.wire_codec, &done_msg)?;
+        }
+        self.transcript.record(&done_msg);
+
+        let capabilities = ServerCapabilities {
+            kex_algorithm: kex.algorithm(),
+            cipher,
+            group: named_group,
+        };
+        let session_params = self.group_params.as_ref().map(|(p, g)| params_fingerprint(p, g));
+        self.confirm_keys(&shared_secret, capabilities, Fingerprint::of(&server_identity_key), session_params)?;
+        Ok((shared_secret, capabilities))
+    }
+
+    /// Step 7: Confirm both sides derived the same keys, keep any ticket the server
+    /// issues, and set up the record layer
+    ///
+    /// The Finished messages (and the ticket) are not recorded, so the transcript, and
+    /// channel binding, end at Done, or at ServerHelloResume in a resumed handshake.
+    /// `session_params` is the fingerprint of the session's finite-field parameters,
+    /// recorded with the ticket.
+    fn confirm_keys(
+        &mut self,
+        shared_secret: &BigInt,
+        capabilities: ServerCapabilities,
+        server_fingerprint: Fingerprint,
+        session_params: Option<[u8; 32]>,
+    ) -> std::io::Result<()> {
+        let keys = SessionKeys::derive(self.kdf, shared_secret, &self.transcript);
+        let transcript_hash = self.transcript.hash();
+        println!("[CLIENT] Sending ClientFinished");
+        write_message(&mut self.stream, self.wire_codec, &DHMessage::ClientFinished {
+            verify_data: keys.finished(CLIENT_FINISHED_LABEL, &transcript_hash),
+        })?;
+
+        println!("[CLIENT] Waiting for ServerFinished");
+        let server_finished = self.read_handshake_message()?;
+        let confirmed = match &server_finished {
+            Some(DHMessage::ServerFinished { verify_data }) => {
+                keys.verify_finished(SERVER_FINISHED_LABEL, &transcript_hash, verify_data)
+            }
+            _ => {
+                eprintln!("[CLIENT] Expected ServerFinished, got {:?}", server_finished);
+                self.report(AnomalyKind::ProtocolViolation(format!("expected ServerFinished, got {:?}", server_finished)));
+                return Err(self.alert(AlertCode::unexpected(&server_finished), "Invalid response from server"));
+            }
+        };
+        if !confirmed {
+            eprintln!("[CLIENT] Aborting key exchange: server Finished does not verify");
+            self.report(AnomalyKind::AuthenticationFailed("server Finished does not verify"));
+            return Err(self.alert(AlertCode::DecryptError, "Server Finished does not verify"));
+        }
+        println!("[CLIENT] Received ServerFinished");
+
+        if let Some(store) = self.tickets.clone() {
+            match self.read_handshake_message()? {
+                Some(DHMessage::NewSessionTicket { ticket, .. }) if ticket.is_empty() => {
+                    println!("[CLIENT] Server issues no session tickets");
+                }
+                Some(DHMessage::NewSessionTicket { lifetime, ticket }) => {
+                    println!("[CLIENT] Received a session ticket valid for {}s", lifetime);
+                    store.store(&self.server_addr, SessionTicket {
+                        ticket,
+                        resumption: Resumption::of(&keys, capabilities.cipher),
+                        capabilities,
+                        server_fingerprint,
+                        params_fingerprint: session_params,
+                        expires: Instant::now() + Duration::from_secs(lifetime.into()),
+                    });
+                }
+                other => {
+                    eprintln!("[CLIENT] Expected NewSessionTicket, got {:?}", other);
+                    self.report(AnomalyKind::ProtocolViolation(format!("expected NewSessionTicket, got {:?}", other)));
+                    return Err(self.alert(AlertCode::unexpected(&other), "Expected a session ticket"));
+                }
+            }
+        }
 
         println!("[CLIENT] DH key exchange complete!");
-        println!("[CLIENT] Shared secret established: {}", shared_secret);
 
-        Ok(shared_secret)
+        let binding = channel_binding(shared_secret, &transcript_hash);
+        println!("[CLIENT] Channel binding: {}", to_hex(&binding));
+        println!("[CLIENT] Server key fingerprint: {}", server_fingerprint);
+        self.channel_binding = Some(binding);
+        self.server_fingerprint = Some(server_fingerprint);
+        let mut records = RecordLayer::client(&keys, capabilities.cipher).with_rekeying(self.rekey);
+        if let Some(policy) = self.keepalive {
+            records = records.with_keepalive(policy);
+        }
+        if let Some(padding) = self.padding {
+            records = records.with_padding(padding);
+        }
+        let channel = SecureChannel::new(self.stream.try_clone()?, records, self.cancel.clone());
+        self.channel = Some(channel.with_anomaly_listener(self.anomaly_listener.clone(), &self.server_addr));
+        self.session_keys = Some(keys);
+        Ok(())
     }
 
-    /// Send a message to the server (after key exchange)
-    pub fn send_message(&mut self, data: &[u8]) -> std::io::Result<()> {
-        self.stream.write_all(data)?;
-        self.stream.flush()?;
+    /// Read the PrimeCertificate following an explicit ServerHello and check it proves p
+    ///
+    /// # Returns
+    /// The subgroup order q = (p - 1) / 2, proven prime along with p
+    fn read_prime_certificate(&mut self, p: &BigInt) -> std::io::Result<BigInt> {
+        let message = self.read_handshake_message()?;
+        if let Some(message) = &message {
+            self.transcript.record(message);
+        }
+        let certificate = match message {
+            Some(DHMessage::PrimeCertificate { certificate }) => PrimeCertificate::from_bytes(&certificate),
+            other => {
+                eprintln!("[CLIENT] Expected PrimeCertificate, got {:?}", other);
+                self.report(AnomalyKind::ProtocolViolation(format!("expected PrimeCertificate, got {:?}", other)));
+                return Err(self.alert(AlertCode::unexpected(&other), "Expected a prime certificate"));
+            }
+        };
+        let verified = match certificate {
+            Some(certificate) => certificate.verify_safe_prime(p),
+            None => Err("server sent no valid prime certificate"),
+        };
+        match verified {
+            Ok(q) => {
+                println!("[CLIENT] Prime certificate verified: p is a safe prime");
+                Ok(q)
+            }
+            Err(reason) => {
+                eprintln!("[CLIENT] Rejecting parameters: {}", reason);
+                self.report(AnomalyKind::ParametersRejected(reason));
+                Err(self.alert(AlertCode::IllegalParameter, reason))
+            }
+        }
+    }
+
+    /// Handshake encryption keyed by `secret` and the transcript so far
+    fn handshake_protection(&self, secret: &BigInt) -> HandshakeProtection {
+        let protection = HandshakeProtection::client(secret, &self.transcript);
+        match self.padding {
+            Some(padding) => protection.with_padding(padding),
+            None => protection,
+        }
+    }
+
+    /// Replace the connection with a new one to the same server
+    fn reconnect(&mut self) -> std::io::Result<()> {
+        let stream = cancel::connect(&self.server_addr, &self.cancel)?;
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(30)))?;
+        self.stream = stream;
         Ok(())
     }
 
-    /// Receive a message from the server (after key exchange)
+    /// Send ClientHello, followed by the parameters we propose, if any
+    fn send_hello(&mut self, client_hello: &DHMessage) -> std::io::Result<()> {
+        self.wire_codec = self.codec.hello_codec();
+        self.send_grease()?;
+        write_message(&mut self.stream, self.wire_codec, client_hello)?;
+        self.transcript.record(client_hello);
+        self.wire_codec = self.codec;
+        if let Some((p, g)) = &self.proposed_params {
+            println!("[CLIENT] Proposing {}-bit parameters", p.bits());
+            let client_params = DHMessage::ClientParams {
+                p: p.magnitude().clone(),
+                g: g.magnitude().clone(),
+            };
+            write_message(&mut self.stream, self.wire_codec, &client_params)?;
+            self.transcript.record(&client_params);
+        }
+        Ok(())
+    }
+
+    /// Deliver an anomaly about this connection to the listener, if any
+    fn report(&self, kind: AnomalyKind) {
+        report(&self.anomaly_listener, &self.server_addr, kind);
+    }
+
+    /// Read the next handshake message, giving up early if cancelled
+    ///
+    /// An Alert from the server ends the handshake as an error carrying the `Alert`
+    /// (see `Alert::from_error`); a message over our limits is answered with a
+    /// decode_error alert and ends it as an error carrying the `LimitExceeded`.
+    fn read_handshake_message(&mut self) -> std::io::Result<Option<DHMessage>> {
+        wait_readable(&self.stream, &self.cancel, self.stream.read_timeout()?)?;
+        let message = match read_message_within(&mut self.stream, self.wire_codec, &self.limits, self.selected_kex) {
+            Err(error) if LimitExceeded::from_error(&error).is_some() => {
+                eprintln!("[CLIENT] Rejecting server message: {}", error);
+                let _ = write_message(&mut self.stream, self.wire_codec, &Alert::message(AlertCode::DecodeError, &error.to_string()));
+                return Err(error);
+            }
+            result => result?,
+        };
+        match message.as_ref().and_then(Alert::from_message) {
+            Some(alert) => {
+                eprintln!("[CLIENT] Server ended the handshake: {}", alert);
+                Err(alert.into())
+            }
+            None => Ok(message),
+        }
+    }
+
+    /// Tell the server why we abandon the handshake (best effort, as it may already
+    /// be gone)
+    ///
+    /// # Returns
+    /// The error the handshake fails with
+    fn alert(&mut self, code: AlertCode, reason: &'static str) -> std::io::Error {
+        let _ = write_message(&mut self.stream, self.wire_codec, &Alert::message(code, reason));
+        std::io::Error::new(std::io::ErrorKind::InvalidData, reason)
+    }
+
+    /// Send a GREASE message if enabled (not recorded in the transcript)
+    fn send_grease(&mut self) -> std::io::Result<()> {
+        if self.grease {
+            write_message(&mut self.stream, self.wire_codec, &DHMessage::grease())?;
+        }
+        Ok(())
+    }
+
+    /// Send a message to the server (after key exchange), encrypted as one record
+    pub fn send_message(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.established()?.send(data)
+    }
+
+    /// Send a message assembled from several buffers (after key exchange), encrypted as
+    /// one record without first concatenating the buffers
+    pub fn send_vectored(&mut self, parts: &[std::io::IoSlice]) -> std::io::Result<()> {
+        self.established()?.send_vectored(parts)
+    }
+
+    /// Receive and decrypt one record from the server (after key exchange)
+    ///
+    /// If a record was partly consumed through `Read`, its remaining bytes are
+    /// returned first.
+    ///
+    /// # Returns
+    /// The number of plaintext bytes written to `buffer`, or 0 if the server closed the connection
     pub fn receive_message(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
-        self.stream.read(buffer)
+        match self.established()?.receive()? {
+            Some(plaintext) if plaintext.len() <= buffer.len() => {
+                buffer[..plaintext.len()].copy_from_slice(&plaintext);
+                Ok(plaintext.len())
+            }
+            Some(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Buffer too small for received record",
+            )),
+            None => Ok(0),
+        }
+    }
+
+    /// Ping the server if it has been quiet for the keepalive interval (after key exchange)
+    ///
+    /// # Returns
+    /// A TimedOut error once the server missed too many Pings
+    pub fn keepalive(&mut self) -> std::io::Result<()> {
+        self.established()?.keepalive()
+    }
+
+    /// Close the connection cleanly (after key exchange)
+    ///
+    /// Sends Close and waits, up to the read timeout, for the server's Close in reply,
+    /// which confirms it read everything we sent; records the server sent meanwhile are
+    /// discarded. The stream is shut down either way.
+    ///
+    /// # Returns
+    /// An UnexpectedEof error if the server went away without confirming
+    pub fn close(&mut self) -> std::io::Result<()> {
+        let channel = self.established()?;
+        println!("[CLIENT] Closing connection");
+        channel.close()
+    }
+
+    /// Why the server closed the connection, if it said so before closing
+    ///
+    /// `CloseReason::Drained` means the server is shedding connections and the
+    /// client should reconnect.
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.channel.as_ref().and_then(SecureChannel::close_reason)
+    }
+
+    /// The encrypted channel to the server (after key exchange), which the client's
+    /// own `Read` and `Write` go through
+    pub fn channel(&mut self) -> Option<&mut SecureChannel> {
+        self.channel.as_mut()
+    }
+
+    fn established(&mut self) -> std::io::Result<&mut SecureChannel> {
+        self.channel.as_mut().ok_or_else(not_established)
     }
 
     /// Get the server address
     pub fn server_addr(&self) -> &str {
         &self.server_addr
     }
-}
 
-/// Read a DHMessage from the stream
-fn read_message(stream: &mut TcpStream) -> std::io::Result<Option<DHMessage>> {
-    let mut type_byte = [0; 1];
-    stream.read_exact(&mut type_byte)?;
+    /// Channel binding value for this session (after key exchange)
+    ///
+    /// External authentication protocols can sign or MAC this value to tie their
+    /// credentials to this specific DH session.
+    pub fn channel_binding(&self) -> Option<[u8; 32]> {
+        self.channel_binding
+    }
+
+    /// SHA-256 of the handshake transcript, through Done (after key exchange)
+    ///
+    /// The session keys and channel binding are derived from it. It covers the native
+    /// encoding of every message (see `Transcript`), so it is the same whichever codec
+    /// carried them.
+    pub fn transcript_hash(&self) -> Option<[u8; 32]> {
+        self.session_keys.as_ref().map(|_| self.transcript.hash())
+    }
+
+    /// Fingerprint of the server's public key for this session (after key exchange)
+    ///
+    /// Both sides print it; if the user sees the same words on the server, no one
+    /// substituted the server's key in transit.
+    pub fn server_fingerprint(&self) -> Option<Fingerprint> {
+        self.server_fingerprint
+    }
+
+    /// Prime and generator of the group the key exchange ran over (after ServerHello)
+    ///
+    /// None when the server selected a curve, or resumed a session instead.
+    pub fn group_params(&self) -> Option<&(BigInt, BigInt)> {
+        self.group_params.as_ref()
+    }
 
-    match type_byte[0] {
-        0 => Ok(Some(DHMessage::ClientHello)),
-        1 | 2 | 3 => {
-            // For ServerHello, ClientPublicKey, ServerPublicKey:
-            // First BigInt: [4-byte length][data]
-            // If ServerHello, second BigInt follows
-            let mut data = vec![type_byte[0]];
+    /// Whether the server resumed a session from our ticket (after key exchange)
+    pub fn resumed(&self) -> bool {
+        self.resumed
+    }
 
-            // Read first BigInt
-            let mut len_bytes = [0; 4];
-            stream.read_exact(&mut len_bytes)?;
-            data.extend_from_slice(&len_bytes);
+    /// Protocol version the server selected (after ServerHello)
+    pub fn protocol_version(&self) -> Option<u8> {
+        self.protocol_version
+    }
 
-            let len = u32::from_be_bytes(len_bytes) as usize;
-            let mut value_bytes = vec![0; len];
-            stream.read_exact(&mut value_bytes)?;
-            data.extend(value_bytes);
+    /// Symmetric session keys derived via HKDF (after key exchange)
+    ///
+    /// These are the handshake's keys; rekeys replace them inside the record layer only.
+    pub fn session_keys(&self) -> Option<&SessionKeys> {
+        self.session_keys.as_ref()
+    }
+}
 
-            // If ServerHello, read second BigInt
-            if type_byte[0] == 1 {
-                let mut len_bytes = [0; 4];
-                stream.read_exact(&mut len_bytes)?;
-                data.extend_from_slice(&len_bytes);
+/// Reads the decrypted byte stream through `SecureChannel` (after key exchange)
+///
+/// Records larger than the caller's buffer are handed out over several reads;
+/// Ok(0) means the server closed the connection.
+impl Read for DHClient {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.established()?.read(buf)
+    }
+}
 
-                let len = u32::from_be_bytes(len_bytes) as usize;
-                let mut value_bytes = vec![0; len];
-                stream.read_exact(&mut value_bytes)?;
-                data.extend(value_bytes);
-            }
+/// Encrypts written bytes through `SecureChannel`, as records of at most
+/// MAX_RECORD_PLAINTEXT bytes (after key exchange)
+///
+/// Every successful write sends one record straight away, so wrap the client in a
+/// BufWriter when writing many small pieces.
+impl Write for DHClient {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.established()?.write(buf)
+    }
 
-            Ok(DHMessage::from_bytes(&data))
-        }
-        4 => Ok(Some(DHMessage::Done)),
-        _ => Ok(None),
+    fn write_vectored(&mut self, bufs: &[std::io::IoSlice]) -> std::io::Result<usize> {
+        self.established()?.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
     }
 }
 
-/// Write a DHMessage to the stream
-fn write_message(stream: &mut TcpStream, message: &DHMessage) -> std::io::Result<()> {
-    let bytes = message.to_bytes();
-    stream.write_all(&bytes)?;
-    stream.flush()?;
-    Ok(())
+/// Error for application data calls made before the key exchange completed
+fn not_established() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::NotConnected, "Key exchange not complete")
 }
+
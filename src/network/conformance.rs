@@ -0,0 +1,399 @@
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::time::{Duration, Instant};
+
+use num_bigint::{BigInt, BigUint};
+use rand::Rng;
+
+use crate::crypto::cookie::answer_hello_retry;
+use crate::crypto::groups::DhGroup;
+use crate::crypto::kex::KexAlgorithm;
+use crate::crypto::record::CipherSuite;
+use crate::crypto::rng::{with_rng, RngPurpose};
+use crate::network::client::DHClient;
+use crate::network::alert::{Alert, AlertCode};
+use crate::network::framing::{self, read_message, Codec};
+use crate::structs::DH_Prot::{DHMessage, MAX_INTEGER_FIELD, PROTOCOL_VERSIONS};
+
+/// A single conformance check: Ok(detail) on pass, Err(detail) on failure
+type ConformanceTest = fn(&ConformanceSuite) -> Result<String, String>;
+
+/// Outcome of a single conformance test
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    /// Short identifier of the test
+    pub name: &'static str,
+    /// Whether the server behaved as the protocol requires
+    pub passed: bool,
+    /// Human readable explanation of the outcome
+    pub detail: String,
+}
+
+/// Pass/fail report for a whole conformance run
+#[derive(Debug, Clone)]
+pub struct ConformanceReport {
+    /// Address of the server under test
+    pub target: String,
+    /// Individual test outcomes, in execution order
+    pub results: Vec<TestResult>,
+}
+
+impl ConformanceReport {
+    /// True if every test passed
+    pub fn all_passed(&self) -> bool {
+        self.results.iter().all(|result| result.passed)
+    }
+
+    /// Print the report in a human readable table
+    pub fn print(&self) {
+        println!("Conformance report for {}", self.target);
+        for result in &self.results {
+            let status = if result.passed { "PASS" } else { "FAIL" };
+            println!("  [{}] {:<20} {}", status, result.name, result.detail);
+        }
+        let passed = self.results.iter().filter(|result| result.passed).count();
+        println!("{}/{} tests passed", passed, self.results.len());
+    }
+}
+
+/// Runs positive and negative protocol tests against any server implementing this protocol
+pub struct ConformanceSuite {
+    target: String,
+    /// How long the server may take to close a connection it should reject
+    reject_timeout: Duration,
+    /// How long the server may keep a stalled handshake open
+    idle_timeout: Duration,
+}
+
+impl ConformanceSuite {
+    /// Create a suite targeting the given server address
+    ///
+    /// # Arguments
+    /// * `target` - Server address (e.g., "127.0.0.1:8080")
+    /// * `idle_timeout` - Upper bound on how long the server may keep a silent client connected
+    pub fn new(target: &str, idle_timeout: Duration) -> Self {
+        ConformanceSuite {
+            target: target.to_string(),
+            reject_timeout: Duration::from_secs(5),
+            idle_timeout,
+        }
+    }
+
+    /// Run every test and collect the results
+    pub fn run(&self) -> ConformanceReport {
+        self.run_matching(|_| true)
+    }
+
+    /// Run only the tests with these names (e.g., "reflected_key"), in suite order
+    pub fn run_only(&self, names: &[&str]) -> ConformanceReport {
+        self.run_matching(|name| names.contains(&name))
+    }
+
+    fn run_matching(&self, selected: impl Fn(&str) -> bool) -> ConformanceReport {
+        let tests: [(&'static str, ConformanceTest); 12] = [
+            ("happy_path", Self::test_happy_path),
+            ("wrong_order", Self::test_wrong_order),
+            ("unknown_type", Self::test_unknown_type),
+            ("grease_tolerance", Self::test_grease_tolerance),
+            ("replayed_hello", Self::test_replayed_hello),
+            ("malformed_length", Self::test_malformed_length),
+            ("oversized_frame", Self::test_oversized_frame),
+            ("oversized_integer", Self::test_oversized_integer),
+            ("stalled_handshake", Self::test_stalled_handshake),
+            ("generator_as_key", Self::test_generator_as_key),
+            ("reflected_key", Self::test_reflected_key),
+            ("degenerate_key", Self::test_degenerate_key),
+        ];
+
+        let results = tests
+            .iter()
+            .filter(|(name, _)| selected(name))
+            .map(|(name, test)| {
+                println!("[CONFORMANCE] Running {}", name);
+                match test(self) {
+                    Ok(detail) => TestResult { name, passed: true, detail },
+                    Err(detail) => TestResult { name, passed: false, detail },
+                }
+            })
+            .collect();
+
+        ConformanceReport {
+            target: self.target.clone(),
+            results,
+        }
+    }
+
+    /// A well-behaved client must be able to complete the exchange, and to close the
+    /// connection with the server confirming
+    fn test_happy_path(&self) -> Result<String, String> {
+        let mut client = DHClient::new(&self.target).map_err(|e| format!("connect failed: {}", e))?;
+        client
+            .perform_key_exchange()
+            .map_err(|e| format!("key exchange failed: {}", e))?;
+        client
+            .close()
+            .map(|_| "key exchange completed, connection closed".to_string())
+            .map_err(|e| format!("close failed: {}", e))
+    }
+
+    /// Sending a public key before ClientHello must be rejected
+    fn test_wrong_order(&self) -> Result<String, String> {
+        let mut stream = self.connect()?;
+        let message = DHMessage::ClientPublicKey { x: 2u32.into() };
+        send_raw(&mut stream, &framing::encode(Codec::Native, &message))?;
+        self.expect_alert(&mut stream, AlertCode::UnexpectedMessage)
+    }
+
+    /// An unassigned message type must be rejected
+    fn test_unknown_type(&self) -> Result<String, String> {
+        let mut stream = self.connect()?;
+        send_raw(&mut stream, &[0, 0, 0, 1, 0xFF])?;
+        self.expect_alert(&mut stream, AlertCode::DecodeError)
+    }
+
+    /// Reserved GREASE messages must be skipped, not treated as errors
+    fn test_grease_tolerance(&self) -> Result<String, String> {
+        let mut stream = self.connect()?;
+        stream
+            .set_read_timeout(Some(self.reject_timeout))
+            .map_err(|e| format!("set timeout failed: {}", e))?;
+        send_raw(&mut stream, &framing::encode(Codec::Native, &DHMessage::grease()))?;
+        match send_hello(&mut stream, ffdh_client_hello())?.1 {
+            Ok(Some(DHMessage::ServerHello { .. } | DHMessage::ServerHelloNamed { .. })) => {
+                Ok("server skipped GREASE message".to_string())
+            }
+            Ok(other) => Err(format!("expected ServerHello, got {:?}", other)),
+            Err(e) => Err(format!("no ServerHello after GREASE: {}", e)),
+        }
+    }
+
+    /// A ClientHello sent again verbatim, random included, must be rejected
+    fn test_replayed_hello(&self) -> Result<String, String> {
+        let mut first = self.connect()?;
+        first
+            .set_read_timeout(Some(self.reject_timeout))
+            .map_err(|e| format!("set timeout failed: {}", e))?;
+        // The Hello the server accepted, with its cookie if it asked for one
+        let hello = match send_hello(&mut first, ffdh_client_hello())? {
+            (hello, Ok(Some(message))) if message.protocol_version().is_some() => hello,
+            (_, Ok(other)) => return Err(format!("expected ServerHello, got {:?}", other)),
+            (_, Err(e)) => return Err(format!("no ServerHello: {}", e)),
+        };
+        let mut replay = self.connect()?;
+        send_raw(&mut replay, &framing::encode(Codec::Native, &hello))?;
+        self.expect_alert(&mut replay, AlertCode::IllegalParameter)
+    }
+
+    /// A length prefix that overruns its frame must be rejected
+    fn test_malformed_length(&self) -> Result<String, String> {
+        let (mut stream, _, _) = self.start_handshake()?;
+        let mut frame = 9u32.to_be_bytes().to_vec();
+        frame.push(2);
+        frame.extend(64u32.to_be_bytes());
+        frame.extend([1, 2, 3, 4]);
+        send_raw(&mut stream, &frame)?;
+        stream
+            .shutdown(Shutdown::Write)
+            .map_err(|e| format!("shutdown failed: {}", e))?;
+        self.expect_alert(&mut stream, AlertCode::DecodeError)
+    }
+
+    /// A frame length far larger than any valid message must be rejected without waiting for the payload
+    fn test_oversized_frame(&self) -> Result<String, String> {
+        let (mut stream, _, _) = self.start_handshake()?;
+        let mut frame = (16u32 * 1024 * 1024).to_be_bytes().to_vec();
+        frame.push(2);
+        send_raw(&mut stream, &frame)?;
+        self.expect_alert(&mut stream, AlertCode::DecodeError)
+    }
+
+    /// A public key wider than the largest group, in a frame of acceptable size, must
+    /// be rejected as undecodable rather than checked against p
+    fn test_oversized_integer(&self) -> Result<String, String> {
+        let (mut stream, _, _) = self.start_handshake()?;
+        let x = BigUint::from_bytes_be(&[0xFF; MAX_INTEGER_FIELD + 1]);
+        send_raw(&mut stream, &framing::encode(Codec::Native, &DHMessage::ClientPublicKey { x }))?;
+        self.expect_alert(&mut stream, AlertCode::DecodeError)
+    }
+
+    /// A client that stops mid-handshake must eventually be disconnected
+    fn test_stalled_handshake(&self) -> Result<String, String> {
+        let (mut stream, _, _) = self.start_handshake()?;
+        self.expect_close(&mut stream, self.idle_timeout)
+    }
+
+    /// A client public key equal to g (secret exponent 1) must be rejected
+    fn test_generator_as_key(&self) -> Result<String, String> {
+        let (mut stream, _, base) = self.start_handshake()?;
+        send_raw(&mut stream, &framing::encode(Codec::Native, &DHMessage::ClientPublicKey { x: base.magnitude().clone() }))?;
+        match read_message(&mut stream, Codec::Native) {
+            Ok(Some(DHMessage::ServerPublicKey { .. })) => {
+                Err("server accepted g as the client public key".to_string())
+            }
+            received => self.check_alert(&mut stream, received, AlertCode::IllegalParameter),
+        }
+    }
+
+    /// The server's own public key sent back to it must be rejected
+    ///
+    /// Only a server with a static key (see `KeyMode::Static`) uses the same public key
+    /// twice, so one learned in a first handshake is reflected in a second; a server
+    /// whose key changed in between has nothing to reflect and passes.
+    fn test_reflected_key(&self) -> Result<String, String> {
+        let (mut first, p, g) = self.start_handshake()?;
+        let x = g.modpow(&BigInt::from(2), &p);
+        send_raw(&mut first, &framing::encode(Codec::Native, &DHMessage::ClientPublicKey { x: x.magnitude().clone() }))?;
+        let server_key = match read_message(&mut first, Codec::Native) {
+            Ok(Some(DHMessage::ServerPublicKey { y })) => y,
+            Ok(other) => return Err(format!("expected ServerPublicKey, got {:?}", other)),
+            Err(e) => return Err(format!("no ServerPublicKey: {}", e)),
+        };
+        drop(first);
+
+        let (mut stream, _, _) = self.start_handshake()?;
+        send_raw(&mut stream, &framing::encode(Codec::Native, &DHMessage::ClientPublicKey { x: server_key.clone() }))?;
+        match read_message(&mut stream, Codec::Native) {
+            Ok(Some(DHMessage::ServerPublicKey { y })) if y == server_key => {
+                Err("server accepted its own public key as the client's".to_string())
+            }
+            Ok(Some(DHMessage::ServerPublicKey { .. })) => Ok("server key is ephemeral; nothing to reflect".to_string()),
+            received => self.check_alert(&mut stream, received, AlertCode::IllegalParameter),
+        }
+    }
+
+    /// A client public key of 1 (shared secret always 1) must be rejected
+    fn test_degenerate_key(&self) -> Result<String, String> {
+        let (mut stream, _, _) = self.start_handshake()?;
+        send_raw(&mut stream, &framing::encode(Codec::Native, &DHMessage::ClientPublicKey { x: 1u32.into() }))?;
+        match read_message(&mut stream, Codec::Native) {
+            Ok(Some(DHMessage::ServerPublicKey { .. })) => {
+                Err("server accepted 1 as the client public key".to_string())
+            }
+            received => self.check_alert(&mut stream, received, AlertCode::IllegalParameter),
+        }
+    }
+
+    fn connect(&self) -> Result<TcpStream, String> {
+        TcpStream::connect(&self.target).map_err(|e| format!("connect failed: {}", e))
+    }
+
+    /// Connect and complete the Hello round, returning the stream ready for ClientPublicKey
+    /// together with the (p, g) the server offered
+    fn start_handshake(&self) -> Result<(TcpStream, BigInt, BigInt), String> {
+        let mut stream = self.connect()?;
+        stream
+            .set_read_timeout(Some(self.reject_timeout))
+            .map_err(|e| format!("set timeout failed: {}", e))?;
+        match send_hello(&mut stream, ffdh_client_hello())?.1 {
+            Ok(Some(DHMessage::ServerHello { p, g, .. })) => Ok((stream, BigInt::from(p), BigInt::from(g))),
+            Ok(Some(DHMessage::ServerHelloNamed { group, .. })) => match DhGroup::from_id(group) {
+                Some(named) => Ok((stream, named.prime(), named.generator())),
+                None => Err(format!("server selected unknown group ID {}", group)),
+            },
+            Ok(other) => Err(format!("expected ServerHello, got {:?}", other)),
+            Err(e) => Err(format!("no ServerHello: {}", e)),
+        }
+    }
+
+    /// Succeeds if the server sends an Alert with this code and then closes the connection
+    fn expect_alert(&self, stream: &mut TcpStream, code: AlertCode) -> Result<String, String> {
+        stream
+            .set_read_timeout(Some(self.reject_timeout))
+            .map_err(|e| format!("set timeout failed: {}", e))?;
+        let received = read_message(stream, Codec::Native);
+        self.check_alert(stream, received, code)
+    }
+
+    /// Succeeds if the message the server sent is an Alert with this code, and the
+    /// server then closes the connection
+    fn check_alert(
+        &self,
+        stream: &mut TcpStream,
+        received: std::io::Result<Option<DHMessage>>,
+        code: AlertCode,
+    ) -> Result<String, String> {
+        let message = match received {
+            Ok(Some(message)) => message,
+            Ok(None) => return Err(format!("expected a {} alert, got an undecodable message", code.name())),
+            Err(e) => return Err(format!("expected a {} alert: {}", code.name(), e)),
+        };
+        match Alert::from_message(&message) {
+            Some(alert) if alert.code == code.id() => self
+                .expect_close(stream, self.reject_timeout)
+                .map(|closed| format!("{} ({})", closed, alert.description)),
+            _ => Err(format!("expected a {} alert, got {:?}", code.name(), message)),
+        }
+    }
+
+    /// Succeeds if the server closes the connection before the deadline
+    fn expect_close(&self, stream: &mut TcpStream, timeout: Duration) -> Result<String, String> {
+        let start = Instant::now();
+        let mut buffer = [0; 1024];
+
+        while start.elapsed() < timeout {
+            let remaining = timeout - start.elapsed();
+            stream
+                .set_read_timeout(Some(remaining.max(Duration::from_millis(1))))
+                .map_err(|e| format!("set timeout failed: {}", e))?;
+
+            match stream.read(&mut buffer) {
+                Ok(0) => return Ok(format!("server closed connection after {:?}", start.elapsed())),
+                // The server may explain itself before closing; keep draining
+                Ok(_) => continue,
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => break,
+                Err(e) => return Ok(format!("server aborted connection: {}", e)),
+            }
+        }
+
+        Err(format!("connection still open after {:?}", timeout))
+    }
+}
+
+/// ClientHello offering only finite-field DH, which every server must support
+fn ffdh_client_hello() -> DHMessage {
+    DHMessage::ClientHello {
+        kex_algorithms: vec![KexAlgorithm::FiniteField.id()],
+        ciphers: CipherSuite::DEFAULT.iter().map(CipherSuite::id).collect(),
+        nonce: Vec::new(),
+        versions: PROTOCOL_VERSIONS.to_vec(),
+        groups: Vec::new(),
+        ticket: Vec::new(),
+        random: with_rng(RngPurpose::Nonce, |rng| rng.r#gen()),
+        cookie: Vec::new(),
+    }
+}
+
+/// Send a ClientHello and read the server's answer, repeating the Hello once with the
+/// cookie (and puzzle solution) of a HelloRetry over a new connection, as the server closes the first
+///
+/// # Returns
+/// The Hello sent last, and what the server answered it with
+fn send_hello(
+    stream: &mut TcpStream,
+    mut hello: DHMessage,
+) -> Result<(DHMessage, std::io::Result<Option<DHMessage>>), String> {
+    send_raw(stream, &framing::encode(Codec::Native, &hello))?;
+    let answer = read_message(stream, Codec::Native);
+    let Ok(Some(DHMessage::HelloRetry { cookie: issued })) = answer else {
+        return Ok((hello, answer));
+    };
+    let answer = answer_hello_retry(&issued).ok_or("HelloRetry puzzle is too hard to solve")?;
+    if let DHMessage::ClientHello { cookie, .. } = &mut hello {
+        *cookie = answer;
+    }
+    let timeout = stream.read_timeout().map_err(|e| format!("get timeout failed: {}", e))?;
+    let server = stream.peer_addr().map_err(|e| format!("peer address unknown: {}", e))?;
+    *stream = TcpStream::connect(server).map_err(|e| format!("reconnect failed: {}", e))?;
+    stream
+        .set_read_timeout(timeout)
+        .map_err(|e| format!("set timeout failed: {}", e))?;
+    send_raw(stream, &framing::encode(Codec::Native, &hello))?;
+    Ok((hello, read_message(stream, Codec::Native)))
+}
+
+fn send_raw(stream: &mut TcpStream, bytes: &[u8]) -> Result<(), String> {
+    stream
+        .write_all(bytes)
+        .and_then(|_| stream.flush())
+        .map_err(|e| format!("send failed: {}", e))
+}
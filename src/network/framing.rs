@@ -0,0 +1,341 @@
+use std::fmt;
+use std::io::{Read, Write};
+
+use ciborium::Value;
+
+use crate::crypto::kex::KexAlgorithm;
+
+#[cfg(feature = "protobuf")]
+use crate::structs::DH_Prot::PROTOBUF_SIGNAL;
+use crate::structs::DH_Prot::{DHMessage, BINCODE_SIGNAL, CBOR_SIGNAL, MAX_FRAME, MAX_GROUP_BITS};
+
+/// Size limits a reader enforces on the messages it receives
+///
+/// The frame limit is checked from the length prefix alone, before any payload is
+/// read; the integer limit once the message is decoded, whatever the codec. A
+/// message over either fails the read with a `LimitExceeded` error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageLimits {
+    /// Largest frame, at most `MAX_FRAME`
+    pub max_frame: usize,
+    /// Largest integer or group element in bytes (see `DHMessage::largest_integer`)
+    pub max_integer: usize,
+}
+
+impl Default for MessageLimits {
+    fn default() -> Self {
+        Self::for_group_bits(MAX_GROUP_BITS)
+    }
+}
+
+impl MessageLimits {
+    /// Limits for peers that use groups of at most `bits` bits (up to `MAX_GROUP_BITS`)
+    pub fn for_group_bits(bits: usize) -> Self {
+        MessageLimits {
+            max_frame: MAX_FRAME,
+            max_integer: bits.min(MAX_GROUP_BITS).div_ceil(8),
+        }
+    }
+
+    /// Lower the frame limit (it cannot be raised past `MAX_FRAME`)
+    pub fn with_max_frame(mut self, max_frame: usize) -> Self {
+        self.max_frame = max_frame.min(MAX_FRAME);
+        self
+    }
+
+    /// Check the integers and group elements of a message, reading key shares in the
+    /// format of `kex`
+    pub fn check(&self, message: &DHMessage, kex: KexAlgorithm) -> Result<(), LimitExceeded> {
+        let len = message.largest_integer(kex);
+        if len > self.max_integer {
+            return Err(LimitExceeded::Integer { len, max: self.max_integer });
+        }
+        Ok(())
+    }
+
+    /// Largest line a `Codec::Json` peer may send: a maximum frame in hex, with room
+    /// for the field names
+    fn max_json_line(&self) -> usize {
+        2 * self.max_frame + 1024
+    }
+}
+
+/// A message over the reader's `MessageLimits`, returned as the source of the
+/// `InvalidData` error the read fails with (see `LimitExceeded::from_error`), so it
+/// is not mistaken for a message that does not decode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    /// A frame, or a `Codec::Json` line, longer than `max` bytes
+    Frame { max: usize },
+    /// An integer or group element of `len` bytes, over `max`
+    Integer { len: usize, max: usize },
+}
+
+impl LimitExceeded {
+    /// The limit a failed read ran into, if that is why it failed
+    pub fn from_error(error: &std::io::Error) -> Option<&Self> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Frame { max } => write!(f, "message exceeds the {}-byte frame limit", max),
+            LimitExceeded::Integer { len, max } => {
+                write!(f, "{}-byte integer exceeds the {}-byte limit", len, max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+impl From<LimitExceeded> for std::io::Error {
+    fn from(exceeded: LimitExceeded) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, exceeded)
+    }
+}
+
+/// Encodings of the messages inside frames
+///
+/// ClientHello and everything before it use `Native` (or `Json`, which is not
+/// negotiated); a client listing a codec's signal asks for it for the rest of the
+/// handshake.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Codec {
+    /// The hand-written layout of `DHMessage::to_bytes`
+    #[default]
+    Native,
+    /// bincode's standard configuration over the serde derive of `DHMessage`
+    Bincode,
+    /// Deterministically encoded CBOR (RFC 8949, section 4.2.1) over the serde derive
+    /// of `DHMessage`, for implementations in other languages
+    ///
+    /// A message is a map from the variant name to a map of its fields ("Done" is just
+    /// the text string); integers and byte fields are byte strings, ID lists arrays of
+    /// unsigned integers. Encodings that are not deterministic are rejected.
+    Cbor,
+    /// Protobuf messages of proto/dhke.proto, through prost (see `network::protobuf`)
+    #[cfg(feature = "protobuf")]
+    Protobuf,
+    /// Newline-delimited JSON instead of frames, with integers and byte fields in hex,
+    /// so a handshake can be followed with netcat or tcpdump
+    ///
+    /// For teaching and debugging only. It is not negotiated: both sides must be
+    /// started in it, and it carries ClientHello too.
+    Json,
+}
+
+impl Codec {
+    /// Every codec known to this implementation
+    pub const ALL: &'static [Codec] = &[
+        Codec::Native,
+        Codec::Bincode,
+        Codec::Cbor,
+        #[cfg(feature = "protobuf")]
+        Codec::Protobuf,
+        Codec::Json,
+    ];
+
+    /// Short lowercase name of the codec (e.g., "bincode")
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Native => "native",
+            Codec::Bincode => "bincode",
+            Codec::Cbor => "cbor",
+            #[cfg(feature = "protobuf")]
+            Codec::Protobuf => "protobuf",
+            Codec::Json => "json",
+        }
+    }
+
+    /// Look up a codec by its short name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|codec| codec.name() == name)
+    }
+
+    /// Signal a client lists in ClientHello to ask for this codec, None for the codecs
+    /// that carry ClientHello itself
+    pub fn signal(&self) -> Option<u8> {
+        match self {
+            Codec::Native | Codec::Json => None,
+            Codec::Bincode => Some(BINCODE_SIGNAL),
+            Codec::Cbor => Some(CBOR_SIGNAL),
+            #[cfg(feature = "protobuf")]
+            Codec::Protobuf => Some(PROTOBUF_SIGNAL),
+        }
+    }
+
+    /// Codec of ClientHello and the messages before it, for a client using this codec
+    pub fn hello_codec(&self) -> Codec {
+        match self {
+            Codec::Json => Codec::Json,
+            _ => Codec::Native,
+        }
+    }
+
+    /// Encode a message, without the frame (or newline)
+    pub fn encode(&self, message: &DHMessage) -> Vec<u8> {
+        match self {
+            Codec::Native => message.to_bytes(),
+            Codec::Bincode => bincode::serde::encode_to_vec(message, bincode_config())
+                .expect("every DHMessage is serializable"),
+            Codec::Cbor => {
+                let value = Value::serialized(message).expect("every DHMessage is serializable");
+                cbor_bytes(&deterministic(value))
+            }
+            #[cfg(feature = "protobuf")]
+            Codec::Protobuf => crate::network::protobuf::encode(message),
+            Codec::Json => serde_json::to_vec(message).expect("every DHMessage is serializable"),
+        }
+    }
+
+    /// Decode a message from a whole frame
+    ///
+    /// Messages from the serde codecs must also survive the native encoding, so they
+    /// meet the same size limits and canonical forms as native ones.
+    pub fn decode(&self, bytes: &[u8]) -> Option<DHMessage> {
+        let message: DHMessage = match self {
+            Codec::Native => return DHMessage::from_bytes(bytes),
+            Codec::Bincode => match bincode::serde::decode_from_slice(bytes, bincode_config()) {
+                Ok((message, read)) if read == bytes.len() => message,
+                _ => return None,
+            },
+            Codec::Cbor => match ciborium::from_reader(bytes) {
+                Ok(message) if self.encode(&message) == bytes => message,
+                _ => return None,
+            },
+            #[cfg(feature = "protobuf")]
+            Codec::Protobuf => crate::network::protobuf::decode(bytes)?,
+            Codec::Json => serde_json::from_slice(bytes).ok()?,
+        };
+        DHMessage::from_bytes(&message.to_bytes()).filter(|checked| *checked == message)
+    }
+}
+
+fn bincode_config() -> impl bincode::config::Config {
+    bincode::config::standard().with_limit::<MAX_FRAME>()
+}
+
+/// Sort the entries of every map by the bytes of their encoded keys, the one ordering
+/// deterministic CBOR allows; ciborium already writes shortest-form heads and
+/// definite lengths
+fn deterministic(value: Value) -> Value {
+    match value {
+        Value::Map(entries) => {
+            let mut entries: Vec<_> = entries
+                .into_iter()
+                .map(|(key, value)| (cbor_bytes(&key), key, deterministic(value)))
+                .collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            Value::Map(entries.into_iter().map(|(_, key, value)| (key, value)).collect())
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(deterministic).collect()),
+        Value::Tag(tag, inner) => Value::Tag(tag, Box::new(deterministic(*inner))),
+        other => other,
+    }
+}
+
+fn cbor_bytes(value: &Value) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    ciborium::into_writer(value, &mut bytes).expect("writing to a Vec cannot fail");
+    bytes
+}
+
+/// Encode a message as one frame: [4-byte length][encoded message]
+///
+/// The length covers the whole encoded message (for `Codec::Native`, the type byte
+/// and payload), so a receiver can read any message, including types it does not
+/// know, without parsing it. `Codec::Json` messages end in a newline instead.
+pub fn encode(codec: Codec, message: &DHMessage) -> Vec<u8> {
+    let body = codec.encode(message);
+    if codec == Codec::Json {
+        return [body, vec![b'\n']].concat();
+    }
+    let mut frame = Vec::with_capacity(4 + body.len());
+    frame.extend((body.len() as u32).to_be_bytes());
+    frame.extend(body);
+    frame
+}
+
+/// Read the next message within the default `MessageLimits`, skipping any reserved
+/// GREASE messages the peer interleaves
+///
+/// # Returns
+/// The message, or None if a frame is empty or does not decode; a message over the
+/// limits is an error carrying `LimitExceeded`
+pub fn read_message<R: Read>(reader: &mut R, codec: Codec) -> std::io::Result<Option<DHMessage>> {
+    read_message_within(reader, codec, &MessageLimits::default(), KexAlgorithm::FiniteField)
+}
+
+/// Read the next message like `read_message`, within the given limits, reading key
+/// shares in the format of `kex` (see `KexAlgorithm::largest_integer`)
+///
+/// # Returns
+/// The message, or None if a frame is empty or does not decode; a message over the
+/// limits is an error carrying `LimitExceeded`
+pub fn read_message_within<R: Read>(
+    reader: &mut R,
+    codec: Codec,
+    limits: &MessageLimits,
+    kex: KexAlgorithm,
+) -> std::io::Result<Option<DHMessage>> {
+    loop {
+        let frame = match codec {
+            Codec::Json => read_line(reader, limits.max_json_line())?,
+            _ => {
+                let mut len_bytes = [0; 4];
+                reader.read_exact(&mut len_bytes)?;
+                let len = u32::from_be_bytes(len_bytes) as usize;
+                if len > limits.max_frame {
+                    return Err(LimitExceeded::Frame { max: limits.max_frame }.into());
+                }
+                if len == 0 {
+                    return Ok(None);
+                }
+                let mut frame = vec![0; len];
+                reader.read_exact(&mut frame)?;
+                frame
+            }
+        };
+        match codec.decode(&frame) {
+            Some(DHMessage::Grease { .. }) => continue,
+            Some(message) => {
+                limits.check(&message, kex)?;
+                return Ok(Some(message));
+            }
+            None => return Ok(None),
+        }
+    }
+}
+
+/// Read one line a byte at a time, so nothing after the newline is consumed
+///
+/// # Returns
+/// The line without its newline, or a `LimitExceeded` error once it is longer than
+/// `max_len`
+fn read_line<R: Read>(reader: &mut R, max_len: usize) -> std::io::Result<Vec<u8>> {
+    let mut line = Vec::new();
+    let mut byte = [0; 1];
+    loop {
+        reader.read_exact(&mut byte)?;
+        match byte[0] {
+            b'\n' => return Ok(line),
+            _ if line.len() == max_len => return Err(LimitExceeded::Frame { max: max_len }.into()),
+            byte => line.push(byte),
+        }
+    }
+}
+
+/// Write a message as one frame
+pub fn write_message<W: Write>(writer: &mut W, codec: Codec, message: &DHMessage) -> std::io::Result<()> {
+    write_messages(writer, codec, &[message])
+}
+
+/// Write several messages in a single write, so they leave in one flight
+pub fn write_messages<W: Write>(writer: &mut W, codec: Codec, messages: &[&DHMessage]) -> std::io::Result<()> {
+    let bytes: Vec<u8> = messages.iter().flat_map(|message| encode(codec, message)).collect();
+    writer.write_all(&bytes)?;
+    writer.flush()
+}
@@ -0,0 +1,130 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::network::lock_ignoring_poison;
+
+/// How often an idle connection checks whether it should be drained
+pub(crate) const DRAIN_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Limits that keep a long-running server's thread and file descriptor budget bounded
+///
+/// Connections closed under this policy receive a Close record with the Drained
+/// reason, so well-behaved clients know to reconnect.
+#[derive(Debug, Clone)]
+pub struct LifecyclePolicy {
+    /// Close established connections once they are this old, idle or not
+    pub max_age: Option<Duration>,
+    /// Most connections (including handshakes in progress) served at once; further
+    /// connections are refused right after accept
+    pub max_connections: Option<usize>,
+    /// Fraction of `max_connections` at which the server starts draining the oldest
+    /// idle connection for every new one it accepts
+    pub drain_threshold: f64,
+    /// How long an established connection must go without a record to count as idle
+    pub idle_after: Duration,
+}
+
+impl Default for LifecyclePolicy {
+    fn default() -> Self {
+        LifecyclePolicy {
+            max_age: None,
+            max_connections: None,
+            drain_threshold: 0.9,
+            idle_after: Duration::from_secs(5),
+        }
+    }
+}
+
+impl LifecyclePolicy {
+    /// Number of open connections at which draining starts, if there is a limit
+    pub fn drain_at(&self) -> Option<usize> {
+        self.max_connections
+            .map(|max| ((max as f64 * self.drain_threshold).ceil() as usize).clamp(1, max))
+    }
+}
+
+/// State the server keeps about one established connection
+#[derive(Debug)]
+struct ConnectionEntry {
+    opened: Instant,
+    last_active: Mutex<Instant>,
+    drain: AtomicBool,
+}
+
+/// Established connections of one listener, oldest first
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnectionRegistry {
+    entries: Arc<Mutex<Vec<Arc<ConnectionEntry>>>>,
+}
+
+impl ConnectionRegistry {
+    /// Track a newly established connection until the returned handle is dropped
+    pub(crate) fn register(&self) -> ConnectionHandle {
+        let now = Instant::now();
+        let entry = Arc::new(ConnectionEntry {
+            opened: now,
+            last_active: Mutex::new(now),
+            drain: AtomicBool::new(false),
+        });
+        self.lock().push(entry.clone());
+        ConnectionHandle {
+            registry: self.clone(),
+            entry,
+        }
+    }
+
+    /// Number of established connections
+    pub(crate) fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Ask the oldest connection that has been idle for at least `idle_after` (and is
+    /// not already draining) to close
+    ///
+    /// # Returns
+    /// Whether a connection was picked
+    pub(crate) fn drain_oldest_idle(&self, idle_after: Duration) -> bool {
+        let entries = self.lock();
+        let candidate = entries.iter().find(|entry| {
+            !entry.drain.load(Ordering::SeqCst) && lock_ignoring_poison(&entry.last_active).elapsed() >= idle_after
+        });
+        match candidate {
+            Some(entry) => {
+                entry.drain.store(true, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Arc<ConnectionEntry>>> {
+        lock_ignoring_poison(&self.entries)
+    }
+}
+
+/// A connection's registration; dropping it removes the connection from the registry
+pub(crate) struct ConnectionHandle {
+    registry: ConnectionRegistry,
+    entry: Arc<ConnectionEntry>,
+}
+
+impl ConnectionHandle {
+    /// Note that a record was just exchanged, so the connection is not idle
+    pub(crate) fn touch(&self) {
+        *lock_ignoring_poison(&self.entry.last_active) = Instant::now();
+    }
+
+    /// Whether the connection should be drained now: it was picked to make room, or
+    /// it outlived the policy's maximum age
+    pub(crate) fn should_drain(&self, policy: &LifecyclePolicy) -> bool {
+        self.entry.drain.load(Ordering::SeqCst)
+            || policy.max_age.is_some_and(|max_age| self.entry.opened.elapsed() >= max_age)
+    }
+}
+
+impl Drop for ConnectionHandle {
+    fn drop(&mut self) {
+        self.registry.lock().retain(|entry| !Arc::ptr_eq(entry, &self.entry));
+    }
+}
@@ -0,0 +1,112 @@
+use std::io::ErrorKind;
+use std::thread;
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::network::cancel::is_cancelled;
+
+/// Error kinds that usually clear up on their own (server restarting, dropped
+/// connection, slow peer). Protocol and key-validation failures surface as
+/// InvalidData and are never retried.
+pub const TRANSIENT_ERRORS: &[ErrorKind] = &[
+    ErrorKind::ConnectionRefused,
+    ErrorKind::ConnectionReset,
+    ErrorKind::ConnectionAborted,
+    ErrorKind::BrokenPipe,
+    ErrorKind::TimedOut,
+    ErrorKind::WouldBlock,
+    ErrorKind::Interrupted,
+    ErrorKind::UnexpectedEof,
+];
+
+/// Retry/backoff policy for client network operations
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total attempts, including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubles on every further retry
+    pub base_delay: Duration,
+    /// Upper bound on the delay between attempts
+    pub max_delay: Duration,
+    /// Randomize each delay between half and all of its nominal value, so many
+    /// clients failing at once do not retry in lockstep
+    pub jitter: bool,
+    /// Error kinds worth retrying; anything else fails immediately
+    pub retry_on: Vec<ErrorKind>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+            jitter: true,
+            retry_on: TRANSIENT_ERRORS.to_vec(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that runs the operation exactly once
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Whether an error of this kind should be retried
+    pub fn should_retry(&self, kind: ErrorKind) -> bool {
+        self.retry_on.contains(&kind)
+    }
+
+    /// Whether this error should be retried; cancellations never are, whatever their kind
+    fn should_retry_error(&self, error: &std::io::Error) -> bool {
+        self.should_retry(error.kind()) && !is_cancelled(error)
+    }
+
+    /// Delay to wait after the given failed attempt (1-based)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self.base_delay.saturating_mul(factor).min(self.max_delay);
+        if self.jitter {
+            delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+        } else {
+            delay
+        }
+    }
+
+    /// Run an operation under this policy
+    ///
+    /// # Arguments
+    /// * `operation` - Called with the 1-based attempt number. It must start from
+    ///   scratch each time (e.g., open a new connection), since a failed attempt may
+    ///   leave a stream in an unknown state.
+    ///
+    /// # Returns
+    /// The first successful result, or the last error once attempts run out or an
+    /// error is not retryable
+    pub fn run<T, F>(&self, mut operation: F) -> std::io::Result<T>
+    where
+        F: FnMut(u32) -> std::io::Result<T>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation(attempt) {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < self.max_attempts && self.should_retry_error(&e) => {
+                    let delay = self.delay(attempt);
+                    eprintln!(
+                        "[RETRY] Attempt {}/{} failed: {}; retrying in {:?}",
+                        attempt, self.max_attempts, e, delay
+                    );
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
@@ -1 +1,32 @@
-pub mod server;pub mod client;
\ No newline at end of file
+pub mod server;
+pub mod client;
+pub mod channel;
+pub mod conformance;
+pub mod alert;
+pub mod framing;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+pub mod anomaly;
+pub mod middleware;
+pub mod probe;
+pub mod tasks;
+pub mod cancel;
+pub mod capabilities;
+pub mod lifecycle;
+pub mod prekeys;
+pub mod ratchet;
+pub mod telemetry;
+pub mod tickets;
+pub mod replay;
+pub mod quotas;
+pub mod bench;
+
+use std::sync::{Mutex, MutexGuard};
+
+/// Lock a mutex whose data stays consistent even if a holder panicked
+///
+/// Every shared map and list in this module is updated in single steps that a panic
+/// cannot interrupt halfway, so a poisoned lock still guards valid data.
+pub(crate) fn lock_ignoring_poison<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
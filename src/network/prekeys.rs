@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::crypto::fingerprint::Fingerprint;
+use crate::crypto::x3dh::{PrekeyBundle, PrekeyPublication};
+use crate::network::framing::{read_message, write_message, Codec};
+use crate::network::lock_ignoring_poison;
+use crate::structs::DH_Prot::{DHMessage, ABORT_PREKEYS_REJECTED, ABORT_UNKNOWN_IDENTITY};
+
+/// Server-side store of X3DH prekeys, so parties can start sessions with peers that
+/// are offline
+///
+/// Publications are indexed by identity fingerprint. Each one-time prekey is handed
+/// out at most once; once they run out, bundles carry only the signed prekey.
+/// Clones share the same entries.
+#[derive(Debug, Clone, Default)]
+pub struct PrekeyDirectory {
+    entries: Arc<Mutex<HashMap<[u8; 32], PrekeyPublication>>>,
+}
+
+impl PrekeyDirectory {
+    /// Create an empty directory
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer a PrekeyUpload or PrekeyRequest
+    ///
+    /// # Returns
+    /// The reply to send: Done for an accepted upload, PrekeyResponse for a known
+    /// identity, or Abort
+    pub(crate) fn handle(&self, request: &DHMessage) -> DHMessage {
+        match request {
+            DHMessage::PrekeyUpload { publication } => {
+                let published = PrekeyPublication::from_bytes(publication)
+                    .ok_or("publication is malformed")
+                    .and_then(|publication| self.publish(publication));
+                match published {
+                    Ok(()) => DHMessage::Done,
+                    Err(_) => DHMessage::Abort { reason: ABORT_PREKEYS_REJECTED },
+                }
+            }
+            DHMessage::PrekeyRequest { identity } => match self.take_bundle(identity) {
+                Some(bundle) => DHMessage::PrekeyResponse { bundle: bundle.to_bytes() },
+                None => DHMessage::Abort { reason: ABORT_UNKNOWN_IDENTITY },
+            },
+            _ => DHMessage::Abort { reason: ABORT_PREKEYS_REJECTED },
+        }
+    }
+
+    /// Store a publication, replacing an older one of the same identity
+    fn publish(&self, publication: PrekeyPublication) -> Result<(), &'static str> {
+        publication.verify()?;
+        let mut entries = lock_ignoring_poison(&self.entries);
+        let key = *publication.identity.fingerprint().as_bytes();
+        if entries.get(&key).is_some_and(|stored| stored.version >= publication.version) {
+            return Err("publication is not newer than the stored one");
+        }
+        entries.insert(key, publication);
+        Ok(())
+    }
+
+    /// Bundle for an identity, consuming one of its one-time prekeys if any are left
+    fn take_bundle(&self, identity: &[u8; 32]) -> Option<PrekeyBundle> {
+        let mut entries = lock_ignoring_poison(&self.entries);
+        let publication = entries.get_mut(identity)?;
+        let one_time_prekey = publication.one_time_prekeys.pop();
+        Some(publication.bundle(one_time_prekey))
+    }
+}
+
+/// Upload prekeys to a server's directory
+///
+/// # Arguments
+/// * `server_addr` - Server address (e.g., "127.0.0.1:8080")
+/// * `publication` - Signed prekeys from `PrekeyOwner::publication`
+pub fn publish_prekeys(server_addr: &str, publication: &PrekeyPublication) -> std::io::Result<()> {
+    let reply = directory_round(server_addr, &DHMessage::PrekeyUpload { publication: publication.to_bytes() })?;
+    match reply {
+        Some(DHMessage::Done) => Ok(()),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Server refused the prekey publication",
+        )),
+    }
+}
+
+/// Fetch the prekey bundle of an identity from a server's directory
+///
+/// # Arguments
+/// * `server_addr` - Server address (e.g., "127.0.0.1:8080")
+/// * `identity` - Fingerprint of the peer's `X3dhPublicIdentity`
+///
+/// # Returns
+/// The bundle, whose identity is checked against the requested fingerprint
+pub fn fetch_prekey_bundle(server_addr: &str, identity: &Fingerprint) -> std::io::Result<PrekeyBundle> {
+    let reply = directory_round(server_addr, &DHMessage::PrekeyRequest { identity: *identity.as_bytes() })?;
+    let bundle = match reply {
+        Some(DHMessage::PrekeyResponse { bundle }) => PrekeyBundle::from_bytes(&bundle),
+        Some(DHMessage::Abort { reason: ABORT_UNKNOWN_IDENTITY }) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Server has no prekeys for this identity",
+            ));
+        }
+        _ => None,
+    };
+    match bundle {
+        Some(bundle) if bundle.identity.fingerprint() == *identity => Ok(bundle),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Server sent an invalid prekey bundle",
+        )),
+    }
+}
+
+/// Send one directory request on a fresh connection and read the reply
+fn directory_round(server_addr: &str, request: &DHMessage) -> std::io::Result<Option<DHMessage>> {
+    let mut stream = TcpStream::connect(server_addr)?;
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+    write_message(&mut stream, Codec::Native, request)?;
+    read_message(&mut stream, Codec::Native)
+}
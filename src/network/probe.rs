@@ -0,0 +1,249 @@
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use num_bigint::BigInt;
+use rand::Rng;
+
+use crate::crypto::cookie::answer_hello_retry;
+use crate::crypto::groups::{params_fingerprint, DhGroup};
+use crate::crypto::kex::KexAlgorithm;
+use crate::crypto::record::{CipherSuite, MAX_RECORD_PLAINTEXT};
+use crate::crypto::rng::{with_rng, RngPurpose};
+use crate::crypto::transcript::to_hex;
+use crate::network::client::DHClient;
+use crate::network::framing::{read_message, write_message, Codec};
+use crate::structs::DH_Prot::{DHMessage, ABORT_PROBE, PROTOCOL_VERSIONS};
+
+/// Record sizes tried when looking for the largest frame the path carries
+const PROBE_FRAME_SIZES: &[usize] = &[64, 512, 1200, 1472, 4096, 8192, MAX_RECORD_PLAINTEXT];
+
+/// Latency and frame-size measurements for an established session
+#[derive(Debug, Clone)]
+pub struct PathReport {
+    /// Round-trip time of every successful ping, in order
+    pub rtts: Vec<Duration>,
+    /// Mean absolute difference between consecutive round trips
+    pub jitter: Duration,
+    /// Largest record plaintext that made it to the peer and back intact
+    pub max_frame: usize,
+}
+
+impl PathReport {
+    /// Fastest round trip observed
+    pub fn min_rtt(&self) -> Duration {
+        self.rtts.iter().min().copied().unwrap_or_default()
+    }
+
+    /// Slowest round trip observed
+    pub fn max_rtt(&self) -> Duration {
+        self.rtts.iter().max().copied().unwrap_or_default()
+    }
+
+    /// Mean round-trip time
+    pub fn avg_rtt(&self) -> Duration {
+        match self.rtts.len() {
+            0 => Duration::ZERO,
+            n => self.rtts.iter().sum::<Duration>() / n as u32,
+        }
+    }
+
+    /// Print the report in a human readable form
+    pub fn print(&self) {
+        println!("Path report ({} pings)", self.rtts.len());
+        println!("  rtt min/avg/max: {:?} / {:?} / {:?}", self.min_rtt(), self.avg_rtt(), self.max_rtt());
+        println!("  jitter:          {:?}", self.jitter);
+        println!("  max frame:       {} bytes", self.max_frame);
+    }
+}
+
+impl DHClient {
+    /// Measure round-trip latency, jitter, and the largest frame that survives the path
+    ///
+    /// Relies on the server echoing application records back, which the reference
+    /// server does. Run it right after the key exchange, before application traffic,
+    /// since echoed pings would otherwise interleave with real responses.
+    ///
+    /// # Arguments
+    /// * `pings` - Number of small pings used for the latency measurement
+    ///
+    /// # Returns
+    /// A PathReport; a frame size that fails to come back ends the size search
+    pub fn probe(&mut self, pings: usize) -> std::io::Result<PathReport> {
+        let mut rtts = Vec::with_capacity(pings);
+        for sequence in 0..pings {
+            rtts.push(self.ping(&(sequence as u64).to_be_bytes())?);
+        }
+
+        let jitter = match rtts.len() {
+            0 | 1 => Duration::ZERO,
+            n => {
+                let total: Duration = rtts.windows(2).map(|pair| pair[0].abs_diff(pair[1])).sum();
+                total / (n - 1) as u32
+            }
+        };
+
+        let mut max_frame = 0;
+        for &size in PROBE_FRAME_SIZES {
+            let payload: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            match self.ping(&payload) {
+                Ok(_) => max_frame = size,
+                Err(e) => {
+                    eprintln!("[CLIENT] Frame of {} bytes did not survive: {}", size, e);
+                    break;
+                }
+            }
+        }
+
+        Ok(PathReport { rtts, jitter, max_frame })
+    }
+
+    /// Send one record and time how long its echo takes to come back
+    fn ping(&mut self, payload: &[u8]) -> std::io::Result<Duration> {
+        let mut buffer = vec![0; MAX_RECORD_PLAINTEXT];
+        let start = Instant::now();
+        self.send_message(payload)?;
+        let n = self.receive_message(&mut buffer)?;
+        if buffer[..n] != *payload {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Echoed record does not match the ping",
+            ));
+        }
+        Ok(start.elapsed())
+    }
+}
+
+/// What a server reveals in its Hello, gathered without completing any key exchange
+#[derive(Debug, Clone)]
+pub struct ServerProbe {
+    /// Address of the probed server
+    pub target: String,
+    /// Key-exchange algorithms the server accepted when offered on their own
+    pub kex_algorithms: Vec<KexAlgorithm>,
+    /// Record-layer ciphers the server accepted when offered on their own
+    pub ciphers: Vec<CipherSuite>,
+    /// Named group the server uses for finite-field DH, if it announces one
+    pub group: Option<DhGroup>,
+    /// Size of the finite-field prime in bits
+    pub prime_bits: Option<u64>,
+    /// SHA-256 fingerprint of the finite-field (p, g)
+    pub params_fingerprint: Option<[u8; 32]>,
+}
+
+impl ServerProbe {
+    /// Print the probe results in a human readable form
+    pub fn print(&self) {
+        let names = |names: Vec<&str>| if names.is_empty() { "none".to_string() } else { names.join(", ") };
+        println!("Probe report for {}", self.target);
+        println!("  key exchanges: {}", names(self.kex_algorithms.iter().map(KexAlgorithm::name).collect()));
+        println!("  ciphers:       {}", names(self.ciphers.iter().map(CipherSuite::name).collect()));
+        if let Some(bits) = self.prime_bits {
+            let group = self.group.map(|group| group.name()).unwrap_or("custom");
+            println!("  ffdh group:    {} ({} bits)", group, bits);
+        }
+        if let Some(fingerprint) = &self.params_fingerprint {
+            println!("  fingerprint:   {}", to_hex(fingerprint));
+        }
+    }
+}
+
+/// Run only the Hello round against a server, once per algorithm and cipher, aborting
+/// each attempt with an Abort message before any key is exchanged
+///
+/// # Arguments
+/// * `target` - Server address (e.g., "127.0.0.1:8080")
+///
+/// # Returns
+/// The server's capabilities and finite-field parameters, or an error if it cannot be reached
+pub fn probe_server(target: &str) -> std::io::Result<ServerProbe> {
+    let all_kex: Vec<u8> = KexAlgorithm::ALL.iter().map(KexAlgorithm::id).collect();
+    let all_ciphers: Vec<u8> = CipherSuite::ALL.iter().map(CipherSuite::id).collect();
+
+    let mut probe = ServerProbe {
+        target: target.to_string(),
+        kex_algorithms: Vec::new(),
+        ciphers: Vec::new(),
+        group: None,
+        prime_bits: None,
+        params_fingerprint: None,
+    };
+
+    for &algorithm in KexAlgorithm::ALL {
+        let Some(hello) = hello_round(target, vec![algorithm.id()], all_ciphers.clone())? else {
+            continue;
+        };
+        probe.kex_algorithms.push(algorithm);
+
+        let params = match hello {
+            DHMessage::ServerHello { p, g, .. } => Some((BigInt::from(p), BigInt::from(g))),
+            DHMessage::ServerHelloNamed { group, .. }
+            | DHMessage::ServerHelloHmqv { group, .. }
+            | DHMessage::ServerHelloSrp { group, .. }
+            | DHMessage::ServerHelloPadded { group, .. } => DhGroup::from_id(group).map(|named| {
+                probe.group = Some(named);
+                named.params()
+            }),
+            _ => None,
+        };
+        if let Some((p, g)) = params {
+            probe.prime_bits = Some(p.bits());
+            probe.params_fingerprint = Some(params_fingerprint(&p, &g));
+        }
+    }
+
+    for &cipher in CipherSuite::ALL {
+        if hello_round(target, all_kex.clone(), vec![cipher.id()])?.is_some() {
+            probe.ciphers.push(cipher);
+        }
+    }
+
+    Ok(probe)
+}
+
+/// Send one ClientHello and return the server's Hello, or None if it rejected the offer
+fn hello_round(target: &str, kex_algorithms: Vec<u8>, ciphers: Vec<u8>) -> std::io::Result<Option<DHMessage>> {
+    let mut stream = TcpStream::connect(target)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut client_hello = DHMessage::ClientHello {
+        kex_algorithms,
+        ciphers,
+        nonce: Vec::new(),
+        versions: PROTOCOL_VERSIONS.to_vec(),
+        groups: Vec::new(),
+        ticket: Vec::new(),
+        random: with_rng(RngPurpose::Nonce, |rng| rng.r#gen()),
+        cookie: Vec::new(),
+    };
+    write_message(&mut stream, Codec::Native, &client_hello)?;
+    let mut answer = read_message(&mut stream, Codec::Native);
+    // Servers requiring cookies only answer a Hello repeated with one, over a new
+    // connection
+    if let Ok(Some(DHMessage::HelloRetry { cookie: issued })) = answer {
+        let echoed = answer_hello_retry(&issued).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "HelloRetry puzzle is too hard to solve")
+        })?;
+        if let DHMessage::ClientHello { cookie, .. } = &mut client_hello {
+            *cookie = echoed;
+        }
+        stream = TcpStream::connect(target)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        write_message(&mut stream, Codec::Native, &client_hello)?;
+        answer = read_message(&mut stream, Codec::Native);
+    }
+
+    let hello = match answer {
+        Ok(Some(
+            hello @ (DHMessage::ServerHello { .. }
+            | DHMessage::ServerHelloNamed { .. }
+            | DHMessage::ServerHelloHmqv { .. }
+            | DHMessage::ServerHelloSrp { .. }
+            | DHMessage::ServerHelloPadded { .. }
+            | DHMessage::ServerHelloKex { .. }),
+        )) => hello,
+        // A server that cannot serve the offer closes the connection
+        _ => return Ok(None),
+    };
+
+    write_message(&mut stream, Codec::Native, &DHMessage::Abort { reason: ABORT_PROBE })?;
+    Ok(Some(hello))
+}
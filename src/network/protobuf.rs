@@ -0,0 +1,492 @@
+use num_bigint::BigUint;
+use prost::Message as _;
+
+use crate::structs::DH_Prot::DHMessage;
+
+/// One frame of proto/dhke.proto: a single handshake message
+///
+/// The prost types are written out by hand rather than generated, so building needs
+/// no protoc; they must be kept in step with the schema.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Message {
+    #[prost(
+        oneof = "Kind",
+        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 28, 29, 30, 31, 32, 33, 100"
+    )]
+    pub kind: Option<Kind>,
+}
+
+/// The message in a frame; the tag is its native type byte plus one
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum Kind {
+    #[prost(message, tag = "1")]
+    ClientHello(ClientHello),
+    #[prost(message, tag = "2")]
+    ServerHello(ServerHello),
+    #[prost(message, tag = "3")]
+    ClientPublicKey(PublicValue),
+    #[prost(message, tag = "4")]
+    ServerPublicKey(PublicValue),
+    #[prost(message, tag = "5")]
+    Done(Done),
+    #[prost(message, tag = "6")]
+    ServerHelloNamed(GroupHello),
+    #[prost(message, tag = "7")]
+    ServerHelloKex(KexHello),
+    #[prost(message, tag = "8")]
+    ClientKeyShare(KeyShare),
+    #[prost(message, tag = "9")]
+    ServerKeyShare(KeyShare),
+    #[prost(message, tag = "10")]
+    Abort(Abort),
+    #[prost(message, tag = "12")]
+    ServerSignature(ServerSignature),
+    #[prost(message, tag = "13")]
+    ClientFinished(Finished),
+    #[prost(message, tag = "14")]
+    ServerFinished(Finished),
+    #[prost(message, tag = "15")]
+    ServerHelloHmqv(GroupHello),
+    #[prost(message, tag = "16")]
+    EncryptedHandshake(EncryptedHandshake),
+    #[prost(message, tag = "17")]
+    StaticKey(KeyShare),
+    #[prost(message, tag = "18")]
+    PrekeyUpload(PrekeyUpload),
+    #[prost(message, tag = "19")]
+    PrekeyRequest(PrekeyRequest),
+    #[prost(message, tag = "20")]
+    PrekeyResponse(PrekeyResponse),
+    #[prost(message, tag = "21")]
+    ServerHelloSrp(GroupHello),
+    #[prost(message, tag = "22")]
+    ServerHelloPadded(GroupHello),
+    #[prost(message, tag = "23")]
+    PrimeCertificate(PrimeCertificate),
+    #[prost(message, tag = "24")]
+    Rekey(Rekey),
+    #[prost(message, tag = "25")]
+    RekeyFinished(Finished),
+    #[prost(message, tag = "26")]
+    ClientPublicKeyFixed(KeyShare),
+    #[prost(message, tag = "28")]
+    ServerPublicKeyFixed(KeyShare),
+    #[prost(message, tag = "29")]
+    Alert(Alert),
+    #[prost(message, tag = "30")]
+    ServerHelloResume(ResumeHello),
+    #[prost(message, tag = "31")]
+    NewSessionTicket(NewSessionTicket),
+    #[prost(message, tag = "32")]
+    ClientParams(ClientParams),
+    #[prost(message, tag = "33")]
+    HelloRetry(HelloRetry),
+    #[prost(message, tag = "100")]
+    Grease(Grease),
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ClientHello {
+    #[prost(bytes = "vec", tag = "1")]
+    pub kex_algorithms: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub ciphers: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub nonce: Vec<u8>,
+    #[prost(bytes = "vec", tag = "4")]
+    pub versions: Vec<u8>,
+    #[prost(uint32, repeated, tag = "5")]
+    pub groups: Vec<u32>,
+    #[prost(bytes = "vec", tag = "6")]
+    pub ticket: Vec<u8>,
+    #[prost(bytes = "vec", tag = "7")]
+    pub random: Vec<u8>,
+    #[prost(bytes = "vec", tag = "8")]
+    pub cookie: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ServerHello {
+    #[prost(bytes = "vec", tag = "1")]
+    pub p: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub g: Vec<u8>,
+    #[prost(uint32, tag = "3")]
+    pub cipher: u32,
+    #[prost(uint32, tag = "4")]
+    pub version: u32,
+    #[prost(bytes = "vec", tag = "5")]
+    pub random: Vec<u8>,
+}
+
+/// ServerHelloNamed, ServerHelloHmqv, ServerHelloSrp and ServerHelloPadded
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GroupHello {
+    #[prost(uint32, tag = "1")]
+    pub group: u32,
+    #[prost(uint32, tag = "2")]
+    pub cipher: u32,
+    #[prost(uint32, tag = "3")]
+    pub version: u32,
+    #[prost(bytes = "vec", tag = "4")]
+    pub random: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct KexHello {
+    #[prost(uint32, tag = "1")]
+    pub algorithm: u32,
+    #[prost(uint32, tag = "2")]
+    pub cipher: u32,
+    #[prost(uint32, tag = "3")]
+    pub version: u32,
+    #[prost(bytes = "vec", tag = "4")]
+    pub random: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PublicValue {
+    #[prost(bytes = "vec", tag = "1")]
+    pub value: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Done {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct KeyShare {
+    #[prost(bytes = "vec", tag = "1")]
+    pub key: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Abort {
+    #[prost(uint32, tag = "1")]
+    pub reason: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ServerSignature {
+    #[prost(uint32, tag = "1")]
+    pub scheme: u32,
+    #[prost(bytes = "vec", tag = "2")]
+    pub public_key: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Finished {
+    #[prost(bytes = "vec", tag = "1")]
+    pub verify_data: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EncryptedHandshake {
+    #[prost(bytes = "vec", tag = "1")]
+    pub ciphertext: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PrekeyUpload {
+    #[prost(bytes = "vec", tag = "1")]
+    pub publication: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PrekeyRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub identity: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PrekeyResponse {
+    #[prost(bytes = "vec", tag = "1")]
+    pub bundle: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PrimeCertificate {
+    #[prost(bytes = "vec", tag = "1")]
+    pub certificate: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Rekey {
+    #[prost(bytes = "vec", tag = "1")]
+    pub public_key: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ResumeHello {
+    #[prost(uint32, tag = "1")]
+    pub cipher: u32,
+    #[prost(uint32, tag = "2")]
+    pub version: u32,
+    #[prost(bytes = "vec", tag = "3")]
+    pub random: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct NewSessionTicket {
+    #[prost(uint32, tag = "1")]
+    pub lifetime: u32,
+    #[prost(bytes = "vec", tag = "2")]
+    pub ticket: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct HelloRetry {
+    #[prost(bytes = "vec", tag = "1")]
+    pub cookie: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ClientParams {
+    #[prost(bytes = "vec", tag = "1")]
+    pub p: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub g: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Alert {
+    #[prost(uint32, tag = "1")]
+    pub code: u32,
+    #[prost(string, tag = "2")]
+    pub description: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Grease {
+    #[prost(uint32, tag = "1")]
+    pub kind: u32,
+    #[prost(bytes = "vec", tag = "2")]
+    pub payload: Vec<u8>,
+}
+
+/// Encode a message as protobuf
+pub fn encode(message: &DHMessage) -> Vec<u8> {
+    Message::from(message).encode_to_vec()
+}
+
+/// Decode a protobuf message
+///
+/// # Returns
+/// The message, or None if it does not parse, has no kind set, or holds a value out
+/// of range for its native field
+pub fn decode(bytes: &[u8]) -> Option<DHMessage> {
+    Message::decode(bytes).ok()?.kind?.try_into().ok()
+}
+
+impl From<&DHMessage> for Message {
+    fn from(message: &DHMessage) -> Self {
+        let group_hello = |group: &u16, cipher: &u8, version: &u8, random: &[u8; 32]| GroupHello {
+            group: (*group).into(),
+            cipher: (*cipher).into(),
+            version: (*version).into(),
+            random: random.to_vec(),
+        };
+        let key_share = |key: &[u8]| KeyShare { key: key.to_vec() };
+        let finished = |verify_data: &[u8; 32]| Finished { verify_data: verify_data.to_vec() };
+        let kind = match message {
+            DHMessage::ClientHello { kex_algorithms, ciphers, nonce, versions, groups, ticket, random, cookie } => {
+                Kind::ClientHello(ClientHello {
+                    kex_algorithms: kex_algorithms.clone(),
+                    ciphers: ciphers.clone(),
+                    nonce: nonce.clone(),
+                    versions: versions.clone(),
+                    groups: groups.iter().map(|&group| group.into()).collect(),
+                    ticket: ticket.clone(),
+                    random: random.to_vec(),
+                    cookie: cookie.clone(),
+                })
+            }
+            DHMessage::ServerHello { p, g, cipher, version, random } => Kind::ServerHello(ServerHello {
+                p: p.to_bytes_be(),
+                g: g.to_bytes_be(),
+                cipher: (*cipher).into(),
+                version: (*version).into(),
+                random: random.to_vec(),
+            }),
+            DHMessage::ServerHelloNamed { group, cipher, version, random } => {
+                Kind::ServerHelloNamed(group_hello(group, cipher, version, random))
+            }
+            DHMessage::ClientPublicKey { x } => Kind::ClientPublicKey(PublicValue { value: x.to_bytes_be() }),
+            DHMessage::ServerPublicKey { y } => Kind::ServerPublicKey(PublicValue { value: y.to_bytes_be() }),
+            DHMessage::Done => Kind::Done(Done {}),
+            DHMessage::ServerHelloKex { algorithm, cipher, version, random } => Kind::ServerHelloKex(KexHello {
+                algorithm: (*algorithm).into(),
+                cipher: (*cipher).into(),
+                version: (*version).into(),
+                random: random.to_vec(),
+            }),
+            DHMessage::ClientKeyShare { key } => Kind::ClientKeyShare(key_share(key)),
+            DHMessage::ServerKeyShare { key } => Kind::ServerKeyShare(key_share(key)),
+            DHMessage::Abort { reason } => Kind::Abort(Abort { reason: (*reason).into() }),
+            DHMessage::ServerSignature { scheme, public_key, signature } => {
+                Kind::ServerSignature(ServerSignature {
+                    scheme: (*scheme).into(),
+                    public_key: public_key.clone(),
+                    signature: signature.clone(),
+                })
+            }
+            DHMessage::ClientFinished { verify_data } => Kind::ClientFinished(finished(verify_data)),
+            DHMessage::ServerFinished { verify_data } => Kind::ServerFinished(finished(verify_data)),
+            DHMessage::ServerHelloHmqv { group, cipher, version, random } => {
+                Kind::ServerHelloHmqv(group_hello(group, cipher, version, random))
+            }
+            DHMessage::EncryptedHandshake { ciphertext } => {
+                Kind::EncryptedHandshake(EncryptedHandshake { ciphertext: ciphertext.clone() })
+            }
+            DHMessage::StaticKey { key } => Kind::StaticKey(key_share(key)),
+            DHMessage::PrekeyUpload { publication } => {
+                Kind::PrekeyUpload(PrekeyUpload { publication: publication.clone() })
+            }
+            DHMessage::PrekeyRequest { identity } => Kind::PrekeyRequest(PrekeyRequest { identity: identity.to_vec() }),
+            DHMessage::PrekeyResponse { bundle } => Kind::PrekeyResponse(PrekeyResponse { bundle: bundle.clone() }),
+            DHMessage::ServerHelloSrp { group, cipher, version, random } => {
+                Kind::ServerHelloSrp(group_hello(group, cipher, version, random))
+            }
+            DHMessage::ServerHelloPadded { group, cipher, version, random } => {
+                Kind::ServerHelloPadded(group_hello(group, cipher, version, random))
+            }
+            DHMessage::PrimeCertificate { certificate } => {
+                Kind::PrimeCertificate(PrimeCertificate { certificate: certificate.clone() })
+            }
+            DHMessage::Rekey { public_key } => Kind::Rekey(Rekey { public_key: public_key.clone() }),
+            DHMessage::RekeyFinished { verify_data } => Kind::RekeyFinished(finished(verify_data)),
+            DHMessage::ClientPublicKeyFixed { key } => Kind::ClientPublicKeyFixed(key_share(key)),
+            DHMessage::ServerPublicKeyFixed { key } => Kind::ServerPublicKeyFixed(key_share(key)),
+            DHMessage::Alert { code, description } => Kind::Alert(Alert {
+                code: (*code).into(),
+                description: description.clone(),
+            }),
+            DHMessage::ServerHelloResume { cipher, version, random } => Kind::ServerHelloResume(ResumeHello {
+                cipher: (*cipher).into(),
+                version: (*version).into(),
+                random: random.to_vec(),
+            }),
+            DHMessage::NewSessionTicket { lifetime, ticket } => {
+                Kind::NewSessionTicket(NewSessionTicket { lifetime: *lifetime, ticket: ticket.clone() })
+            }
+            DHMessage::HelloRetry { cookie } => Kind::HelloRetry(HelloRetry { cookie: cookie.clone() }),
+            DHMessage::ClientParams { p, g } => Kind::ClientParams(ClientParams {
+                p: p.to_bytes_be(),
+                g: g.to_bytes_be(),
+            }),
+            DHMessage::Grease { kind, payload } => Kind::Grease(Grease { kind: (*kind).into(), payload: payload.clone() }),
+        };
+        Message { kind: Some(kind) }
+    }
+}
+
+impl TryFrom<Kind> for DHMessage {
+    type Error = &'static str;
+
+    fn try_from(kind: Kind) -> Result<Self, Self::Error> {
+        Ok(match kind {
+            Kind::ClientHello(hello) => DHMessage::ClientHello {
+                kex_algorithms: hello.kex_algorithms,
+                ciphers: hello.ciphers,
+                nonce: hello.nonce,
+                versions: hello.versions,
+                groups: hello.groups.into_iter().map(narrow).collect::<Result<_, _>>()?,
+                ticket: hello.ticket,
+                random: fixed(hello.random)?,
+                cookie: hello.cookie,
+            },
+            Kind::ServerHello(hello) => DHMessage::ServerHello {
+                p: integer(&hello.p)?,
+                g: integer(&hello.g)?,
+                cipher: narrow(hello.cipher)?,
+                version: narrow(hello.version)?,
+                random: fixed(hello.random)?,
+            },
+            Kind::ServerHelloNamed(hello) => DHMessage::ServerHelloNamed {
+                group: narrow(hello.group)?,
+                cipher: narrow(hello.cipher)?,
+                version: narrow(hello.version)?,
+                random: fixed(hello.random)?,
+            },
+            Kind::ClientPublicKey(public) => DHMessage::ClientPublicKey { x: integer(&public.value)? },
+            Kind::ServerPublicKey(public) => DHMessage::ServerPublicKey { y: integer(&public.value)? },
+            Kind::Done(_) => DHMessage::Done,
+            Kind::ServerHelloKex(hello) => DHMessage::ServerHelloKex {
+                algorithm: narrow(hello.algorithm)?,
+                cipher: narrow(hello.cipher)?,
+                version: narrow(hello.version)?,
+                random: fixed(hello.random)?,
+            },
+            Kind::ClientKeyShare(share) => DHMessage::ClientKeyShare { key: share.key },
+            Kind::ServerKeyShare(share) => DHMessage::ServerKeyShare { key: share.key },
+            Kind::Abort(abort) => DHMessage::Abort { reason: narrow(abort.reason)? },
+            Kind::ServerSignature(signature) => DHMessage::ServerSignature {
+                scheme: narrow(signature.scheme)?,
+                public_key: signature.public_key,
+                signature: signature.signature,
+            },
+            Kind::ClientFinished(finished) => DHMessage::ClientFinished { verify_data: fixed(finished.verify_data)? },
+            Kind::ServerFinished(finished) => DHMessage::ServerFinished { verify_data: fixed(finished.verify_data)? },
+            Kind::ServerHelloHmqv(hello) => DHMessage::ServerHelloHmqv {
+                group: narrow(hello.group)?,
+                cipher: narrow(hello.cipher)?,
+                version: narrow(hello.version)?,
+                random: fixed(hello.random)?,
+            },
+            Kind::EncryptedHandshake(sealed) => DHMessage::EncryptedHandshake { ciphertext: sealed.ciphertext },
+            Kind::StaticKey(share) => DHMessage::StaticKey { key: share.key },
+            Kind::PrekeyUpload(upload) => DHMessage::PrekeyUpload { publication: upload.publication },
+            Kind::PrekeyRequest(request) => DHMessage::PrekeyRequest { identity: fixed(request.identity)? },
+            Kind::PrekeyResponse(response) => DHMessage::PrekeyResponse { bundle: response.bundle },
+            Kind::ServerHelloSrp(hello) => DHMessage::ServerHelloSrp {
+                group: narrow(hello.group)?,
+                cipher: narrow(hello.cipher)?,
+                version: narrow(hello.version)?,
+                random: fixed(hello.random)?,
+            },
+            Kind::ServerHelloPadded(hello) => DHMessage::ServerHelloPadded {
+                group: narrow(hello.group)?,
+                cipher: narrow(hello.cipher)?,
+                version: narrow(hello.version)?,
+                random: fixed(hello.random)?,
+            },
+            Kind::PrimeCertificate(certificate) => DHMessage::PrimeCertificate { certificate: certificate.certificate },
+            Kind::Rekey(rekey) => DHMessage::Rekey { public_key: rekey.public_key },
+            Kind::RekeyFinished(finished) => DHMessage::RekeyFinished { verify_data: fixed(finished.verify_data)? },
+            Kind::ClientPublicKeyFixed(share) => DHMessage::ClientPublicKeyFixed { key: share.key },
+            Kind::ServerPublicKeyFixed(share) => DHMessage::ServerPublicKeyFixed { key: share.key },
+            Kind::Alert(alert) => DHMessage::Alert { code: narrow(alert.code)?, description: alert.description },
+            Kind::ServerHelloResume(hello) => DHMessage::ServerHelloResume {
+                cipher: narrow(hello.cipher)?,
+                version: narrow(hello.version)?,
+                random: fixed(hello.random)?,
+            },
+            Kind::NewSessionTicket(ticket) => DHMessage::NewSessionTicket { lifetime: ticket.lifetime, ticket: ticket.ticket },
+            Kind::HelloRetry(retry) => DHMessage::HelloRetry { cookie: retry.cookie },
+            Kind::ClientParams(params) => DHMessage::ClientParams {
+                p: integer(&params.p)?,
+                g: integer(&params.g)?,
+            },
+            Kind::Grease(grease) => DHMessage::Grease { kind: narrow(grease.kind)?, payload: grease.payload },
+        })
+    }
+}
+
+/// Narrow a protobuf uint32 to its native width
+fn narrow<T: TryFrom<u32>>(value: u32) -> Result<T, &'static str> {
+    T::try_from(value).map_err(|_| "field value out of range")
+}
+
+/// Parse a big-endian integer, rejecting zero and leading zero bytes as the native
+/// encoding does
+fn integer(bytes: &[u8]) -> Result<BigUint, &'static str> {
+    match bytes.first() {
+        Some(&first) if first != 0 => Ok(BigUint::from_bytes_be(bytes)),
+        _ => Err("integer is empty or has leading zeros"),
+    }
+}
+
+fn fixed<const N: usize>(bytes: Vec<u8>) -> Result<[u8; N], &'static str> {
+    bytes.try_into().map_err(|_| "field has the wrong length")
+}
@@ -0,0 +1,89 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::network::lock_ignoring_poison;
+
+/// Span over which `IdentityQuota::max_handshakes_per_hour` counts handshakes
+const HANDSHAKE_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Limits on what a single authenticated client identity may use, so one credential
+/// cannot monopolize the server
+///
+/// Identities are the SRP username or the fingerprint of the HMQV static key a client
+/// proved it holds; resumed sessions keep the identity of the session that issued
+/// their ticket. Anonymous handshakes are not subject to quotas. Clients over a quota
+/// get a RateLimited alert in place of ServerFinished.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IdentityQuota {
+    /// Most sessions one identity may hold open at once
+    pub max_sessions: Option<usize>,
+    /// Most handshakes one identity may complete within any hour
+    pub max_handshakes_per_hour: Option<usize>,
+}
+
+/// What one identity is currently using
+#[derive(Default)]
+struct Usage {
+    sessions: usize,
+    /// When each handshake admitted within the last hour completed, oldest first
+    handshakes: VecDeque<Instant>,
+}
+
+/// Usage of every identity under a quota, shared by every connection
+///
+/// Identities with no open session and no handshake within the hour are forgotten at
+/// the next admission. Clones share the same usage.
+#[derive(Clone, Default)]
+pub(crate) struct QuotaTracker {
+    usage: Arc<Mutex<HashMap<String, Usage>>>,
+}
+
+impl QuotaTracker {
+    /// Count a handshake by `identity` and open a session for it, if `quota` allows
+    ///
+    /// # Returns
+    /// A handle that holds the session open until dropped, or the limit the identity
+    /// reached
+    pub(crate) fn admit(&self, quota: &IdentityQuota, identity: &str) -> Result<QuotaHandle, &'static str> {
+        let now = Instant::now();
+        let mut usage = self.lock();
+        usage.retain(|_, entry| {
+            entry.sessions > 0 || entry.handshakes.back().is_some_and(|last| now.duration_since(*last) < HANDSHAKE_WINDOW)
+        });
+        let entry = usage.entry(identity.to_string()).or_default();
+        while entry.handshakes.front().is_some_and(|completed| now.duration_since(*completed) >= HANDSHAKE_WINDOW) {
+            entry.handshakes.pop_front();
+        }
+        if quota.max_sessions.is_some_and(|max| entry.sessions >= max) {
+            return Err("too many concurrent sessions for this identity");
+        }
+        if quota.max_handshakes_per_hour.is_some_and(|max| entry.handshakes.len() >= max) {
+            return Err("too many handshakes this hour for this identity");
+        }
+        entry.sessions += 1;
+        entry.handshakes.push_back(now);
+        Ok(QuotaHandle {
+            tracker: self.clone(),
+            identity: identity.to_string(),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Usage>> {
+        lock_ignoring_poison(&self.usage)
+    }
+}
+
+/// A session counted against its identity's quota; dropping it closes the session
+pub(crate) struct QuotaHandle {
+    tracker: QuotaTracker,
+    identity: String,
+}
+
+impl Drop for QuotaHandle {
+    fn drop(&mut self) {
+        if let Some(entry) = self.tracker.lock().get_mut(&self.identity) {
+            entry.sessions -= 1;
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use crate::crypto::ratchet::{responder_key_pair, DoubleRatchet, RatchetHeader, HEADER_LEN};
+use crate::crypto::record::MAX_RECORD_PLAINTEXT;
+
+/// Size of the authentication tag the ratchet appends to every message
+const TAG_LEN: usize = 16;
+
+/// Messages over a TcpStream, each under its own Double Ratchet key
+///
+/// Before the first message the responder sends its initial ratchet public key in
+/// the clear; only parties holding the shared secret can use it. After that, every
+/// message is sent as [4-byte length][header][ciphertext || tag], the header being
+/// authenticated as associated data.
+pub struct RatchetChannel {
+    stream: TcpStream,
+    ratchet: DoubleRatchet,
+}
+
+impl RatchetChannel {
+    /// Channel for the party that sends first; waits for the responder's ratchet key
+    ///
+    /// # Arguments
+    /// * `stream` - Connection to the responder
+    /// * `secret` - 256-bit secret both parties agreed on (e.g., exported from
+    ///   `SessionKeys` under a label of the application's choosing)
+    /// * `associated_data` - Authenticated with every message; must match the responder's
+    pub fn initiator(mut stream: TcpStream, secret: &[u8; 32], associated_data: &[u8]) -> std::io::Result<Self> {
+        let mut responder_key = [0; 32];
+        stream.read_exact(&mut responder_key)?;
+        let ratchet = DoubleRatchet::initiator(secret, &responder_key, associated_data)
+            .map_err(|reason| std::io::Error::new(std::io::ErrorKind::InvalidData, reason))?;
+        Ok(RatchetChannel { stream, ratchet })
+    }
+
+    /// Channel for the party that answers; sends its ratchet key, and can send
+    /// messages once the initiator's first message has arrived
+    pub fn responder(mut stream: TcpStream, secret: &[u8; 32], associated_data: &[u8]) -> std::io::Result<Self> {
+        let (ratchet_secret, ratchet_public) = responder_key_pair();
+        stream.write_all(&ratchet_public)?;
+        stream.flush()?;
+        Ok(RatchetChannel {
+            stream,
+            ratchet: DoubleRatchet::responder(secret, ratchet_secret, associated_data),
+        })
+    }
+
+    /// Encrypt one message under a fresh message key and send it
+    ///
+    /// # Arguments
+    /// * `plaintext` - At most `MAX_RECORD_PLAINTEXT` bytes
+    pub fn send(&mut self, plaintext: &[u8]) -> std::io::Result<()> {
+        if plaintext.len() > MAX_RECORD_PLAINTEXT {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Ratchet message too large",
+            ));
+        }
+        let (header, ciphertext) = self
+            .ratchet
+            .encrypt(plaintext)
+            .map_err(|reason| std::io::Error::new(std::io::ErrorKind::InvalidInput, reason))?;
+
+        let mut frame = Vec::with_capacity(4 + HEADER_LEN + ciphertext.len());
+        frame.extend_from_slice(&((HEADER_LEN + ciphertext.len()) as u32).to_be_bytes());
+        frame.extend_from_slice(&header.to_bytes());
+        frame.extend_from_slice(&ciphertext);
+        self.stream.write_all(&frame)?;
+        self.stream.flush()
+    }
+
+    /// Receive and decrypt one message
+    ///
+    /// # Returns
+    /// The plaintext, None if the peer closed the connection between messages, or an
+    /// InvalidData error if the message is oversized or fails authentication
+    pub fn receive(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        let mut len_bytes = [0; 4];
+        match self.stream.read(&mut len_bytes[..1])? {
+            0 => return Ok(None),
+            _ => self.stream.read_exact(&mut len_bytes[1..])?,
+        }
+        let len = u32::from_be_bytes(len_bytes) as usize;
+        if !(HEADER_LEN + TAG_LEN..=HEADER_LEN + MAX_RECORD_PLAINTEXT + TAG_LEN).contains(&len) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Ratchet message length out of range",
+            ));
+        }
+
+        let mut frame = vec![0; len];
+        self.stream.read_exact(&mut frame)?;
+        let (header, ciphertext) = frame.split_at(HEADER_LEN);
+        let header = RatchetHeader::from_bytes(header).expect("header slice has the exact length");
+        self.ratchet
+            .decrypt(&header, ciphertext)
+            .map(Some)
+            .map_err(|reason| std::io::Error::new(std::io::ErrorKind::InvalidData, reason))
+    }
+
+    /// Get the peer address of the connection
+    pub fn peer_addr(&self) -> std::io::Result<std::net::SocketAddr> {
+        self.stream.peer_addr()
+    }
+}
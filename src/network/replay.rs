@@ -0,0 +1,81 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::network::lock_ignoring_poison;
+
+/// How long a server remembers client randoms unless configured otherwise
+pub const DEFAULT_REPLAY_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// Most client randoms remembered at once; past this the oldest are forgotten early,
+/// so a flood of handshakes cannot exhaust memory
+const MAX_REMEMBERED: usize = 1 << 16;
+
+/// Server-side record of the ClientHello randoms seen recently, shared by every
+/// connection
+///
+/// A ClientHello whose random was already seen within the window is a replay and is
+/// refused. Clients pick a fresh random for every handshake, so honest clients never
+/// collide. The server keeps a second record of the session tickets redeemed, by
+/// `ticket_id`, over the ticket lifetime. Clones share the same record.
+#[derive(Clone)]
+pub struct ReplayWindow {
+    window: Duration,
+    seen: Arc<Mutex<Seen>>,
+}
+
+#[derive(Default)]
+struct Seen {
+    /// Randoms in the order they arrived, which is also the order they expire in
+    arrivals: VecDeque<(Instant, [u8; 32])>,
+    randoms: HashSet<[u8; 32]>,
+}
+
+impl ReplayWindow {
+    /// Remember client randoms for `window`; a zero window remembers none
+    pub fn new(window: Duration) -> Self {
+        ReplayWindow {
+            window,
+            seen: Arc::new(Mutex::new(Seen::default())),
+        }
+    }
+
+    /// How long client randoms are remembered
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Record a client random
+    ///
+    /// # Returns
+    /// false if it was already seen within the window (a replay), true otherwise
+    pub fn check(&self, random: &[u8; 32]) -> bool {
+        if self.window.is_zero() {
+            return true;
+        }
+        let now = Instant::now();
+        let mut seen = self.lock();
+        while let Some(&(arrived, oldest)) = seen.arrivals.front() {
+            if now.duration_since(arrived) < self.window && seen.arrivals.len() < MAX_REMEMBERED {
+                break;
+            }
+            seen.arrivals.pop_front();
+            seen.randoms.remove(&oldest);
+        }
+        if !seen.randoms.insert(*random) {
+            return false;
+        }
+        seen.arrivals.push_back((now, *random));
+        true
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Seen> {
+        lock_ignoring_poison(&self.seen)
+    }
+}
+
+impl Default for ReplayWindow {
+    fn default() -> Self {
+        Self::new(DEFAULT_REPLAY_WINDOW)
+    }
+}
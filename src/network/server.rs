@@ -1,10 +1,79 @@
+use std::io::Write;
 use std::net::{TcpListener, TcpStream};
-use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::thread;
-use num_bigint::BigInt;
+use num_bigint::{BigInt, BigUint, Sign};
+use num_traits::Zero;
+use rand::Rng;
 
-use crate::structs::DH_Prot::{DHMessage, DHConnection};
-use crate::crypto::crypto::{generate_dh_params, generate_secret_key, compute_public_key, mod_pow};
+use crate::structs::DH_Prot::{DHMessage, DHConnection, ABORT_PREKEYS_REJECTED, ABORT_UNAUTHENTICATED, ABORT_VERSION_MISMATCH, PRIME_CERTIFICATE_SIGNAL, FIXED_WIDTH_KEYS_SIGNAL, ONE_STEP_KDF_SIGNAL, PROTECT_IDENTITIES_SIGNAL, PROTOCOL_VERSIONS, REKEY_SIGNAL, SESSION_TICKET_SIGNAL, CLIENT_PARAMS_SIGNAL, RECORD_PADDING_SIGNAL};
+use crate::crypto::cookie::{CookieKey, PuzzlePolicy, DEFAULT_COOKIE_LIFETIME};
+use crate::crypto::crypto::{
+    generate_dh_params, generate_dh_params_seeded, generate_secret_key, validate_dh_params, Blinding, ExponentPolicy,
+    PrimalityConfig,
+};
+use crate::crypto::fingerprint::Fingerprint;
+use crate::crypto::groups::DhGroup;
+use crate::crypto::identity::ServerIdentity;
+use crate::crypto::kdf::{Kdf, SessionKeys, CLIENT_FINISHED_LABEL, SERVER_FINISHED_LABEL};
+use crate::crypto::param_cache;
+use crate::crypto::params_policy::ParamsPolicy;
+use crate::crypto::pkcs3::DhParams;
+use crate::crypto::prime_certificate::generate_provable_dh_params;
+use crate::crypto::static_key;
+use crate::crypto::handshake_protection::HandshakeProtection;
+use crate::crypto::hmqv::{join_share, HmqvKeyExchange, HmqvRole};
+use crate::crypto::kex::{check_fixed_width, curve_key_exchange, to_fixed_width, FiniteFieldKeyExchange, KexAlgorithm, KeyExchange};
+use crate::crypto::obfuscation::PaddedFiniteFieldKeyExchange;
+use crate::crypto::rng::{with_rng, RngPurpose};
+use crate::crypto::srp::{self, SrpServerExchange, SrpVerifierStore};
+use crate::crypto::ticket::{ticket_id, Resumption, TicketKey};
+use crate::crypto::record::{CipherSuite, CloseReason, KeepalivePolicy, Padding, RecordLayer, RekeyPolicy};
+use crate::crypto::transcript::to_hex;
+use crate::network::anomaly::{report, AnomalyKind, AnomalyListener};
+use crate::network::alert::{Alert, AlertCode};
+use crate::network::channel::SecureChannel;
+use crate::network::framing::{read_message_within, write_message, Codec, LimitExceeded, MessageLimits};
+use crate::network::cancel::{is_cancelled, wait_readable, CancelToken, CANCEL_POLL_INTERVAL};
+use crate::network::lifecycle::{ConnectionRegistry, LifecyclePolicy, DRAIN_CHECK_INTERVAL};
+use crate::network::prekeys::PrekeyDirectory;
+use crate::network::quotas::{IdentityQuota, QuotaTracker};
+use crate::network::replay::ReplayWindow;
+use crate::network::telemetry::{ConnectionTrace, TelemetryExporter};
+use crate::network::tasks::TaskTracker;
+
+/// Where the server's DH parameters (p, g) come from
+#[derive(Debug, Clone)]
+pub enum ParamSource {
+    /// Generate a fresh random safe prime of the given bit length at startup (slow for 2048+ bits)
+    Generate(usize),
+    /// Regenerate the same parameters every time from a seed (reproducible test setups)
+    Seeded(usize, Vec<u8>),
+    /// Use a predefined well-known group (no generation cost)
+    Group(DhGroup),
+    /// Generate a fresh safe prime of the given bit length with a Pocklington
+    /// certificate, sent to clients that ask for proof that p is prime
+    Provable(usize),
+    /// Load PKCS#3 parameters, PEM or DER, from a file (e.g., written by `openssl dhparam`)
+    File(PathBuf),
+    /// Use PKCS#3 parameters decoded elsewhere (e.g., `openssl dhparam` output piped to
+    /// stdin); they are validated like those from a file
+    Params(DhParams),
+}
+
+/// How the server picks its finite-field DH exponent
+#[derive(Debug, Clone, Default)]
+pub enum KeyMode {
+    /// A fresh random exponent for every client (forward secrecy)
+    #[default]
+    Ephemeral,
+    /// One long-lived exponent loaded from (or generated into) this file, so the
+    /// server's public value never changes and clients can pin its fingerprint.
+    /// Clients stay ephemeral, but a leaked key file exposes every past session.
+    /// X25519 handshakes are unaffected and stay ephemeral on both sides.
+    Static(PathBuf),
+}
 
 /// DH Server that listens for and handles multiple client connections
 pub struct DHServer {
@@ -12,8 +81,79 @@ pub struct DHServer {
     prime: BigInt,
     /// Server's DH base generator
     base: BigInt,
+    /// Order q of the subgroup generated by the base, for validating client keys
+    subgroup_order: BigInt,
     /// Listener socket
     listener: TcpListener,
+    /// Negotiation options handed to every client thread
+    settings: HandshakeSettings,
+    /// Every client thread spawned by `run`
+    tasks: TaskTracker,
+}
+
+/// Handshake options shared by every client thread
+#[derive(Clone)]
+struct HandshakeSettings {
+    /// Whether to interleave GREASE messages into handshakes
+    grease: bool,
+    /// Key-exchange algorithms this server accepts
+    kex_algorithms: Vec<KexAlgorithm>,
+    /// Record-layer ciphers this server accepts
+    ciphers: Vec<CipherSuite>,
+    /// Key derivation functions this server accepts
+    kdfs: Vec<Kdf>,
+    /// Message encodings this server accepts after ClientHello
+    codecs: Vec<Codec>,
+    /// Whether handshakes are newline-delimited JSON (`Codec::Json`) from the start
+    json_wire: bool,
+    /// Notified of protocol violations, rejected keys, and other security events
+    anomaly_listener: Option<AnomalyListener>,
+    /// Stops the accept loop, pending handshakes, and idle connections when cancelled
+    cancel: CancelToken,
+    /// Signs handshakes for clients that ask the server to authenticate
+    identity: Option<Arc<ServerIdentity>>,
+    /// Exponent used for every finite-field handshake in `KeyMode::Static`
+    static_secret: Option<BigInt>,
+    /// Connection age and count limits
+    lifecycle: LifecyclePolicy,
+    /// Established connections, shared with the accept loop so it can drain them
+    connections: ConnectionRegistry,
+    /// What each authenticated client identity may use, if limited
+    quota: Option<IdentityQuota>,
+    /// Sessions and recent handshakes of every identity under the quota
+    quotas: QuotaTracker,
+    /// X3DH prekeys served to clients that send a directory request instead of ClientHello
+    prekeys: Option<PrekeyDirectory>,
+    /// Password verifiers for SRP handshakes
+    srp: Option<Arc<SrpVerifierStore>>,
+    /// Receives a span tree for every connection once it ends
+    telemetry: Option<TelemetryExporter>,
+    /// Encoded certificate proving p a safe prime, for `ParamSource::Provable`
+    prime_certificate: Option<Vec<u8>>,
+    /// Randomization of the finite-field exponentiations with our secret exponent
+    blinding: Blinding,
+    /// How long the ephemeral finite-field exponents are
+    exponent_policy: ExponentPolicy,
+    /// When to rekey sessions with clients that can rekey
+    rekey: RekeyPolicy,
+    /// When to ping quiet clients, and how many missed Pongs end their connection
+    keepalive: Option<KeepalivePolicy>,
+    /// Padding of what we encrypt for clients that ask for padded records
+    padding: Padding,
+    /// Seals the session tickets issued to clients that ask, and opens those they present
+    tickets: Option<TicketKey>,
+    /// Tickets redeemed within their lifetime (by `ticket_id`), so each resumes one session
+    redeemed_tickets: ReplayWindow,
+    /// ClientHello randoms seen recently, to refuse replayed handshakes
+    replay_window: ReplayWindow,
+    /// Issues and checks HelloRetry cookies, if clients must echo one before a handshake
+    cookies: Option<CookieKey>,
+    /// How hard the puzzles on those cookies are, if they carry any
+    puzzle: Option<PuzzlePolicy>,
+    /// Which (p, g) clients may propose in place of ours; None refuses every proposal
+    client_params: Option<ParamsPolicy>,
+    /// Size limits of the handshake messages we read
+    limits: MessageLimits,
 }
 
 impl DHServer {
@@ -21,13 +161,75 @@ impl DHServer {
     ///
     /// # Arguments
     /// * `addr` - Address to bind to (e.g., "127.0.0.1:8080")
-    /// * `bit_length` - Bit length for DH prime (e.g., 1024, 2048)
+    /// * `params` - Where to get the DH parameters from (e.g., `ParamSource::Group(DhGroup::Modp2048)`)
+    /// * `param_cache` - File to reuse generated parameters from across restarts; parameters
+    ///   from `ParamSource::Generate` are loaded from it if valid and written to it otherwise
+    /// * `key_mode` - Whether each client gets a fresh exponent or all share a static one;
+    ///   a static key needs stable parameters (a group, a seed, or a parameter cache)
     ///
     /// # Returns
     /// A new DHServer instance
-    pub fn new(addr: &str, bit_length: usize) -> std::io::Result<Self> {
-        println!("[SERVER] Generating DH parameters ({} bits)...", bit_length);
-        let (prime, base) = generate_dh_params(bit_length);
+    pub fn new(addr: &str, params: ParamSource, param_cache: Option<&Path>, key_mode: KeyMode) -> std::io::Result<Self> {
+        let mut prime_certificate = None;
+        let (prime, base, subgroup_order) = match params {
+            ParamSource::Generate(bit_length) => match param_cache.and_then(|path| param_cache::load(path, bit_length)) {
+                Some(cached) => {
+                    println!("[SERVER] Loaded cached DH parameters ({} bits)", bit_length);
+                    cached
+                }
+                None => {
+                    let config = PrimalityConfig::new(bit_length);
+                    println!(
+                        "[SERVER] Generating DH parameters ({} bits, primality error below 2^-{:.0})...",
+                        bit_length,
+                        config.false_positive_bound_bits()
+                    );
+                    let generated = generate_dh_params(&config);
+                    if let Some(path) = param_cache {
+                        param_cache::store(path, &generated.0, &generated.1)?;
+                        println!("[SERVER] Saved DH parameters to {}", path.display());
+                    }
+                    generated
+                }
+            },
+            ParamSource::Seeded(bit_length, seed) => {
+                println!("[SERVER] Deriving DH parameters ({} bits) from seed...", bit_length);
+                generate_dh_params_seeded(&PrimalityConfig::new(bit_length), &seed)
+            }
+            ParamSource::Group(group) => {
+                println!("[SERVER] Using predefined DH group {}", group.name());
+                let (p, g) = group.params();
+                (p, g, group.subgroup_order())
+            }
+            ParamSource::Provable(bit_length) => {
+                println!("[SERVER] Generating provable DH parameters ({} bits)...", bit_length);
+                let (p, g, q, certificate) = generate_provable_dh_params(&PrimalityConfig::new(bit_length));
+                prime_certificate = Some(certificate.to_bytes());
+                (p, g, q)
+            }
+            ParamSource::File(path) => {
+                let params = DhParams::load(&path)?;
+                let q = validate_loaded_params(&params, &path.display().to_string())?;
+                println!("[SERVER] Loaded DH parameters ({} bits) from {}", params.p.bits(), path.display());
+                (params.p, params.g, q)
+            }
+            ParamSource::Params(params) => {
+                let q = validate_loaded_params(&params, "the given parameters")?;
+                println!("[SERVER] Using given DH parameters ({} bits)", params.p.bits());
+                (params.p, params.g, q)
+            }
+        };
+        
+        let static_secret = match &key_mode {
+            KeyMode::Ephemeral => None,
+            KeyMode::Static(path) => {
+                let secret = static_key::load_or_generate(path, &prime)?;
+                println!("[SERVER] Using static DH key from {}", path.display());
+                let public_key = FiniteFieldKeyExchange::with_secret(&prime, &base, &subgroup_order, secret.clone()).public_key();
+                println!("[SERVER] Static key fingerprint: {}", Fingerprint::of(&public_key));
+                Some(secret)
+            }
+        };
         
         println!("[SERVER] Binding to {}", addr);
         let listener: TcpListener = TcpListener::bind(addr)?;
@@ -36,38 +238,330 @@ impl DHServer {
         Ok(DHServer {
             prime,
             base,
+            subgroup_order,
             listener,
+            settings: HandshakeSettings {
+                grease: false,
+                kex_algorithms: KexAlgorithm::ALL.to_vec(),
+                ciphers: CipherSuite::DEFAULT.to_vec(),
+                kdfs: Kdf::ALL.to_vec(),
+                codecs: Codec::ALL.to_vec(),
+                json_wire: false,
+                anomaly_listener: None,
+                cancel: CancelToken::new(),
+                identity: None,
+                static_secret,
+                lifecycle: LifecyclePolicy::default(),
+                connections: ConnectionRegistry::default(),
+                quota: None,
+                quotas: QuotaTracker::default(),
+                prekeys: None,
+                srp: None,
+                tickets: None,
+                redeemed_tickets: ReplayWindow::new(std::time::Duration::ZERO),
+                replay_window: ReplayWindow::default(),
+                cookies: None,
+                puzzle: None,
+                client_params: None,
+                telemetry: None,
+                prime_certificate,
+                blinding: Blinding::None,
+                exponent_policy: ExponentPolicy::Full,
+                rekey: RekeyPolicy::default(),
+                keepalive: None,
+                padding: Padding::None,
+                limits: MessageLimits::default(),
+            },
+            tasks: TaskTracker::new(),
         })
     }
 
+    /// Restrict the key-exchange algorithms this server accepts
+    pub fn with_kex_algorithms(mut self, algorithms: &[KexAlgorithm]) -> Self {
+        self.settings.kex_algorithms = algorithms.to_vec();
+        self
+    }
+
+    /// Restrict the record-layer ciphers this server accepts
+    pub fn with_ciphers(mut self, ciphers: &[CipherSuite]) -> Self {
+        self.settings.ciphers = ciphers.to_vec();
+        self
+    }
+
+    /// Enable or disable sending GREASE messages during handshakes
+    pub fn with_grease(mut self, enabled: bool) -> Self {
+        self.settings.grease = enabled;
+        self
+    }
+
+    /// Blind the finite-field exponentiations with our secret exponent
+    ///
+    /// Worth its cost mainly with a static key (`KeyMode::Static`), whose exponent an
+    /// attacker could otherwise time across any number of handshakes. Applies to
+    /// ffdh and ffdh-padded; HMQV and SRP exponentiate unblinded.
+    pub fn with_blinding(mut self, blinding: Blinding) -> Self {
+        self.settings.blinding = blinding;
+        self
+    }
+
+    /// Draw ephemeral finite-field exponents under this policy (full-length by default)
+    ///
+    /// Applies to ffdh, ffdh-padded, HMQV's ephemeral key and SRP; a static key
+    /// (`KeyMode::Static`) stays full-length. Exponentiation cost scales with the
+    /// exponent length, so `ExponentPolicy::Short` makes a 2048-bit key exchange
+    /// roughly 9 times cheaper.
+    pub fn with_exponent_policy(mut self, policy: ExponentPolicy) -> Self {
+        self.settings.exponent_policy = policy;
+        self
+    }
+
+    /// Restrict the key derivation functions this server accepts (e.g., to
+    /// `Kdf::OneStep` where a compliance regime requires SP 800-56A)
+    pub fn with_kdfs(mut self, kdfs: &[Kdf]) -> Self {
+        self.settings.kdfs = kdfs.to_vec();
+        self
+    }
+
+    /// Restrict the message encodings this server accepts after ClientHello
+    pub fn with_codecs(mut self, codecs: &[Codec]) -> Self {
+        self.settings.codecs = codecs.to_vec();
+        self
+    }
+
+    /// Exchange every handshake message as a line of JSON (see `Codec::Json`), for
+    /// following handshakes with netcat; only clients in the same mode can connect
+    pub fn with_json_wire(mut self, enabled: bool) -> Self {
+        self.settings.json_wire = enabled;
+        self
+    }
+
+    /// Rekey sessions once they reach the policy's thresholds
+    ///
+    /// Only clients that list `REKEY_SIGNAL` are rekeyed; their own thresholds may
+    /// start rekeys sooner.
+    pub fn with_rekey_policy(mut self, policy: RekeyPolicy) -> Self {
+        self.settings.rekey = policy;
+        self
+    }
+
+    /// Ping established clients that go quiet, closing the connections of those that
+    /// stop answering
+    pub fn with_keepalive(mut self, policy: KeepalivePolicy) -> Self {
+        self.settings.keepalive = Some(policy);
+        self
+    }
+
+    /// Pad the records and encrypted handshake messages sent to clients that ask for
+    /// padded records (by default they only carry the padding length)
+    pub fn with_padding(mut self, padding: Padding) -> Self {
+        self.settings.padding = padding;
+        self
+    }
+
+    /// Limit the size of the handshake messages clients send (by default, frames of
+    /// `MAX_FRAME` bytes and integers of up to `MAX_GROUP_BITS` bits)
+    ///
+    /// Messages over a limit end the handshake with a DecodeError alert.
+    pub fn with_message_limits(mut self, limits: MessageLimits) -> Self {
+        self.settings.limits = limits;
+        self
+    }
+
+    /// Sign handshakes with this identity when clients ask the server to authenticate
+    ///
+    /// Clients pin the identity's public key; without an identity, handshakes that
+    /// request a signature are aborted.
+    pub fn with_identity(mut self, identity: ServerIdentity) -> Self {
+        let server_key = identity.server_key();
+        println!("[SERVER] Server identity: {}", server_key.describe());
+        println!("[SERVER] Identity fingerprint: {}", server_key.fingerprint());
+        self.settings.identity = Some(Arc::new(identity));
+        self
+    }
+
+    /// Bound connection count and age; connections closed under the policy are told
+    /// they were drained, so clients can reconnect
+    pub fn with_lifecycle_policy(mut self, policy: LifecyclePolicy) -> Self {
+        self.settings.lifecycle = policy;
+        self
+    }
+
+    /// Limit the sessions and handshakes of each authenticated client identity (an SRP
+    /// username or HMQV static key)
+    ///
+    /// The limits are checked once the client's Finished verifies; clients over them
+    /// get a RateLimited alert instead of ServerFinished.
+    pub fn with_identity_quota(mut self, quota: IdentityQuota) -> Self {
+        self.settings.quota = Some(quota);
+        self
+    }
+
+    /// Serve an X3DH prekey directory alongside key exchanges
+    ///
+    /// Parties upload signed prekeys before going offline, and peers fetch them to
+    /// start a session (see `crypto::x3dh`). Each request takes one connection.
+    pub fn with_prekey_directory(mut self, directory: PrekeyDirectory) -> Self {
+        self.settings.prekeys = Some(directory);
+        self
+    }
+
+    /// Issue session tickets to clients that ask, and resume the sessions they came from
+    ///
+    /// A resumed handshake skips the key exchange and authentication: its keys come
+    /// from the ticket's secret and a fresh random. Tickets are sealed under this key,
+    /// so the server only remembers those already redeemed, until they expire: each
+    /// ticket resumes one session, and a client presenting it again gets a full
+    /// handshake. A flood of resumptions past `ReplayWindow`'s capacity forgets the
+    /// oldest early, reopening them to reuse.
+    pub fn with_ticket_key(mut self, key: TicketKey) -> Self {
+        self.settings.redeemed_tickets = ReplayWindow::new(key.lifetime());
+        self.settings.tickets = Some(key);
+        self
+    }
+
+    /// Refuse ClientHellos whose random was already seen within `window`
+    /// (default: `DEFAULT_REPLAY_WINDOW`); a zero window turns the check off
+    pub fn with_replay_window(mut self, window: std::time::Duration) -> Self {
+        self.settings.replay_window = ReplayWindow::new(window);
+        self
+    }
+
+    /// Answer every ClientHello without a valid cookie with a HelloRetry and close the
+    /// connection, starting the handshake only once the client reconnects and repeats
+    /// its Hello with the cookie
+    ///
+    /// Until then the server does no key exchange and remembers nothing about the
+    /// client, not even its random, so a flood of Hellos from addresses that never
+    /// read the answers costs one HMAC each. Cookies are bound to the client's IP
+    /// address and ClientHello random (and the difficulty of their puzzle, see
+    /// `with_client_puzzle`), and authenticated with this key.
+    pub fn with_cookie_key(mut self, key: CookieKey) -> Self {
+        self.settings.cookies = Some(key);
+        self
+    }
+
+    /// Put a proof-of-work puzzle on every HelloRetry cookie, harder the more
+    /// connections are established, so a client pays for the exponentiation it is
+    /// about to cost us before we spend it
+    ///
+    /// Requires cookies, and generates a cookie key unless `with_cookie_key` set one.
+    /// Checking a solution costs one hash. Clients refuse puzzles harder than
+    /// `MAX_PUZZLE_DIFFICULTY`, so a larger maximum locks them out.
+    pub fn with_client_puzzle(mut self, policy: PuzzlePolicy) -> Self {
+        if self.settings.cookies.is_none() {
+            self.settings.cookies = Some(CookieKey::generate(DEFAULT_COOKIE_LIFETIME));
+        }
+        self.settings.puzzle = Some(policy);
+        self
+    }
+
+    /// Let clients that list `CLIENT_PARAMS_SIGNAL` propose the (p, g) for finite-field
+    /// DH, using them instead of ours if the policy accepts them
+    ///
+    /// Such handshakes use a fresh secret exponent even in `KeyMode::Static`, and never
+    /// select HMQV or SRP, whose keys belong to other groups. Without a policy,
+    /// proposals are refused.
+    pub fn with_client_params(mut self, policy: ParamsPolicy) -> Self {
+        self.settings.client_params = Some(policy);
+        self
+    }
+
+    /// Accept SRP handshakes from the users in this store
+    ///
+    /// SRP runs over the store's group rather than the server's parameters. Clients
+    /// that know their password get a key resistant to offline guessing; the others
+    /// fail at ClientFinished, whether or not their username exists.
+    pub fn with_srp_verifiers(mut self, verifiers: SrpVerifierStore) -> Self {
+        println!(
+            "[SERVER] Accepting SRP for {} user(s) over {}",
+            verifiers.len(),
+            verifiers.group().name()
+        );
+        self.settings.srp = Some(Arc::new(verifiers));
+        self
+    }
+
+    /// Install a callback for security-relevant events on any connection
+    pub fn with_anomaly_listener(mut self, listener: AnomalyListener) -> Self {
+        self.settings.anomaly_listener = Some(listener);
+        self
+    }
+
+    /// Export a span for every connection, with child spans for the handshake phases
+    /// and crypto operations (see `telemetry`)
+    ///
+    /// With the `opentelemetry` feature, `telemetry::opentelemetry_exporter` sends
+    /// them to existing tracing infrastructure.
+    pub fn with_telemetry_exporter(mut self, exporter: TelemetryExporter) -> Self {
+        self.settings.telemetry = Some(exporter);
+        self
+    }
+
+    /// Stop `run` and every connection it serves when this token is cancelled
+    pub fn with_cancel_token(mut self, cancel: CancelToken) -> Self {
+        self.settings.cancel = cancel;
+        self
+    }
+
+    /// Token that stops `run` from another thread when cancelled
+    pub fn cancel_token(&self) -> CancelToken {
+        self.settings.cancel.clone()
+    }
+
+    /// Address the server listens on, with the port the system picked if bound to port 0
+    pub fn local_addr(&self) -> std::io::Result<std::net::SocketAddr> {
+        self.listener.local_addr()
+    }
+
     /// Start the server and listen for incoming connections
-    /// Spawns a new thread for each client connection
+    /// Spawns a new tracked thread for each client connection
+    ///
+    /// Returns once the cancel token is cancelled and every client thread has finished.
+    /// Connections notice within a poll interval, whether idle or between handshake
+    /// steps; only a message that has started arriving is read to completion first.
     pub fn run(&self) -> std::io::Result<()> {
         println!("[SERVER] Waiting for client connections...");
         
-        for stream in self.listener.incoming() {
-            match stream {
-                Ok(client_stream) => {
-                    let client_addr = client_stream.peer_addr().ok();
+        // Poll instead of blocking in accept so cancellation is noticed
+        self.listener.set_nonblocking(true)?;
+        while !self.settings.cancel.is_cancelled() {
+            match self.listener.accept() {
+                Ok((client_stream, peer)) => {
+                    let client_addr = Some(peer);
                     println!("[SERVER] New client connection: {:?}", client_addr);
+                    if !self.admit() {
+                        println!("[SERVER] Connection limit reached, refusing {}", peer);
+                        continue;
+                    }
+                    client_stream.set_nonblocking(false)?;
                     
                     // Clone shared parameters (p, g) for this client's thread
                     // Note: p and g are shared per DH protocol, but each client gets unique secret exponent
                     let prime = self.prime.clone();
                     let base = self.base.clone();
+                    let subgroup_order = self.subgroup_order.clone();
+                    let settings = self.settings.clone();
                     
                     // Spawn a NEW THREAD for this client with completely isolated state
                     // Each thread:
                     // - Generates its own random secret exponent (y)
                     // - Maintains its own DHConnection with unique client public key (X)
                     // - Computes its own unique shared secret (not shared with other clients)
-                    thread::spawn(move || {
-                        if let Err(e) = handle_client(client_stream, prime, base) {
-                            eprintln!("[SERVER] Error handling client {:?}: {}", client_addr, e);
+                    // Failures are reported centrally when the task is reaped
+                    // Thread exits when handle_client returns, taking the connection and secrets with it
+                    // No state persists between clients
+                    self.tasks.spawn(format!("client-{}", peer), move || {
+                        let trace = ConnectionTrace::new(settings.telemetry.clone(), &peer.to_string());
+                        let result = handle_client(client_stream, prime, base, subgroup_order, settings, &trace);
+                        if let Err(e) = &result {
+                            trace.fail(e);
                         }
-                        // Thread exits here, taking the connection and secrets with it
-                        // No state persists between clients
-                    });
+                        result
+                    })?;
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    self.tasks.reap();
+                    thread::sleep(CANCEL_POLL_INTERVAL);
                 }
                 Err(e) => {
                     eprintln!("[SERVER] Error accepting connection: {}", e);
@@ -75,77 +569,663 @@ impl DHServer {
             }
         }
         
+        println!("[SERVER] Shutting down, waiting for {} client tasks", self.tasks.active());
+        let joined = self.tasks.join_all();
+        println!("[SERVER] All {} client tasks terminated", joined);
         Ok(())
     }
+
+    /// Apply the lifecycle policy to a newly accepted connection: drain the oldest idle
+    /// connection once the count nears the limit, and refuse the new one at the limit
+    fn admit(&self) -> bool {
+        let policy = &self.settings.lifecycle;
+        let open = self.tasks.reap();
+        if policy.drain_at().is_some_and(|drain_at| open + 1 >= drain_at)
+            && self.settings.connections.drain_oldest_idle(policy.idle_after)
+        {
+            println!(
+                "[SERVER] {} connections open ({} established), draining the oldest idle one",
+                open,
+                self.settings.connections.len()
+            );
+        }
+        policy.max_connections.is_none_or(|max| open < max)
+    }
+}
+
+/// Check loaded parameters are a safe prime and generator this implementation accepts
+///
+/// # Returns
+/// The subgroup order q, or an InvalidData error naming where the parameters came from
+fn validate_loaded_params(params: &DhParams, origin: &str) -> std::io::Result<BigInt> {
+    validate_dh_params(&params.p, &params.g).map_err(|reason| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("DH parameters in {} are invalid: {}", origin, reason),
+        )
+    })
 }
 
 /// Handle a single client connection through the DH key exchange
 /// Each invocation is in its own thread with completely isolated state
-fn handle_client(stream: TcpStream, prime: BigInt, base: BigInt) -> std::io::Result<()> {
+fn handle_client(
+    stream: TcpStream,
+    prime: BigInt,
+    base: BigInt,
+    subgroup_order: BigInt,
+    settings: HandshakeSettings,
+    trace: &ConnectionTrace,
+) -> std::io::Result<()> {
     let client_addr = stream.peer_addr()?;
+    let peer = client_addr.to_string();
+    let anomaly = |kind: AnomalyKind| {
+        trace.fail(format!("{:?}", kind));
+        report(&settings.anomaly_listener, &peer, kind)
+    };
     println!("[CLIENT {}] Starting DH key exchange", client_addr);
     
-    // *** CRITICAL: Generate UNIQUE secret exponent for THIS CLIENT ONLY ***
-    // This is called once per client thread, ensuring each client gets a different secret
-    let secret = generate_secret_key(&prime);
-    println!("[CLIENT {}] Generated unique secret exponent for this client", client_addr);
-    
-    // Create a connection state for this client (local to this thread, not shared)
-    let mut connection = DHConnection::new(stream, prime.clone(), base.clone(), secret.clone());
+    // Create a connection state for this client (local to this thread, not shared);
+    // the secret exponent comes once the Hello is accepted
+    let mut connection = DHConnection::new(stream, prime.clone(), base.clone(), BigInt::zero());
+    if settings.json_wire {
+        connection.codec = Codec::Json;
+    }
     
     // Set non-blocking to timeout reads
     connection.stream.set_read_timeout(Some(std::time::Duration::from_secs(30)))?;
     
     // Step 1: Receive ClientHello
+    trace.phase("hello");
+    let hello_codec = connection.codec;
     println!("[CLIENT {}] Waiting for ClientHello", client_addr);
-    let client_hello = read_message(&mut connection.stream)?;
-    
-    match client_hello {
-        Some(DHMessage::ClientHello) => {
+    let client_hello = read_handshake_message(&mut connection.stream, hello_codec, connection.algorithm, &settings)?;
+    if let Some(message) = &client_hello {
+        connection.transcript.record(message);
+    }
+
+    let (offered, offered_ciphers, nonce, offered_versions, offered_groups, ticket, client_random, cookie) = match client_hello {
+        Some(DHMessage::ClientHello { kex_algorithms, ciphers, nonce, versions, groups, ticket, random, cookie }) => {
             println!("[CLIENT {}] Received ClientHello", client_addr);
+            (kex_algorithms, ciphers, nonce, versions, groups, ticket, random, cookie)
+        }
+        Some(request @ (DHMessage::PrekeyUpload { .. } | DHMessage::PrekeyRequest { .. })) => {
+            let reply = match &settings.prekeys {
+                Some(directory) => directory.handle(&request),
+                None => DHMessage::Abort { reason: ABORT_PREKEYS_REJECTED },
+            };
+            let outcome = match (&request, &reply) {
+                (DHMessage::PrekeyUpload { .. }, DHMessage::Done) => "stored",
+                (DHMessage::PrekeyRequest { .. }, DHMessage::PrekeyResponse { .. }) => "answered",
+                _ => "refused",
+            };
+            let kind = match request {
+                DHMessage::PrekeyUpload { .. } => "prekey upload",
+                _ => "prekey request",
+            };
+            println!("[CLIENT {}] Directory {} {}", client_addr, kind, outcome);
+            trace.attribute("directory_request", kind);
+            if outcome == "refused" {
+                trace.fail(format!("{} refused", kind));
+            }
+            write_message(&mut connection.stream, connection.codec, &reply)?;
+            return Ok(());
         }
         _ => {
             eprintln!("[CLIENT {}] Expected ClientHello, got {:?}", client_addr, client_hello);
+            anomaly(AnomalyKind::ProtocolViolation(format!("expected ClientHello, got {:?}", client_hello)));
+            send_alert(&mut connection.stream, connection.codec, AlertCode::unexpected(&client_hello), "expected ClientHello");
             return Ok(());
         }
+    };
+
+    // Clients listing a codec's signal want every later message in that encoding; it
+    // comes first so that even our Aborts reach the client in a form it can read
+    let codec = Codec::ALL
+        .iter()
+        .copied()
+        .find(|codec| codec.signal().is_some_and(|signal| offered.contains(&signal)))
+        .unwrap_or(connection.codec);
+    if !settings.codecs.contains(&codec) {
+        eprintln!("[CLIENT {}] Codec {} not accepted", client_addr, codec.name());
+        anomaly(AnomalyKind::NegotiationFailed(format!("codec {} not accepted", codec.name())));
+        send_alert(&mut connection.stream, codec, AlertCode::HandshakeFailure, "codec not accepted");
+        return Ok(());
+    }
+    if codec != Codec::Native {
+        println!("[CLIENT {}] Selected codec {}", client_addr, codec.name());
     }
+    connection.codec = codec;
+    trace.attribute("codec", codec.name());
+
+    // With cookies required, a Hello without a valid one only gets a HelloRetry, and
+    // the connection closes before we generate anything: the client reconnects with
+    // the cookie, so until then it costs us no exponentiation and no per-client state
+    if let Some(cookies) = settings.cookies.as_ref().filter(|cookies| !cookies.verify(&cookie, client_addr.ip(), &client_random)) {
+        if !cookie.is_empty() {
+            eprintln!("[CLIENT {}] Invalid or expired cookie", client_addr);
+            anomaly(AnomalyKind::ProtocolViolation("invalid or expired cookie".to_string()));
+            send_alert(&mut connection.stream, connection.codec, AlertCode::IllegalParameter, "invalid or expired cookie");
+            return Ok(());
+        }
+        println!("[CLIENT {}] Sending HelloRetry with a cookie", client_addr);
+        trace.attribute("hello_retry", true);
+        let difficulty = settings.puzzle.map_or(0, |puzzle| puzzle.difficulty(settings.connections.len()));
+        trace.attribute("puzzle_difficulty", difficulty);
+        let cookie = cookies.issue(client_addr.ip(), &client_random, difficulty);
+        write_message(&mut connection.stream, connection.codec, &DHMessage::HelloRetry { cookie })?;
+        // Proposed parameters were sent before the client saw the HelloRetry; reading
+        // them keeps the close from resetting the connection under it
+        if offered.contains(&CLIENT_PARAMS_SIGNAL) {
+            read_handshake_message(&mut connection.stream, connection.codec, connection.algorithm, &settings)?;
+        }
+        return Ok(());
+    }
+    
+    // A random seen before means the whole ClientHello is being replayed
+    if !settings.replay_window.check(&client_random) {
+        eprintln!("[CLIENT {}] Replayed ClientHello random", client_addr);
+        anomaly(AnomalyKind::ReplayDetected("ClientHello random seen before"));
+        send_alert(&mut connection.stream, connection.codec, AlertCode::IllegalParameter, "replayed ClientHello");
+        return Ok(());
+    }
+    // Every ServerHello variant carries the same fresh random of ours
+    let random: [u8; 32] = with_rng(RngPurpose::Nonce, |rng| rng.r#gen());
     
-    // Step 2: Send ServerHello with (p, g)
-    let server_hello = DHMessage::ServerHello {
-        p: connection.prime.clone(),
-        g: connection.base.clone(),
+    // Pick the client's most preferred protocol version that we also speak
+    let version = match offered_versions.iter().find(|version| PROTOCOL_VERSIONS.contains(version)) {
+        Some(version) => *version,
+        None => {
+            eprintln!("[CLIENT {}] No supported protocol version in {:?}", client_addr, offered_versions);
+            anomaly(AnomalyKind::NegotiationFailed(format!("no supported protocol version in {:?}", offered_versions)));
+            write_message(&mut connection.stream, connection.codec, &DHMessage::Abort { reason: ABORT_VERSION_MISMATCH })?;
+            return Ok(());
+        }
     };
+    println!("[CLIENT {}] Selected protocol version {}", client_addr, version);
+    connection.version = version;
+    trace.attribute("version", version);
     
-    println!("[CLIENT {}] Sending ServerHello with p and g", client_addr);
-    write_message(&mut connection.stream, &server_hello)?;
+    // Clients that list the signal only accept identities under handshake encryption
+    let protect_identities = offered.contains(&PROTECT_IDENTITIES_SIGNAL);
+    // Clients listing this signal propose the parameters themselves, in ClientParams
+    let params_proposed = offered.contains(&CLIENT_PARAMS_SIGNAL);
+    // Clients listing this signal want explicit parameters proven prime, unless they
+    // are their own
+    let wants_prime_certificate = offered.contains(&PRIME_CERTIFICATE_SIGNAL) && !params_proposed;
+    // Only clients listing this signal understand Rekey messages
+    if offered.contains(&REKEY_SIGNAL) {
+        connection.rekey = Some(settings.rekey);
+    }
+    // Clients listing this signal pad what they encrypt, and want the same from us
+    if offered.contains(&RECORD_PADDING_SIGNAL) {
+        connection.padding = Some(settings.padding);
+    }
+    // Clients listing this signal want a session ticket once the handshake completes
+    let wants_ticket = offered.contains(&SESSION_TICKET_SIGNAL);
     
-    // Step 3: Receive ClientPublicKey
-    println!("[CLIENT {}] Waiting for ClientPublicKey", client_addr);
-    let client_pub_key = read_message(&mut connection.stream)?;
+    // Proposed parameters replace ours, with a fresh exponent in them, if the policy
+    // accepts them
+    let (secret, subgroup_order, secret_policy) = match params_proposed {
+        // *** CRITICAL: Generate UNIQUE secret exponent for THIS CLIENT ONLY ***
+        // This is called once per client thread, ensuring each client gets a different
+        // secret, unless the server was deliberately configured with a static key
+        false => {
+            let (secret, policy) = match &settings.static_secret {
+                Some(secret) => (secret.clone(), ExponentPolicy::Full),
+                None => {
+                    let secret = generate_secret_key(&connection.prime, settings.exponent_policy);
+                    println!("[CLIENT {}] Generated unique secret exponent for this client", client_addr);
+                    (secret, settings.exponent_policy)
+                }
+            };
+            connection.secret_exponent = secret.clone();
+            (secret, subgroup_order, policy)
+        }
+        true => {
+            let proposal = read_handshake_message(&mut connection.stream, connection.codec, connection.algorithm, &settings)?;
+            if let Some(message) = &proposal {
+                connection.transcript.record(message);
+            }
+            let (p, g) = match proposal {
+                Some(DHMessage::ClientParams { p, g }) => (BigInt::from(p), BigInt::from(g)),
+                other => {
+                    eprintln!("[CLIENT {}] Expected ClientParams, got {:?}", client_addr, other);
+                    anomaly(AnomalyKind::ProtocolViolation(format!("expected ClientParams, got {:?}", other)));
+                    send_alert(&mut connection.stream, connection.codec, AlertCode::unexpected(&other), "expected ClientParams");
+                    return Ok(());
+                }
+            };
+            let Some(policy) = settings.client_params else {
+                eprintln!("[CLIENT {}] Client proposed parameters, which this server does not accept", client_addr);
+                anomaly(AnomalyKind::NegotiationFailed("client-proposed parameters not accepted".to_string()));
+                send_alert(&mut connection.stream, connection.codec, AlertCode::HandshakeFailure, "client-proposed parameters not accepted");
+                return Ok(());
+            };
+            match policy.check(&p, &g) {
+                Ok(q) => {
+                    println!("[CLIENT {}] Accepted client-proposed parameters ({} bits)", client_addr, p.bits());
+                    trace.attribute("client_params", true);
+                    let secret = generate_secret_key(&p, settings.exponent_policy);
+                    connection.prime = p;
+                    connection.base = g;
+                    connection.secret_exponent = secret.clone();
+                    (secret, q, settings.exponent_policy)
+                }
+                Err(reason) => {
+                    eprintln!("[CLIENT {}] Rejecting client-proposed parameters: {}", client_addr, reason);
+                    anomaly(AnomalyKind::ParametersRejected(reason));
+                    send_alert(&mut connection.stream, connection.codec, AlertCode::IllegalParameter, reason);
+                    return Ok(());
+                }
+            }
+        }
+    };
     
-    match client_pub_key {
-        Some(DHMessage::ClientPublicKey { x }) => {
-            println!("[CLIENT {}] Received ClientPublicKey: {}", client_addr, x);
-            connection.client_public_key = Some(x.clone());
+    // Clients listing the signal derive keys with the SP 800-56A KDF, others with HKDF
+    let kdf = match offered.contains(&ONE_STEP_KDF_SIGNAL) {
+        true => Kdf::OneStep,
+        false => Kdf::Hkdf,
+    };
+    if !settings.kdfs.contains(&kdf) {
+        eprintln!("[CLIENT {}] Key derivation function {} not accepted", client_addr, kdf.name());
+        anomaly(AnomalyKind::NegotiationFailed(format!("key derivation function {} not accepted", kdf.name())));
+        send_alert(&mut connection.stream, connection.codec, AlertCode::HandshakeFailure, "key derivation function not accepted");
+        return Ok(());
+    }
+    println!("[CLIENT {}] Selected key derivation function {}", client_addr, kdf.name());
+    connection.kdf = kdf;
+    trace.attribute("kdf", kdf.name());
+    
+    // A valid ticket, for a cipher the client still offers and the KDF it asks for,
+    // resumes the session that issued it, once; otherwise the rest of the Hello applies
+    let resumption = settings
+        .tickets
+        .as_ref()
+        .filter(|_| !ticket.is_empty())
+        .and_then(|key| key.open(&ticket))
+        .filter(|resumption| {
+            resumption.kdf == kdf
+                && offered_ciphers.contains(&resumption.cipher.id())
+                && settings.ciphers.contains(&resumption.cipher)
+        })
+        .filter(|_| {
+            let fresh = settings.redeemed_tickets.check(&ticket_id(&ticket));
+            if !fresh {
+                eprintln!("[CLIENT {}] Session ticket redeemed before", client_addr);
+                anomaly(AnomalyKind::ReplayDetected("session ticket redeemed before"));
+            }
+            fresh
+        });
+    if let Some(resumption) = resumption {
+        println!("[CLIENT {}] Resuming session with cipher {}", client_addr, resumption.cipher.name());
+        connection.cipher = resumption.cipher;
+        trace.attribute("cipher", resumption.cipher.name());
+        trace.attribute("resumed", true);
+        let server_hello = DHMessage::ServerHelloResume {
+            cipher: resumption.cipher.id(),
+            version,
+            random,
+        };
+        if settings.grease {
+            write_message(&mut connection.stream, connection.codec, &DHMessage::grease())?;
         }
-        _ => {
-            eprintln!("[CLIENT {}] Expected ClientPublicKey, got {:?}", client_addr, client_pub_key);
+        write_message(&mut connection.stream, connection.codec, &server_hello)?;
+        connection.transcript.record(&server_hello);
+        connection.shared_secret = Some(resumption.shared_secret());
+        connection.client_identity = resumption.identity;
+        trace.phase("finished");
+        return finish_handshake(connection, wants_ticket, &settings, trace);
+    }
+    if !ticket.is_empty() {
+        println!("[CLIENT {}] Declining session ticket", client_addr);
+    }
+    
+    // A nonce asks us to sign the handshake, which needs an identity key
+    let identity = match (nonce.is_empty(), &settings.identity) {
+        (true, _) => None,
+        (false, Some(identity)) => Some(identity),
+        (false, None) => {
+            eprintln!("[CLIENT {}] Client requested authentication but no identity is configured", client_addr);
+            anomaly(AnomalyKind::NegotiationFailed("authentication requested without a server identity".to_string()));
+            write_message(&mut connection.stream, connection.codec, &DHMessage::Abort { reason: ABORT_UNAUTHENTICATED })?;
+            return Ok(());
+        }
+    };
+    
+    // Pick the client's most preferred algorithm that we also support; HMQV needs our
+    // static key and a well-known group the client can look up, SRP needs verifiers,
+    // and padded FFDH a well-known group. A client listing groups accepts the
+    // finite-field algorithms only over one of them.
+    let named_group = DhGroup::identify(&connection.prime, &connection.base);
+    let srp_group = settings.srp.as_ref().map(|verifiers| verifiers.group());
+    let group_accepted = |group: Option<DhGroup>| {
+        offered_groups.is_empty() || group.is_some_and(|group| offered_groups.contains(&group.id()))
+    };
+    let algorithm = match offered
+        .iter()
+        .filter_map(|id| KexAlgorithm::from_id(*id))
+        .filter(|algorithm| match algorithm {
+            KexAlgorithm::FiniteField => group_accepted(named_group),
+            KexAlgorithm::Hmqv => {
+                !params_proposed && settings.static_secret.is_some() && named_group.is_some() && group_accepted(named_group)
+            }
+            KexAlgorithm::Srp => !params_proposed && srp_group.is_some() && group_accepted(srp_group),
+            KexAlgorithm::FiniteFieldPadded => named_group.is_some() && group_accepted(named_group),
+            _ => true,
+        })
+        .find(|algorithm| settings.kex_algorithms.contains(algorithm))
+    {
+        Some(algorithm) => algorithm,
+        None => {
+            eprintln!("[CLIENT {}] No supported key-exchange algorithm in {:?}", client_addr, offered);
+            anomaly(AnomalyKind::NegotiationFailed(format!("no supported key exchange in {:?}", offered)));
+            send_alert(&mut connection.stream, connection.codec, AlertCode::HandshakeFailure, "no supported key exchange");
             return Ok(());
         }
+    };
+    println!("[CLIENT {}] Selected key exchange {}", client_addr, algorithm.name());
+    connection.algorithm = algorithm;
+    trace.attribute("kex", algorithm.name());
+    connection.group = match algorithm {
+        KexAlgorithm::FiniteField | KexAlgorithm::FiniteFieldPadded | KexAlgorithm::Hmqv => named_group,
+        KexAlgorithm::Srp => srp_group,
+        _ => None,
+    };
+    // Clients listing this signal send and expect finite-field keys of a fixed width
+    let fixed_width_keys = algorithm == KexAlgorithm::FiniteField && offered.contains(&FIXED_WIDTH_KEYS_SIGNAL);
+    
+    // Likewise for the record-layer cipher
+    let cipher = match offered_ciphers
+        .iter()
+        .filter_map(|id| CipherSuite::from_id(*id))
+        .find(|cipher| settings.ciphers.contains(cipher))
+    {
+        Some(cipher) => cipher,
+        None => {
+            eprintln!("[CLIENT {}] No supported cipher in {:?}", client_addr, offered_ciphers);
+            anomaly(AnomalyKind::NegotiationFailed(format!("no supported cipher in {:?}", offered_ciphers)));
+            send_alert(&mut connection.stream, connection.codec, AlertCode::HandshakeFailure, "no supported cipher");
+            return Ok(());
+        }
+    };
+    println!("[CLIENT {}] Selected cipher {}", client_addr, cipher.name());
+    if !cipher.encrypts() {
+        eprintln!(
+            "[CLIENT {}] WARNING: {} does not encrypt; application data is readable on the wire",
+            client_addr,
+            cipher.name()
+        );
     }
+    connection.cipher = cipher;
+    trace.attribute("cipher", cipher.name());
     
-    // Step 4: Compute and send ServerPublicKey
-    let server_public_key = compute_public_key(&secret, &connection.base, &connection.prime);
-    let server_key_msg = DHMessage::ServerPublicKey {
-        y: server_public_key.clone(),
+    // Step 2: Send ServerHello with (p, g), just the group ID if (p, g) is a well-known group,
+    // or only the selected algorithm if it needs no parameters
+    let mut hmqv = None;
+    let (server_hello, kex): (DHMessage, Box<dyn KeyExchange>) = match algorithm {
+        KexAlgorithm::FiniteField => {
+            let kex = FiniteFieldKeyExchange::blinded(&connection.prime, &connection.base, &subgroup_order, secret, settings.blinding, secret_policy);
+            match named_group {
+                Some(group) => {
+                    println!("[CLIENT {}] Sending ServerHello for named group {:?}", client_addr, group);
+                    (DHMessage::ServerHelloNamed { group: group.id(), cipher: cipher.id(), version, random }, Box::new(kex))
+                }
+                None => {
+                    println!("[CLIENT {}] Sending ServerHello with p and g", client_addr);
+                    let message = DHMessage::ServerHello {
+                        p: connection.prime.magnitude().clone(),
+                        g: connection.base.magnitude().clone(),
+                        cipher: cipher.id(),
+                        version,
+                        random,
+                    };
+                    (message, Box::new(kex))
+                }
+            }
+        }
+        KexAlgorithm::Hmqv => {
+            // Selected only with a static key and a named group, both checked above
+            let Some(group) = named_group else { return Ok(()) };
+            let kex = HmqvKeyExchange::new(&connection.prime, &connection.base, &subgroup_order, secret, HmqvRole::Responder, settings.exponent_policy);
+            hmqv = Some(kex.clone());
+            println!("[CLIENT {}] Sending ServerHello selecting hmqv over {:?}", client_addr, group);
+            (DHMessage::ServerHelloHmqv { group: group.id(), cipher: cipher.id(), version, random }, Box::new(kex))
+        }
+        KexAlgorithm::FiniteFieldPadded => {
+            // Selected only over a named group, checked above
+            let Some(group) = named_group else { return Ok(()) };
+            let kex = PaddedFiniteFieldKeyExchange::blinded(&connection.prime, &connection.base, &subgroup_order, secret, settings.blinding, secret_policy);
+            println!("[CLIENT {}] Sending ServerHello selecting ffdh-padded over {:?}", client_addr, group);
+            (DHMessage::ServerHelloPadded { group: group.id(), cipher: cipher.id(), version, random }, Box::new(kex))
+        }
+        KexAlgorithm::Srp => {
+            // Selected only with verifiers, checked above
+            let Some(verifiers) = &settings.srp else { return Ok(()) };
+            let group = verifiers.group();
+            println!("[CLIENT {}] Sending ServerHello selecting srp over {:?}", client_addr, group);
+            let kex = SrpServerExchange::new(Arc::clone(verifiers), settings.exponent_policy);
+            (DHMessage::ServerHelloSrp { group: group.id(), cipher: cipher.id(), version, random }, Box::new(kex))
+        }
+        _ => match curve_key_exchange(algorithm) {
+            Some(kex) => {
+                println!("[CLIENT {}] Sending ServerHello selecting {}", client_addr, algorithm.name());
+                (DHMessage::ServerHelloKex { algorithm: algorithm.id(), cipher: cipher.id(), version, random }, kex)
+            }
+            None => return Ok(()),
+        },
     };
     
-    println!("[CLIENT {}] Sending ServerPublicKey", client_addr);
-    write_message(&mut connection.stream, &server_key_msg)?;
+    match &server_hello {
+        DHMessage::ServerHelloNamed { group, .. }
+        | DHMessage::ServerHelloHmqv { group, .. }
+        | DHMessage::ServerHelloSrp { group, .. }
+        | DHMessage::ServerHelloPadded { group, .. } => {
+            if let Some(group) = DhGroup::from_id(*group) {
+                trace.attribute("group", group.name());
+            }
+        }
+        DHMessage::ServerHello { p, .. } => trace.attribute("group", format!("custom {}-bit", p.bits())),
+        _ => {}
+    }
+    if settings.grease {
+        write_message(&mut connection.stream, connection.codec, &DHMessage::grease())?;
+    }
+    write_message(&mut connection.stream, connection.codec, &server_hello)?;
+    connection.transcript.record(&server_hello);
+    if wants_prime_certificate && matches!(server_hello, DHMessage::ServerHello { .. }) {
+        // An empty certificate tells the client we have none, rather than leaving it waiting
+        let certificate = settings.prime_certificate.clone().unwrap_or_default();
+        println!("[CLIENT {}] Sending PrimeCertificate ({} bytes)", client_addr, certificate.len());
+        let certificate_msg = DHMessage::PrimeCertificate { certificate };
+        write_message(&mut connection.stream, connection.codec, &certificate_msg)?;
+        connection.transcript.record(&certificate_msg);
+    }
+    
+    // Step 3: Receive the client's public key
+    trace.phase("key_exchange");
+    println!("[CLIENT {}] Waiting for client public key", client_addr);
+    let client_pub_key = read_handshake_message(&mut connection.stream, connection.codec, connection.algorithm, &settings)?;
+    if let Some(message) = &client_pub_key {
+        connection.transcript.record(message);
+    }
+    
+    let client_public_key = match (algorithm, client_pub_key) {
+        (KexAlgorithm::FiniteField, Some(DHMessage::ClientPublicKey { x })) if !fixed_width_keys => {
+            println!("[CLIENT {}] Received ClientPublicKey: {}", client_addr, x);
+            connection.client_public_key = Some(BigInt::from(x.clone()));
+            x.to_bytes_be()
+        }
+        (KexAlgorithm::FiniteField, Some(DHMessage::ClientPublicKeyFixed { key })) if fixed_width_keys => {
+            if let Err(reason) = check_fixed_width(&key, &connection.prime) {
+                eprintln!("[CLIENT {}] Aborting key exchange: {}", client_addr, reason);
+                anomaly(AnomalyKind::PublicKeyRejected(reason));
+                send_alert(&mut connection.stream, connection.codec, AlertCode::IllegalParameter, reason);
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, reason));
+            }
+            let x = BigInt::from_bytes_be(Sign::Plus, &key);
+            println!("[CLIENT {}] Received ClientPublicKeyFixed: {}", client_addr, x);
+            connection.client_public_key = Some(x);
+            key
+        }
+        (algorithm, Some(DHMessage::ClientKeyShare { key })) if algorithm != KexAlgorithm::FiniteField => {
+            println!("[CLIENT {}] Received ClientKeyShare: {}", client_addr, to_hex(&key));
+            if algorithm == KexAlgorithm::Hmqv && !protect_identities {
+                // Only the holder of this static key can complete the handshake
+                let fingerprint = Fingerprint::of(kex.identity_key(&key));
+                println!("[CLIENT {}] Client static key fingerprint: {}", client_addr, fingerprint);
+                connection.client_identity = Some(hmqv_identity(&fingerprint));
+            }
+            // Likewise, only a client knowing this user's password completes it
+            if algorithm == KexAlgorithm::Srp {
+                connection.client_identity = srp::split(&key)
+                    .and_then(|(username, _)| std::str::from_utf8(username).ok())
+                    .map(|username| format!("srp:{}", username));
+            }
+            connection.client_public_key = Some(BigInt::from_bytes_be(Sign::Plus, &key));
+            key
+        }
+        (_, Some(DHMessage::Abort { reason })) => {
+            println!("[CLIENT {}] Client aborted the handshake (reason {})", client_addr, reason);
+            trace.fail(format!("client aborted (reason {})", reason));
+            return Ok(());
+        }
+        (_, other) => {
+            eprintln!("[CLIENT {}] Expected client public key, got {:?}", client_addr, other);
+            anomaly(AnomalyKind::ProtocolViolation(format!("expected client public key, got {:?}", other)));
+            send_alert(&mut connection.stream, connection.codec, AlertCode::unexpected(&other), "expected client public key");
+            return Ok(());
+        }
+    };
+    
+    // Step 4: Compute the shared secret and send our public key
+    // *** UNIQUE to this client: each client's shared_secret is different ***
+    // SRP's key share depends on the client's, so it only exists after this
+    // Protected HMQV has only the client's ephemeral key so far, which is enough to
+    // key handshake encryption; the shared secret follows with its static key
+    let protected_hmqv = hmqv.filter(|_| protect_identities);
+    let handshake_secret = trace.crypto("shared_secret", || match &protected_hmqv {
+        Some(hmqv) => hmqv.ephemeral_shared_secret(&client_public_key),
+        None => kex.shared_secret(&client_public_key),
+    });
+    let handshake_secret = match handshake_secret {
+        Ok(secret) => secret,
+        Err(reason) => {
+            eprintln!("[CLIENT {}] Aborting key exchange: {}", client_addr, reason);
+            anomaly(AnomalyKind::PublicKeyRejected(reason));
+            send_alert(&mut connection.stream, connection.codec, AlertCode::IllegalParameter, reason);
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, reason));
+        }
+    };
+    if protected_hmqv.is_none() {
+        connection.shared_secret = Some(handshake_secret.clone());
+    }
+    let server_key_msg = match (algorithm, &protected_hmqv) {
+        (KexAlgorithm::FiniteField, _) if fixed_width_keys => DHMessage::ServerPublicKeyFixed {
+            key: to_fixed_width(&kex.public_key(), &connection.prime),
+        },
+        (KexAlgorithm::FiniteField, _) => DHMessage::ServerPublicKey {
+            y: BigUint::from_bytes_be(&kex.public_key()),
+        },
+        (_, Some(hmqv)) => DHMessage::ServerKeyShare {
+            key: hmqv.ephemeral_public_key(),
+        },
+        (_, None) => DHMessage::ServerKeyShare {
+            key: kex.public_key(),
+        },
+    };
+    
+    println!("[CLIENT {}] Sending server public key", client_addr);
+    let server_public_key = kex.public_key();
+    println!(
+        "[CLIENT {}] Server key fingerprint: {}",
+        client_addr,
+        Fingerprint::of(kex.identity_key(&server_public_key))
+    );
+    if settings.grease {
+        write_message(&mut connection.stream, connection.codec, &DHMessage::grease())?;
+    }
+    write_message(&mut connection.stream, connection.codec, &server_key_msg)?;
+    connection.transcript.record(&server_key_msg);
+    
+    // Step 4a: From here on, whatever identifies either side is encrypted if the
+    // client asked for it. The transcript records the messages, not their ciphertexts
+    trace.phase("authentication");
+    let mut protection = (protect_identities && (identity.is_some() || protected_hmqv.is_some())).then(|| {
+        let protection = HandshakeProtection::server(&handshake_secret, &connection.transcript);
+        match connection.padding {
+            Some(padding) => protection.with_padding(padding),
+            None => protection,
+        }
+    });
+    if let Some(hmqv) = &protected_hmqv {
+        println!("[CLIENT {}] Sending encrypted StaticKey", client_addr);
+        let static_key_msg = DHMessage::StaticKey { key: hmqv.static_public_key() };
+        write_protected(&mut connection.stream, connection.codec, protection.as_mut(), &static_key_msg)?;
+        connection.transcript.record(&static_key_msg);
+    }
+    
+    // Step 4b: Sign everything so far, including the client's nonce, if asked to
+    if let Some(identity) = identity {
+        println!("[CLIENT {}] Sending ServerSignature", client_addr);
+        let server_key = identity.server_key();
+        let signature_msg = DHMessage::ServerSignature {
+            scheme: server_key.scheme().id(),
+            public_key: server_key.to_bytes(),
+            signature: trace.crypto("sign", || identity.sign(&connection.transcript.hash())),
+        };
+        write_protected(&mut connection.stream, connection.codec, protection.as_mut(), &signature_msg)?;
+        connection.transcript.record(&signature_msg);
+    }
+    
+    // Step 4c: Protected HMQV needs the client's static key for the shared secret
+    if let (Some(hmqv), Some(protection)) = (&protected_hmqv, protection.as_mut()) {
+        println!("[CLIENT {}] Waiting for encrypted StaticKey", client_addr);
+        let static_key_msg = read_handshake_message(&mut connection.stream, connection.codec, connection.algorithm, &settings)?;
+        let client_static_key = match protection.open(static_key_msg) {
+            Ok(message) if settings.limits.check(&message, algorithm).is_err() => {
+                eprintln!("[CLIENT {}] Aborting key exchange: static key exceeds our size limits", client_addr);
+                anomaly(AnomalyKind::ProtocolViolation("oversized static key".to_string()));
+                send_alert(&mut connection.stream, connection.codec, AlertCode::DecodeError, "static key exceeds the size limits");
+                return Ok(());
+            }
+            Ok(DHMessage::StaticKey { key }) => {
+                connection.transcript.record(&DHMessage::StaticKey { key: key.clone() });
+                key
+            }
+            Ok(other) => {
+                eprintln!("[CLIENT {}] Expected StaticKey, got {:?}", client_addr, other);
+                anomaly(AnomalyKind::ProtocolViolation(format!("expected StaticKey, got {:?}", other)));
+                send_alert(&mut connection.stream, connection.codec, AlertCode::UnexpectedMessage, "expected StaticKey");
+                return Ok(());
+            }
+            Err(reason) => {
+                eprintln!("[CLIENT {}] Aborting key exchange: {}", client_addr, reason);
+                anomaly(AnomalyKind::ProtocolViolation(reason.to_string()));
+                send_alert(&mut connection.stream, connection.codec, AlertCode::DecryptError, reason);
+                return Ok(());
+            }
+        };
+        let fingerprint = Fingerprint::of(&client_static_key);
+        println!("[CLIENT {}] Client static key fingerprint: {}", client_addr, fingerprint);
+        connection.client_identity = Some(hmqv_identity(&fingerprint));
+        match trace.crypto("shared_secret", || hmqv.shared_secret(&join_share(&client_static_key, &client_public_key))) {
+            Ok(shared_secret) => connection.shared_secret = Some(shared_secret),
+            Err(reason) => {
+                eprintln!("[CLIENT {}] Aborting key exchange: {}", client_addr, reason);
+                anomaly(AnomalyKind::PublicKeyRejected(reason));
+                send_alert(&mut connection.stream, connection.codec, AlertCode::IllegalParameter, reason);
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, reason));
+            }
+        }
+    }
     
     // Step 5: Receive Done
+    trace.phase("finished");
     println!("[CLIENT {}] Waiting for Done message", client_addr);
-    let done_msg = read_message(&mut connection.stream)?;
+    let done_msg = read_handshake_message(&mut connection.stream, connection.codec, connection.algorithm, &settings)?;
+    if let Some(message) = &done_msg {
+        connection.transcript.record(message);
+    }
     
     match done_msg {
         Some(DHMessage::Done) => {
@@ -153,98 +1233,230 @@ fn handle_client(stream: TcpStream, prime: BigInt, base: BigInt) -> std::io::Res
         }
         _ => {
             eprintln!("[CLIENT {}] Expected Done, got {:?}", client_addr, done_msg);
+            anomaly(AnomalyKind::ProtocolViolation(format!("expected Done, got {:?}", done_msg)));
+            send_alert(&mut connection.stream, connection.codec, AlertCode::unexpected(&done_msg), "expected Done");
             return Ok(());
         }
     }
     
-    // Compute shared secret: X^secret mod p
-    // *** UNIQUE to this client: each client's shared_secret is different ***
-    if let Some(client_key) = &connection.client_public_key {
-        let shared_secret = mod_pow(client_key, &secret, &connection.prime);
-        connection.shared_secret = Some(shared_secret.clone());
-        println!("[CLIENT {}] DH key exchange complete! Shared secret established.", client_addr);
-        println!("[CLIENT {}] Shared secret (unique to this client): {}", client_addr, connection.shared_secret.as_ref().unwrap());
+    finish_handshake(connection, wants_ticket, &settings, trace)
+}
+
+/// Check the client's Finished and send ours (and a ticket if asked for), then serve
+/// the session until either side closes it
+///
+/// The connection's transcript must be complete and its shared secret set, by a key
+/// exchange or a resumed ticket.
+///
+/// # Returns
+/// Ok once either side ended the session with a Close record, or an error if the
+/// client vanished without one, stopped answering Pings, or sent a record we rejected
+fn finish_handshake(
+    mut connection: DHConnection,
+    wants_ticket: bool,
+    settings: &HandshakeSettings,
+    trace: &ConnectionTrace,
+) -> std::io::Result<()> {
+    let client_addr = connection.peer_addr()?;
+    let peer = client_addr.to_string();
+    let anomaly = |kind: AnomalyKind| {
+        trace.fail(format!("{:?}", kind));
+        report(&settings.anomaly_listener, &peer, kind)
+    };
+    let Some(shared_secret) = connection.shared_secret.clone() else {
+        return Ok(());
+    };
+    let keys = trace.crypto("derive_keys", || SessionKeys::derive(connection.kdf, &shared_secret, &connection.transcript));
+    
+    // Step 6: Check the client's Finished, then send ours. Neither is recorded, so
+    // the transcript (and channel binding) ends at Done
+    println!("[CLIENT {}] Waiting for ClientFinished", client_addr);
+    let transcript_hash = connection.transcript.hash();
+    let client_finished = read_handshake_message(&mut connection.stream, connection.codec, connection.algorithm, settings)?;
+    match client_finished {
+        Some(DHMessage::ClientFinished { verify_data })
+            if keys.verify_finished(CLIENT_FINISHED_LABEL, &transcript_hash, &verify_data) =>
+        {
+            println!("[CLIENT {}] Received ClientFinished", client_addr);
+        }
+        Some(DHMessage::ClientFinished { .. }) => {
+            eprintln!("[CLIENT {}] Aborting key exchange: client Finished does not verify", client_addr);
+            anomaly(AnomalyKind::AuthenticationFailed("client Finished does not verify"));
+            send_alert(&mut connection.stream, connection.codec, AlertCode::DecryptError, "client Finished does not verify");
+            return Ok(());
+        }
+        _ => {
+            eprintln!("[CLIENT {}] Expected ClientFinished, got {:?}", client_addr, client_finished);
+            anomaly(AnomalyKind::ProtocolViolation(format!("expected ClientFinished, got {:?}", client_finished)));
+            send_alert(&mut connection.stream, connection.codec, AlertCode::unexpected(&client_finished), "expected ClientFinished");
+            return Ok(());
+        }
+    }
+    // The client has now proven its identity, which may be over its quota
+    let _quota = match (&settings.quota, &connection.client_identity) {
+        (Some(quota), Some(identity)) => match settings.quotas.admit(quota, identity) {
+            Ok(handle) => Some(handle),
+            Err(limit) => {
+                eprintln!("[CLIENT {}] Refusing {}: {}", client_addr, identity, limit);
+                anomaly(AnomalyKind::QuotaExceeded(format!("{}: {}", identity, limit)));
+                send_alert(&mut connection.stream, connection.codec, AlertCode::RateLimited, limit);
+                return Ok(());
+            }
+        },
+        _ => None,
+    };
+    println!("[CLIENT {}] Sending ServerFinished", client_addr);
+    write_message(&mut connection.stream, connection.codec, &DHMessage::ServerFinished {
+        verify_data: keys.finished(SERVER_FINISHED_LABEL, &transcript_hash),
+    })?;
+    if wants_ticket {
+        // An empty ticket tells the client we issue none, rather than leaving it waiting
+        let resumption = Resumption::of(&keys, connection.cipher).with_identity(connection.client_identity.clone());
+        let ticket = settings.tickets.as_ref().map(|key| key.seal(&resumption));
+        let lifetime = settings.tickets.as_ref().map_or(0, |key| key.lifetime().as_secs());
+        println!("[CLIENT {}] Sending NewSessionTicket", client_addr);
+        write_message(&mut connection.stream, connection.codec, &DHMessage::NewSessionTicket {
+            lifetime: u32::try_from(lifetime).unwrap_or(u32::MAX),
+            ticket: ticket.unwrap_or_default(),
+        })?;
     }
     
+    println!("[CLIENT {}] DH key exchange complete! Shared secret established.", client_addr);
+    if let Some(binding) = connection.channel_binding() {
+        println!("[CLIENT {}] Channel binding: {}", client_addr, to_hex(&binding));
+    }
+    let mut records = RecordLayer::server(&keys, connection.cipher);
+    if let Some(policy) = connection.rekey {
+        records = records.with_rekeying(policy);
+    }
+    if let Some(policy) = settings.keepalive {
+        records = records.with_keepalive(policy);
+    }
+    if let Some(padding) = connection.padding {
+        records = records.with_padding(padding);
+    }
+    connection.session_keys = Some(keys);
+    let mut channel = SecureChannel::new(connection.stream.try_clone()?, records, settings.cancel.clone());
+    
     // Keep connection alive for future communication
     println!("[CLIENT {}] Connection ready for future communication", client_addr);
+    trace.phase("session");
+    let registration = settings.connections.register();
+    let mut rekeys = 0;
     
-    let mut buffer = [0; 1024];
-    loop {
-        match connection.stream.read(&mut buffer) {
-            Ok(0) => {
-                println!("[CLIENT {}] Client disconnected", client_addr);
-                break;
-            }
-            Ok(n) => {
-                println!("[CLIENT {}] Received {} bytes", client_addr, n);
+    // Clean closes (a Close record either way) end in Ok; a client that vanishes, stops
+    // answering Pings, or sends something we reject ends the connection as an error
+    let outcome = loop {
+        if registration.should_drain(&settings.lifecycle) {
+            println!("[CLIENT {}] Draining connection", client_addr);
+            channel.write_close(CloseReason::Drained)?;
+            break Ok(());
+        }
+        
+        // Idle clients may stay connected until cancelled or drained; a record that
+        // has started arriving gets the full read timeout
+        let record = channel.read_record(Some(DRAIN_CHECK_INTERVAL));
+        if channel.rekeys() > rekeys {
+            rekeys = channel.rekeys();
+            println!("[CLIENT {}] Session rekeyed ({} so far)", client_addr, rekeys);
+        }
+        match record {
+            Ok(None) => match channel.close_reason() {
+                Some(reason) => {
+                    println!("[CLIENT {}] Client closed the connection ({})", client_addr, reason.name());
+                    // Best effort: confirm, so the client knows nothing it sent was lost
+                    let _ = channel.write_close(CloseReason::Normal);
+                    break Ok(());
+                }
+                None => {
+                    eprintln!("[CLIENT {}] Client disconnected without closing", client_addr);
+                    break Err(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionAborted,
+                        "Client disconnected without closing",
+                    ));
+                }
+            },
+            Ok(Some(plaintext)) => {
+                registration.touch();
+                println!("[CLIENT {}] Received {} bytes", client_addr, plaintext.len());
                 // Echo back for now (can be extended for application-specific messages)
-                connection.stream.write_all(&buffer[..n])?;
+                channel.write_all(&plaintext)?;
             }
-            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                // Timeout, continue waiting
-                thread::sleep(std::time::Duration::from_millis(100));
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+            Err(e) if is_cancelled(&e) => {
+                println!("[CLIENT {}] Server shutting down", client_addr);
+                // Best effort: the client may already be gone
+                let _ = channel.write_close(CloseReason::Shutdown);
+                break Ok(());
             }
             Err(e) => {
                 eprintln!("[CLIENT {}] Error reading from client: {}", client_addr, e);
-                break;
+                // The channel already told the client why it rejected the record
+                if e.kind() == std::io::ErrorKind::InvalidData && Alert::from_error(&e).is_none() {
+                    anomaly(AnomalyKind::RecordRejected(e.to_string()));
+                }
+                break Err(e);
             }
         }
-    }
+    };
     
-    println!("[CLIENT {}] Closing connection", client_addr);
+    match &outcome {
+        Ok(()) => println!("[CLIENT {}] Connection closed cleanly", client_addr),
+        Err(_) => println!("[CLIENT {}] Connection aborted", client_addr),
+    }
     // *** ISOLATION GUARANTEED ***
     // All client-specific state (secret exponent, public key, shared secret, connection)
     // is dropped here and cleaned up from memory. No secrets persist after disconnect.
-    Ok(())
+    outcome
+}
+
+/// Quota identity of a client that proved it holds the HMQV static key with this
+/// fingerprint
+fn hmqv_identity(fingerprint: &Fingerprint) -> String {
+    format!("hmqv:{}", to_hex(fingerprint.as_bytes()))
 }
 
-/// Read a DHMessage from the stream
-fn read_message(stream: &mut TcpStream) -> std::io::Result<Option<DHMessage>> {
-    let mut type_byte = [0; 1];
-    stream.read_exact(&mut type_byte)?;
-    
-    match type_byte[0] {
-        0 => Ok(Some(DHMessage::ClientHello)),
-        1 | 2 | 3 => {
-            // For ServerHello, ClientPublicKey, ServerPublicKey:
-            // First BigInt: [4-byte length][data]
-            // If ServerHello, second BigInt follows
-            let mut data = vec![type_byte[0]];
-            
-            // Read first BigInt
-            let mut len_bytes = [0; 4];
-            stream.read_exact(&mut len_bytes)?;
-            data.extend_from_slice(&len_bytes);
-            
-            let len = u32::from_be_bytes(len_bytes) as usize;
-            let mut value_bytes = vec![0; len];
-            stream.read_exact(&mut value_bytes)?;
-            data.extend(value_bytes);
-            
-            // If ServerHello, read second BigInt
-            if type_byte[0] == 1 {
-                let mut len_bytes = [0; 4];
-                stream.read_exact(&mut len_bytes)?;
-                data.extend_from_slice(&len_bytes);
-                
-                let len = u32::from_be_bytes(len_bytes) as usize;
-                let mut value_bytes = vec![0; len];
-                stream.read_exact(&mut value_bytes)?;
-                data.extend(value_bytes);
-            }
-            
-            Ok(DHMessage::from_bytes(&data))
-        }
-        4 => Ok(Some(DHMessage::Done)),
-        _ => Ok(None),
+/// Read the next handshake message, key shares in the format of `kex`, giving up
+/// early if the server is cancelled
+///
+/// An Alert from the client ends the handshake as an error carrying the `Alert`; a
+/// message over our limits is answered with a decode_error alert and ends it as an
+/// error carrying the `LimitExceeded`.
+fn read_handshake_message(
+    stream: &mut TcpStream,
+    codec: Codec,
+    kex: KexAlgorithm,
+    settings: &HandshakeSettings,
+) -> std::io::Result<Option<DHMessage>> {
+    wait_readable(stream, &settings.cancel, stream.read_timeout()?)?;
+    let message = match read_message_within(stream, codec, &settings.limits, kex) {
+        Err(error) if LimitExceeded::from_error(&error).is_some() => {
+            send_alert(stream, codec, AlertCode::DecodeError, &error.to_string());
+            return Err(error);
+        }
+        result => result?,
+    };
+    match message.as_ref().and_then(Alert::from_message) {
+        Some(alert) => Err(alert.into()),
+        None => Ok(message),
     }
 }
 
-/// Write a DHMessage to the stream
-fn write_message(stream: &mut TcpStream, message: &DHMessage) -> std::io::Result<()> {
-    let bytes = message.to_bytes();
-    stream.write_all(&bytes)?;
-    stream.flush()?;
-    Ok(())
+/// Tell the client why the handshake ends; best effort, as it may already be gone
+fn send_alert(stream: &mut TcpStream, codec: Codec, code: AlertCode, description: &str) {
+    let _ = write_message(stream, codec, &Alert::message(code, description));
 }
+
+/// Write a handshake message, sealed if handshake encryption is active
+fn write_protected(
+    stream: &mut TcpStream,
+    codec: Codec,
+    protection: Option<&mut HandshakeProtection>,
+    message: &DHMessage,
+) -> std::io::Result<()> {
+    match protection {
+        Some(protection) => write_message(stream, codec, &protection.seal(message)),
+        None => write_message(stream, codec, message),
+    }
+}
+
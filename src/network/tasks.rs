@@ -0,0 +1,85 @@
+use std::sync::{Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+use crate::network::cancel::is_cancelled;
+use crate::network::lock_ignoring_poison;
+
+/// A thread started through a TaskTracker
+struct Task {
+    name: String,
+    handle: JoinHandle<std::io::Result<()>>,
+}
+
+/// Owns every thread a server spawns, so failures are reported in one place and
+/// shutdown can confirm that nothing is left running
+#[derive(Default)]
+pub struct TaskTracker {
+    tasks: Mutex<Vec<Task>>,
+}
+
+impl TaskTracker {
+    /// Create an empty tracker
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `task` on a new thread tracked under `name`
+    pub fn spawn<F>(&self, name: String, task: F) -> std::io::Result<()>
+    where
+        F: FnOnce() -> std::io::Result<()> + Send + 'static,
+    {
+        let handle = thread::Builder::new().name(name.clone()).spawn(task)?;
+        self.lock().push(Task { name, handle });
+        Ok(())
+    }
+
+    /// Join every finished task, reporting failures and panics
+    ///
+    /// # Returns
+    /// The number of tasks still running
+    pub fn reap(&self) -> usize {
+        let mut tasks = self.lock();
+        let (finished, running): (Vec<Task>, Vec<Task>) =
+            tasks.drain(..).partition(|task| task.handle.is_finished());
+        *tasks = running;
+        drop(tasks);
+
+        for task in finished {
+            report(task);
+        }
+        self.active()
+    }
+
+    /// Number of tasks that have not been reaped yet
+    pub fn active(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Wait for every task to finish, reporting failures and panics
+    ///
+    /// # Returns
+    /// The number of tasks that were joined
+    pub fn join_all(&self) -> usize {
+        let tasks: Vec<Task> = self.lock().drain(..).collect();
+        let count = tasks.len();
+        for task in tasks {
+            report(task);
+        }
+        count
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Task>> {
+        lock_ignoring_poison(&self.tasks)
+    }
+}
+
+/// Join a task and log how it ended
+fn report(task: Task) {
+    match task.handle.join() {
+        Ok(Ok(())) => {}
+        // Cancellation is a requested stop, not a failure
+        Ok(Err(e)) if is_cancelled(&e) => {}
+        Ok(Err(e)) => eprintln!("[SERVER] Task {} failed: {}", task.name, e),
+        Err(_) => eprintln!("[SERVER] Task {} panicked", task.name),
+    }
+}
@@ -0,0 +1,173 @@
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// A timed part of a connection, with the parts it was made of
+///
+/// The connection span has one child per handshake phase ("hello", "key_exchange",
+/// "authentication", "finished", "session"), and phases have one child per timed
+/// crypto operation ("shared_secret", "sign", "derive_keys").
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TelemetrySpan {
+    /// What the span covers (e.g., "dhke.connection" or "key_exchange")
+    pub name: &'static str,
+    /// When it started
+    pub start: SystemTime,
+    /// When it ended
+    pub end: SystemTime,
+    /// Key-value details, such as "kex", "group", "cipher", and "failure_reason"
+    pub attributes: Vec<(&'static str, String)>,
+    /// Nested spans, in the order they started
+    pub children: Vec<TelemetrySpan>,
+}
+
+impl TelemetrySpan {
+    fn new(name: &'static str) -> Self {
+        let now = SystemTime::now();
+        TelemetrySpan {
+            name,
+            start: now,
+            end: now,
+            attributes: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    /// Value of an attribute, if set
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn set_attribute(&mut self, key: &'static str, value: String) {
+        match self.attributes.iter_mut().find(|(name, _)| *name == key) {
+            Some((_, existing)) => *existing = value,
+            None => self.attributes.push((key, value)),
+        }
+    }
+}
+
+/// Callback receiving the span of every finished connection; called from connection
+/// threads, so it must be thread-safe
+pub type TelemetryExporter = Arc<dyn Fn(&TelemetrySpan) + Send + Sync>;
+
+/// Export connection spans through the globally installed OpenTelemetry tracer
+///
+/// Spans keep their recorded start and end times. Failed connections get an error
+/// status carrying the failure reason. Install a tracer provider with
+/// `opentelemetry::global::set_tracer_provider` before the first connection ends;
+/// until then spans are dropped.
+#[cfg(feature = "opentelemetry")]
+pub fn opentelemetry_exporter() -> TelemetryExporter {
+    use opentelemetry::trace::{Span, SpanKind, Status, TraceContextExt, Tracer};
+    use opentelemetry::{global, Context, KeyValue};
+
+    fn export(tracer: &global::BoxedTracer, span: &TelemetrySpan, parent: &Context, kind: SpanKind) {
+        let attributes: Vec<KeyValue> = span
+            .attributes
+            .iter()
+            .map(|(key, value)| KeyValue::new(*key, value.clone()))
+            .collect();
+        let mut exported = tracer
+            .span_builder(span.name)
+            .with_kind(kind)
+            .with_start_time(span.start)
+            .with_attributes(attributes)
+            .start_with_context(tracer, parent);
+        if let Some(reason) = span.attribute("failure_reason") {
+            exported.set_status(Status::error(reason.to_string()));
+        }
+        let context = parent.with_span(exported);
+        for child in &span.children {
+            export(tracer, child, &context, SpanKind::Internal);
+        }
+        context.span().end_with_timestamp(span.end);
+    }
+
+    Arc::new(|span| {
+        let tracer = global::tracer("dhke");
+        export(&tracer, span, &Context::new(), SpanKind::Server);
+    })
+}
+
+/// Records the span tree of one connection and exports it when dropped, so early
+/// returns are covered
+///
+/// Phases follow each other: starting one ends the previous. Without an exporter
+/// nothing is recorded.
+pub(crate) struct ConnectionTrace {
+    exporter: Option<TelemetryExporter>,
+    root: RefCell<TelemetrySpan>,
+    phase: RefCell<Option<TelemetrySpan>>,
+}
+
+impl ConnectionTrace {
+    pub(crate) fn new(exporter: Option<TelemetryExporter>, peer: &str) -> Self {
+        let mut root = TelemetrySpan::new("dhke.connection");
+        root.set_attribute("peer", peer.to_string());
+        ConnectionTrace {
+            exporter,
+            root: RefCell::new(root),
+            phase: RefCell::new(None),
+        }
+    }
+
+    /// End the current phase and start the next
+    pub(crate) fn phase(&self, name: &'static str) {
+        if self.exporter.is_none() {
+            return;
+        }
+        self.end_phase();
+        *self.phase.borrow_mut() = Some(TelemetrySpan::new(name));
+    }
+
+    /// Set an attribute on the connection span
+    pub(crate) fn attribute(&self, key: &'static str, value: impl ToString) {
+        if self.exporter.is_some() {
+            self.root.borrow_mut().set_attribute(key, value.to_string());
+        }
+    }
+
+    /// Record why the connection failed; the first reason recorded wins
+    pub(crate) fn fail(&self, reason: impl ToString) {
+        if self.exporter.is_some() && self.root.borrow().attribute("failure_reason").is_none() {
+            self.attribute("failure_reason", reason);
+        }
+    }
+
+    /// Run a crypto operation, timing it as a child of the current phase
+    pub(crate) fn crypto<T>(&self, name: &'static str, operation: impl FnOnce() -> T) -> T {
+        if self.exporter.is_none() {
+            return operation();
+        }
+        let mut span = TelemetrySpan::new(name);
+        let result = operation();
+        span.end = SystemTime::now();
+        match self.phase.borrow_mut().as_mut() {
+            Some(phase) => phase.children.push(span),
+            None => self.root.borrow_mut().children.push(span),
+        }
+        result
+    }
+
+    fn end_phase(&self) {
+        if let Some(mut phase) = self.phase.borrow_mut().take() {
+            phase.end = SystemTime::now();
+            self.root.borrow_mut().children.push(phase);
+        }
+    }
+}
+
+impl Drop for ConnectionTrace {
+    fn drop(&mut self) {
+        let Some(exporter) = &self.exporter else {
+            return;
+        };
+        self.end_phase();
+        let mut root = self.root.borrow_mut();
+        root.end = SystemTime::now();
+        exporter(&root);
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::crypto::fingerprint::Fingerprint;
+use crate::crypto::groups::DhGroup;
+use crate::crypto::kdf::Kdf;
+use crate::crypto::kex::KexAlgorithm;
+use crate::crypto::record::CipherSuite;
+use crate::crypto::ticket::Resumption;
+use crate::crypto::transcript::{from_hex, to_hex};
+use crate::network::capabilities::ServerCapabilities;
+use crate::network::lock_ignoring_poison;
+
+/// A ticket a server issued, with what the client needs to resume from it
+#[derive(Clone)]
+pub struct SessionTicket {
+    /// The sealed ticket, presented in ClientHello
+    pub ticket: Vec<u8>,
+    /// The secret, cipher and KDF sealed inside it, which the client keeps itself
+    pub resumption: Resumption,
+    /// What the server selected in the session that issued the ticket
+    pub capabilities: ServerCapabilities,
+    /// Fingerprint of the server's key in that session, which a resumed session has too
+    pub server_fingerprint: Fingerprint,
+    /// Fingerprint (`params_fingerprint`) of the finite-field (p, g) of that session,
+    /// if it had any
+    pub params_fingerprint: Option<[u8; 32]>,
+    /// When the server stops accepting the ticket
+    pub expires: Instant,
+}
+
+/// Client-side store of session tickets, keyed by endpoint
+///
+/// Each ticket is used at most once, so no two resumed handshakes share one; the
+/// server issues a fresh ticket at the end of each. Clones share the same tickets.
+#[derive(Clone, Default)]
+pub struct TicketStore {
+    tickets: Arc<Mutex<HashMap<String, SessionTicket>>>,
+    /// File the tickets are mirrored to, if the store is persistent
+    path: Option<PathBuf>,
+}
+
+impl TicketStore {
+    /// Create an empty in-memory store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open a store mirrored to a file, loading the unexpired tickets it already holds,
+    /// so a later run resumes the sessions of an earlier one
+    ///
+    /// Tickets hold their sessions' resumption secrets, so the file is readable only
+    /// by its owner where supported. Lines that do not parse are skipped, and those
+    /// servers get a full handshake.
+    pub fn persistent(path: &Path) -> std::io::Result<Self> {
+        let mut tickets = HashMap::new();
+        if path.exists() {
+            for line in fs::read_to_string(path)?.lines() {
+                if let Some((endpoint, ticket)) = parse_entry(line).filter(|(_, ticket)| ticket.expires > Instant::now()) {
+                    tickets.insert(endpoint, ticket);
+                }
+            }
+        }
+        Ok(TicketStore {
+            tickets: Arc::new(Mutex::new(tickets)),
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    /// Take the ticket for an endpoint, unless it has expired
+    pub fn take(&self, endpoint: &str) -> Option<SessionTicket> {
+        let mut tickets = self.lock();
+        let ticket = tickets.remove(endpoint)?;
+        self.persist(&tickets);
+        Some(ticket).filter(|ticket| ticket.expires > Instant::now())
+    }
+
+    /// Keep the latest ticket an endpoint issued, replacing any earlier one
+    pub fn store(&self, endpoint: &str, ticket: SessionTicket) {
+        let mut tickets = self.lock();
+        tickets.insert(endpoint.to_string(), ticket);
+        self.persist(&tickets);
+    }
+
+    /// Endpoints holding an unexpired ticket, with their tickets, sorted by endpoint
+    pub fn entries(&self) -> Vec<(String, SessionTicket)> {
+        let now = Instant::now();
+        let mut entries: Vec<(String, SessionTicket)> = self
+            .lock()
+            .iter()
+            .filter(|(_, ticket)| ticket.expires > now)
+            .map(|(endpoint, ticket)| (endpoint.clone(), ticket.clone()))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    /// Forget every ticket, so every server gets a full handshake next time
+    pub fn clear(&self) {
+        let mut tickets = self.lock();
+        tickets.clear();
+        self.persist(&tickets);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, SessionTicket>> {
+        lock_ignoring_poison(&self.tickets)
+    }
+
+    /// Rewrite the ticket file atomically; resumption is only an optimization, so a
+    /// failed write is logged rather than returned
+    fn persist(&self, tickets: &HashMap<String, SessionTicket>) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let mut contents = String::from(
+            "# Session tickets stored by dhke; keep this file secret\n\
+             # endpoint expires params-fingerprint server-fingerprint algorithm cipher group kdf ticket secret\n",
+        );
+        let now = Instant::now();
+        for (endpoint, ticket) in tickets.iter().filter(|(_, ticket)| ticket.expires > now) {
+            contents.push_str(&format!(
+                "{} {} {} {} {} {} {} {} {} {}\n",
+                endpoint,
+                unix_time() + ticket.expires.duration_since(now).as_secs(),
+                ticket.params_fingerprint.map_or("-".to_string(), |fingerprint| to_hex(&fingerprint)),
+                to_hex(ticket.server_fingerprint.as_bytes()),
+                ticket.capabilities.kex_algorithm.name(),
+                ticket.capabilities.cipher.name(),
+                ticket.capabilities.group.map_or("-", |group| group.name()),
+                ticket.resumption.kdf.name(),
+                to_hex(&ticket.ticket),
+                to_hex(ticket.resumption.secret()),
+            ));
+        }
+
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let written = fs::write(&temp_path, contents).and_then(|_| {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&temp_path, fs::Permissions::from_mode(0o600))?;
+            }
+            fs::rename(&temp_path, path)
+        });
+        if let Err(e) = written {
+            eprintln!("[CLIENT] Failed to write session tickets {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Parse an entry line written by `TicketStore::persist`
+fn parse_entry(line: &str) -> Option<(String, SessionTicket)> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let [endpoint, expires, params, server, algorithm, cipher, group, kdf, ticket, secret] = fields[..] else {
+        return None;
+    };
+    if endpoint.starts_with('#') {
+        return None;
+    }
+    let remaining = expires.parse::<u64>().ok()?.checked_sub(unix_time())?;
+    let params_fingerprint = match params {
+        "-" => None,
+        hex => Some(from_hex(hex)?.try_into().ok()?),
+    };
+    let cipher = CipherSuite::from_name(cipher)?;
+    let capabilities = ServerCapabilities {
+        kex_algorithm: KexAlgorithm::from_name(algorithm)?,
+        cipher,
+        group: match group {
+            "-" => None,
+            name => Some(DhGroup::from_name(name)?),
+        },
+    };
+    let ticket = SessionTicket {
+        ticket: from_hex(ticket)?,
+        resumption: Resumption::from_secret(from_hex(secret)?.try_into().ok()?, cipher, Kdf::from_name(kdf)?),
+        capabilities,
+        server_fingerprint: Fingerprint::from_hex(server)?,
+        params_fingerprint,
+        expires: Instant::now() + Duration::from_secs(remaining),
+    };
+    Some((endpoint.to_string(), ticket))
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
@@ -0,0 +1,136 @@
+use std::io::{Read, Write};
+
+/// Size of a record header: [content type][length:u32]
+pub const RECORD_HEADER_LEN: usize = 5;
+
+/// What a record after the handshake carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentType {
+    /// Bytes the application sent
+    ApplicationData,
+    /// The one-byte `CloseReason` of a peer about to close the connection
+    Close,
+    /// A Rekey or RekeyFinished message
+    Rekey,
+    /// An Alert message from a peer that rejected one of our records
+    Alert,
+    /// Asks the peer to show it is still there; answered with a Pong
+    Ping,
+    /// Answers a Ping, echoing its payload
+    Pong,
+}
+
+impl ContentType {
+    /// Every content type known to this implementation
+    pub const ALL: &'static [ContentType] = &[
+        ContentType::ApplicationData,
+        ContentType::Close,
+        ContentType::Rekey,
+        ContentType::Alert,
+        ContentType::Ping,
+        ContentType::Pong,
+    ];
+
+    /// Wire identifier of the content type
+    pub fn id(&self) -> u8 {
+        match self {
+            ContentType::ApplicationData => 0,
+            ContentType::Close => 1,
+            ContentType::Rekey => 2,
+            ContentType::Alert => 3,
+            ContentType::Ping => 4,
+            ContentType::Pong => 5,
+        }
+    }
+
+    /// Look up a content type by its wire identifier
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.iter().copied().find(|content_type| content_type.id() == id)
+    }
+
+    /// Short lowercase name of the content type (e.g., "application_data")
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContentType::ApplicationData => "application_data",
+            ContentType::Close => "close",
+            ContentType::Rekey => "rekey",
+            ContentType::Alert => "alert",
+            ContentType::Ping => "ping",
+            ContentType::Pong => "pong",
+        }
+    }
+}
+
+/// One record as it travels after the handshake: [content type][length:u32][payload]
+///
+/// The payload is whatever the record layer made of the plaintext (ciphertext and
+/// tag); the header is its associated data, so neither field can be altered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub content_type: ContentType,
+    /// Payload length in bytes, as sent in the header
+    pub length: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Record {
+    /// Record carrying `payload`, with the length taken from it
+    pub fn new(content_type: ContentType, payload: Vec<u8>) -> Self {
+        Record {
+            content_type,
+            length: payload.len() as u32,
+            payload,
+        }
+    }
+
+    /// Header of a record of this type and payload length
+    pub fn header(content_type: ContentType, length: u32) -> [u8; RECORD_HEADER_LEN] {
+        let [a, b, c, d] = length.to_be_bytes();
+        [content_type.id(), a, b, c, d]
+    }
+
+    /// Encode the record for the wire
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(RECORD_HEADER_LEN + self.payload.len());
+        bytes.extend(Self::header(self.content_type, self.length));
+        bytes.extend(&self.payload);
+        bytes
+    }
+
+    /// Write the record in a single write, so Nagle's algorithm does not hold back
+    /// the payload
+    pub fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&self.to_bytes())?;
+        writer.flush()
+    }
+
+    /// Read one record from the stream
+    ///
+    /// # Arguments
+    /// * `reader` - Stream to read from
+    /// * `max_length` - Largest payload accepted, checked before the payload is read
+    ///
+    /// # Returns
+    /// The record, None if the stream ended cleanly before a header, or an
+    /// InvalidData error for an unknown content type or a length over `max_length`
+    pub fn read<R: Read>(reader: &mut R, max_length: usize) -> std::io::Result<Option<Self>> {
+        let mut header = [0; RECORD_HEADER_LEN];
+        match reader.read(&mut header[..1])? {
+            0 => return Ok(None),
+            _ => reader.read_exact(&mut header[1..])?,
+        }
+        let content_type = ContentType::from_id(header[0]).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "Unknown record content type")
+        })?;
+        let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]);
+        if length as usize > max_length {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Record length out of range",
+            ));
+        }
+        let mut payload = vec![0; length as usize];
+        reader.read_exact(&mut payload)?;
+        Ok(Some(Record { content_type, length, payload }))
+    }
+}
@@ -1,101 +1,940 @@
-use num_bigint::BigInt;
+use num_bigint::{BigInt, BigUint};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::groups::DhGroup;
+use crate::crypto::kdf::{Kdf, SessionKeys};
+use crate::crypto::kex::KexAlgorithm;
+use crate::crypto::record::{CipherSuite, Padding, RekeyPolicy};
+use crate::crypto::rng::{with_rng, RngPurpose};
+use crate::crypto::transcript::{channel_binding, Transcript};
+use crate::network::framing::Codec;
+
+/// Largest finite-field group either side handles, explicit parameters included
+pub const MAX_GROUP_BITS: usize = 16384;
+
+/// Largest integer or group element a message may carry unless a reader is
+/// configured otherwise (see `MessageLimits`): one element of the largest group
+pub const MAX_INTEGER_FIELD: usize = MAX_GROUP_BITS / 8;
+
+/// Largest frame (type byte and payload) a peer may send: room for explicit
+/// parameters of up to `MAX_GROUP_BITS` bits and the largest prekey or certificate message
+pub const MAX_FRAME: usize = 16 * 1024;
+
+/// Largest payload a GREASE message may carry
+pub const MAX_GREASE_PAYLOAD: usize = 255;
+
+/// Largest public key or signature a ServerSignature may carry (fits RSA-8192)
+pub const MAX_SIGNATURE_FIELD: usize = 2048;
+
+/// Largest EncryptedHandshake ciphertext: a sealed ServerSignature of maximum size
+pub const MAX_ENCRYPTED_HANDSHAKE: usize = 2 * MAX_SIGNATURE_FIELD + 64;
+
+/// Largest prekey publication or bundle the prekey directory messages may carry
+pub const MAX_PREKEY_MESSAGE: usize = 8192;
+
+/// Listed among ClientHello's key-exchange algorithms to ask the server to send
+/// identities only under handshake encryption (like a TLS signaling cipher suite
+/// value, it names no algorithm, so servers that do not know it ignore it)
+pub const PROTECT_IDENTITIES_SIGNAL: u8 = 0xFE;
+
+/// Listed among ClientHello's key-exchange algorithms to ask the server to prove
+/// explicit parameters prime with a PrimeCertificate message (see `PROTECT_IDENTITIES_SIGNAL`)
+pub const PRIME_CERTIFICATE_SIGNAL: u8 = 0xFD;
+
+/// Listed among ClientHello's key-exchange algorithms when the client can answer
+/// Rekey messages, so the server may start rekeys of its own (see `PROTECT_IDENTITIES_SIGNAL`)
+pub const REKEY_SIGNAL: u8 = 0xFC;
+
+/// Listed among ClientHello's key-exchange algorithms to derive the session keys with
+/// the SP 800-56A one-step KDF instead of HKDF (see `PROTECT_IDENTITIES_SIGNAL`); a
+/// server that does not know it derives different keys, so the Finished check fails
+pub const ONE_STEP_KDF_SIGNAL: u8 = 0xFB;
+
+/// Listed among ClientHello's key-exchange algorithms to send finite-field public
+/// keys as fixed-width ClientPublicKeyFixed/ServerPublicKeyFixed messages (see
+/// `PROTECT_IDENTITIES_SIGNAL`); a server that does not know them closes the connection
+pub const FIXED_WIDTH_KEYS_SIGNAL: u8 = 0xFA;
+
+/// Listed among ClientHello's key-exchange algorithms to encode every later message
+/// with bincode (see `framing::Codec`); a server that does not know it answers in
+/// the native encoding, which the client then fails to decode
+pub const BINCODE_SIGNAL: u8 = 0xF9;
+
+/// Listed among ClientHello's key-exchange algorithms to encode every later message
+/// as deterministic CBOR (see `framing::Codec::Cbor`), failing like `BINCODE_SIGNAL`
+/// with servers that do not know it
+pub const CBOR_SIGNAL: u8 = 0xF8;
+
+/// Listed among ClientHello's key-exchange algorithms to encode every later message
+/// as protobuf (see proto/dhke.proto), failing like `BINCODE_SIGNAL` with servers
+/// that do not know it; only built with the `protobuf` feature
+pub const PROTOBUF_SIGNAL: u8 = 0xF7;
+
+/// Listed among ClientHello's key-exchange algorithms to ask for a NewSessionTicket
+/// after ServerFinished, which a later ClientHello can present to resume the session
+/// (see `TicketKey`); a server that does not know it sends none, leaving the client
+/// waiting
+pub const SESSION_TICKET_SIGNAL: u8 = 0xF6;
+
+/// Listed among ClientHello's key-exchange algorithms when a ClientParams message
+/// follows ClientHello, proposing the (p, g) for finite-field DH (see
+/// `PROTECT_IDENTITIES_SIGNAL`); a server that does not know it reads ClientParams
+/// in place of the client's public key and closes the connection
+pub const CLIENT_PARAMS_SIGNAL: u8 = 0xF5;
+
+/// Listed among ClientHello's key-exchange algorithms to pad every encrypted handshake
+/// message and record in both directions (see `Padding`); a server that does not
+/// know it sends unpadded records, which the client rejects
+pub const RECORD_PADDING_SIGNAL: u8 = 0xF4;
+
+/// Largest encoded Pocklington certificate a PrimeCertificate message may carry
+pub const MAX_PRIME_CERTIFICATE: usize = 8192;
+
+/// Protocol versions this implementation speaks, newest first
+///
+/// ClientHello lists the versions a client speaks and every ServerHello variant
+/// carries the one the server picked, so later versions can change the messages
+/// after the Hellos while peers that only know older versions keep working.
+pub const PROTOCOL_VERSIONS: &[u8] = &[1];
+
+/// Abort reason: the client only wanted the server's Hello (parameter probing)
+pub const ABORT_PROBE: u8 = 0;
+
+/// Abort reason: the client's user or application cancelled the handshake
+pub const ABORT_CANCELLED: u8 = 1;
+
+/// Abort reason: the client asked the server to sign the handshake, but the server
+/// has no identity key configured
+pub const ABORT_UNAUTHENTICATED: u8 = 2;
+
+/// Abort reason: the prekey directory refused a publication (bad signature, or not
+/// newer than the one it holds), or the server runs no directory
+pub const ABORT_PREKEYS_REJECTED: u8 = 3;
+
+/// Abort reason: the prekey directory holds no prekeys for the requested identity
+pub const ABORT_UNKNOWN_IDENTITY: u8 = 4;
+
+/// Abort reason: the server speaks none of the protocol versions the client listed
+pub const ABORT_VERSION_MISMATCH: u8 = 5;
+
+/// Reserved GREASE message types (0x0A, 0x1A, ..., 0xFA) never assigned to real messages
+///
+/// Receivers must skip these, so peers that send them keep implementations from
+/// ossifying around the set of message types that exist today.
+pub fn is_grease_type(type_byte: u8) -> bool {
+    type_byte & 0x0F == 0x0A
+}
 
 /// Protocol messages for Diffie-Hellman Key Exchange
-#[derive(Debug, Clone)]
+///
+/// `to_bytes` and `from_bytes` are the native encoding; the serde derive backs the
+/// other codecs (see `framing::Codec`), so new variants need no hand-written layout
+/// there.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum DHMessage {
-    /// Client initiates the key exchange
-    ClientHello,
+    /// Client initiates the key exchange, listing the key-exchange algorithm IDs,
+    /// record-layer cipher IDs and protocol versions it supports, each in order of
+    /// preference. A non-empty nonce asks the server to sign the handshake with its
+    /// identity key. Groups lists the named group IDs the client accepts for
+    /// finite-field algorithms; empty accepts any group, explicit parameters included.
+    /// A non-empty ticket asks to resume the session that issued it, the rest of the
+    /// Hello still applying if the server declines. The random is fresh for every
+    /// handshake (see `ReplayWindow`). The cookie is empty unless the Hello repeats
+    /// one the server answered with a HelloRetry.
+    ClientHello {
+        kex_algorithms: Vec<u8>,
+        ciphers: Vec<u8>,
+        #[serde(with = "byte_field")]
+        nonce: Vec<u8>,
+        versions: Vec<u8>,
+        groups: Vec<u16>,
+        #[serde(with = "byte_field")]
+        ticket: Vec<u8>,
+        #[serde(with = "byte_field")]
+        random: [u8; 32],
+        #[serde(with = "byte_field")]
+        cookie: Vec<u8>,
+    },
 
-    /// Server responds with agreed prime modulus (p) and base (g)
+    /// Server responds with agreed prime modulus (p) and base (g), and the selected
+    /// cipher and protocol version (as do all ServerHello variants). Every ServerHello
+    /// also carries a fresh random; both randoms are in the transcript, whose hash is
+    /// the KDF's context, so a session's keys are fresh even if a key share is reused.
     ServerHello {
-        p: BigInt,
-        g: BigInt,
+        #[serde(with = "biguint_bytes")]
+        p: BigUint,
+        #[serde(with = "biguint_bytes")]
+        g: BigUint,
+        cipher: u8,
+        version: u8,
+        #[serde(with = "byte_field")]
+        random: [u8; 32],
+    },
+
+    /// Server responds with the ID of a well-known group instead of explicit (p, g)
+    ServerHelloNamed {
+        group: u16,
+        cipher: u8,
+        version: u8,
+        #[serde(with = "byte_field")]
+        random: [u8; 32],
     },
 
     /// Client sends its public key: X = (g^x mod p)
     ClientPublicKey {
-        x: BigInt,
+        #[serde(with = "biguint_bytes")]
+        x: BigUint,
     },
 
     /// Server sends its public key: Y = (g^y mod p)
     ServerPublicKey {
-        y: BigInt,
+        #[serde(with = "biguint_bytes")]
+        y: BigUint,
     },
 
     /// Signals completion of the key exchange
     Done,
+
+    /// Server selects a key-exchange algorithm that needs no (p, g) parameters
+    ServerHelloKex {
+        algorithm: u8,
+        cipher: u8,
+        version: u8,
+        #[serde(with = "byte_field")]
+        random: [u8; 32],
+    },
+
+    /// Client sends its public key for a non finite-field algorithm (e.g. a 32-byte X25519 point)
+    ClientKeyShare {
+        #[serde(with = "byte_field")]
+        key: Vec<u8>,
+    },
+
+    /// Server sends its public key for a non finite-field algorithm
+    ServerKeyShare {
+        #[serde(with = "byte_field")]
+        key: Vec<u8>,
+    },
+
+    /// Either side ends the handshake early, e.g. after probing the server's Hello;
+    /// the reason is one of the `ABORT_*` codes
+    Abort {
+        reason: u8,
+    },
+
+    /// Server proves its identity with a signature over the transcript hash up to and
+    /// including its public key, which covers the parameters, both public keys, and
+    /// the client's nonce; the scheme is a `SignatureScheme` ID
+    ServerSignature {
+        scheme: u8,
+        #[serde(with = "byte_field")]
+        public_key: Vec<u8>,
+        #[serde(with = "byte_field")]
+        signature: Vec<u8>,
+    },
+
+    /// Client confirms it derived the session keys: an HMAC of the transcript hash
+    /// through Done, keyed with the derived Finished key
+    ClientFinished {
+        #[serde(with = "byte_field")]
+        verify_data: [u8; 32],
+    },
+
+    /// Server confirms it derived the session keys, once the client's Finished verified
+    ServerFinished {
+        #[serde(with = "byte_field")]
+        verify_data: [u8; 32],
+    },
+
+    /// Server selects HMQV over a well-known group; both sides then send key shares
+    /// holding their static and ephemeral public keys (or, with identity protection,
+    /// ephemeral keys only, the static keys following in StaticKey messages)
+    ServerHelloHmqv {
+        group: u16,
+        cipher: u8,
+        version: u8,
+        #[serde(with = "byte_field")]
+        random: [u8; 32],
+    },
+
+    /// A handshake message sealed under keys from the ephemeral exchange, so passive
+    /// observers never see the identities it carries (see `HandshakeProtection`)
+    EncryptedHandshake {
+        #[serde(with = "byte_field")]
+        ciphertext: Vec<u8>,
+    },
+
+    /// A party's static HMQV public key; only ever sent inside EncryptedHandshake
+    StaticKey {
+        #[serde(with = "byte_field")]
+        key: Vec<u8>,
+    },
+
+    /// Sent instead of ClientHello: store a signed X3DH prekey publication in the
+    /// server's prekey directory, answered with Done
+    PrekeyUpload {
+        #[serde(with = "byte_field")]
+        publication: Vec<u8>,
+    },
+
+    /// Sent instead of ClientHello: ask for the prekey bundle of the identity with
+    /// this fingerprint
+    PrekeyRequest {
+        #[serde(with = "byte_field")]
+        identity: [u8; 32],
+    },
+
+    /// The directory's answer to PrekeyRequest
+    PrekeyResponse {
+        #[serde(with = "byte_field")]
+        bundle: Vec<u8>,
+    },
+
+    /// Server selects SRP over a well-known group. The client's key share is its
+    /// username and A, the server's the user's salt and B; the Finished messages then
+    /// serve as the SRP proofs of both sides
+    ServerHelloSrp {
+        group: u16,
+        cipher: u8,
+        version: u8,
+        #[serde(with = "byte_field")]
+        random: [u8; 32],
+    },
+
+    /// Server selects finite-field DH over a well-known group with padded public
+    /// values, sent as key shares of a fixed width (see `PaddedFiniteFieldKeyExchange`)
+    ServerHelloPadded {
+        group: u16,
+        cipher: u8,
+        version: u8,
+        #[serde(with = "byte_field")]
+        random: [u8; 32],
+    },
+
+    /// Sent right after ServerHello with explicit (p, g) when the client listed
+    /// `PRIME_CERTIFICATE_SIGNAL`: an encoded `PrimeCertificate` proving p a safe prime,
+    /// or empty if the server has none
+    PrimeCertificate {
+        #[serde(with = "byte_field")]
+        certificate: Vec<u8>,
+    },
+
+    /// Starts or answers a rekey with a fresh X25519 public key; only ever sent
+    /// inside a Rekey record (see `RecordLayer`)
+    Rekey {
+        #[serde(with = "byte_field")]
+        public_key: Vec<u8>,
+    },
+
+    /// Confirms the sender derived the keys of a rekey, whose records it sends from
+    /// now on: an HMAC of both Rekey messages under the new Finished key
+    RekeyFinished {
+        #[serde(with = "byte_field")]
+        verify_data: [u8; 32],
+    },
+
+    /// Client public key X = g^x mod p as exactly ⌈|p|/8⌉ big-endian bytes, after
+    /// `FIXED_WIDTH_KEYS_SIGNAL`; the negotiated group implies the length
+    ClientPublicKeyFixed {
+        #[serde(with = "byte_field")]
+        key: Vec<u8>,
+    },
+
+    /// Server public key Y = g^y mod p, encoded like `ClientPublicKeyFixed`
+    ServerPublicKeyFixed {
+        #[serde(with = "byte_field")]
+        key: Vec<u8>,
+    },
+
+    /// Either side gives up on the handshake because the peer broke the protocol; the
+    /// code is an `AlertCode` ID and the description (at most 255 bytes of UTF-8)
+    /// says what was wrong, for logs
+    Alert {
+        code: u8,
+        description: String,
+    },
+
+    /// Server accepts the ticket in ClientHello: both sides skip the key exchange and
+    /// derive the session keys from the ticket's secret, with the transcript through
+    /// this message, whose random makes them fresh, as context
+    ServerHelloResume {
+        cipher: u8,
+        version: u8,
+        #[serde(with = "byte_field")]
+        random: [u8; 32],
+    },
+
+    /// Sent after ServerFinished when the client listed `SESSION_TICKET_SIGNAL`: a
+    /// ticket valid for `lifetime` seconds, or empty if the server issues none
+    NewSessionTicket {
+        lifetime: u32,
+        #[serde(with = "byte_field")]
+        ticket: Vec<u8>,
+    },
+
+    /// Sent right after ClientHello when the client listed `CLIENT_PARAMS_SIGNAL`: the
+    /// (p, g) it trusts, which the server either uses for finite-field DH or refuses
+    ClientParams {
+        #[serde(with = "biguint_bytes")]
+        p: BigUint,
+        #[serde(with = "biguint_bytes")]
+        g: BigUint,
+    },
+
+    /// Server answers a ClientHello without a valid cookie, then closes the
+    /// connection, keeping no state and doing no key exchange until the client
+    /// reconnects and sends the same ClientHello again with this cookie (see
+    /// `CookieKey`); at most 65535 bytes
+    HelloRetry {
+        #[serde(with = "byte_field")]
+        cookie: Vec<u8>,
+    },
+
+    /// Reserved message type carrying random bytes, ignored by the receiver
+    Grease {
+        kind: u8,
+        #[serde(with = "byte_field")]
+        payload: Vec<u8>,
+    },
 }
 
 impl DHMessage {
     /// Serialize message to bytes for transmission
-    /// Format: [type_byte] [data...]
-    /// For BigInt values: [length:u32] [bytes...]
+    /// Format: [type_byte] [data...], sent on the wire inside a frame (see `framing`)
+    /// For integer values: [length:u32] [big-endian bytes, no leading zeros]
     pub fn to_bytes(&self) -> Vec<u8> {
         match self {
-            DHMessage::ClientHello => {
-                vec![0]
+            DHMessage::ClientHello { kex_algorithms, ciphers, nonce, versions, groups, ticket, random, cookie } => {
+                let mut bytes = vec![0, kex_algorithms.len() as u8];
+                bytes.extend(kex_algorithms);
+                bytes.push(ciphers.len() as u8);
+                bytes.extend(ciphers);
+                bytes.push(nonce.len() as u8);
+                bytes.extend(nonce);
+                bytes.push(versions.len() as u8);
+                bytes.extend(versions);
+                let group_count = u16::try_from(groups.len()).expect("a ClientHello offers at most 65535 groups");
+                bytes.extend(group_count.to_be_bytes());
+                bytes.extend(groups.iter().flat_map(|group| group.to_be_bytes()));
+                serialize_short_bytes(&mut bytes, ticket);
+                bytes.extend(random);
+                serialize_short_bytes(&mut bytes, cookie);
+                bytes
             }
-            DHMessage::ServerHello { p, g } => {
+            DHMessage::ServerHello { p, g, cipher, version, random } => {
                 let mut bytes = vec![1];
-                serialize_bigint(&mut bytes, p);
-                serialize_bigint(&mut bytes, g);
+                serialize_biguint(&mut bytes, p);
+                serialize_biguint(&mut bytes, g);
+                bytes.extend([*cipher, *version]);
+                bytes.extend(random);
                 bytes
             }
             DHMessage::ClientPublicKey { x } => {
                 let mut bytes = vec![2];
-                serialize_bigint(&mut bytes, x);
+                serialize_biguint(&mut bytes, x);
                 bytes
             }
             DHMessage::ServerPublicKey { y } => {
                 let mut bytes = vec![3];
-                serialize_bigint(&mut bytes, y);
+                serialize_biguint(&mut bytes, y);
                 bytes
             }
             DHMessage::Done => {
                 vec![4]
             }
+            DHMessage::ServerHelloNamed { group, cipher, version, random } => {
+                let mut bytes = vec![5];
+                bytes.extend(group.to_be_bytes());
+                bytes.extend([*cipher, *version]);
+                bytes.extend(random);
+                bytes
+            }
+            DHMessage::ServerHelloKex { algorithm, cipher, version, random } => {
+                let mut bytes = vec![6, *algorithm, *cipher, *version];
+                bytes.extend(random);
+                bytes
+            }
+            DHMessage::ClientKeyShare { key } => {
+                let mut bytes = vec![7];
+                serialize_bytes(&mut bytes, key);
+                bytes
+            }
+            DHMessage::ServerKeyShare { key } => {
+                let mut bytes = vec![8];
+                serialize_bytes(&mut bytes, key);
+                bytes
+            }
+            DHMessage::Abort { reason } => {
+                vec![9, *reason]
+            }
+            DHMessage::ServerSignature { scheme, public_key, signature } => {
+                let mut bytes = vec![11, *scheme];
+                serialize_bytes(&mut bytes, public_key);
+                serialize_bytes(&mut bytes, signature);
+                bytes
+            }
+            DHMessage::ClientFinished { verify_data } => {
+                let mut bytes = vec![12];
+                bytes.extend(verify_data);
+                bytes
+            }
+            DHMessage::ServerFinished { verify_data } => {
+                let mut bytes = vec![13];
+                bytes.extend(verify_data);
+                bytes
+            }
+            DHMessage::ServerHelloHmqv { group, cipher, version, random } => {
+                let mut bytes = vec![14];
+                bytes.extend(group.to_be_bytes());
+                bytes.extend([*cipher, *version]);
+                bytes.extend(random);
+                bytes
+            }
+            DHMessage::EncryptedHandshake { ciphertext } => {
+                let mut bytes = vec![15];
+                serialize_bytes(&mut bytes, ciphertext);
+                bytes
+            }
+            DHMessage::StaticKey { key } => {
+                let mut bytes = vec![16];
+                serialize_bytes(&mut bytes, key);
+                bytes
+            }
+            DHMessage::PrekeyUpload { publication } => {
+                let mut bytes = vec![17];
+                serialize_bytes(&mut bytes, publication);
+                bytes
+            }
+            DHMessage::PrekeyRequest { identity } => {
+                let mut bytes = vec![18];
+                bytes.extend(identity);
+                bytes
+            }
+            DHMessage::PrekeyResponse { bundle } => {
+                let mut bytes = vec![19];
+                serialize_bytes(&mut bytes, bundle);
+                bytes
+            }
+            DHMessage::ServerHelloSrp { group, cipher, version, random } => {
+                let mut bytes = vec![20];
+                bytes.extend(group.to_be_bytes());
+                bytes.extend([*cipher, *version]);
+                bytes.extend(random);
+                bytes
+            }
+            DHMessage::ServerHelloPadded { group, cipher, version, random } => {
+                let mut bytes = vec![21];
+                bytes.extend(group.to_be_bytes());
+                bytes.extend([*cipher, *version]);
+                bytes.extend(random);
+                bytes
+            }
+            DHMessage::PrimeCertificate { certificate } => {
+                let mut bytes = vec![22];
+                serialize_bytes(&mut bytes, certificate);
+                bytes
+            }
+            DHMessage::Rekey { public_key } => {
+                let mut bytes = vec![23];
+                serialize_bytes(&mut bytes, public_key);
+                bytes
+            }
+            DHMessage::RekeyFinished { verify_data } => {
+                let mut bytes = vec![24];
+                bytes.extend(verify_data);
+                bytes
+            }
+            DHMessage::ClientPublicKeyFixed { key } => {
+                let mut bytes = vec![25];
+                bytes.extend(key);
+                bytes
+            }
+            DHMessage::ServerPublicKeyFixed { key } => {
+                let mut bytes = vec![27];
+                bytes.extend(key);
+                bytes
+            }
+            DHMessage::Alert { code, description } => {
+                // Longer descriptions are cut at a character boundary
+                let mut len = description.len().min(u8::MAX as usize);
+                while !description.is_char_boundary(len) {
+                    len -= 1;
+                }
+                let mut bytes = vec![28, *code, len as u8];
+                bytes.extend(&description.as_bytes()[..len]);
+                bytes
+            }
+            DHMessage::ServerHelloResume { cipher, version, random } => {
+                let mut bytes = vec![29, *cipher, *version];
+                bytes.extend(random);
+                bytes
+            }
+            DHMessage::NewSessionTicket { lifetime, ticket } => {
+                let mut bytes = vec![30];
+                bytes.extend(lifetime.to_be_bytes());
+                serialize_bytes(&mut bytes, ticket);
+                bytes
+            }
+            DHMessage::ClientParams { p, g } => {
+                let mut bytes = vec![31];
+                serialize_biguint(&mut bytes, p);
+                serialize_biguint(&mut bytes, g);
+                bytes
+            }
+            DHMessage::HelloRetry { cookie } => {
+                let mut bytes = vec![32];
+                serialize_short_bytes(&mut bytes, cookie);
+                bytes
+            }
+            DHMessage::Grease { kind, payload } => {
+                let mut bytes = vec![*kind];
+                serialize_bytes(&mut bytes, payload);
+                bytes
+            }
+        }
+    }
+
+    /// Protocol version a ServerHello variant selects, or None for other messages
+    pub fn protocol_version(&self) -> Option<u8> {
+        match self {
+            DHMessage::ServerHello { version, .. }
+            | DHMessage::ServerHelloNamed { version, .. }
+            | DHMessage::ServerHelloKex { version, .. }
+            | DHMessage::ServerHelloHmqv { version, .. }
+            | DHMessage::ServerHelloSrp { version, .. }
+            | DHMessage::ServerHelloPadded { version, .. }
+            | DHMessage::ServerHelloResume { version, .. } => Some(*version),
+            _ => None,
+        }
+    }
+
+    /// Length in bytes of the largest integer or group element the message carries:
+    /// p and g, public keys, key shares in the format of `kex`, and static HMQV keys
+    /// (0 for the other messages)
+    pub fn largest_integer(&self, kex: KexAlgorithm) -> usize {
+        let len = |value: &BigUint| value.bits().div_ceil(8) as usize;
+        match self {
+            DHMessage::ServerHello { p, g, .. } | DHMessage::ClientParams { p, g } => len(p).max(len(g)),
+            DHMessage::ClientPublicKey { x } => len(x),
+            DHMessage::ServerPublicKey { y } => len(y),
+            DHMessage::ClientPublicKeyFixed { key } | DHMessage::ServerPublicKeyFixed { key } => key.len(),
+            DHMessage::ClientKeyShare { key } | DHMessage::ServerKeyShare { key } => kex.largest_integer(key),
+            DHMessage::StaticKey { key } => key.len(),
+            _ => 0,
         }
     }
 
+    /// Build a GREASE message with a random reserved type and random payload
+    pub fn grease() -> Self {
+        with_rng(RngPurpose::Nonce, |rng| {
+            let kind = (rng.gen_range(0..16u8) << 4) | 0x0A;
+            let payload = (0..rng.gen_range(0..16)).map(|_| rng.r#gen()).collect();
+            DHMessage::Grease { kind, payload }
+        })
+    }
+
     /// Deserialize message from bytes
     pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
         if bytes.is_empty() {
             return None;
         }
 
-        let mut cursor = 1;
+        let cursor = 1;
 
         match bytes[0] {
-            0 => Some(DHMessage::ClientHello),
+            0 => {
+                let count = *bytes.get(cursor)? as usize;
+                let kex_algorithms = bytes.get(cursor + 1..cursor + 1 + count)?.to_vec();
+                let cursor = cursor + 1 + count;
+                let count = *bytes.get(cursor)? as usize;
+                let ciphers = bytes.get(cursor + 1..cursor + 1 + count)?.to_vec();
+                let cursor = cursor + 1 + count;
+                let count = *bytes.get(cursor)? as usize;
+                let nonce = bytes.get(cursor + 1..cursor + 1 + count)?.to_vec();
+                let cursor = cursor + 1 + count;
+                let count = *bytes.get(cursor)? as usize;
+                let versions = bytes.get(cursor + 1..cursor + 1 + count)?.to_vec();
+                let cursor = cursor + 1 + count;
+                let count = u16::from_be_bytes(bytes.get(cursor..cursor + 2)?.try_into().ok()?) as usize;
+                let groups = bytes
+                    .get(cursor + 2..cursor + 2 + 2 * count)?
+                    .chunks_exact(2)
+                    .map(|id| u16::from_be_bytes([id[0], id[1]]))
+                    .collect();
+                let (ticket, cursor) = deserialize_short_bytes(bytes, cursor + 2 + 2 * count)?;
+                let random = bytes.get(cursor..cursor + 32)?.try_into().ok()?;
+                let (cookie, _) = deserialize_short_bytes(bytes, cursor + 32)?;
+                Some(DHMessage::ClientHello { kex_algorithms, ciphers, nonce, versions, groups, ticket, random, cookie })
+            }
             1 => {
-                let (p, new_cursor) = deserialize_bigint(&bytes, cursor)?;
-                let (g, _) = deserialize_bigint(&bytes, new_cursor)?;
-                Some(DHMessage::ServerHello { p, g })
+                let (p, new_cursor) = deserialize_biguint(bytes, cursor)?;
+                let (g, new_cursor) = deserialize_biguint(bytes, new_cursor)?;
+                let cipher = *bytes.get(new_cursor)?;
+                let version = *bytes.get(new_cursor + 1)?;
+                let random = bytes.get(new_cursor + 2..new_cursor + 34)?.try_into().ok()?;
+                Some(DHMessage::ServerHello { p, g, cipher, version, random })
             }
             2 => {
-                let (x, _) = deserialize_bigint(&bytes, cursor)?;
+                let (x, _) = deserialize_biguint(bytes, cursor)?;
                 Some(DHMessage::ClientPublicKey { x })
             }
             3 => {
-                let (y, _) = deserialize_bigint(&bytes, cursor)?;
+                let (y, _) = deserialize_biguint(bytes, cursor)?;
                 Some(DHMessage::ServerPublicKey { y })
             }
             4 => Some(DHMessage::Done),
+            5 => {
+                let group = bytes.get(cursor..cursor + 2)?;
+                Some(DHMessage::ServerHelloNamed {
+                    group: u16::from_be_bytes([group[0], group[1]]),
+                    cipher: *bytes.get(cursor + 2)?,
+                    version: *bytes.get(cursor + 3)?,
+                    random: bytes.get(cursor + 4..cursor + 36)?.try_into().ok()?,
+                })
+            }
+            6 => Some(DHMessage::ServerHelloKex {
+                algorithm: *bytes.get(cursor)?,
+                cipher: *bytes.get(cursor + 1)?,
+                version: *bytes.get(cursor + 2)?,
+                random: bytes.get(cursor + 3..cursor + 35)?.try_into().ok()?,
+            }),
+            7 => {
+                let (key, _) = deserialize_bytes(bytes, cursor)?;
+                Some(DHMessage::ClientKeyShare { key })
+            }
+            8 => {
+                let (key, _) = deserialize_bytes(bytes, cursor)?;
+                Some(DHMessage::ServerKeyShare { key })
+            }
+            9 => Some(DHMessage::Abort {
+                reason: *bytes.get(cursor)?,
+            }),
+            11 => {
+                let scheme = *bytes.get(cursor)?;
+                let (public_key, new_cursor) = deserialize_bytes(bytes, cursor + 1)?;
+                let (signature, _) = deserialize_bytes(bytes, new_cursor)?;
+                if public_key.len() > MAX_SIGNATURE_FIELD || signature.len() > MAX_SIGNATURE_FIELD {
+                    return None;
+                }
+                Some(DHMessage::ServerSignature { scheme, public_key, signature })
+            }
+            12 => Some(DHMessage::ClientFinished {
+                verify_data: bytes.get(cursor..cursor + 32)?.try_into().ok()?,
+            }),
+            13 => Some(DHMessage::ServerFinished {
+                verify_data: bytes.get(cursor..cursor + 32)?.try_into().ok()?,
+            }),
+            14 => {
+                let group = bytes.get(cursor..cursor + 2)?;
+                Some(DHMessage::ServerHelloHmqv {
+                    group: u16::from_be_bytes([group[0], group[1]]),
+                    cipher: *bytes.get(cursor + 2)?,
+                    version: *bytes.get(cursor + 3)?,
+                    random: bytes.get(cursor + 4..cursor + 36)?.try_into().ok()?,
+                })
+            }
+            15 => {
+                let (ciphertext, _) = deserialize_bytes(bytes, cursor)?;
+                if ciphertext.len() > MAX_ENCRYPTED_HANDSHAKE {
+                    return None;
+                }
+                Some(DHMessage::EncryptedHandshake { ciphertext })
+            }
+            16 => {
+                let (key, _) = deserialize_bytes(bytes, cursor)?;
+                Some(DHMessage::StaticKey { key })
+            }
+            17 => {
+                let (publication, _) = deserialize_bytes(bytes, cursor)?;
+                if publication.len() > MAX_PREKEY_MESSAGE {
+                    return None;
+                }
+                Some(DHMessage::PrekeyUpload { publication })
+            }
+            18 => Some(DHMessage::PrekeyRequest {
+                identity: bytes.get(cursor..cursor + 32)?.try_into().ok()?,
+            }),
+            19 => {
+                let (bundle, _) = deserialize_bytes(bytes, cursor)?;
+                if bundle.len() > MAX_PREKEY_MESSAGE {
+                    return None;
+                }
+                Some(DHMessage::PrekeyResponse { bundle })
+            }
+            20 => {
+                let group = bytes.get(cursor..cursor + 2)?;
+                Some(DHMessage::ServerHelloSrp {
+                    group: u16::from_be_bytes([group[0], group[1]]),
+                    cipher: *bytes.get(cursor + 2)?,
+                    version: *bytes.get(cursor + 3)?,
+                    random: bytes.get(cursor + 4..cursor + 36)?.try_into().ok()?,
+                })
+            }
+            21 => {
+                let group = bytes.get(cursor..cursor + 2)?;
+                Some(DHMessage::ServerHelloPadded {
+                    group: u16::from_be_bytes([group[0], group[1]]),
+                    cipher: *bytes.get(cursor + 2)?,
+                    version: *bytes.get(cursor + 3)?,
+                    random: bytes.get(cursor + 4..cursor + 36)?.try_into().ok()?,
+                })
+            }
+            22 => {
+                let (certificate, _) = deserialize_bytes(bytes, cursor)?;
+                if certificate.len() > MAX_PRIME_CERTIFICATE {
+                    return None;
+                }
+                Some(DHMessage::PrimeCertificate { certificate })
+            }
+            23 => {
+                let (public_key, _) = deserialize_bytes(bytes, cursor)?;
+                Some(DHMessage::Rekey { public_key })
+            }
+            24 => Some(DHMessage::RekeyFinished {
+                verify_data: bytes.get(cursor..cursor + 32)?.try_into().ok()?,
+            }),
+            // The key runs to the end; its width is checked against the negotiated group
+            // (26 is a GREASE type)
+            25 | 27 => {
+                let key = bytes.get(cursor..).filter(|key| !key.is_empty())?.to_vec();
+                Some(match bytes[0] {
+                    25 => DHMessage::ClientPublicKeyFixed { key },
+                    _ => DHMessage::ServerPublicKeyFixed { key },
+                })
+            }
+            28 => {
+                let code = *bytes.get(cursor)?;
+                let len = *bytes.get(cursor + 1)? as usize;
+                let description = bytes.get(cursor + 2..cursor + 2 + len)?;
+                Some(DHMessage::Alert {
+                    code,
+                    description: String::from_utf8(description.to_vec()).ok()?,
+                })
+            }
+            29 => Some(DHMessage::ServerHelloResume {
+                cipher: *bytes.get(cursor)?,
+                version: *bytes.get(cursor + 1)?,
+                random: bytes.get(cursor + 2..cursor + 34)?.try_into().ok()?,
+            }),
+            30 => {
+                let lifetime = u32::from_be_bytes(bytes.get(cursor..cursor + 4)?.try_into().ok()?);
+                let (ticket, _) = deserialize_bytes(bytes, cursor + 4)?;
+                if ticket.len() > u16::MAX as usize {
+                    return None;
+                }
+                Some(DHMessage::NewSessionTicket { lifetime, ticket })
+            }
+            31 => {
+                let (p, new_cursor) = deserialize_biguint(bytes, cursor)?;
+                let (g, _) = deserialize_biguint(bytes, new_cursor)?;
+                Some(DHMessage::ClientParams { p, g })
+            }
+            32 => {
+                let (cookie, _) = deserialize_short_bytes(bytes, cursor)?;
+                Some(DHMessage::HelloRetry { cookie })
+            }
+            kind if is_grease_type(kind) => {
+                let (payload, _) = deserialize_bytes(bytes, cursor)?;
+                if payload.len() > MAX_GREASE_PAYLOAD {
+                    return None;
+                }
+                Some(DHMessage::Grease { kind, payload })
+            }
             _ => None,
         }
     }
 }
 
-/// Serialize a BigInt to bytes with length prefix
-fn serialize_bigint(bytes: &mut Vec<u8>, value: &BigInt) {
-    let value_bytes = value.to_bytes_be();
-    let len = value_bytes.1.len() as u32;
+/// serde adapter carrying a BigUint as its big-endian bytes, or as lowercase hex in
+/// human-readable formats, with the same canonical form the native encoding requires
+/// (see `deserialize_biguint`)
+mod biguint_bytes {
+    use num_bigint::BigUint;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &BigUint, serializer: S) -> Result<S::Ok, S::Error> {
+        match serializer.is_human_readable() {
+            true => serializer.serialize_str(&value.to_str_radix(16)),
+            false => serializer.serialize_bytes(&value.to_bytes_be()),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BigUint, D::Error> {
+        if deserializer.is_human_readable() {
+            let hex = String::deserialize(deserializer)?;
+            return BigUint::parse_bytes(hex.as_bytes(), 16)
+                .filter(|value| value.to_str_radix(16) == hex && hex != "0")
+                .ok_or_else(|| D::Error::custom("non-canonical integer encoding"));
+        }
+        let bytes = serde_bytes::ByteBuf::deserialize(deserializer)?;
+        if bytes.first().is_none_or(|&byte| byte == 0) {
+            return Err(D::Error::custom("non-canonical integer encoding"));
+        }
+        Ok(BigUint::from_bytes_be(&bytes))
+    }
+}
+
+/// serde adapter carrying byte fields as byte strings, or as lowercase hex in
+/// human-readable formats
+mod byte_field {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::crypto::transcript::{from_hex, to_hex};
+
+    pub fn serialize<T: AsRef<[u8]>, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        match serializer.is_human_readable() {
+            true => serializer.serialize_str(&to_hex(value.as_ref())),
+            false => serializer.serialize_bytes(value.as_ref()),
+        }
+    }
+
+    pub fn deserialize<'de, T: TryFrom<Vec<u8>>, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+        let bytes = match deserializer.is_human_readable() {
+            true => from_hex(&String::deserialize(deserializer)?).ok_or_else(|| D::Error::custom("invalid hex"))?,
+            false => serde_bytes::ByteBuf::deserialize(deserializer)?.into_vec(),
+        };
+        T::try_from(bytes).map_err(|_| D::Error::custom("byte field of the wrong length"))
+    }
+}
+
+/// Serialize an unsigned integer to bytes with length prefix
+fn serialize_biguint(bytes: &mut Vec<u8>, value: &BigUint) {
+    serialize_bytes(bytes, &value.to_bytes_be());
+}
+
+/// Serialize a byte string with length prefix
+fn serialize_bytes(bytes: &mut Vec<u8>, value: &[u8]) {
+    let len = value.len() as u32;
+    bytes.extend(len.to_be_bytes());
+    bytes.extend(value);
+}
+
+/// Serialize a byte string with a u16 length prefix, for fields that stay short
+/// (tickets and cookies)
+fn serialize_short_bytes(bytes: &mut Vec<u8>, value: &[u8]) {
+    let len = u16::try_from(value.len()).expect("short fields hold at most 65535 bytes");
     bytes.extend(len.to_be_bytes());
-    bytes.extend(&value_bytes.1);
+    bytes.extend(value);
 }
 
-/// Deserialize a BigInt from bytes with length prefix
-fn deserialize_bigint(bytes: &[u8], cursor: usize) -> Option<(BigInt, usize)> {
+/// Deserialize an unsigned integer from bytes with length prefix
+///
+/// Only the canonical encoding is accepted: at least one byte and no leading zero
+/// byte. That also rejects zero, which is never a valid modulus, generator or
+/// public key, and leaves every value exactly one encoding for the transcript.
+fn deserialize_biguint(bytes: &[u8], cursor: usize) -> Option<(BigUint, usize)> {
+    let (value_bytes, new_cursor) = deserialize_bytes(bytes, cursor)?;
+    if value_bytes.first().is_none_or(|&byte| byte == 0) {
+        return None;
+    }
+    Some((BigUint::from_bytes_be(&value_bytes), new_cursor))
+}
+
+/// Deserialize a byte string from bytes with length prefix
+fn deserialize_bytes(bytes: &[u8], cursor: usize) -> Option<(Vec<u8>, usize)> {
     if cursor + 4 > bytes.len() {
         return None;
     }
@@ -111,12 +950,18 @@ fn deserialize_bigint(bytes: &[u8], cursor: usize) -> Option<(BigInt, usize)> {
         return None;
     }
 
-    let value_bytes = &bytes[cursor + 4..cursor + 4 + len];
-    let value = BigInt::from_bytes_be(num_bigint::Sign::Plus, value_bytes);
+    let value = bytes[cursor + 4..cursor + 4 + len].to_vec();
 
     Some((value, cursor + 4 + len))
 }
 
+/// Deserialize a byte string written by `serialize_short_bytes`
+fn deserialize_short_bytes(bytes: &[u8], cursor: usize) -> Option<(Vec<u8>, usize)> {
+    let len = u16::from_be_bytes(bytes.get(cursor..cursor + 2)?.try_into().ok()?) as usize;
+    let value = bytes.get(cursor + 2..cursor + 2 + len)?.to_vec();
+    Some((value, cursor + 2 + len))
+}
+
 /// Manages a Diffie-Hellman key exchange connection with a client
 #[derive(Debug)]
 pub struct DHConnection {
@@ -137,6 +982,39 @@ pub struct DHConnection {
 
     /// Computed shared secret (X^secret_exponent mod p)
     pub shared_secret: Option<BigInt>,
+
+    /// Symmetric keys derived from the shared secret and transcript
+    pub session_keys: Option<SessionKeys>,
+
+    /// Key-exchange algorithm negotiated with the client
+    pub algorithm: KexAlgorithm,
+
+    /// Named group negotiated with the client, or None for explicit parameters and
+    /// algorithms without a finite-field group
+    pub group: Option<DhGroup>,
+
+    /// Record-layer cipher negotiated with the client
+    pub cipher: CipherSuite,
+
+    /// Key derivation function negotiated with the client
+    pub kdf: Kdf,
+
+    /// Protocol version negotiated with the client
+    pub version: u8,
+
+    /// Encoding of the messages after ClientHello, negotiated with the client
+    pub codec: Codec,
+
+    /// Running hash of the handshake messages exchanged with the client
+    pub transcript: Transcript,
+
+    /// When to rekey the session, or None if the client cannot rekey
+    pub rekey: Option<RekeyPolicy>,
+    /// Padding of what we encrypt, or None if the client did not ask for padding
+    pub padding: Option<Padding>,
+    /// Identity the client authenticates as (an SRP username or HMQV static key), or
+    /// None for anonymous clients; proven only once its Finished verifies
+    pub client_identity: Option<String>,
 }
 
 impl DHConnection {
@@ -154,6 +1032,17 @@ impl DHConnection {
             secret_exponent,
             client_public_key: None,
             shared_secret: None,
+            session_keys: None,
+            algorithm: KexAlgorithm::FiniteField,
+            group: None,
+            cipher: CipherSuite::Aes256Gcm,
+            kdf: Kdf::Hkdf,
+            version: PROTOCOL_VERSIONS[0],
+            codec: Codec::Native,
+            transcript: Transcript::new(),
+            rekey: None,
+            padding: None,
+            client_identity: None,
         }
     }
 
@@ -161,4 +1050,17 @@ impl DHConnection {
     pub fn peer_addr(&self) -> std::io::Result<std::net::SocketAddr> {
         self.stream.peer_addr()
     }
+
+    /// SHA-256 of the handshake transcript through Done, available once the session
+    /// keys, which are derived from it, are
+    pub fn transcript_hash(&self) -> Option<[u8; 32]> {
+        self.session_keys.as_ref().map(|_| self.transcript.hash())
+    }
+
+    /// Channel binding value for this session, available once the shared secret is computed
+    pub fn channel_binding(&self) -> Option<[u8; 32]> {
+        self.shared_secret
+            .as_ref()
+            .map(|secret| channel_binding(secret, &self.transcript.hash()))
+    }
 }
@@ -1 +1,2 @@
+#[allow(non_snake_case)]
 pub mod DH_Prot;
@@ -0,0 +1,48 @@
+//! Stored benchmark reports and the comparison behind `dhke bench compare`
+
+use rust_dhke::network::bench::{BenchReport, BenchResult};
+
+fn report(timings: &[(&str, u64)]) -> BenchReport {
+    BenchReport {
+        results: timings
+            .iter()
+            .map(|&(name, median_ns)| BenchResult {
+                name: name.to_string(),
+                iterations: 10,
+                median_ns,
+                min_ns: median_ns / 2,
+            })
+            .collect(),
+    }
+}
+
+#[test]
+fn reports_survive_a_json_round_trip() {
+    let path = std::env::temp_dir().join(format!("dhke-bench-{}.json", std::process::id()));
+    let stored = report(&[("mod_pow/ffdhe2048", 10_000_000), ("handshake/ffdhe2048", 150_000_000)]);
+    stored.store(&path).expect("report is written");
+    let loaded = BenchReport::load(&path);
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(loaded.expect("report is read back"), stored);
+}
+
+#[test]
+fn comparison_reports_the_slowdown_of_shared_operations() {
+    let old = report(&[("mod_pow/ffdhe2048", 10_000_000), ("prime_generation/512", 200_000_000), ("retired", 1)]);
+    let new = report(&[("mod_pow/ffdhe2048", 11_500_000), ("prime_generation/512", 150_000_000), ("added", 1)]);
+
+    let comparisons = old.compare(&new);
+    let names: Vec<&str> = comparisons.iter().map(|comparison| comparison.name.as_str()).collect();
+    assert_eq!(names, ["mod_pow/ffdhe2048", "prime_generation/512"]);
+    assert!((comparisons[0].slowdown_percent() - 15.0).abs() < 1e-9);
+    assert!((comparisons[1].slowdown_percent() + 25.0).abs() < 1e-9);
+}
+
+#[test]
+fn malformed_reports_are_invalid_data() {
+    let path = std::env::temp_dir().join(format!("dhke-bench-malformed-{}.json", std::process::id()));
+    std::fs::write(&path, b"{\"results\": 3}").expect("file is written");
+    let error = BenchReport::load(&path).expect_err("report is rejected");
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,90 @@
+//! Proof-of-work puzzles on HelloRetry cookies: what the cookie key accepts, how the
+//! difficulty scales with load, and handshakes against servers that set them
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::thread;
+use std::time::Duration;
+
+use rust_dhke::crypto::cookie::{answer_hello_retry, CookieKey, PuzzlePolicy, MAX_PUZZLE_DIFFICULTY};
+use rust_dhke::crypto::groups::DhGroup;
+use rust_dhke::network::client::DHClient;
+use rust_dhke::network::server::{DHServer, KeyMode, ParamSource};
+
+const PEER: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+const RANDOM: [u8; 32] = [7; 32];
+
+/// Run a handshake against a server on `server_addr` whose puzzles take `difficulty`
+/// leading zero bits, then shut the server down
+fn handshake(server_addr: &str, difficulty: u8) -> std::io::Result<()> {
+    let server = DHServer::new(server_addr, ParamSource::Group(DhGroup::Ffdhe2048), None, KeyMode::Ephemeral)
+        .expect("server binds")
+        .with_client_puzzle(PuzzlePolicy {
+            min_difficulty: difficulty,
+            max_difficulty: difficulty,
+            busy_connections: 1,
+        });
+    let cancel = server.cancel_token();
+    let server_thread = thread::spawn(move || server.run());
+
+    let mut client = DHClient::new(server_addr).expect("client connects");
+    let result = client.perform_key_exchange().map(|_| ());
+    if result.is_ok() {
+        client.close().expect("close is confirmed");
+    }
+
+    cancel.cancel();
+    server_thread.join().expect("server thread does not panic").expect("server shuts down cleanly");
+    result
+}
+
+#[test]
+fn solved_puzzles_are_accepted() {
+    let key = CookieKey::generate(Duration::from_secs(30));
+    for difficulty in [0, 1, 12] {
+        let cookie = key.issue(PEER, &RANDOM, difficulty);
+        let echoed = answer_hello_retry(&cookie).expect("puzzle is solvable");
+        assert!(key.verify(&echoed, PEER, &RANDOM), "difficulty {} solution rejected", difficulty);
+    }
+}
+
+#[test]
+fn unsolved_and_weakened_puzzles_are_rejected() {
+    let key = CookieKey::generate(Duration::from_secs(30));
+    let cookie = key.issue(PEER, &RANDOM, 16);
+    assert!(!key.verify(&cookie, PEER, &RANDOM), "cookie echoed without a solution");
+
+    // A zero solution solves a 16-bit puzzle with probability 2^-16
+    let unsolved = [cookie.as_slice(), &[0; 8]].concat();
+    assert!(!key.verify(&unsolved, PEER, &RANDOM), "cookie echoed with a wrong solution");
+
+    // Lowering the difficulty in the clear breaks the MAC
+    let mut weakened = cookie.clone();
+    weakened[8] = 0;
+    assert!(!key.verify(&weakened, PEER, &RANDOM), "cookie with its difficulty lowered");
+
+    let echoed = answer_hello_retry(&cookie).expect("puzzle is solvable");
+    assert!(!key.verify(&echoed, PEER, &[8; 32]), "solution for another random");
+}
+
+#[test]
+fn difficulty_rises_with_established_connections() {
+    let policy = PuzzlePolicy {
+        min_difficulty: 8,
+        max_difficulty: 20,
+        busy_connections: 12,
+    };
+    let difficulties: Vec<u8> = [0, 6, 12, 100].into_iter().map(|established| policy.difficulty(established)).collect();
+    assert_eq!(difficulties, [8, 14, 20, 20]);
+}
+
+#[test]
+fn clients_refuse_puzzles_harder_than_the_maximum() {
+    let key = CookieKey::generate(Duration::from_secs(30));
+    assert_eq!(answer_hello_retry(&key.issue(PEER, &RANDOM, MAX_PUZZLE_DIFFICULTY + 1)), None);
+    handshake("127.0.0.1:18489", MAX_PUZZLE_DIFFICULTY + 1).expect_err("client gives up on the puzzle");
+}
+
+#[test]
+fn handshake_completes_after_solving_the_puzzle() {
+    handshake("127.0.0.1:18488", 10).expect("handshake completes");
+}
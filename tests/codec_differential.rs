@@ -0,0 +1,210 @@
+//! Differential tests of the message codecs against the native encoding
+//!
+//! The native layout of `DHMessage::to_bytes` is the legacy format every peer
+//! speaks; bincode, CBOR, protobuf and JSON are the codecs a peer can move to.
+//! Random messages must come out of every codec exactly as they come out of the
+//! native one, and every byte stream fed to a native reader must either decode to a
+//! message all codecs carry unchanged or be rejected in one of the classified ways.
+//! Inputs come from fixed seeds, so a failing case reproduces from its number.
+
+use std::io::Cursor;
+
+use num_bigint::BigUint;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rust_dhke::crypto::kex::KexAlgorithm;
+use rust_dhke::network::framing::{self, read_message_within, Codec, LimitExceeded, MessageLimits};
+use rust_dhke::structs::DH_Prot::DHMessage;
+
+/// Random messages checked through every codec
+const MESSAGE_CASES: u64 = 2000;
+
+/// Corrupted native streams fed to the reader
+const STREAM_CASES: u64 = 5000;
+
+/// What a native reader made of a byte stream
+#[derive(Debug)]
+enum Outcome {
+    Decoded(DHMessage),
+    /// A frame that is empty or does not decode (`Ok(None)`)
+    Undecodable,
+    /// A frame or field over the message limits
+    OverLimit,
+    /// The stream ended inside a frame
+    Truncated,
+}
+
+fn rng_for(case: u64) -> ChaCha8Rng {
+    ChaCha8Rng::seed_from_u64(case)
+}
+
+fn bytes(rng: &mut impl Rng, max_len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; rng.gen_range(0..=max_len)];
+    rng.fill_bytes(&mut bytes);
+    bytes
+}
+
+fn nonempty_bytes(rng: &mut impl Rng, max_len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; rng.gen_range(1..=max_len)];
+    rng.fill_bytes(&mut bytes);
+    bytes
+}
+
+fn array(rng: &mut impl Rng) -> [u8; 32] {
+    let mut array = [0; 32];
+    rng.fill_bytes(&mut array);
+    array
+}
+
+/// A nonzero integer of up to 256 bytes, the only kind the native encoding carries
+fn integer(rng: &mut impl Rng) -> BigUint {
+    let mut bytes = nonempty_bytes(rng, 256);
+    bytes[0] = rng.gen_range(1..=u8::MAX);
+    BigUint::from_bytes_be(&bytes)
+}
+
+fn ascii(rng: &mut impl Rng, max_len: usize) -> String {
+    (0..rng.gen_range(0..=max_len)).map(|_| rng.gen_range(' '..='~')).collect()
+}
+
+/// A random message of any type, within the limits of the native encoding
+fn random_message(rng: &mut impl Rng) -> DHMessage {
+    match rng.gen_range(0..32) {
+        0 => DHMessage::ClientHello {
+            kex_algorithms: bytes(rng, 8),
+            ciphers: bytes(rng, 8),
+            nonce: bytes(rng, 32),
+            versions: bytes(rng, 4),
+            groups: (0..rng.gen_range(0..6)).map(|_| rng.r#gen()).collect(),
+            ticket: bytes(rng, 512),
+            random: array(rng),
+            cookie: bytes(rng, 300),
+        },
+        1 => DHMessage::ServerHello {
+            p: integer(rng),
+            g: integer(rng),
+            cipher: rng.r#gen(),
+            version: rng.r#gen(),
+            random: array(rng),
+        },
+        2 => DHMessage::ServerHelloNamed { group: rng.r#gen(), cipher: rng.r#gen(), version: rng.r#gen(), random: array(rng) },
+        3 => DHMessage::ClientPublicKey { x: integer(rng) },
+        4 => DHMessage::ServerPublicKey { y: integer(rng) },
+        5 => DHMessage::Done,
+        6 => DHMessage::ServerHelloKex { algorithm: rng.r#gen(), cipher: rng.r#gen(), version: rng.r#gen(), random: array(rng) },
+        7 => DHMessage::ClientKeyShare { key: bytes(rng, 256) },
+        8 => DHMessage::ServerKeyShare { key: bytes(rng, 256) },
+        9 => DHMessage::Abort { reason: rng.r#gen() },
+        10 => DHMessage::ServerSignature { scheme: rng.r#gen(), public_key: bytes(rng, 64), signature: bytes(rng, 512) },
+        11 => DHMessage::ClientFinished { verify_data: array(rng) },
+        12 => DHMessage::ServerFinished { verify_data: array(rng) },
+        13 => DHMessage::ServerHelloHmqv { group: rng.r#gen(), cipher: rng.r#gen(), version: rng.r#gen(), random: array(rng) },
+        14 => DHMessage::EncryptedHandshake { ciphertext: bytes(rng, 1024) },
+        15 => DHMessage::StaticKey { key: bytes(rng, 256) },
+        16 => DHMessage::PrekeyUpload { publication: bytes(rng, 1024) },
+        17 => DHMessage::PrekeyRequest { identity: array(rng) },
+        18 => DHMessage::PrekeyResponse { bundle: bytes(rng, 1024) },
+        19 => DHMessage::ServerHelloSrp { group: rng.r#gen(), cipher: rng.r#gen(), version: rng.r#gen(), random: array(rng) },
+        20 => DHMessage::ServerHelloPadded { group: rng.r#gen(), cipher: rng.r#gen(), version: rng.r#gen(), random: array(rng) },
+        21 => DHMessage::PrimeCertificate { certificate: bytes(rng, 1024) },
+        22 => DHMessage::Rekey { public_key: bytes(rng, 256) },
+        23 => DHMessage::RekeyFinished { verify_data: array(rng) },
+        24 => DHMessage::ClientPublicKeyFixed { key: nonempty_bytes(rng, 256) },
+        25 => DHMessage::ServerPublicKeyFixed { key: nonempty_bytes(rng, 256) },
+        26 => DHMessage::Alert { code: rng.r#gen(), description: ascii(rng, 64) },
+        27 => DHMessage::ServerHelloResume { cipher: rng.r#gen(), version: rng.r#gen(), random: array(rng) },
+        28 => DHMessage::NewSessionTicket { lifetime: rng.r#gen(), ticket: bytes(rng, 512) },
+        29 => DHMessage::ClientParams { p: integer(rng), g: integer(rng) },
+        30 => DHMessage::HelloRetry { cookie: bytes(rng, 300) },
+        _ => DHMessage::Grease { kind: (rng.gen_range(0..16u8) << 4) | 0x0A, payload: bytes(rng, 255) },
+    }
+}
+
+/// A native frame of a random message, damaged in one random way
+fn corrupted_stream(rng: &mut impl Rng) -> Vec<u8> {
+    let mut stream = framing::encode(Codec::Native, &random_message(rng));
+    match rng.gen_range(0..6) {
+        0 => {
+            let at = rng.gen_range(0..stream.len());
+            stream[at] ^= rng.gen_range(1..=u8::MAX);
+        }
+        1 => stream.truncate(rng.gen_range(0..stream.len())),
+        2 => stream.extend(nonempty_bytes(rng, 64)),
+        3 => {
+            let len = rng.gen_range(0..=2 * MessageLimits::default().max_frame) as u32;
+            stream[..4].copy_from_slice(&len.to_be_bytes());
+        }
+        4 => {
+            // A different message type over the same payload
+            stream[4] = rng.r#gen();
+        }
+        _ => stream = bytes(rng, 512),
+    }
+    stream
+}
+
+/// Read one message from `stream` with the native codec, classifying any failure
+fn read_native(stream: &[u8]) -> Outcome {
+    let read = read_message_within(&mut Cursor::new(stream), Codec::Native, &MessageLimits::default(), KexAlgorithm::FiniteField);
+    match read {
+        Ok(Some(message)) => Outcome::Decoded(message),
+        Ok(None) => Outcome::Undecodable,
+        Err(error) if LimitExceeded::from_error(&error).is_some() => Outcome::OverLimit,
+        Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => Outcome::Truncated,
+        Err(error) => panic!("unclassified error reading {:02x?}: {}", stream, error),
+    }
+}
+
+#[test]
+fn every_codec_decodes_what_native_decodes() {
+    for case in 0..MESSAGE_CASES {
+        let message = random_message(&mut rng_for(case));
+        let native = Codec::Native.decode(&Codec::Native.encode(&message));
+        assert_eq!(native.as_ref(), Some(&message), "case {}: native encoding does not round-trip", case);
+
+        for &codec in Codec::ALL {
+            let decoded = codec.decode(&codec.encode(&message));
+            assert_eq!(decoded, native, "case {}: {} decodes differently from native", case, codec.name());
+
+            // The reader skips GREASE, so only other messages come back out of a frame
+            if !matches!(message, DHMessage::Grease { .. }) {
+                let frame = framing::encode(codec, &message);
+                let read = read_message_within(&mut Cursor::new(frame), codec, &MessageLimits::default(), KexAlgorithm::FiniteField)
+                    .unwrap_or_else(|error| panic!("case {}: {} frame rejected: {}", case, codec.name(), error));
+                assert_eq!(read, native, "case {}: {} frame reads differently from native", case, codec.name());
+            }
+        }
+    }
+}
+
+#[test]
+fn native_streams_decode_identically_or_are_rejected() {
+    let mut decoded = 0;
+    let mut rejected = 0;
+    for case in 0..STREAM_CASES {
+        let stream = corrupted_stream(&mut rng_for(case));
+        let message = match read_native(&stream) {
+            Outcome::Decoded(message) => message,
+            Outcome::Undecodable | Outcome::OverLimit | Outcome::Truncated => {
+                rejected += 1;
+                continue;
+            }
+        };
+        decoded += 1;
+
+        // Whatever the native reader accepts, every codec must carry unchanged
+        for &codec in Codec::ALL {
+            assert_eq!(
+                codec.decode(&codec.encode(&message)).as_ref(),
+                Some(&message),
+                "case {}: {} changes {:?}, decoded from {:02x?}",
+                case,
+                codec.name(),
+                message,
+                stream
+            );
+        }
+    }
+    // Both paths must actually be exercised
+    assert!(decoded > 0 && rejected > 0, "decoded {}, rejected {}", decoded, rejected);
+}
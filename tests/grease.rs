@@ -0,0 +1,77 @@
+//! GREASE messages: decoded by every codec, skipped by the reader, and kept out of
+//! the transcript, so peers that send them interoperate with peers that do not
+//!
+//! The reader hands GREASE to no one, so neither side can record it. The handshakes
+//! with GREASE on one side only check that: they would fail at Finished if either
+//! side's transcript held the GREASE messages it sent or received.
+
+use std::thread;
+
+use rust_dhke::crypto::groups::DhGroup;
+use rust_dhke::network::client::DHClient;
+use rust_dhke::network::framing::{encode, read_message, Codec};
+use rust_dhke::network::server::{DHServer, KeyMode, ParamSource};
+use rust_dhke::structs::DH_Prot::{is_grease_type, DHMessage};
+
+/// Run a handshake between a client and a server on `server_addr` with GREASE
+/// enabled as given, then close it
+fn handshake(server_addr: &str, client_grease: bool, server_grease: bool) {
+    let server = DHServer::new(server_addr, ParamSource::Group(DhGroup::Ffdhe2048), None, KeyMode::Ephemeral)
+        .expect("server binds")
+        .with_grease(server_grease);
+    let cancel = server.cancel_token();
+    let server_thread = thread::spawn(move || server.run());
+
+    let mut client = DHClient::new(server_addr).expect("client connects").with_grease(client_grease);
+    let result = client.perform_key_exchange();
+    if result.is_ok() {
+        client.close().expect("close is confirmed");
+    }
+
+    cancel.cancel();
+    server_thread.join().expect("server thread does not panic").expect("server shuts down cleanly");
+    result.expect("handshake completes");
+}
+
+#[test]
+fn handshake_completes_with_grease_on_both_sides() {
+    handshake("127.0.0.1:18481", true, true);
+}
+
+#[test]
+fn handshake_completes_with_grease_from_the_client_only() {
+    handshake("127.0.0.1:18482", true, false);
+}
+
+#[test]
+fn handshake_completes_with_grease_from_the_server_only() {
+    handshake("127.0.0.1:18483", false, true);
+}
+
+#[test]
+fn grease_decodes_and_is_skipped_by_the_reader() {
+    for &codec in Codec::ALL {
+        let grease = DHMessage::grease();
+        let DHMessage::Grease { kind, .. } = &grease else {
+            panic!("DHMessage::grease built {:?}", grease);
+        };
+        assert!(is_grease_type(*kind), "{:#04x} is not a reserved GREASE type", kind);
+        let grease_frame = encode(codec, &grease);
+        let body = match codec {
+            Codec::Json => &grease_frame[..grease_frame.len() - 1],
+            _ => &grease_frame[4..],
+        };
+        assert_eq!(codec.decode(body), Some(grease.clone()), "{} does not decode GREASE", codec.name());
+
+        let mut wire = grease_frame.clone();
+        wire.extend(encode(codec, &DHMessage::Done));
+        wire.extend(grease_frame);
+        wire.extend(encode(codec, &DHMessage::Done));
+        let mut reader = wire.as_slice();
+        for _ in 0..2 {
+            let message = read_message(&mut reader, codec).expect("message is read");
+            assert_eq!(message, Some(DHMessage::Done), "{} did not skip GREASE", codec.name());
+        }
+        assert!(reader.is_empty(), "{} left bytes unread", codec.name());
+    }
+}
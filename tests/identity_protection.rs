@@ -0,0 +1,138 @@
+//! Identities on the wire, with and without identity protection
+//!
+//! Each test runs a server with an Ed25519 identity and a static DH key, and an
+//! HMQV client pinning the server's key, through a relay that records every byte
+//! either side sends. The recording is then searched for the signing key and both
+//! static public keys.
+
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use num_bigint::BigInt;
+use rust_dhke::crypto::crypto::{compute_public_key, ExponentPolicy};
+use rust_dhke::crypto::groups::DhGroup;
+use rust_dhke::crypto::identity::ServerIdentity;
+use rust_dhke::crypto::static_key;
+use rust_dhke::network::client::DHClient;
+use rust_dhke::network::server::{DHServer, KeyMode, ParamSource};
+
+/// Identity material of one handshake, and what went over the wire
+struct Recording {
+    bytes: Vec<u8>,
+    signing_key: Vec<u8>,
+    server_static_key: Vec<u8>,
+    client_static_key: Vec<u8>,
+}
+
+impl Recording {
+    fn contains(&self, needle: &[u8]) -> bool {
+        self.bytes.windows(needle.len()).any(|window| window == needle)
+    }
+}
+
+/// Run one handshake between a fresh server on `server_addr` and a client that
+/// protects identities or not, recording the traffic
+fn record_handshake(server_addr: &str, protect_identities: bool) -> Recording {
+    let dir = std::env::temp_dir().join(format!("dhke-identity-{}-{}", std::process::id(), protect_identities));
+    std::fs::create_dir_all(&dir).expect("temporary directory is writable");
+    let server_key_file = dir.join("server.key");
+    let client_key_file = dir.join("client.key");
+
+    let identity = ServerIdentity::generate();
+    let signing_key = identity.server_key();
+    let server = DHServer::new(
+        server_addr,
+        ParamSource::Group(DhGroup::Ffdhe2048),
+        None,
+        KeyMode::Static(server_key_file.clone()),
+    )
+    .expect("server binds")
+    .with_identity(identity);
+    let cancel = server.cancel_token();
+    let server_thread = thread::spawn(move || server.run());
+
+    let (relay_addr, recorded, relay_thread) = start_relay(server_addr);
+    let mut client = DHClient::new(&relay_addr)
+        .expect("client connects through the relay")
+        .with_static_key(client_key_file.clone())
+        .with_server_key(signing_key.clone())
+        .with_identity_protection(protect_identities);
+    client.perform_key_exchange().expect("handshake completes");
+    client.close().expect("close is confirmed");
+
+    relay_thread.join().expect("relay thread does not panic");
+    cancel.cancel();
+    server_thread.join().expect("server thread does not panic").expect("server shuts down cleanly");
+
+    let bytes = recorded.lock().expect("relay released the recording").clone();
+    let recording = Recording {
+        bytes,
+        signing_key: signing_key.to_bytes(),
+        server_static_key: static_public_key(&server_key_file),
+        client_static_key: static_public_key(&client_key_file),
+    };
+    let _ = std::fs::remove_dir_all(&dir);
+    recording
+}
+
+/// The public key of a static key file for ffdhe2048, as it is encoded on the wire
+fn static_public_key(path: &Path) -> Vec<u8> {
+    let group = DhGroup::Ffdhe2048;
+    let secret = static_key::load_or_generate(path, &group.prime()).expect("static key was stored");
+    let public: BigInt = compute_public_key(&secret, &group.generator(), &group.prime(), ExponentPolicy::Full);
+    public.to_bytes_be().1
+}
+
+/// Forward one connection to `target`, recording both directions
+fn start_relay(target: &str) -> (String, Arc<Mutex<Vec<u8>>>, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("relay binds");
+    let relay_addr = listener.local_addr().expect("relay has an address").to_string();
+    let recorded = Arc::new(Mutex::new(Vec::new()));
+    let target = target.to_string();
+    let recording = Arc::clone(&recorded);
+    let relay = thread::spawn(move || {
+        let (client, _) = listener.accept().expect("client connects");
+        let server = TcpStream::connect(&target).expect("server accepts the relay");
+        let upstream = pump(client.try_clone().expect("socket clones"), server.try_clone().expect("socket clones"), Arc::clone(&recording));
+        let downstream = pump(server, client, recording);
+        upstream.join().expect("pump does not panic");
+        downstream.join().expect("pump does not panic");
+    });
+    (relay_addr, recorded, relay)
+}
+
+/// Copy `from` into `to` until `from` closes, appending everything to `recording`
+fn pump(mut from: TcpStream, mut to: TcpStream, recording: Arc<Mutex<Vec<u8>>>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        from.set_read_timeout(Some(Duration::from_secs(10))).expect("timeout is valid");
+        let mut buffer = [0; 4096];
+        while let Ok(read) = from.read(&mut buffer) {
+            if read == 0 || to.write_all(&buffer[..read]).is_err() {
+                break;
+            }
+            recording.lock().expect("recording is not poisoned").extend_from_slice(&buffer[..read]);
+        }
+        let _ = to.shutdown(Shutdown::Write);
+    })
+}
+
+#[test]
+fn protected_identities_never_appear_in_plaintext() {
+    let recording = record_handshake("127.0.0.1:18471", true);
+    assert!(!recording.bytes.is_empty());
+    assert!(!recording.contains(&recording.signing_key), "signing key sent in the clear");
+    assert!(!recording.contains(&recording.server_static_key), "server static key sent in the clear");
+    assert!(!recording.contains(&recording.client_static_key), "client static key sent in the clear");
+}
+
+#[test]
+fn unprotected_identities_appear_in_plaintext() {
+    let recording = record_handshake("127.0.0.1:18472", false);
+    assert!(recording.contains(&recording.signing_key), "signing key not found in the recording");
+    assert!(recording.contains(&recording.server_static_key), "server static key not found in the recording");
+    assert!(recording.contains(&recording.client_static_key), "client static key not found in the recording");
+}
@@ -0,0 +1,109 @@
+//! Per-identity quotas, enforced against SRP users once their Finished verifies
+//!
+//! Each test runs a server with verifiers for alice and bob, and checks which of
+//! their handshakes end in a RateLimited alert instead of a session.
+
+use std::thread;
+use std::time::Duration;
+
+use rust_dhke::crypto::groups::DhGroup;
+use rust_dhke::crypto::kex::KexAlgorithm;
+use rust_dhke::crypto::srp::SrpVerifierStore;
+use rust_dhke::crypto::ticket::{TicketKey, DEFAULT_TICKET_LIFETIME};
+use rust_dhke::network::alert::{Alert, AlertCode};
+use rust_dhke::network::client::DHClient;
+use rust_dhke::network::quotas::IdentityQuota;
+use rust_dhke::network::server::{DHServer, KeyMode, ParamSource};
+use rust_dhke::network::tickets::TicketStore;
+
+/// Time for the server to notice a closed session before the next handshake
+const CLOSE_SETTLE: Duration = Duration::from_millis(200);
+
+/// Run `clients` against a server on `server_addr` that limits identities by `quota`,
+/// then shut it down
+fn with_quota_server(server_addr: &str, quota: IdentityQuota, clients: impl FnOnce()) {
+    let mut verifiers = SrpVerifierStore::new(DhGroup::Ffdhe2048);
+    verifiers.add("alice", "correct horse").expect("alice is added");
+    verifiers.add("bob", "battery staple").expect("bob is added");
+    let server = DHServer::new(server_addr, ParamSource::Group(DhGroup::Ffdhe2048), None, KeyMode::Ephemeral)
+        .expect("server binds")
+        .with_srp_verifiers(verifiers)
+        .with_ticket_key(TicketKey::generate(DEFAULT_TICKET_LIFETIME))
+        .with_identity_quota(quota);
+    let cancel = server.cancel_token();
+    let server_thread = thread::spawn(move || server.run());
+
+    clients();
+
+    cancel.cancel();
+    server_thread.join().expect("server thread does not panic").expect("server shuts down cleanly");
+}
+
+fn srp_client(server_addr: &str, username: &str, password: &str) -> DHClient {
+    DHClient::new(server_addr)
+        .expect("client connects")
+        .with_kex_algorithms(&[KexAlgorithm::Srp])
+        .with_srp_credentials(username, password)
+}
+
+fn assert_rate_limited<T: std::fmt::Debug>(result: std::io::Result<T>) {
+    let error = result.expect_err("handshake is refused");
+    let alert = Alert::from_error(&error).expect("server sent an alert");
+    assert_eq!(alert.kind(), Some(AlertCode::RateLimited), "unexpected alert: {}", alert);
+}
+
+#[test]
+fn concurrent_sessions_are_limited_per_identity() {
+    let server_addr = "127.0.0.1:18478";
+    let quota = IdentityQuota { max_sessions: Some(1), ..IdentityQuota::default() };
+    with_quota_server(server_addr, quota, || {
+        let mut alice = srp_client(server_addr, "alice", "correct horse");
+        alice.perform_key_exchange().expect("alice's first session is admitted");
+
+        assert_rate_limited(srp_client(server_addr, "alice", "correct horse").perform_key_exchange());
+
+        // Other identities and anonymous clients have their own allowance
+        let mut bob = srp_client(server_addr, "bob", "battery staple");
+        bob.perform_key_exchange().expect("bob is admitted");
+        let mut anonymous = DHClient::new(server_addr).expect("client connects");
+        anonymous.perform_key_exchange().expect("anonymous clients are not limited");
+
+        alice.close().expect("close is confirmed");
+        thread::sleep(CLOSE_SETTLE);
+        let mut again = srp_client(server_addr, "alice", "correct horse");
+        again.perform_key_exchange().expect("alice is admitted once her session closed");
+
+        for client in [&mut again, &mut bob, &mut anonymous] {
+            client.close().expect("close is confirmed");
+        }
+    });
+}
+
+#[test]
+fn handshakes_per_hour_are_limited_per_identity() {
+    let server_addr = "127.0.0.1:18479";
+    let quota = IdentityQuota { max_handshakes_per_hour: Some(2), ..IdentityQuota::default() };
+    with_quota_server(server_addr, quota, || {
+        for _ in 0..2 {
+            let mut alice = srp_client(server_addr, "alice", "correct horse");
+            alice.perform_key_exchange().expect("alice is within her hourly handshakes");
+            alice.close().expect("close is confirmed");
+        }
+        assert_rate_limited(srp_client(server_addr, "alice", "correct horse").perform_key_exchange());
+    });
+}
+
+#[test]
+fn resumed_sessions_keep_their_identity() {
+    let server_addr = "127.0.0.1:18480";
+    let quota = IdentityQuota { max_handshakes_per_hour: Some(1), ..IdentityQuota::default() };
+    with_quota_server(server_addr, quota, || {
+        let tickets = TicketStore::new();
+        let mut alice = srp_client(server_addr, "alice", "correct horse").with_ticket_store(tickets.clone());
+        alice.perform_key_exchange().expect("alice is admitted");
+        alice.close().expect("close is confirmed");
+
+        let mut resumed = DHClient::new(server_addr).expect("client connects").with_ticket_store(tickets);
+        assert_rate_limited(resumed.perform_key_exchange());
+    });
+}
@@ -0,0 +1,141 @@
+//! Message size limits at their boundaries
+//!
+//! Each test reads messages that sit exactly at a non-default limit, which must
+//! decode, and messages one byte over it, which must fail with the limit they broke.
+
+use std::io::Cursor;
+
+use num_bigint::BigUint;
+use rust_dhke::crypto::hmqv::join_share;
+use rust_dhke::crypto::kex::KexAlgorithm;
+use rust_dhke::crypto::obfuscation::FFDH_PADDING_BYTES;
+use rust_dhke::network::framing::{self, read_message_within, Codec, LimitExceeded, MessageLimits};
+use rust_dhke::structs::DH_Prot::DHMessage;
+
+/// Limits for peers using groups of at most 1024 bits: 128-byte integers
+const GROUP_BITS: usize = 1024;
+const MAX_INTEGER: usize = GROUP_BITS / 8;
+
+/// Frame `message` in `codec` and read it back within `limits`
+fn read_back(message: &DHMessage, codec: Codec, limits: &MessageLimits, kex: KexAlgorithm) -> std::io::Result<Option<DHMessage>> {
+    let mut reader = Cursor::new(framing::encode(codec, message));
+    read_message_within(&mut reader, codec, limits, kex)
+}
+
+/// The limit a read ran into, panicking if it succeeded or failed otherwise
+fn exceeded(result: std::io::Result<Option<DHMessage>>) -> LimitExceeded {
+    let error = result.expect_err("a message over the limit is an error");
+    *LimitExceeded::from_error(&error).expect("the error carries the limit")
+}
+
+/// An integer of exactly `len` bytes
+fn integer(len: usize) -> BigUint {
+    BigUint::from_bytes_be(&vec![0xFF; len])
+}
+
+/// [4-byte length][first][value], the layout of SRP key shares
+fn srp_share(first: &[u8], value: &[u8]) -> Vec<u8> {
+    [&(first.len() as u32).to_be_bytes()[..], first, value].concat()
+}
+
+#[test]
+fn public_keys_at_the_integer_limit() {
+    let limits = MessageLimits::for_group_bits(GROUP_BITS);
+    for codec in Codec::ALL.iter().copied() {
+        let at_limit = DHMessage::ClientPublicKey { x: integer(MAX_INTEGER) };
+        let read = read_back(&at_limit, codec, &limits, KexAlgorithm::FiniteField).expect("read succeeds");
+        assert_eq!(read, Some(at_limit), "{} rejected an integer at the limit", codec.name());
+
+        let over = DHMessage::ServerPublicKey { y: integer(MAX_INTEGER + 1) };
+        assert_eq!(
+            exceeded(read_back(&over, codec, &limits, KexAlgorithm::FiniteField)),
+            LimitExceeded::Integer { len: MAX_INTEGER + 1, max: MAX_INTEGER },
+            "{} accepted an integer over the limit",
+            codec.name()
+        );
+    }
+}
+
+#[test]
+fn parameters_at_the_integer_limit() {
+    let limits = MessageLimits::for_group_bits(GROUP_BITS);
+    let at_limit = DHMessage::ClientParams { p: integer(MAX_INTEGER), g: BigUint::from(2u8) };
+    assert!(read_back(&at_limit, Codec::Native, &limits, KexAlgorithm::FiniteField).expect("read succeeds").is_some());
+
+    let over = DHMessage::ClientParams { p: integer(MAX_INTEGER), g: integer(MAX_INTEGER + 1) };
+    assert!(matches!(
+        exceeded(read_back(&over, Codec::Native, &limits, KexAlgorithm::FiniteField)),
+        LimitExceeded::Integer { .. }
+    ));
+}
+
+#[test]
+fn key_shares_are_sized_per_algorithm() {
+    let limits = MessageLimits::for_group_bits(GROUP_BITS);
+    let element = vec![0xFF; MAX_INTEGER];
+    let oversized = vec![0xFF; MAX_INTEGER + 1];
+    let shares = [
+        (KexAlgorithm::Hmqv, join_share(&element, &element), join_share(&element, &oversized)),
+        (KexAlgorithm::Hmqv, element.clone(), oversized.clone()),
+        (KexAlgorithm::Srp, srp_share(b"alice", &element), srp_share(b"alice", &oversized)),
+        (
+            KexAlgorithm::FiniteFieldPadded,
+            vec![0xFF; MAX_INTEGER + FFDH_PADDING_BYTES],
+            vec![0xFF; MAX_INTEGER + 1 + FFDH_PADDING_BYTES],
+        ),
+    ];
+    for (kex, at_limit, over) in shares {
+        let at_limit = DHMessage::ClientKeyShare { key: at_limit };
+        let read = read_back(&at_limit, Codec::Native, &limits, kex).expect("read succeeds");
+        assert_eq!(read, Some(at_limit), "{} share at the limit rejected", kex.name());
+
+        let over = DHMessage::ServerKeyShare { key: over };
+        assert_eq!(
+            exceeded(read_back(&over, Codec::Native, &limits, kex)),
+            LimitExceeded::Integer { len: MAX_INTEGER + 1, max: MAX_INTEGER },
+            "{} share over the limit accepted",
+            kex.name()
+        );
+    }
+
+    // Curve points have a fixed size of their own, whatever the group limit
+    let point = DHMessage::ClientKeyShare { key: vec![0x04; 65] };
+    let tight = MessageLimits::for_group_bits(256);
+    assert!(read_back(&point, Codec::Native, &tight, KexAlgorithm::X25519).expect("read succeeds").is_some());
+}
+
+#[test]
+fn static_keys_at_the_integer_limit() {
+    let limits = MessageLimits::for_group_bits(GROUP_BITS);
+    let at_limit = DHMessage::StaticKey { key: vec![0xFF; MAX_INTEGER] };
+    assert_eq!(limits.check(&at_limit, KexAlgorithm::Hmqv), Ok(()));
+
+    let over = DHMessage::StaticKey { key: vec![0xFF; MAX_INTEGER + 1] };
+    assert_eq!(
+        limits.check(&over, KexAlgorithm::Hmqv),
+        Err(LimitExceeded::Integer { len: MAX_INTEGER + 1, max: MAX_INTEGER })
+    );
+}
+
+#[test]
+fn frames_at_the_frame_limit() {
+    let message = DHMessage::ClientPublicKey { x: integer(64) };
+    let frame_len = Codec::Native.encode(&message).len();
+
+    let at_limit = MessageLimits::default().with_max_frame(frame_len);
+    let read = read_back(&message, Codec::Native, &at_limit, KexAlgorithm::FiniteField).expect("read succeeds");
+    assert_eq!(read, Some(message.clone()));
+
+    let below = MessageLimits::default().with_max_frame(frame_len - 1);
+    assert_eq!(
+        exceeded(read_back(&message, Codec::Native, &below, KexAlgorithm::FiniteField)),
+        LimitExceeded::Frame { max: frame_len - 1 }
+    );
+}
+
+#[test]
+fn malformed_frames_are_not_limit_errors() {
+    let mut reader = Cursor::new([&4u32.to_be_bytes()[..], &[0xEE; 4]].concat());
+    let read = read_message_within(&mut reader, Codec::Native, &MessageLimits::default(), KexAlgorithm::FiniteField);
+    assert!(matches!(read, Ok(None)));
+}
@@ -0,0 +1,123 @@
+//! Interoperability with OpenSSL: parameters and keys cross in both directions and
+//! both implementations derive the same shared secret for fixed keys
+//!
+//! Needs the `openssl` command (1.1.1 or 3.x) on the PATH; without it every test
+//! passes without checking anything, so machines lacking OpenSSL can still run the
+//! suite.
+
+use std::path::Path;
+use std::process::Command;
+
+use num_bigint::{BigInt, Sign};
+use num_traits::Num;
+use rust_dhke::crypto::crypto::validate_dh_params;
+use rust_dhke::crypto::groups::DhGroup;
+use rust_dhke::crypto::kex::{FiniteFieldKeyExchange, KeyExchange};
+use rust_dhke::crypto::pkcs3::{DhParams, DhPrivateKey, DhPublicKey};
+
+/// Fixed exponents, so every run derives the same public values for a group
+const OUR_SECRET: &str = "6d6f6e6b657920776f726b3a206669786564206578706f6e656e742066726f6d2072757374";
+const OPENSSL_SECRET: &str = "6f70656e73736c3a206669786564206578706f6e656e7420666f722074686520706565722e";
+
+/// Size of the parameters OpenSSL generates; the smallest it allows, so generation
+/// takes a fraction of a second
+const OPENSSL_PARAM_BITS: &str = "512";
+
+/// Run `check` in a fresh temporary directory, unless openssl is not on the PATH
+fn with_openssl(name: &str, check: impl FnOnce(&Path) -> std::io::Result<()>) {
+    if openssl(&["version"]).is_err() {
+        println!("openssl not found on the PATH; skipping");
+        return;
+    }
+    let dir = std::env::temp_dir().join(format!("dhke-openssl-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).expect("temporary directory is created");
+    let result = check(&dir);
+    let _ = std::fs::remove_dir_all(&dir);
+    result.expect("OpenSSL interoperates");
+}
+
+#[test]
+fn openssl_accepts_our_parameters_and_agrees_on_the_secret() {
+    with_openssl("ours", |dir| {
+        let (p, g) = DhGroup::Ffdhe2048.params();
+        let ours = DhParams::new(p, g);
+        let ours_path = dir.join("ffdhe2048.pem");
+        ours.store_pem(&ours_path)?;
+        openssl(&["dhparam", "-in", path(&ours_path), "-noout", "-check"])?;
+        agree(dir, &ours)
+    });
+}
+
+#[test]
+fn we_accept_openssl_parameters_and_agree_on_the_secret() {
+    with_openssl("theirs", |dir| {
+        // Loaded and validated as `DHServer` would
+        let theirs_path = dir.join("openssl.pem");
+        openssl(&["dhparam", "-out", path(&theirs_path), OPENSSL_PARAM_BITS])?;
+        let theirs = DhParams::load(&theirs_path)?;
+        validate_dh_params(&theirs.p, &theirs.g).map_err(invalid)?;
+        agree(dir, &theirs)
+    });
+}
+
+/// Derive a shared secret on both sides from fixed keys and compare
+fn agree(dir: &Path, params: &DhParams) -> std::io::Result<()> {
+    let hex = |text: &str| BigInt::from_str_radix(text, 16).expect("fixed exponents are hex");
+    let q = (&params.p - 1) / 2;
+
+    // OpenSSL's key pair: we write the private key, OpenSSL computes the public half
+    let openssl_key = DhPrivateKey { params: params.clone(), x: hex(OPENSSL_SECRET) };
+    let openssl_key_path = dir.join("openssl-key.pem");
+    let openssl_public_path = dir.join("openssl-public.pem");
+    openssl_key.store_pem(&openssl_key_path)?;
+    openssl(&["pkey", "-in", path(&openssl_key_path), "-pubout", "-out", path(&openssl_public_path)])?;
+    let openssl_public = DhPublicKey::load(&openssl_public_path)?;
+    if openssl_public != openssl_key.public_key() {
+        return Err(invalid("OpenSSL computed a different public key"));
+    }
+
+    // Our key pair, through the same key exchange the handshake uses
+    let exchange = FiniteFieldKeyExchange::with_secret(&params.p, &params.g, &q, hex(OUR_SECRET));
+    let our_public = DhPublicKey {
+        params: params.clone(),
+        y: BigInt::from_bytes_be(Sign::Plus, &exchange.public_key()),
+    };
+    let our_public_path = dir.join("our-public.pem");
+    our_public.store_pem(&our_public_path)?;
+
+    let ours = exchange.shared_secret(&openssl_public.y.to_bytes_be().1).map_err(invalid)?;
+    let theirs = openssl(&[
+        "pkeyutl",
+        "-derive",
+        "-inkey",
+        path(&openssl_key_path),
+        "-peerkey",
+        path(&our_public_path),
+    ])?;
+    // OpenSSL may or may not pad the secret to the size of p; compare as integers
+    if ours != BigInt::from_bytes_be(Sign::Plus, &theirs) {
+        return Err(invalid("shared secrets differ"));
+    }
+    Ok(())
+}
+
+/// Run openssl, returning its stdout or an error with its stderr
+fn openssl(args: &[&str]) -> std::io::Result<Vec<u8>> {
+    let output = Command::new("openssl").args(args).output()?;
+    if !output.status.success() {
+        return Err(std::io::Error::other(format!(
+            "openssl {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+fn path(path: &Path) -> &str {
+    path.to_str().expect("temporary paths are UTF-8")
+}
+
+fn invalid(reason: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, reason.to_string())
+}
@@ -0,0 +1,56 @@
+//! Pocklington certificates: hand-checked chains, generated group primes, and
+//! certificates that were tampered with
+
+use num_bigint::BigInt;
+use rust_dhke::crypto::crypto::{validate_dh_params, PrimalityConfig};
+use rust_dhke::crypto::prime_certificate::{generate_provable_dh_params, PocklingtonStep, PrimeCertificate};
+
+fn step(n: u32, witness: u32) -> PocklingtonStep {
+    PocklingtonStep { n: BigInt::from(n), witness: BigInt::from(witness) }
+}
+
+/// 11 -> 23 -> 47, a Cunningham chain: each prime is twice the previous one plus one,
+/// and 5 is a witness for both steps
+fn chain_to_47() -> PrimeCertificate {
+    PrimeCertificate { base: 11, steps: vec![step(23, 5), step(47, 5)] }
+}
+
+#[test]
+fn known_chain_proves_a_safe_prime() {
+    let certificate = chain_to_47();
+    assert_eq!(certificate.verify(), Ok(BigInt::from(47)));
+    assert_eq!(certificate.verify_safe_prime(&BigInt::from(47)), Ok(BigInt::from(23)));
+    assert_eq!(PrimeCertificate::from_bytes(&certificate.to_bytes()), Some(certificate));
+}
+
+#[test]
+fn tampered_chains_are_rejected() {
+    let composite_base = PrimeCertificate { base: 9, ..chain_to_47() };
+    assert_eq!(composite_base.verify(), Err("certificate does not start from a prime"));
+
+    // 93 = 3 * 31, though 23 divides 92 and exceeds its square root
+    let composite_step = PrimeCertificate { base: 11, steps: vec![step(23, 5), step(93, 2)] };
+    assert_eq!(composite_step.verify(), Err("certificate witness fails Fermat's test"));
+
+    let skipped_step = PrimeCertificate { base: 11, steps: vec![step(47, 5)] };
+    assert_eq!(skipped_step.verify(), Err("certificate step does not extend the previous prime"));
+
+    let trivial_witness = PrimeCertificate { base: 11, steps: vec![step(23, 5), step(47, 1)] };
+    assert_eq!(trivial_witness.verify(), Err("certificate witness is out of range"));
+
+    assert_eq!(chain_to_47().verify_safe_prime(&BigInt::from(23)), Err("certificate is for a different prime"));
+}
+
+#[test]
+fn generated_group_primes_come_with_a_valid_certificate() {
+    let (p, g, q, certificate) = generate_provable_dh_params(&PrimalityConfig::new(256));
+    assert_eq!(certificate.verify_safe_prime(&p), Ok(q.clone()));
+    assert_eq!(validate_dh_params(&p, &g), Ok(q));
+
+    assert_eq!(PrimeCertificate::from_bytes(&certificate.to_bytes()).as_ref(), Some(&certificate));
+
+    let mut tampered = certificate;
+    let last = tampered.steps.last_mut().expect("certificate has steps");
+    last.n += 2;
+    assert!(tampered.verify().is_err(), "certificate for p + 2 verified");
+}
@@ -0,0 +1,67 @@
+//! The Record codec of protocol::record: round trips, and the streams it must reject
+
+use std::io::ErrorKind;
+
+use rust_dhke::protocol::record::{ContentType, Record, RECORD_HEADER_LEN};
+
+/// Largest payload the reads below accept
+const MAX_LENGTH: usize = 1024;
+
+fn read(bytes: &[u8]) -> std::io::Result<Option<Record>> {
+    Record::read(&mut &bytes[..], MAX_LENGTH)
+}
+
+#[test]
+fn every_content_type_round_trips() {
+    let ids: Vec<u8> = ContentType::ALL.iter().map(ContentType::id).collect();
+    assert_eq!(ids, [0, 1, 2, 3, 4, 5]);
+    for &content_type in ContentType::ALL {
+        let record = Record::new(content_type, format!("{} payload", content_type.name()).into_bytes());
+        let bytes = record.to_bytes();
+        assert_eq!(bytes.len(), RECORD_HEADER_LEN + record.payload.len());
+        assert_eq!(bytes[0], content_type.id());
+        assert_eq!(bytes[1..RECORD_HEADER_LEN], (record.payload.len() as u32).to_be_bytes());
+        assert_eq!(read(&bytes).expect("record is read"), Some(record));
+    }
+}
+
+#[test]
+fn empty_payloads_and_consecutive_records_round_trip() {
+    let first = Record::new(ContentType::Ping, Vec::new());
+    let second = Record::new(ContentType::ApplicationData, vec![7; MAX_LENGTH]);
+    let bytes = [first.to_bytes(), second.to_bytes()].concat();
+    let mut reader = bytes.as_slice();
+    assert_eq!(Record::read(&mut reader, MAX_LENGTH).expect("first record is read"), Some(first));
+    assert_eq!(Record::read(&mut reader, MAX_LENGTH).expect("second record is read"), Some(second));
+    assert_eq!(Record::read(&mut reader, MAX_LENGTH).expect("end of stream is clean"), None);
+}
+
+#[test]
+fn truncated_headers_are_rejected() {
+    let header = Record::header(ContentType::ApplicationData, 4);
+    for len in 1..RECORD_HEADER_LEN {
+        let error = read(&header[..len]).expect_err("truncated header is rejected");
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof, "{}-byte header", len);
+    }
+}
+
+#[test]
+fn lengths_disagreeing_with_the_payload_are_rejected() {
+    // The header promises more payload than the stream holds
+    let short = [Record::header(ContentType::ApplicationData, 10).as_slice(), b"four"].concat();
+    assert_eq!(read(&short).expect_err("short payload is rejected").kind(), ErrorKind::UnexpectedEof);
+
+    // A length over the limit is refused before any payload is read
+    let oversized = Record::header(ContentType::ApplicationData, MAX_LENGTH as u32 + 1);
+    assert_eq!(read(&oversized).expect_err("oversized record is rejected").kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn unknown_content_types_are_rejected() {
+    let mut bytes = Record::new(ContentType::ApplicationData, b"data".to_vec()).to_bytes();
+    for id in [6, 0x80, u8::MAX] {
+        assert_eq!(ContentType::from_id(id), None);
+        bytes[0] = id;
+        assert_eq!(read(&bytes).expect_err("unknown type is rejected").kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,89 @@
+//! Reflected and generator public keys, injected by a peer that breaks the protocol
+//!
+//! The client tests run against a scripted server that answers the client's public
+//! key with either that same key or g; the server tests run the conformance checks
+//! that send them, against a server whose static key makes reflection possible.
+
+use std::net::TcpListener;
+use std::thread;
+
+use num_bigint::BigUint;
+use rand::Rng;
+use rust_dhke::crypto::groups::DhGroup;
+use rust_dhke::crypto::record::CipherSuite;
+use rust_dhke::network::client::DHClient;
+use rust_dhke::network::conformance::ConformanceSuite;
+use rust_dhke::network::framing::{read_message, write_message, Codec};
+use rust_dhke::network::server::{DHServer, KeyMode, ParamSource};
+use rust_dhke::structs::DH_Prot::{DHMessage, PROTOCOL_VERSIONS};
+
+/// Run a handshake against a server on `server_addr` that selects ffdhe2048 and
+/// answers the client's public key x with `server_key(x)`
+///
+/// # Returns
+/// The error the client's handshake failed with
+fn handshake_against(server_addr: &str, server_key: fn(BigUint) -> BigUint) -> std::io::Error {
+    let listener = TcpListener::bind(server_addr).expect("server binds");
+    let server_thread = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("client connects");
+        let hello = read_message(&mut stream, Codec::Native).expect("ClientHello arrives");
+        assert!(matches!(hello, Some(DHMessage::ClientHello { .. })), "expected ClientHello, got {:?}", hello);
+        let server_hello = DHMessage::ServerHelloNamed {
+            group: DhGroup::Ffdhe2048.id(),
+            cipher: CipherSuite::Aes256Gcm.id(),
+            version: PROTOCOL_VERSIONS[0],
+            random: rand::thread_rng().r#gen(),
+        };
+        write_message(&mut stream, Codec::Native, &server_hello).expect("ServerHello is sent");
+        let x = match read_message(&mut stream, Codec::Native).expect("client public key arrives") {
+            Some(DHMessage::ClientPublicKey { x }) => x,
+            other => panic!("expected ClientPublicKey, got {:?}", other),
+        };
+        write_message(&mut stream, Codec::Native, &DHMessage::ServerPublicKey { y: server_key(x) })
+            .expect("server public key is sent");
+        // The client explains its rejection before closing
+        read_message(&mut stream, Codec::Native).expect("alert arrives")
+    });
+
+    let mut client = DHClient::new(server_addr).expect("client connects");
+    let error = client.perform_key_exchange().expect_err("handshake is rejected");
+    let alert = server_thread.join().expect("server thread does not panic");
+    assert!(matches!(alert, Some(DHMessage::Alert { .. })), "expected an Alert, got {:?}", alert);
+    error
+}
+
+#[test]
+fn client_rejects_its_own_key_reflected() {
+    let error = handshake_against("127.0.0.1:18475", |x| x);
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(error.to_string(), "peer public key is identical to our own (reflection)");
+}
+
+#[test]
+fn client_rejects_the_generator_as_server_key() {
+    let error = handshake_against("127.0.0.1:18476", |_| DhGroup::Ffdhe2048.generator().magnitude().clone());
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(error.to_string(), "public key equals the generator g");
+}
+
+#[test]
+fn static_key_server_rejects_reflected_and_generator_keys() {
+    let server_addr = "127.0.0.1:18477";
+    let key_file = std::env::temp_dir().join(format!("dhke-reflection-{}.key", std::process::id()));
+    let server = DHServer::new(server_addr, ParamSource::Group(DhGroup::Ffdhe2048), None, KeyMode::Static(key_file.clone()))
+        .expect("server binds");
+    let cancel = server.cancel_token();
+    let server_thread = thread::spawn(move || server.run());
+
+    let report = ConformanceSuite::new(server_addr, std::time::Duration::from_secs(5)).run_only(&["reflected_key", "generator_as_key"]);
+    cancel.cancel();
+    server_thread.join().expect("server thread does not panic").expect("server shuts down cleanly");
+    let _ = std::fs::remove_file(key_file);
+
+    assert_eq!(report.results.len(), 2);
+    for result in &report.results {
+        assert!(result.passed, "{} failed: {}", result.name, result.detail);
+    }
+    let reflected = report.results.iter().find(|result| result.name == "reflected_key").expect("reflected_key ran");
+    assert!(!reflected.detail.contains("ephemeral"), "static key was not reflected: {}", reflected.detail);
+}
@@ -0,0 +1,167 @@
+//! Our key agreement and key schedule checked against ring
+//!
+//! Each test runs our implementation and ring's on the same random inputs and
+//! requires identical outputs, or both to reject the input. Only built with the
+//! ring-oracle feature: `cargo test --features ring-oracle,p256 --test ring_oracle`.
+
+#![cfg(feature = "ring-oracle")]
+
+use num_bigint::{BigInt, Sign};
+use rand::{Rng, RngCore};
+use ring::agreement::{self, EphemeralPrivateKey, UnparsedPublicKey};
+use ring::hkdf::{KeyType, Prk, Salt, HKDF_SHA256};
+use ring::rand::SystemRandom;
+use rust_dhke::crypto::kdf::{Kdf, SessionKeys};
+use rust_dhke::crypto::kex::{KeyExchange, X25519KeyExchange};
+use rust_dhke::crypto::transcript::Transcript;
+use rust_dhke::structs::DH_Prot::DHMessage;
+
+/// Random inputs per test
+const ROUNDS: usize = 64;
+
+/// An HKDF output length, for ring's expand
+struct Len(usize);
+
+impl KeyType for Len {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+/// A shared secret as the fixed-width big-endian bytes ring returns
+fn to_fixed(secret: &BigInt, width: usize) -> Vec<u8> {
+    let (_, bytes) = secret.to_bytes_be();
+    [vec![0; width - bytes.len()], bytes].concat()
+}
+
+/// Agree with ring's side of the exchange on `algorithm`
+///
+/// # Returns
+/// Our shared secret and ring's, as fixed-width bytes
+fn agree(ours: &dyn KeyExchange, algorithm: &'static agreement::Algorithm) -> (Vec<u8>, Vec<u8>) {
+    let rng = SystemRandom::new();
+    let theirs = EphemeralPrivateKey::generate(algorithm, &rng).expect("ring generates a key");
+    let their_public = theirs.compute_public_key().expect("ring computes its public key");
+
+    let our_secret = ours.shared_secret(their_public.as_ref()).expect("ring's public key is accepted");
+    let their_secret = agreement::agree_ephemeral(theirs, &UnparsedPublicKey::new(algorithm, ours.public_key()), |secret| {
+        secret.to_vec()
+    })
+    .expect("ring accepts our public key");
+    (to_fixed(&our_secret, their_secret.len()), their_secret)
+}
+
+/// Whether ring accepts `public_key` from a peer
+fn ring_accepts(algorithm: &'static agreement::Algorithm, public_key: &[u8]) -> bool {
+    let private = EphemeralPrivateKey::generate(algorithm, &SystemRandom::new()).expect("ring generates a key");
+    agreement::agree_ephemeral(private, &UnparsedPublicKey::new(algorithm, public_key), |_| ()).is_ok()
+}
+
+/// ring's HKDF-Expand of `prk` under `label`
+fn ring_expand(prk: &Prk, label: &[u8], len: usize) -> Vec<u8> {
+    let mut output = vec![0; len];
+    prk.expand(&[label], Len(len))
+        .expect("length is within the HKDF-SHA256 limit")
+        .fill(&mut output)
+        .expect("output has the requested length");
+    output
+}
+
+/// A transcript of a few random key shares
+fn random_transcript(rng: &mut impl Rng) -> Transcript {
+    let mut transcript = Transcript::new();
+    for _ in 0..rng.gen_range(1..5) {
+        let mut key = vec![0; rng.gen_range(1..300)];
+        rng.fill_bytes(&mut key);
+        transcript.record(&DHMessage::ClientKeyShare { key });
+    }
+    transcript
+}
+
+#[test]
+fn x25519_agrees_with_ring() {
+    for _ in 0..ROUNDS {
+        let (ours, theirs) = agree(&X25519KeyExchange::new(), &agreement::X25519);
+        assert_eq!(ours, theirs);
+    }
+}
+
+#[test]
+fn x25519_rejects_what_ring_rejects() {
+    // The identity and a point of order 8 both force an all-zero shared secret
+    let order_eight = [
+        0xe0, 0xeb, 0x7a, 0x7c, 0x3b, 0x41, 0xb8, 0xae, 0x16, 0x56, 0xe3, 0xfa, 0xf1, 0x9f, 0xc4, 0x6a,
+        0xda, 0x09, 0x8d, 0xeb, 0x9c, 0x32, 0xb1, 0xfd, 0x86, 0x62, 0x05, 0x16, 0x5f, 0x49, 0xb8, 0x00,
+    ];
+    for point in [[0; 32], order_eight] {
+        assert!(!ring_accepts(&agreement::X25519, &point));
+        assert!(X25519KeyExchange::new().shared_secret(&point).is_err());
+    }
+}
+
+#[cfg(feature = "p256")]
+#[test]
+fn p256_agrees_with_ring() {
+    use rust_dhke::crypto::kex::P256KeyExchange;
+
+    for _ in 0..ROUNDS {
+        let (ours, theirs) = agree(&P256KeyExchange::new(), &agreement::ECDH_P256);
+        assert_eq!(ours, theirs);
+    }
+}
+
+#[cfg(feature = "p256")]
+#[test]
+fn p256_rejects_what_ring_rejects() {
+    use rust_dhke::crypto::kex::P256KeyExchange;
+
+    let mut rng = rand::thread_rng();
+    for _ in 0..ROUNDS {
+        // Random coordinates are off the curve but for a negligible chance
+        let mut point = [0; 65];
+        point[0] = 0x04;
+        rng.fill_bytes(&mut point[1..]);
+        let ours = P256KeyExchange::new().shared_secret(&point).is_ok();
+        assert_eq!(ours, ring_accepts(&agreement::ECDH_P256, &point), "verdicts differ on {:02x?}", point);
+    }
+}
+
+#[test]
+fn session_keys_match_ring_hkdf() {
+    let mut rng = rand::thread_rng();
+    for _ in 0..ROUNDS {
+        let mut z = vec![0; rng.gen_range(1..=512)];
+        rng.fill_bytes(&mut z);
+        let shared_secret = BigInt::from_bytes_be(Sign::Plus, &z);
+        let transcript = random_transcript(&mut rng);
+        let keys = SessionKeys::derive(Kdf::Hkdf, &shared_secret, &transcript);
+
+        // The key schedule extracts from the minimal big-endian shared secret
+        let prk = Salt::new(HKDF_SHA256, &transcript.hash()).extract(&shared_secret.to_bytes_be().1);
+        let expected = [
+            (&b"dhke client write key"[..], &keys.client_write_key[..]),
+            (b"dhke server write key", &keys.server_write_key),
+            (b"dhke client iv", &keys.client_iv),
+            (b"dhke server iv", &keys.server_iv),
+            (b"dhke finished key", &keys.finished_key),
+            (b"dhke exporter secret", &keys.exporter_secret),
+        ];
+        for (label, ours) in expected {
+            assert_eq!(ours, ring_expand(&prk, label, ours.len()), "{} differs", String::from_utf8_lossy(label));
+        }
+    }
+}
+
+#[test]
+fn exports_match_ring_hkdf() {
+    let mut rng = rand::thread_rng();
+    let keys = SessionKeys::derive(Kdf::Hkdf, &BigInt::from(rng.next_u64()), &random_transcript(&mut rng));
+    let prk = Prk::new_less_safe(HKDF_SHA256, &keys.exporter_secret);
+    for _ in 0..ROUNDS {
+        let mut label = vec![0; rng.gen_range(0..64)];
+        rng.fill_bytes(&mut label);
+        let mut ours = vec![0; rng.gen_range(1..=255 * 32)];
+        keys.export(&label, &mut ours);
+        assert_eq!(ours, ring_expand(&prk, &label, ours.len()));
+    }
+}
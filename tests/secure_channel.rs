@@ -0,0 +1,53 @@
+//! The encrypted channel as a byte stream
+//!
+//! The server echoes every record it reads back through its own `SecureChannel`, so
+//! bytes written to the client come back through both ends' `Read` and `Write`.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::thread;
+
+use rust_dhke::crypto::groups::DhGroup;
+use rust_dhke::network::client::DHClient;
+use rust_dhke::network::server::{DHServer, KeyMode, ParamSource};
+
+/// Run `session` against a fresh echo server on `server_addr`, then shut both down
+fn with_echo_session(server_addr: &str, session: impl FnOnce(&mut DHClient)) {
+    let server = DHServer::new(server_addr, ParamSource::Group(DhGroup::Ffdhe2048), None, KeyMode::Ephemeral)
+        .expect("server binds");
+    let cancel = server.cancel_token();
+    let server_thread = thread::spawn(move || server.run());
+
+    let mut client = DHClient::new(server_addr).expect("client connects");
+    client.perform_key_exchange().expect("handshake completes");
+    session(&mut client);
+    client.close().expect("close is confirmed");
+
+    cancel.cancel();
+    server_thread.join().expect("server thread does not panic").expect("server shuts down cleanly");
+}
+
+#[test]
+fn writes_larger_than_a_record_come_back_whole() {
+    with_echo_session("127.0.0.1:18473", |client| {
+        let payload: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        client.write_all(&payload).expect("payload is sent");
+        client.flush().expect("flush succeeds");
+
+        let mut echoed = vec![0; payload.len()];
+        client.read_exact(&mut echoed).expect("echo arrives");
+        assert_eq!(echoed, payload);
+    });
+}
+
+#[test]
+fn buffered_readers_sit_on_the_channel() {
+    with_echo_session("127.0.0.1:18474", |client| {
+        client.write_all(b"first line\nsecond ").expect("first write is sent");
+        client.write_all(b"line\n").expect("second write is sent");
+
+        let channel = client.channel().expect("channel is established");
+        let mut lines = BufReader::new(channel).lines();
+        assert_eq!(lines.next().expect("a line arrives").expect("line reads"), "first line");
+        assert_eq!(lines.next().expect("a line arrives").expect("line reads"), "second line");
+    });
+}
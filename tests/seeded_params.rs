@@ -0,0 +1,23 @@
+//! Parameters generated from a seed, which anyone holding the seed can regenerate
+
+use rust_dhke::crypto::crypto::{generate_dh_params_seeded, validate_dh_params, PrimalityConfig};
+
+/// Small enough to generate quickly in debug builds
+const BITS: usize = 256;
+
+#[test]
+fn the_same_seed_gives_the_same_params() {
+    let config = PrimalityConfig::new(BITS);
+    let (p, g, q) = generate_dh_params_seeded(&config, b"reproducible");
+    assert_eq!(generate_dh_params_seeded(&config, b"reproducible"), (p.clone(), g.clone(), q.clone()));
+    assert_eq!(p.bits(), BITS as u64);
+    assert_eq!(validate_dh_params(&p, &g), Ok(q));
+}
+
+#[test]
+fn different_seeds_give_different_params() {
+    let config = PrimalityConfig::new(BITS);
+    let (p, g, _) = generate_dh_params_seeded(&config, b"one seed");
+    let (other_p, other_g, _) = generate_dh_params_seeded(&config, b"another seed");
+    assert_ne!((p, g), (other_p, other_g));
+}
@@ -0,0 +1,102 @@
+//! Session tickets persisted by a client across runs
+//!
+//! Each "run" opens the store from the same file, as `dhke client --session-cache`
+//! does, against one server whose ticket key outlives the runs.
+
+use std::path::Path;
+use std::thread;
+
+use rust_dhke::crypto::groups::{params_fingerprint, DhGroup};
+use rust_dhke::crypto::kex::KexAlgorithm;
+use rust_dhke::crypto::ticket::{TicketKey, DEFAULT_TICKET_LIFETIME};
+use rust_dhke::network::client::DHClient;
+use rust_dhke::network::server::{DHServer, KeyMode, ParamSource};
+use rust_dhke::network::tickets::TicketStore;
+
+/// Run `runs` against a ticket-issuing server on `server_addr`, with a fresh cache
+/// file, then shut the server down and delete the file
+fn with_ticket_server(server_addr: &str, runs: impl FnOnce(&Path)) {
+    let cache = std::env::temp_dir().join(format!("dhke-session-cache-{}-{}", std::process::id(), server_addr.replace(':', "-")));
+    let _ = std::fs::remove_file(&cache);
+    let server = DHServer::new(server_addr, ParamSource::Group(DhGroup::Ffdhe2048), None, KeyMode::Ephemeral)
+        .expect("server binds")
+        .with_ticket_key(TicketKey::generate(DEFAULT_TICKET_LIFETIME));
+    let cancel = server.cancel_token();
+    let server_thread = thread::spawn(move || server.run());
+
+    runs(&cache);
+
+    cancel.cancel();
+    server_thread.join().expect("server thread does not panic").expect("server shuts down cleanly");
+    let _ = std::fs::remove_file(&cache);
+}
+
+/// Connect with a store opened from `cache`, returning whether the session resumed
+fn run(server_addr: &str, cache: &Path) -> bool {
+    let store = TicketStore::persistent(cache).expect("cache opens");
+    let mut client = DHClient::new(server_addr)
+        .expect("client connects")
+        .with_ticket_store(store);
+    client.perform_key_exchange().expect("handshake completes");
+    let resumed = client.resumed();
+    client.close().expect("close is confirmed");
+    resumed
+}
+
+#[test]
+fn tickets_resume_sessions_across_runs() {
+    let server_addr = "127.0.0.1:18484";
+    with_ticket_server(server_addr, |cache| {
+        assert!(!run(server_addr, cache), "first run has no ticket");
+        let store = TicketStore::persistent(cache).expect("cache opens");
+        let entries = store.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].0, server_addr);
+        let (p, g) = DhGroup::Ffdhe2048.params();
+        assert_eq!(entries[0].1.params_fingerprint, Some(params_fingerprint(&p, &g)));
+
+        assert!(run(server_addr, cache), "second run resumes");
+        assert!(run(server_addr, cache), "the resumed session's ticket resumes too");
+    });
+}
+
+#[test]
+fn cleared_caches_fall_back_to_full_handshakes() {
+    let server_addr = "127.0.0.1:18485";
+    with_ticket_server(server_addr, |cache| {
+        run(server_addr, cache);
+        TicketStore::persistent(cache).expect("cache opens").clear();
+        assert!(TicketStore::persistent(cache).expect("cache opens").entries().is_empty());
+        assert!(!run(server_addr, cache), "a cleared cache has no ticket");
+    });
+}
+
+#[test]
+fn tickets_for_groups_no_longer_accepted_are_not_presented() {
+    let server_addr = "127.0.0.1:18486";
+    with_ticket_server(server_addr, |cache| {
+        run(server_addr, cache);
+        // The server only has ffdhe2048, so the full handshake must fail
+        let store = TicketStore::persistent(cache).expect("cache opens");
+        let mut client = DHClient::new(server_addr)
+            .expect("client connects")
+            .with_kex_algorithms(&[KexAlgorithm::FiniteField])
+            .with_groups(&[DhGroup::Ffdhe3072])
+            .with_ticket_store(store.clone());
+        client.perform_key_exchange().expect_err("no ticket is presented, and no group is shared");
+        assert!(store.entries().is_empty(), "the unusable ticket is dropped");
+    });
+}
+
+#[cfg(unix)]
+#[test]
+fn cache_files_are_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let server_addr = "127.0.0.1:18487";
+    with_ticket_server(server_addr, |cache| {
+        run(server_addr, cache);
+        let mode = std::fs::metadata(cache).expect("cache exists").permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    });
+}
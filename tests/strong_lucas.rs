@@ -0,0 +1,29 @@
+//! Known answers of the strong Lucas test (Selfridge's parameters)
+
+use num_bigint::BigInt;
+use rust_dhke::crypto::crypto::strong_lucas;
+
+#[test]
+fn primes_pass() {
+    for n in [5u64, 7, 11, 13, 5471, 10883, 1_000_000_007] {
+        assert!(strong_lucas(&BigInt::from(n)), "prime {} failed", n);
+    }
+}
+
+#[test]
+fn smallest_strong_lucas_pseudoprimes_pass() {
+    // Composites that pass by definition: 5459 = 53 * 103, 5777 = 53 * 109,
+    // 10877 = 73 * 149; Miller-Rabin is what rejects them
+    for n in [5459u64, 5777, 10877] {
+        assert!(strong_lucas(&BigInt::from(n)), "strong Lucas pseudoprime {} failed", n);
+    }
+}
+
+#[test]
+fn other_composites_fail() {
+    // 3215031751 is a strong pseudoprime to bases 2, 3, 5 and 7; 5329 = 73^2 is a
+    // perfect square, for which no D qualifies
+    for n in [9u64, 15, 5461, 5775, 10879, 3_215_031_751, 5329] {
+        assert!(!strong_lucas(&BigInt::from(n)), "composite {} passed", n);
+    }
+}
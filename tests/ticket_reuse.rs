@@ -0,0 +1,44 @@
+//! Session tickets resume one session each: the server remembers those redeemed and
+//! answers a ticket presented again with a full handshake
+
+use std::thread;
+
+use rust_dhke::crypto::groups::DhGroup;
+use rust_dhke::crypto::ticket::{TicketKey, DEFAULT_TICKET_LIFETIME};
+use rust_dhke::network::client::DHClient;
+use rust_dhke::network::server::{DHServer, KeyMode, ParamSource};
+use rust_dhke::network::tickets::TicketStore;
+
+/// Connect with the tickets in `store`, returning whether the session resumed
+fn run(server_addr: &str, store: &TicketStore) -> bool {
+    let mut client = DHClient::new(server_addr)
+        .expect("client connects")
+        .with_ticket_store(store.clone());
+    client.perform_key_exchange().expect("handshake completes");
+    let resumed = client.resumed();
+    client.close().expect("close is confirmed");
+    resumed
+}
+
+#[test]
+fn redeemed_tickets_get_a_full_handshake() {
+    let server_addr = "127.0.0.1:18490";
+    let server = DHServer::new(server_addr, ParamSource::Group(DhGroup::Ffdhe2048), None, KeyMode::Ephemeral)
+        .expect("server binds")
+        .with_ticket_key(TicketKey::generate(DEFAULT_TICKET_LIFETIME));
+    let cancel = server.cancel_token();
+    let server_thread = thread::spawn(move || server.run());
+
+    let store = TicketStore::new();
+    assert!(!run(server_addr, &store), "first handshake has no ticket");
+    let (_, first_ticket) = store.entries().pop().expect("a ticket was issued");
+    assert!(run(server_addr, &store), "the ticket resumes once");
+
+    store.store(server_addr, first_ticket);
+    let resumed_again = run(server_addr, &store);
+    assert!(run(server_addr, &store), "the ticket issued by the full handshake resumes");
+
+    cancel.cancel();
+    server_thread.join().expect("server thread does not panic").expect("server shuts down cleanly");
+    assert!(!resumed_again, "a redeemed ticket resumed a second session");
+}